@@ -0,0 +1,147 @@
+//! Compares rendering a set of templates with a virtual machine per template
+//! against rendering them with a shared [`budplate::Renderer`], and rendering
+//! a template by substitution against rendering it with the virtual machine.
+//! Also compares compiling templates from their source against compiling
+//! them from a [`budplate::TemplateCache`], as an application starting up
+//! would. Finally, counts the allocations made while parsing templates, which
+//! reuse a per-thread buffer for their segments so that each parse allocates
+//! its segments once rather than growing them tag by tag.
+//!
+//! Run with `cargo bench --features bench`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use budplate::{bench, Configuration};
+
+const RENDERS: usize = 50;
+
+const PARSES: usize = 1_000;
+
+/// The system allocator, counting each allocation it makes.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn main() {
+    let configuration = Configuration::for_html();
+    let workloads = [
+        bench::Workload::expressions(10),
+        bench::Workload::loops(10),
+        bench::Workload::conditionals(4),
+        bench::Workload::sub_templates(4),
+    ];
+
+    let separately = measure(|| bench::render_separately(&configuration, &workloads, RENDERS));
+    let shared = measure(|| bench::render_shared(&configuration, &workloads, RENDERS));
+
+    let renders = workloads.len() * RENDERS;
+    println!(
+        "separately: {separately:?} ({:?} per render)",
+        separately / u32::try_from(renders).unwrap()
+    );
+    println!(
+        "shared:     {shared:?} ({:?} per render)",
+        shared / u32::try_from(renders).unwrap()
+    );
+    println!(
+        "speedup:    {:.1}x",
+        separately.as_secs_f64() / shared.as_secs_f64()
+    );
+
+    let notification = bench::Workload::notification(10);
+    let substituted = bench::compile(&configuration, &notification).expect("workload compiles");
+    let executed = bench::compile(
+        &Configuration::for_html().disable_substitution(),
+        &notification,
+    )
+    .expect("workload compiles");
+    let substituted = measure(|| repeat(|| bench::render(&substituted, &notification)));
+    let executed = measure(|| repeat(|| bench::render(&executed, &notification)));
+    println!(
+        "substituted: {:?} per render",
+        substituted / u32::try_from(RENDERS).unwrap()
+    );
+    println!(
+        "executed:    {:?} per render",
+        executed / u32::try_from(RENDERS).unwrap()
+    );
+    println!(
+        "speedup:     {:.1}x",
+        executed.as_secs_f64() / substituted.as_secs_f64()
+    );
+
+    let workloads = bench::Workload::representative();
+    let cache = bench::precompile(&configuration, &workloads).expect("workloads transpile");
+    let from_source = measure(|| {
+        workloads
+            .iter()
+            .map(|workload| bench::compile(&configuration, workload))
+            .collect()
+    });
+    let from_cache = measure(|| bench::compile_cached(&configuration, &cache, &workloads));
+    println!("from source: {from_source:?} to compile");
+    println!("from cache:  {from_cache:?} to compile");
+    println!(
+        "speedup:     {:.1}x",
+        from_source.as_secs_f64() / from_cache.as_secs_f64()
+    );
+
+    for workload in [
+        bench::Workload::expressions(10),
+        bench::Workload::loops(10),
+        bench::Workload::sub_templates(4),
+    ] {
+        // The first parse on this thread allocates the reused buffer.
+        let segments = bench::parse(&configuration, &workload).expect("workload parses");
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        for _ in 0..PARSES {
+            bench::parse(&configuration, &workload).expect("workload parses");
+        }
+        let elapsed = start.elapsed();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        println!(
+            "parse {}: {segments} segments, {:.1} allocations and {:?} per parse",
+            workload.name,
+            allocations as f64 / PARSES as f64,
+            elapsed / u32::try_from(PARSES).unwrap()
+        );
+    }
+}
+
+fn repeat(
+    mut render: impl FnMut() -> Result<String, budplate::Error>,
+) -> Result<Vec<String>, budplate::Error> {
+    (0..RENDERS).map(|_| render()).collect()
+}
+
+fn measure<T>(mut run: impl FnMut() -> Result<Vec<T>, budplate::Error>) -> Duration {
+    let start = Instant::now();
+    let rendered = run().expect("workloads render");
+    let elapsed = start.elapsed();
+    assert!(!rendered.is_empty());
+    elapsed
+}
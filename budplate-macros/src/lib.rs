@@ -0,0 +1,331 @@
+//! Compile-time checked templates for Budplate.
+//!
+//! This crate is re-exported by `budplate` when its `macros` feature is
+//! enabled, and is not intended to be used directly.
+
+use proc_macro::{Literal, Span, TokenStream, TokenTree};
+
+use directive::{split_keyword, BlockKind};
+
+/// Budplate's parser for the statements it interprets, which is shared so that
+/// templates are checked the same way they are parsed when compiled.
+#[allow(dead_code)]
+#[path = "../../src/directive.rs"]
+mod directive;
+
+/// The error returned by the shared [`directive`] parser.
+#[derive(Debug)]
+struct Error {
+    kind: ErrorKind,
+}
+
+#[derive(Debug)]
+enum ErrorKind {
+    InvalidStatement(String),
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+/// Checks the syntax of a template literal at compile time, expanding to a
+/// `budplate::Template<'static>`.
+///
+/// Mismatched delimiters, unterminated comments and raw blocks, invalid
+/// directives, and unbalanced blocks are reported as compile errors at the
+/// literal. Directives are parsed by the same code that parses them when the
+/// template is compiled. Bud expressions are still checked when the template
+/// is compiled.
+#[proc_macro]
+pub fn template(input: TokenStream) -> TokenStream {
+    let mut tokens = input.into_iter();
+    let (literal, source) = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(literal)), None) => match unquote(&literal.to_string()) {
+            Some(source) => (literal, source),
+            None => return error(literal.span(), "expected a string literal"),
+        },
+        (Some(token), _) => return error(token.span(), "expected a single string literal"),
+        (None, _) => return error(Span::call_site(), "expected a string literal"),
+    };
+
+    if let Err(message) = check(&source) {
+        return error(literal.span(), &message);
+    }
+
+    let mut expansion: TokenStream = "::budplate::Template::from_str".parse().unwrap();
+    expansion.extend([TokenTree::Group(proc_macro::Group::new(
+        proc_macro::Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(literal)),
+    ))]);
+    expansion
+}
+
+/// Expands to a `compile_error!` located at `span`.
+fn error(span: Span, message: &str) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut expansion: TokenStream = "::core::compile_error!".parse().unwrap();
+    expansion.extend([TokenTree::Group(proc_macro::Group::new(
+        proc_macro::Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(message)),
+    ))]);
+    expansion
+        .into_iter()
+        .map(|mut token| {
+            token.set_span(span);
+            token
+        })
+        .collect()
+}
+
+/// Returns the contents of a string literal token, or `None` if the token is
+/// not a string literal.
+fn unquote(token: &str) -> Option<String> {
+    if let Some(raw) = token.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        return raw[hashes..]
+            .strip_prefix('"')?
+            .strip_suffix(&raw[..hashes])?
+            .strip_suffix('"')
+            .map(String::from);
+    }
+
+    let contents = token.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::with_capacity(contents.len());
+    let mut chars = contents.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            value.push(ch);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            't' => value.push('\t'),
+            '0' => value.push('\0'),
+            'x' => {
+                let digits = [chars.next()?, chars.next()?].iter().collect::<String>();
+                value.push(char::from(u8::from_str_radix(&digits, 16).ok()?));
+            }
+            'u' => {
+                chars.next().filter(|ch| *ch == '{')?;
+                let digits = chars
+                    .by_ref()
+                    .take_while(|ch| *ch != '}')
+                    .filter(|ch| *ch != '_')
+                    .collect::<String>();
+                value.push(char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?);
+            }
+            // An escaped newline skips the leading whitespace of the next
+            // line.
+            '\n' => while chars.next_if(|ch| ch.is_whitespace()).is_some() {},
+            other => value.push(other),
+        }
+    }
+    Some(value)
+}
+
+/// Checks the tags of `source` using the default delimiters.
+fn check(source: &str) -> Result<(), String> {
+    let located = |message: &str, offset: usize| {
+        let line = source[..offset].matches('\n').count() + 1;
+        format!("{message} on line {line} of the template")
+    };
+
+    let mut open: Vec<(&str, BlockKind, usize)> = Vec::new();
+    let mut position = 0;
+    while let Some(tag_start) = find(source, position, "{{") {
+        if let Some(stray) = find(&source[..tag_start], position, "}}") {
+            return Err(located("unexpected `}}`", stray));
+        }
+
+        let code_start = tag_start + 2;
+        if source[code_start..].starts_with('#') {
+            let comment_end = find(source, code_start + 1, "#}}")
+                .ok_or_else(|| located("unterminated comment", tag_start))?;
+            position = comment_end + 3;
+            continue;
+        }
+
+        let code_end = match (
+            find(source, code_start, "}}"),
+            find(source, code_start, "{{"),
+        ) {
+            (Some(end), next) if next.is_none_or(|next| end < next) => end,
+            _ => return Err(located("missing `}}`", tag_start)),
+        };
+        position = code_end + 2;
+
+        let code = source[code_start..code_end].trim_matches('-');
+        if code.starts_with('=') || code.starts_with(":=") {
+            continue;
+        }
+        let statement = code.trim();
+        let (keyword, rest) = split_keyword(statement);
+        if (keyword, rest) == ("raw", "") {
+            position = find_end_raw(source, position)
+                .ok_or_else(|| located("unterminated `raw` block", tag_start))?;
+            continue;
+        }
+        let parsed = directive::parse(statement).map_err(|err| {
+            let ErrorKind::InvalidStatement(statement) = err.kind;
+            located(&format!("invalid statement `{statement}`"), tag_start)
+        })?;
+        match parsed {
+            Some(directive) => {
+                if let Some(kind) = directive.opens() {
+                    open.push((keyword, kind, tag_start));
+                } else if let Some(kind) = directive.closes() {
+                    match open.pop() {
+                        Some((_, innermost, _)) if innermost == kind => {}
+                        Some((innermost, _, start)) => {
+                            return Err(located(
+                                &format!("unterminated `{innermost}` block"),
+                                start,
+                            ));
+                        }
+                        None => {
+                            return Err(located(&format!("unexpected `{statement}`"), tag_start))
+                        }
+                    }
+                }
+            }
+            None if directive::is_end(statement) => {
+                open.pop()
+                    .ok_or_else(|| located("unexpected `end`", tag_start))?;
+            }
+            None if directive::opens_block(statement) => {
+                open.push((keyword, BlockKind::Bud, tag_start));
+            }
+            // Other statements are left for Bud to interpret.
+            None => {}
+        }
+    }
+
+    if let Some(stray) = find(source, position, "}}") {
+        return Err(located("unexpected `}}`", stray));
+    }
+    match open.pop() {
+        Some((innermost, _, start)) => {
+            Err(located(&format!("unterminated `{innermost}` block"), start))
+        }
+        None => Ok(()),
+    }
+}
+
+fn find(source: &str, start: usize, pattern: &str) -> Option<usize> {
+    source[start..].find(pattern).map(|index| index + start)
+}
+
+/// Returns the offset after the `{{ end raw }}` tag that closes a raw block
+/// beginning at `start`.
+fn find_end_raw(source: &str, start: usize) -> Option<usize> {
+    let mut position = start;
+    loop {
+        let tag_start = find(source, position, "{{")?;
+        let code_end = find(source, tag_start + 2, "}}")?;
+        let code = source[tag_start + 2..code_end].trim_matches('-');
+        if code.split_whitespace().eq(["end", "raw"]) {
+            return Some(code_end + 2);
+        }
+        position = tag_start + 2;
+    }
+}
+
+#[test]
+fn string_literals() {
+    assert_eq!(
+        unquote(r#""a\n\"b\"\u{1F600}""#).unwrap(),
+        "a\n\"b\"\u{1F600}"
+    );
+    assert_eq!(unquote("\"a\\\n    b\"").unwrap(), "ab");
+    assert_eq!(
+        unquote(r###"r#"{{= "}}" }}"#"###).unwrap(),
+        r#"{{= "}}" }}"#
+    );
+    assert_eq!(unquote("b\"bytes\""), None);
+    assert_eq!(unquote("42"), None);
+}
+
+#[test]
+fn checked_syntax() {
+    assert_eq!(check("Hello, {{= name }}!"), Ok(()));
+    assert_eq!(
+        check("{{ if a }}{{ loop }}{{ end }}{{ end }}{{# {{ #}}{{ raw }}}}{{ end raw }}"),
+        Ok(())
+    );
+    assert_eq!(
+        check(r#"{{ define "row" }}{{ block body }}{{ end }}{{ end define }}"#),
+        Ok(())
+    );
+    assert_eq!(check("{{ if := 1 }}"), Ok(()));
+    assert_eq!(
+        check("a\n{{= name }"),
+        Err(String::from("missing `}}` on line 2 of the template"))
+    );
+    assert_eq!(
+        check("}}"),
+        Err(String::from("unexpected `}}` on line 1 of the template"))
+    );
+    assert_eq!(
+        check("{{ if a }}{{ end define }}"),
+        Err(String::from(
+            "unterminated `if` block on line 1 of the template"
+        ))
+    );
+    assert_eq!(
+        check("{{ end }}"),
+        Err(String::from("unexpected `end` on line 1 of the template"))
+    );
+    assert_eq!(
+        check("\n{{ raw }}"),
+        Err(String::from(
+            "unterminated `raw` block on line 2 of the template"
+        ))
+    );
+    assert!(check("{{# never closed").is_err());
+    assert!(check("{{ loop }}").is_err());
+    assert!(check("{{ for item in items }}").is_err());
+    assert_eq!(
+        check(r#"{{ fragment "head" }}{{ if a }}{{ end }}{{ end fragment }}"#),
+        Ok(())
+    );
+    assert!(check(r#"{{ fragment "head" }}"#).is_err());
+}
+
+#[test]
+fn block_directives() {
+    for (open, close) in [
+        (r#"define "row""#, "end define"),
+        ("block body", "end block"),
+        (r#"component "card""#, "end component"),
+        (r#"fragment "head""#, "end fragment"),
+        ("defer scripts", "end"),
+        ("if a", "end"),
+        ("loop", "end"),
+        ("function f()", "end"),
+        ("for item in items", "end"),
+        (r#"feature "beta""#, "end"),
+        ("sampled 0.1", "end"),
+        ("once", "end"),
+    ] {
+        let balanced = format!("{{{{ {open} }}}}a{{{{ {close} }}}}");
+        assert_eq!(check(&balanced), Ok(()), "{balanced}");
+        let unterminated = format!("{{{{ {open} }}}}a");
+        assert!(check(&unterminated).is_err(), "{unterminated}");
+    }
+    assert_eq!(
+        check("{{ once }}{{ end define }}"),
+        Err(String::from(
+            "unterminated `once` block on line 1 of the template"
+        ))
+    );
+    assert_eq!(
+        check("{{ sampled 1.5 }}{{ end }}"),
+        Err(String::from(
+            "invalid statement `sampled 1.5` on line 1 of the template"
+        ))
+    );
+}
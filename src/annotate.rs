@@ -0,0 +1,141 @@
+use std::ops::Range;
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{CompiledTemplate, Error};
+
+/// The output of [`CompiledTemplate::render_annotated()`], along with the
+/// template tags that produced it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AnnotatedOutput {
+    pub output: String,
+    /// The ranges of `output` produced by each tag, in the order they were
+    /// rendered.
+    pub annotations: Vec<Annotation>,
+}
+
+impl AnnotatedOutput {
+    /// Returns the annotation of the output at byte `offset`, such as where
+    /// a preview of the output was clicked.
+    #[must_use]
+    pub fn at(&self, offset: usize) -> Option<&Annotation> {
+        Self::annotation_at(&self.annotations, offset)
+    }
+
+    pub(crate) fn annotation_at(annotations: &[Annotation], offset: usize) -> Option<&Annotation> {
+        let index = annotations.partition_point(|annotation| annotation.output.end <= offset);
+        annotations
+            .get(index)
+            .filter(|annotation| annotation.output.start <= offset)
+    }
+}
+
+/// A range of rendered output and the template tag that produced it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Annotation {
+    /// The byte range of the output.
+    pub output: Range<usize>,
+    /// The name of the registered template containing the tag, or `None` for
+    /// the template being rendered.
+    pub template: Option<String>,
+    /// The byte range of the tag within its template's source.
+    pub source: Range<usize>,
+}
+
+impl CompiledTemplate {
+    /// Renders this template with `args`, returning which template tag
+    /// produced each range of the output. Only templates compiled with
+    /// [`Configuration::annotate_output()`](crate::Configuration::annotate_output)
+    /// are annotated.
+    ///
+    /// Output is attributed to the tag that writes it, including within
+    /// included templates. The body of a component and the output of a
+    /// memoized partial are captured before they are written, so they are
+    /// attributed to the tag that writes the captured output.
+    ///
+    /// ```rust
+    /// use budplate::{Annotation, Configuration, Templates};
+    ///
+    /// let mut templates = Templates::new();
+    /// templates.register("nav", "<nav>{{= title }}</nav>").unwrap();
+    /// templates
+    ///     .register("page", "<h1>{{= title }}</h1>{{ include \"nav\" with title }}")
+    ///     .unwrap();
+    /// let configuration = Configuration::for_html().annotate_output();
+    /// let page = configuration
+    ///     .renderer(&templates)
+    ///     .compile("page", ["title"])
+    ///     .unwrap();
+    /// let annotated = page.render_annotated([("title", "Home")]).unwrap();
+    /// assert_eq!(annotated.output, "<h1>Home</h1><nav>Home</nav>");
+    /// assert_eq!(
+    ///     annotated.at(20),
+    ///     Some(&Annotation {
+    ///         output: 18..22,
+    ///         template: Some(String::from("nav")),
+    ///         source: 5..17,
+    ///     })
+    /// );
+    /// ```
+    pub fn render_annotated<Name, Arg, Args>(&self, args: Args) -> Result<AnnotatedOutput, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let values = self.arguments(args)?;
+        let mut output = String::new();
+        let annotations = self
+            .machine
+            .state
+            .with_annotations(|| self.render_values_to(values, &mut output))?;
+        Ok(AnnotatedOutput {
+            output,
+            annotations,
+        })
+    }
+}
+
+#[test]
+fn annotated_output() {
+    use crate::{Configuration, Template};
+
+    let template = Template::from("a{{ for item in items }}[{{= item }}]{{ end }}{{# b #}}c");
+    let compiled = template
+        .compile(&Configuration::default().annotate_output(), ["items"])
+        .unwrap();
+    let annotated = compiled
+        .render_annotated([(
+            "items",
+            Value::dynamic(
+                [Value::Integer(1)]
+                    .into_iter()
+                    .collect::<budlang::vm::List>(),
+            ),
+        )])
+        .unwrap();
+    assert_eq!(annotated.output, "a[1]c");
+    let sources = annotated
+        .annotations
+        .iter()
+        .map(|annotation| (annotation.output.clone(), annotation.source.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sources,
+        [
+            (0..1, 0..1),
+            (1..2, 24..25),
+            (2..3, 25..36),
+            (3..4, 36..37),
+            (4..5, 55..56)
+        ]
+    );
+    assert_eq!(annotated.at(5), None);
+
+    let unannotated = template
+        .compile(&Configuration::default(), ["items"])
+        .unwrap()
+        .render_annotated([("items", Value::dynamic(budlang::vm::List::default()))])
+        .unwrap();
+    assert_eq!(unannotated.annotations, []);
+}
@@ -0,0 +1,74 @@
+use std::io::IsTerminal;
+
+/// Controls whether the ANSI styling functions (`bold()`, `color()`, etc.)
+/// emit escape sequences.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum AnsiStyling {
+    /// Emit escape sequences when standard output is a terminal and the
+    /// `NO_COLOR` environment variable is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl AnsiStyling {
+    pub fn is_enabled(self) -> bool {
+        match self {
+            AnsiStyling::Auto => {
+                std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+            }
+            AnsiStyling::Always => true,
+            AnsiStyling::Never => false,
+        }
+    }
+}
+
+pub(crate) const BOLD: &str = "1";
+pub(crate) const DIM: &str = "2";
+pub(crate) const ITALIC: &str = "3";
+pub(crate) const UNDERLINE: &str = "4";
+
+/// Returns the SGR parameter for the color named `name`, using foreground
+/// codes unless `background` is true.
+pub(crate) fn color_code(name: &str, background: bool) -> Option<u8> {
+    let (bright, name) = match name.strip_prefix("bright_") {
+        Some(name) => (true, name),
+        None => (false, name),
+    };
+    let offset = match name {
+        "black" => 0,
+        "red" => 1,
+        "green" => 2,
+        "yellow" => 3,
+        "blue" => 4,
+        "magenta" => 5,
+        "cyan" => 6,
+        "white" => 7,
+        _ => return None,
+    };
+    let base = match (bright, background) {
+        (false, false) => 30,
+        (false, true) => 40,
+        (true, false) => 90,
+        (true, true) => 100,
+    };
+    Some(base + offset)
+}
+
+pub(crate) fn style(text: &str, code: &str, enabled: bool) -> String {
+    if enabled && !text.is_empty() {
+        format!("\u{1b}[{code}m{text}\u{1b}[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+#[test]
+fn ansi_styles() {
+    assert_eq!(style("hi", BOLD, true), "\u{1b}[1mhi\u{1b}[0m");
+    assert_eq!(style("hi", BOLD, false), "hi");
+    assert_eq!(color_code("red", false), Some(31));
+    assert_eq!(color_code("bright_cyan", true), Some(106));
+    assert_eq!(color_code("mauve", false), None);
+}
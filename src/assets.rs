@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{Encoder, HtmlEncoding};
+
+/// Metadata about the static assets a template can reference, used by the
+/// `img()` function to emit dimensions and `srcset` attributes, and by the
+/// `sri()` function to emit Subresource Integrity attributes.
+#[derive(Debug, Clone, Default)]
+pub struct AssetManifest {
+    images: BTreeMap<String, ImageAsset>,
+    integrity: BTreeMap<String, String>,
+}
+
+impl AssetManifest {
+    pub const fn new() -> Self {
+        Self {
+            images: BTreeMap::new(),
+            integrity: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_image(mut self, path: impl Into<String>, image: ImageAsset) -> Self {
+        self.images.insert(path.into(), image);
+        self
+    }
+
+    pub fn image(&self, path: &str) -> Option<&ImageAsset> {
+        self.images.get(path)
+    }
+
+    /// Records the integrity hash of the asset at `path`, such as
+    /// `sha384-oqVuAfXRKap7fdgcCY5uykM6+R9GqQ8K/uxy9rx7HNQlGYl1kPzQho1wx4JwY8wC`,
+    /// as produced when the asset was built.
+    pub fn with_integrity(mut self, path: impl Into<String>, hash: impl Into<String>) -> Self {
+        self.integrity.insert(path.into(), hash.into());
+        self
+    }
+
+    pub fn integrity(&self, path: &str) -> Option<&str> {
+        self.integrity.get(path).map(String::as_str)
+    }
+
+    /// Returns whether `url` is the URL of an image or one of its variants.
+    #[cfg_attr(not(feature = "cdn"), allow(dead_code))]
+    pub(crate) fn contains_url(&self, url: &str) -> bool {
+        self.images
+            .values()
+            .any(|image| image.url == url || image.variants().any(|(_, variant)| variant == url))
+    }
+}
+
+/// An image and the resized variants available for it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ImageAsset {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    variants: BTreeMap<u32, String>,
+}
+
+impl ImageAsset {
+    pub fn new(url: impl Into<String>, width: u32, height: u32) -> Self {
+        Self {
+            url: url.into(),
+            width,
+            height,
+            variants: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a copy of this image resized to `width` pixels wide.
+    pub fn with_variant(mut self, width: u32, url: impl Into<String>) -> Self {
+        self.variants.insert(width, url.into());
+        self
+    }
+
+    pub fn variant(&self, width: u32) -> Option<&str> {
+        self.variants.get(&width).map(String::as_str)
+    }
+
+    pub fn variants(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.variants
+            .iter()
+            .map(|(width, url)| (*width, url.as_str()))
+    }
+}
+
+/// Renders an `<img>` tag for `image`. `srcset` lists the variants in
+/// `widths`, each of which must exist.
+pub(crate) fn img_tag(
+    image: &ImageAsset,
+    widths: &[u32],
+    alt: Option<&str>,
+) -> Result<String, u32> {
+    let mut tag = String::from("<img src=\"");
+    HtmlEncoding.encode_to_string(&image.url, &mut tag);
+    write!(
+        tag,
+        "\" width=\"{}\" height=\"{}\"",
+        image.width, image.height
+    )
+    .expect("String does not fail to write");
+
+    if !widths.is_empty() {
+        tag.push_str(" srcset=\"");
+        for (index, width) in widths.iter().enumerate() {
+            let url = image.variant(*width).ok_or(*width)?;
+            if index > 0 {
+                tag.push_str(", ");
+            }
+            HtmlEncoding.encode_to_string(url, &mut tag);
+            write!(tag, " {width}w").expect("String does not fail to write");
+        }
+        tag.push('"');
+    }
+
+    if let Some(alt) = alt {
+        tag.push_str(" alt=\"");
+        HtmlEncoding.encode_to_string(alt, &mut tag);
+        tag.push('"');
+    }
+    tag.push('>');
+    Ok(tag)
+}
+
+/// Renders the `integrity` and `crossorigin` attributes for an asset whose
+/// integrity hash is `hash`. Browsers only check the integrity of
+/// cross-origin assets fetched using CORS, so `crossorigin` is always
+/// included.
+pub(crate) fn sri_attributes(hash: &str) -> String {
+    let mut attributes = String::from("integrity=\"");
+    HtmlEncoding.encode_to_string(hash, &mut attributes);
+    attributes.push_str("\" crossorigin");
+    attributes
+}
+
+#[test]
+fn img_tags() {
+    let image = ImageAsset::new("/hero.jpg", 1920, 1080)
+        .with_variant(480, "/hero-480.jpg")
+        .with_variant(960, "/hero-960.jpg");
+    assert_eq!(
+        img_tag(&image, &[480, 960], Some("A \"hero\"")).unwrap(),
+        "<img src=\"/hero.jpg\" width=\"1920\" height=\"1080\" \
+         srcset=\"/hero-480.jpg 480w, /hero-960.jpg 960w\" alt=\"A &quot;hero&quot;\">"
+    );
+    assert_eq!(
+        img_tag(&image, &[], None).unwrap(),
+        "<img src=\"/hero.jpg\" width=\"1920\" height=\"1080\">"
+    );
+    assert_eq!(img_tag(&image, &[640], None), Err(640));
+}
+
+#[test]
+fn sri() {
+    let assets = AssetManifest::new().with_integrity("app.js", "sha384-abc+/=");
+    assert_eq!(
+        sri_attributes(assets.integrity("app.js").unwrap()),
+        "integrity=\"sha384-abc+/=\" crossorigin"
+    );
+    assert_eq!(assets.integrity("app.css"), None);
+}
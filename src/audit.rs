@@ -0,0 +1,240 @@
+use std::ops::Range;
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{expression, formats, vm, CompiledTemplate, Error};
+
+/// The words of Bud that [`expression::variables()`] doesn't distinguish
+/// from variables.
+const KEYWORDS: &[&str] = &[
+    "and", "break", "continue", "else", "end", "false", "for", "function", "if", "in", "loop",
+    "not", "or", "return", "to", "true", "while", "xor",
+];
+
+/// The output of [`CompiledTemplate::render_audited()`], along with
+/// everything the render accessed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AuditedOutput {
+    pub output: String,
+    /// The variables read, functions called, and templates included by the
+    /// render, in the order they were accessed.
+    pub accesses: Vec<Access>,
+}
+
+impl AuditedOutput {
+    /// Serializes the accesses of this render as a JSON array of objects
+    /// with the fields `kind`, `name`, `template`, `start`, and `end`.
+    pub fn to_json(&self) -> Result<String, Error> {
+        let accesses = self.accesses.iter().map(|access| {
+            vm::map([
+                ("kind", Value::from(access.kind.as_str())),
+                ("name", Value::from(access.name.as_str())),
+                (
+                    "template",
+                    access.template.as_deref().map_or(Value::Void, Value::from),
+                ),
+                ("start", offset(access.source.start)),
+                ("end", offset(access.source.end)),
+            ])
+        });
+        formats::json(&vm::list(accesses), false)
+    }
+}
+
+fn offset(offset: usize) -> Value {
+    Value::Integer(i64::try_from(offset).unwrap_or(i64::MAX))
+}
+
+/// Something accessed by a render, and the template tag that accessed it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Access {
+    pub kind: AccessKind,
+    /// The name of the variable, function, or template.
+    pub name: String,
+    /// The name of the registered template containing the tag, or `None` for
+    /// the template being rendered.
+    pub template: Option<String>,
+    /// The byte range of the tag within its template's source.
+    pub source: Range<usize>,
+}
+
+/// What an [`Access`] accessed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum AccessKind {
+    /// A variable was read.
+    Variable,
+    /// A function was called.
+    Function,
+    /// A template was included, including as a component.
+    Include,
+}
+
+impl AccessKind {
+    /// Returns the name of this kind in an exported audit log.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Variable => "variable",
+            Self::Function => "function",
+            Self::Include => "include",
+        }
+    }
+
+    pub(crate) fn parse(kind: &str) -> Option<Self> {
+        [Self::Variable, Self::Function, Self::Include]
+            .into_iter()
+            .find(|candidate| candidate.as_str() == kind)
+    }
+}
+
+/// Returns the variables read and the functions called by the Bud code
+/// `code`. Internal functions, whose names begin with `__`, and the
+/// variables assigned by `:=` are skipped.
+pub(crate) fn accesses(code: &str) -> Vec<(AccessKind, &str)> {
+    let mut accesses = Vec::new();
+    let mut variables = expression::variables(code).into_iter().peekable();
+    let mut calls = expression::calls(code).into_iter().peekable();
+    loop {
+        let (kind, range) = match (variables.peek(), calls.peek()) {
+            (Some(variable), Some(call)) if call.start < variable.start => {
+                (AccessKind::Function, calls.next())
+            }
+            (Some(_), _) => (AccessKind::Variable, variables.next()),
+            (None, Some(_)) => (AccessKind::Function, calls.next()),
+            (None, None) => break,
+        };
+        let Some(range) = range else { break };
+        let name = &code[range.clone()];
+        let assigned = code[range.end..].trim_start().starts_with(":=");
+        if KEYWORDS.contains(&name)
+            || name.starts_with("__")
+            || (kind == AccessKind::Variable && assigned)
+        {
+            continue;
+        }
+        accesses.push((kind, name));
+    }
+    accesses
+}
+
+impl CompiledTemplate {
+    /// Renders this template with `args`, returning every variable read,
+    /// function called, and template included, along with the tag that
+    /// accessed it. Only templates compiled with
+    /// [`Configuration::audit_accesses()`](crate::Configuration::audit_accesses)
+    /// are audited.
+    ///
+    /// Accesses are recorded when the tag that performs them is executed,
+    /// such as each time a loop repeats, which allows reviewing an untrusted
+    /// template for the data and functions it uses. The condition of an
+    /// `else if` statement is only recorded when its branch is rendered, and
+    /// the functions called by other functions, such as helpers, aren't
+    /// recorded.
+    ///
+    /// ```rust
+    /// use budplate::{AccessKind, Configuration, Template};
+    ///
+    /// let template = Template::from("<h1>{{= json(title) }}</h1>")
+    ///     .compile(&Configuration::default().audit_accesses(), ["title"])
+    ///     .unwrap();
+    /// let audited = template.render_audited([("title", "Home")]).unwrap();
+    /// assert_eq!(audited.output, r#"<h1>"Home"</h1>"#);
+    /// let accesses = audited
+    ///     .accesses
+    ///     .iter()
+    ///     .map(|access| (access.kind, access.name.as_str()))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(
+    ///     accesses,
+    ///     [(AccessKind::Function, "json"), (AccessKind::Variable, "title")]
+    /// );
+    /// ```
+    pub fn render_audited<Name, Arg, Args>(&self, args: Args) -> Result<AuditedOutput, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let values = self.arguments(args)?;
+        let mut output = String::new();
+        let accesses = self
+            .machine
+            .state
+            .with_audit(|| self.render_values_to(values, &mut output))?;
+        Ok(AuditedOutput { output, accesses })
+    }
+}
+
+#[test]
+fn statement_accesses() {
+    assert_eq!(
+        accesses("if user.admin and not hidden(page)"),
+        [
+            (AccessKind::Variable, "user"),
+            (AccessKind::Function, "hidden"),
+            (AccessKind::Variable, "page"),
+        ]
+    );
+    assert_eq!(
+        accesses("total := __field(order, \"total\") + tax"),
+        [
+            (AccessKind::Variable, "order"),
+            (AccessKind::Variable, "tax"),
+        ]
+    );
+}
+
+#[test]
+fn exported_accesses() {
+    let audited = AuditedOutput {
+        output: String::new(),
+        accesses: vec![Access {
+            kind: AccessKind::Include,
+            name: String::from("nav"),
+            template: None,
+            source: 3..20,
+        }],
+    };
+    assert_eq!(
+        audited.to_json().unwrap(),
+        r#"[{"end":20,"kind":"include","name":"nav","start":3,"template":null}]"#
+    );
+}
+
+#[test]
+fn audited_source() {
+    use crate::{Delimiters, Template, TranspileOptions};
+
+    let template = Template::from(
+        "{{ if user.admin }}{{= json(user) }}{{ else if guest }}{{ for item in guest.items }}{{ end }}{{ end }}",
+    );
+    let source = template
+        .parse(Delimiters::DEFAULT)
+        .unwrap()
+        .into_bud_source(
+            "render",
+            &[Symbol::from("user"), Symbol::from("guest")],
+            TranspileOptions {
+                audit_accesses: true,
+                ..TranspileOptions::DEFAULT
+            },
+        )
+        .unwrap()
+        .source;
+    let audits = source
+        .lines()
+        .filter(|line| line.starts_with("__audit("))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        audits,
+        [
+            r#"__audit("variable", "user", 0, 19)"#,
+            r#"__audit("function", "json", 19, 36)"#,
+            r#"__audit("variable", "user", 19, 36)"#,
+            r#"__audit("variable", "guest", 36, 55)"#,
+            r#"__audit("variable", "guest", 55, 84)"#,
+        ]
+    );
+    // The condition of an `else if` is recorded within its branch.
+    assert!(source.contains("else if guest\n__audit("));
+}
@@ -0,0 +1,322 @@
+use budlang::vm::{Symbol, Value};
+
+use crate::{
+    builtins::ExpressionWriter, ContextEncoding, Error, ParsedTemplate, Segment, SegmentKind,
+};
+
+/// Renders templates in place of the Bud virtual machine, as registered
+/// using
+/// [`Configuration::with_backend`](crate::Configuration::with_backend).
+///
+/// A backend receives the segments of each template as it is compiled, and
+/// may return an [`Executable`] that renders it. Templates the backend
+/// declines are compiled into the Bud virtual machine, which is the default
+/// backend, so a backend only needs to support the templates it can render
+/// faster or differently.
+///
+/// ```rust
+/// use budplate::{
+///     BackendRender, BackendTemplate, Configuration, Error, Executable, BackendSegment,
+///     TemplateBackend,
+/// };
+///
+/// /// Renders templates that contain only text.
+/// struct TextBackend;
+///
+/// struct Text(String);
+///
+/// impl Executable for Text {
+///     fn render(&self, render: &mut BackendRender<'_>) -> Result<(), Error> {
+///         render.write(&self.0)
+///     }
+/// }
+///
+/// impl TemplateBackend for TextBackend {
+///     fn compile(
+///         &self,
+///         template: &BackendTemplate<'_>,
+///     ) -> Result<Option<Box<dyn Executable>>, Error> {
+///         let mut text = String::new();
+///         for segment in &template.segments {
+///             match segment {
+///                 BackendSegment::Text(segment) => text.push_str(segment),
+///                 _ => return Ok(None),
+///             }
+///         }
+///         Ok(Some(Box::new(Text(text))))
+///     }
+/// }
+///
+/// let configuration = Configuration::default().with_backend(TextBackend);
+/// assert_eq!(configuration.render("Hello {{# world #}}").unwrap(), "Hello ");
+/// ```
+pub trait TemplateBackend: Send + Sync + 'static {
+    /// Compiles `template`, returning `None` to compile it using the Bud
+    /// virtual machine instead.
+    fn compile(&self, template: &BackendTemplate<'_>)
+        -> Result<Option<Box<dyn Executable>>, Error>;
+}
+
+/// A template compiled by a [`TemplateBackend`].
+pub trait Executable: 'static {
+    /// Renders the template with the arguments of `render`, writing its
+    /// output to `render`.
+    fn render(&self, render: &mut BackendRender<'_>) -> Result<(), Error>;
+}
+
+/// A template being compiled by a [`TemplateBackend`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct BackendTemplate<'a> {
+    /// The name of the registered template, or `None` for a template that
+    /// isn't registered.
+    pub name: Option<&'a str>,
+    /// The parameters the template is compiled to accept, in the order their
+    /// arguments are passed to [`Executable::render`].
+    pub parameters: &'a [Symbol],
+    /// The text, expressions, and statements of the template, in order.
+    /// Comments are omitted, and whitespace trimming has been applied to the
+    /// text.
+    pub segments: Vec<BackendSegment<'a>>,
+}
+
+impl<'a> BackendTemplate<'a> {
+    pub(crate) fn new(
+        template: &ParsedTemplate<'a>,
+        name: Option<&'a str>,
+        parameters: &'a [Symbol],
+    ) -> Self {
+        let source = template.source;
+        let segments = &template.segments;
+        let mut trim_next_start = false;
+        let mut backend_segments = Vec::with_capacity(segments.len());
+        for (index, segment) in segments.iter().enumerate() {
+            let code = &source[segment.range.clone()];
+            match segment.kind {
+                SegmentKind::Raw => {
+                    let mut text = code;
+                    if trim_next_start {
+                        text = text.trim_start();
+                    }
+                    if segments.get(index + 1).is_some_and(trims_before) {
+                        text = text.trim_end();
+                    }
+                    if !text.is_empty() {
+                        backend_segments.push(BackendSegment::Text(text));
+                    }
+                }
+                SegmentKind::Comment(trimming) => trim_next_start = trimming.trim_after,
+                SegmentKind::Statement(trimming) => {
+                    trim_next_start = trimming.trim_after;
+                    backend_segments.push(BackendSegment::Statement(code.trim()));
+                }
+                SegmentKind::Expression {
+                    trimming,
+                    safe,
+                    context,
+                } => {
+                    trim_next_start = trimming.trim_after;
+                    backend_segments.push(BackendSegment::Expression(BackendExpression {
+                        code: code.trim(),
+                        tag: &source[segment.tag.clone()],
+                        safe,
+                        context,
+                    }));
+                }
+            }
+        }
+        Self {
+            name,
+            parameters,
+            segments: backend_segments,
+        }
+    }
+}
+
+/// Returns true if `segment` trims the whitespace before it.
+fn trims_before(segment: &Segment) -> bool {
+    match segment.kind {
+        SegmentKind::Raw => false,
+        SegmentKind::Comment(trimming)
+        | SegmentKind::Statement(trimming)
+        | SegmentKind::Expression { trimming, .. } => trimming.trim_before,
+    }
+}
+
+/// A part of a [`BackendTemplate`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BackendSegment<'a> {
+    /// Text that is written to the output as it is.
+    Text(&'a str),
+    /// An expression whose value is written to the output.
+    Expression(BackendExpression<'a>),
+    /// A statement, such as `if` or `include`, without its delimiters.
+    Statement(&'a str),
+}
+
+/// An expression within a [`BackendTemplate`], whose value is written using
+/// [`BackendRender::write_expression`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BackendExpression<'a> {
+    /// The Bud source of the expression.
+    pub code: &'a str,
+    /// The entire tag, including its delimiters.
+    pub(crate) tag: &'a str,
+    /// Whether the expression is written without being encoded.
+    pub(crate) safe: bool,
+    /// The encoding selected within the tag.
+    pub(crate) context: Option<ContextEncoding>,
+}
+
+impl BackendExpression<'_> {
+    /// Returns true if the expression's value is written without being
+    /// encoded, as with `{{:= expression }}`.
+    #[must_use]
+    pub const fn is_safe(&self) -> bool {
+        self.safe
+    }
+}
+
+/// The arguments and output of a render by an [`Executable`].
+pub struct BackendRender<'a> {
+    pub(crate) parameters: &'a [Symbol],
+    pub(crate) arguments: &'a [Value],
+    pub(crate) writer: &'a ExpressionWriter,
+}
+
+impl BackendRender<'_> {
+    /// Returns the arguments of the render, in the order of
+    /// [`BackendTemplate::parameters`].
+    #[must_use]
+    pub fn arguments(&self) -> &[Value] {
+        self.arguments
+    }
+
+    /// Returns the argument for the parameter `name`, or `None` if the
+    /// template has no such parameter.
+    #[must_use]
+    pub fn argument(&self, name: &str) -> Option<&Value> {
+        self.parameters
+            .iter()
+            .position(|parameter| &**parameter == name)
+            .map(|index| &self.arguments[index])
+    }
+
+    /// Writes `text` to the output as it is.
+    pub fn write(&mut self, text: &str) -> Result<(), Error> {
+        self.writer.write_str(text)
+    }
+
+    /// Writes `value`, the value of `expression`, to the output as the Bud
+    /// virtual machine would, encoding it and handling missing values
+    /// according to the configuration.
+    pub fn write_expression(
+        &mut self,
+        expression: &BackendExpression<'_>,
+        value: &Value,
+    ) -> Result<(), Error> {
+        self.writer.write(expression, value)
+    }
+}
+
+#[test]
+fn backend_templates() {
+    use crate::{Configuration, OnMissing, Template};
+
+    /// Renders templates whose expressions are all parameters.
+    struct Lookups;
+
+    enum Part {
+        Text(String),
+        Lookup {
+            name: String,
+            tag: String,
+            safe: bool,
+        },
+    }
+
+    struct Parts(Vec<Part>);
+
+    impl Executable for Parts {
+        fn render(&self, render: &mut BackendRender<'_>) -> Result<(), Error> {
+            for part in &self.0 {
+                match part {
+                    Part::Text(text) => render.write(text)?,
+                    Part::Lookup { name, tag, safe } => {
+                        let value = render.argument(name).cloned().unwrap_or(Value::Void);
+                        let expression = BackendExpression {
+                            code: name,
+                            tag,
+                            safe: *safe,
+                            context: None,
+                        };
+                        render.write_expression(&expression, &value)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl TemplateBackend for Lookups {
+        fn compile(
+            &self,
+            template: &BackendTemplate<'_>,
+        ) -> Result<Option<Box<dyn Executable>>, Error> {
+            let mut parts = Vec::new();
+            for segment in &template.segments {
+                match segment {
+                    BackendSegment::Text(text) => parts.push(Part::Text(text.to_string())),
+                    BackendSegment::Expression(expression)
+                        if template.parameters.iter().any(|p| &**p == expression.code) =>
+                    {
+                        parts.push(Part::Lookup {
+                            name: expression.code.to_string(),
+                            tag: expression.tag.to_string(),
+                            safe: expression.is_safe(),
+                        });
+                    }
+                    _ => return Ok(None),
+                }
+            }
+            Ok(Some(Box::new(Parts(parts))))
+        }
+    }
+
+    let template = Template::from("<p>\n  {{=- greeting -}}\n</p>{{# note #}}{{:= greeting }}");
+    let parsed = template.parse(crate::Delimiters::DEFAULT).unwrap();
+    let parameters = [Symbol::from("greeting")];
+    let segments = BackendTemplate::new(&parsed, None, &parameters).segments;
+    assert_eq!(segments.len(), 4);
+    assert_eq!(segments[0], BackendSegment::Text("<p>"));
+    assert!(matches!(
+        segments[1],
+        BackendSegment::Expression(BackendExpression {
+            code: "greeting",
+            safe: false,
+            ..
+        })
+    ));
+    assert_eq!(segments[2], BackendSegment::Text("</p>"));
+
+    let compiled = template
+        .compile(
+            &Configuration::for_html().with_backend(Lookups),
+            ["greeting"],
+        )
+        .unwrap();
+    assert_eq!(
+        compiled.render_with([("greeting", "<b>Hi</b>")]).unwrap(),
+        "<p>&lt;b&gt;Hi&lt;/b&gt;</p><b>Hi</b>"
+    );
+
+    let kept = Template::from("Hi {{= name }}")
+        .compile(
+            &Configuration::default()
+                .on_missing(OnMissing::Keep)
+                .with_backend(Lookups),
+            ["name"],
+        )
+        .unwrap();
+    assert_eq!(kept.render().unwrap(), "Hi {{= name }}");
+}
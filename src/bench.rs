@@ -0,0 +1,319 @@
+//! Entry points for measuring the performance of Budplate.
+//!
+//! Each stage of rendering a template can be measured on its own:
+//! [`parse()`] scans a template's tags, [`transpile()`] also produces its Bud
+//! source, [`compile()`] also evaluates that source, and [`render()`]
+//! executes an already compiled template. [`end_to_end()`] performs every
+//! stage, as [`Configuration::render_with()`] does.
+//!
+//! [`render_separately()`] and [`render_shared()`] compare compiling a set of
+//! workloads into a virtual machine each with compiling them into the single
+//! virtual machine of a [`Renderer`](crate::Renderer).
+//!
+//! [`precompile()`] and [`compile_cached()`] measure the startup of an
+//! application that loads its templates from a
+//! [`TemplateCache`](crate::TemplateCache), which skips parsing and
+//! transpiling them but still compiles their Bud source.
+//!
+//! The [`Workload`] generators produce templates that exercise different
+//! parts of Budplate, so that changes can be compared using the same inputs:
+//!
+//! ```rust
+//! use budplate::{bench, Configuration};
+//!
+//! let configuration = Configuration::for_html();
+//! for workload in bench::Workload::representative() {
+//!     let compiled = bench::compile(&configuration, &workload).unwrap();
+//!     let rendered = bench::render(&compiled, &workload).unwrap();
+//!     assert!(!rendered.is_empty(), "{}", workload.name);
+//! }
+//! ```
+
+use std::fmt::Write;
+
+use budlang::vm::Value;
+
+use crate::{CompiledTemplate, Configuration, Encoder, Error, Template, TemplateCache, Templates};
+
+/// A template along with the arguments it is rendered with.
+#[derive(Debug, Clone)]
+pub struct Workload {
+    /// A short description of the workload, such as `expressions/100`.
+    pub name: String,
+    pub source: String,
+    pub arguments: Vec<(String, Value)>,
+}
+
+impl Workload {
+    pub fn new(name: impl Into<String>, source: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            source: source.into(),
+            arguments: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_argument(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.arguments.push((name.into(), value.into()));
+        self
+    }
+
+    /// Returns the names of this workload's arguments, which its template is
+    /// compiled to accept.
+    pub fn parameters(&self) -> impl Iterator<Item = &str> {
+        self.arguments.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// A template containing `bytes` of text and no tags.
+    pub fn static_text(bytes: usize) -> Self {
+        let mut source = String::with_capacity(bytes);
+        while source.len() < bytes {
+            source.push_str("<p>Lorem ipsum dolor sit amet.</p>\n");
+        }
+        source.truncate(bytes);
+        Self::new(format!("static_text/{bytes}"), source)
+    }
+
+    /// A template that encodes and outputs `count` expressions, each
+    /// surrounded by markup.
+    pub fn expressions(count: usize) -> Self {
+        let mut source = String::new();
+        for index in 0..count {
+            let _ = writeln!(
+                source,
+                "<li class=\"item-{index}\">{{{{= title }}}} #{{{{= number + {index} }}}}</li>"
+            );
+        }
+        Self::new(format!("expressions/{count}"), source)
+            .with_argument("title", "Fish & Chips")
+            .with_argument("number", 42_i64)
+    }
+
+    /// A notification whose `count` expressions only look up arguments and
+    /// their fields, which is rendered by substitution rather than by the
+    /// Bud virtual machine.
+    pub fn notification(count: usize) -> Self {
+        let mut source = String::from("<p>Hi {{= user.name }},</p>\n");
+        for index in 1..count {
+            let _ = writeln!(
+                source,
+                "<p>{{{{= message }}}} ({{{{= user.email }}}}, #{index})</p>"
+            );
+        }
+        let user = crate::vm::map([("name", "Ada"), ("email", "ada@example.com")]);
+        Self::new(format!("notification/{count}"), source)
+            .with_argument("user", user)
+            .with_argument("message", "Your order & receipt are ready.")
+    }
+
+    /// A template whose body is rendered `iterations` times by a loop.
+    pub fn loops(iterations: usize) -> Self {
+        let iterations = i64::try_from(iterations).unwrap_or(i64::MAX);
+        Self::new(
+            format!("loops/{iterations}"),
+            "<ul>\n{{ loop for i := 1 to count }}  <li>{{= label }} {{= i }}</li>\n{{ end }}</ul>",
+        )
+        .with_argument("label", "Row")
+        .with_argument("count", iterations)
+    }
+
+    /// A template that nests `depth` conditionals, each containing markup.
+    pub fn conditionals(depth: usize) -> Self {
+        let mut source = String::new();
+        for level in 0..depth {
+            let _ = writeln!(source, "{{{{ if level > {level} }}}}<div>");
+        }
+        for _ in 0..depth {
+            source.push_str("</div>{{ else }}<span>shallow</span>{{ end }}\n");
+        }
+        Self::new(format!("conditionals/{depth}"), source)
+            .with_argument("level", i64::try_from(depth / 2).unwrap_or(i64::MAX))
+    }
+
+    /// A template that defines `count` sub-templates and renders each of
+    /// them.
+    pub fn sub_templates(count: usize) -> Self {
+        let mut source = String::new();
+        for index in 0..count {
+            let _ = writeln!(
+                source,
+                "{{{{ define \"part{index}\" }}}}<section>{{{{= value }}}}</section>{{{{ end define }}}}"
+            );
+        }
+        for index in 0..count {
+            let _ = writeln!(source, "{{{{ render \"part{index}\" with value }}}}");
+        }
+        Self::new(format!("sub_templates/{count}"), source).with_argument("value", "<value>")
+    }
+
+    /// Returns a set of workloads of typical sizes covering each generator.
+    pub fn representative() -> Vec<Self> {
+        vec![
+            Self::static_text(16 * 1024),
+            Self::expressions(100),
+            Self::notification(20),
+            Self::loops(1_000),
+            Self::conditionals(32),
+            Self::sub_templates(20),
+        ]
+    }
+}
+
+/// Scans the tags of `workload`'s template, returning the number of segments
+/// it contains.
+pub fn parse<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    workload: &Workload,
+) -> Result<usize, Error> {
+    let template = Template::from(workload.source.as_str());
+    let parsed = template.parse(configuration.delimiters)?;
+    Ok(parsed.segments.len())
+}
+
+/// Returns the Bud source of `workload`'s template.
+pub fn transpile(workload: &Workload) -> Result<String, Error> {
+    Template::from(workload.source.as_str()).transpile("render", workload.parameters())
+}
+
+/// Compiles `workload`'s template to accept its arguments.
+pub fn compile<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    workload: &Workload,
+) -> Result<CompiledTemplate, Error> {
+    configuration.compile(
+        &Template::from(workload.source.as_str()),
+        workload.parameters(),
+    )
+}
+
+/// Renders `compiled` with `workload`'s arguments.
+pub fn render(compiled: &CompiledTemplate, workload: &Workload) -> Result<String, Error> {
+    compiled.render_with(
+        workload
+            .arguments
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone())),
+    )
+}
+
+/// Compiles and renders `workload`.
+pub fn end_to_end<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    workload: &Workload,
+) -> Result<String, Error> {
+    render(&compile(configuration, workload)?, workload)
+}
+
+/// Compiles each of `workloads` into a virtual machine of its own, rendering
+/// each of them `renders` times.
+pub fn render_separately<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    workloads: &[Workload],
+    renders: usize,
+) -> Result<Vec<String>, Error> {
+    let templates = registry(workloads)?;
+    let mut rendered = Vec::with_capacity(workloads.len() * renders);
+    for _ in 0..renders {
+        for workload in workloads {
+            let compiled =
+                configuration.compile_from(&templates, &workload.name, workload.parameters())?;
+            rendered.push(render(&compiled, workload)?);
+        }
+    }
+    Ok(rendered)
+}
+
+/// Renders each of `workloads` `renders` times using a single
+/// [`Renderer`](crate::Renderer), which compiles each workload once.
+pub fn render_shared<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    workloads: &[Workload],
+    renders: usize,
+) -> Result<Vec<String>, Error> {
+    let templates = registry(workloads)?;
+    let renderer = configuration.renderer(&templates);
+    let mut rendered = Vec::with_capacity(workloads.len() * renders);
+    for _ in 0..renders {
+        for workload in workloads {
+            rendered.push(
+                renderer.render_with(
+                    &workload.name,
+                    workload
+                        .arguments
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.clone())),
+                )?,
+            );
+        }
+    }
+    Ok(rendered)
+}
+
+/// Transpiles each of `workloads` into a cache, as a build step would.
+pub fn precompile<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    workloads: &[Workload],
+) -> Result<TemplateCache, Error> {
+    let templates = registry(workloads)?;
+    configuration.precompile(
+        &templates,
+        workloads
+            .iter()
+            .map(|workload| (workload.name.as_str(), workload.parameters())),
+    )
+}
+
+/// Compiles each of `workloads` from `cache`, as an application starting up
+/// would.
+pub fn compile_cached<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    cache: &TemplateCache,
+    workloads: &[Workload],
+) -> Result<Vec<CompiledTemplate>, Error> {
+    workloads
+        .iter()
+        .map(|workload| configuration.compile_cached(cache, &workload.name))
+        .collect()
+}
+
+/// Registers each of `workloads` using its name.
+fn registry(workloads: &[Workload]) -> Result<Templates<'_>, Error> {
+    let mut templates = Templates::new();
+    for workload in workloads {
+        templates.register(&workload.name, workload.source.as_str())?;
+    }
+    Ok(templates)
+}
+
+#[test]
+fn workloads_parse() {
+    let configuration = Configuration::for_html();
+    for workload in Workload::representative() {
+        assert!(
+            parse(&configuration, &workload).unwrap() > 0,
+            "{}",
+            workload.name
+        );
+        assert!(transpile(&workload).is_ok(), "{}", workload.name);
+    }
+    let workloads = Workload::representative();
+    let cache = precompile(&configuration, &workloads).unwrap();
+    for (compiled, workload) in compile_cached(&configuration, &cache, &workloads)
+        .unwrap()
+        .iter()
+        .zip(&workloads)
+    {
+        assert_eq!(
+            render(compiled, workload).unwrap(),
+            end_to_end(&configuration, workload).unwrap(),
+            "{}",
+            workload.name
+        );
+    }
+    assert_eq!(Workload::static_text(100).source.len(), 100);
+    assert_eq!(
+        Workload::expressions(3).parameters().collect::<Vec<_>>(),
+        ["title", "number"]
+    );
+}
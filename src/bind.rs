@@ -0,0 +1,55 @@
+use budlang::vm::Value;
+
+/// A tuple of arguments that a [`CompiledTemplate`](crate::CompiledTemplate)
+/// can be bound to using
+/// [`CompiledTemplate::bind()`](crate::CompiledTemplate::bind). Each element
+/// is the argument of the parameter at the same position.
+///
+/// This trait is implemented for tuples of up to eight values that convert
+/// into a [`Value`], including the unit type for templates without
+/// parameters.
+pub trait BoundArguments {
+    /// The number of arguments in the tuple.
+    const COUNT: usize;
+
+    /// Appends each argument to `values`, in order.
+    fn push_values(self, values: &mut Vec<Value>);
+}
+
+macro_rules! impl_bound_arguments {
+    ($count:literal $(, $name:ident)*) => {
+        impl<$($name),*> BoundArguments for ($($name,)*)
+        where
+            $($name: Into<Value>,)*
+        {
+            const COUNT: usize = $count;
+
+            #[allow(non_snake_case, unused_variables)]
+            fn push_values(self, values: &mut Vec<Value>) {
+                let ($($name,)*) = self;
+                $(values.push($name.into());)*
+            }
+        }
+    };
+}
+
+impl_bound_arguments!(0);
+impl_bound_arguments!(1, A);
+impl_bound_arguments!(2, A, B);
+impl_bound_arguments!(3, A, B, C);
+impl_bound_arguments!(4, A, B, C, D);
+impl_bound_arguments!(5, A, B, C, D, E);
+impl_bound_arguments!(6, A, B, C, D, E, F);
+impl_bound_arguments!(7, A, B, C, D, E, F, G);
+impl_bound_arguments!(8, A, B, C, D, E, F, G, H);
+
+#[test]
+fn pushed_in_order() {
+    let mut values = Vec::new();
+    (1_i64, 2_i64, true).push_values(&mut values);
+    assert_eq!(<(i64, i64, bool)>::COUNT, 3);
+    assert!(matches!(
+        values.as_slice(),
+        [Value::Integer(1), Value::Integer(2), Value::Boolean(true)]
+    ));
+}
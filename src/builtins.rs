@@ -0,0 +1,2046 @@
+use std::{
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap},
+    fmt::Write,
+    marker::PhantomData,
+    ops::Range,
+    rc::Rc,
+    sync::Arc,
+};
+
+use budlang::{
+    vm::{FaultKind, NativeFunction, PoppedValues, Symbol, Value},
+    Bud,
+};
+
+#[cfg(feature = "i18n")]
+use crate::i18n::Catalog;
+use crate::{
+    ansi, assets,
+    backend::BackendExpression,
+    classes, clock, formats, html,
+    options::EncodeFn,
+    symbols,
+    text::{self, Cell, TableStyle},
+    value::{self, Entries, Fingerprint, Missing, Shape},
+    variant, Access, AccessKind, Annotation, AssetManifest, CancellationToken, Clock,
+    Configuration, ContextEncoding, ContextProvider, Encoder, Error, ErrorKind, Exposure,
+    FeatureProvider, Fragment, HelperContext, OnMissing, PrettyPrinter, RenderOptions, SafeString,
+    StrictCoercion,
+};
+
+/// State shared between the native functions of a single render.
+#[derive(Clone, Default)]
+pub(crate) struct RenderState(Rc<RefCell<RenderStateData>>);
+
+#[derive(Default)]
+struct RenderStateData {
+    error: Option<Error>,
+    output: Option<*mut (dyn Write + 'static)>,
+    /// Buffers that capture output instead of `output`, innermost last.
+    captures: Vec<String>,
+    /// The registered templates whose classes `class()` scopes, innermost
+    /// last.
+    scopes: Vec<String>,
+    /// The number of bytes written to `output` during the current render.
+    written: usize,
+    /// The number of bytes written to `output` by the previous render.
+    last_written: usize,
+    max_output_len: Option<usize>,
+    context: Option<Box<dyn ContextProvider>>,
+    /// The options overriding the configuration during the current render.
+    options: Option<RenderOptions>,
+    /// The output of each sub-template rendered during the current render,
+    /// keyed by its function and arguments.
+    memos: HashMap<MemoKey, String>,
+    /// The sub-templates whose output is being memoized, innermost last.
+    memoizing: Vec<Memo>,
+    /// The fragments output is divided into, when rendering with
+    /// `with_fragments()`.
+    fragments: Option<Fragments>,
+    /// The output annotated so far, when rendering with
+    /// `with_annotations()`.
+    annotations: Option<Vec<Annotation>>,
+    /// The template and tag whose output is being written.
+    annotating: Option<(Option<String>, Range<usize>)>,
+    /// The accesses recorded so far, when rendering with `with_audit()`.
+    accesses: Option<Vec<Access>>,
+    /// The state of the generator deciding which renders are sampled.
+    sampler: u64,
+    /// The value, between 0 and 1, that the rates of the `sampled` regions
+    /// of the current render are compared against.
+    sample: Option<f64>,
+    /// The number of ids `unique_id()` has returned for each prefix during
+    /// the current render.
+    ids: HashMap<String, usize>,
+    /// The values stored by `set_once()` and `push()` during the current
+    /// render.
+    store: HashMap<String, Stored>,
+}
+
+/// A value in the store of a render.
+enum Stored {
+    /// A value set once using `set_once()`.
+    Value(Value),
+    /// The values pushed using `push()`, in the order they were pushed.
+    List(Vec<Value>),
+    /// The output of the `defer` regions of a name, and whether it has been
+    /// written by `emit`.
+    Deferred { content: String, emitted: bool },
+}
+
+impl RenderStateData {
+    /// Prevents the output of the sub-templates being memoized from being
+    /// reused, since it depends on the render's state or changes it.
+    fn touch_state(&mut self) {
+        for memo in &mut self.memoizing {
+            memo.stateful = true;
+        }
+    }
+
+    /// Attributes the next `len` bytes of output to the tag being rendered,
+    /// if output is being annotated.
+    fn annotate(&mut self, len: usize) {
+        let (Some(annotations), Some((template, source))) =
+            (&mut self.annotations, &self.annotating)
+        else {
+            return;
+        };
+        let output = self.written..self.written + len;
+        match annotations.last_mut() {
+            Some(last)
+                if last.output.end == output.start
+                    && &last.template == template
+                    && &last.source == source =>
+            {
+                last.output.end = output.end;
+            }
+            _ => annotations.push(Annotation {
+                output,
+                template: template.clone(),
+                source: source.clone(),
+            }),
+        }
+    }
+}
+
+type MemoKey = (String, Vec<Fingerprint>);
+
+/// A sub-template call whose output is being memoized.
+struct Memo {
+    /// The function and arguments of the call, or `None` if its arguments
+    /// can't be compared.
+    key: Option<MemoKey>,
+    /// Whether the call used the state of the render, such as by calling
+    /// `unique_id()` or rendering a `once` region, so that rendering it
+    /// again can produce different output.
+    stateful: bool,
+}
+
+const FRAGMENT_IN_CAPTURE: &str =
+    "fragments can't be rendered within components or memoized partials";
+
+type FragmentFn<'f> = dyn FnMut(Fragment<'_>) -> Result<(), Error> + 'f;
+
+struct Fragments {
+    on_fragment: *mut FragmentFn<'static>,
+    /// The name of the `fragment` region being rendered, if any.
+    name: Option<String>,
+    buffer: String,
+}
+
+/// Clears the output installed by [`RenderState::install_output()`], along
+/// with the rest of the render's state, when dropped.
+pub(crate) struct OutputGuard<'a> {
+    state: &'a RenderState,
+    _output: PhantomData<&'a mut dyn Write>,
+}
+
+impl Drop for OutputGuard<'_> {
+    fn drop(&mut self) {
+        let mut data = self.state.0.borrow_mut();
+        data.output = None;
+        data.captures.clear();
+        data.scopes.clear();
+        data.last_written = std::mem::take(&mut data.written);
+        data.memos.clear();
+        data.memoizing.clear();
+        data.sample = None;
+        data.ids.clear();
+        data.store.clear();
+    }
+}
+
+impl RenderState {
+    /// Records `error` as the reason the current render failed, returning a
+    /// fault that aborts execution of the virtual machine.
+    pub fn fail(&self, error: Error) -> FaultKind {
+        self.0.borrow_mut().error = Some(error);
+        FaultKind::ArgumentMissing(Symbol::from("budplate"))
+    }
+
+    pub fn take_error(&self) -> Option<Error> {
+        self.0.borrow_mut().error.take()
+    }
+
+    /// Invokes `render` with `output` as the destination of everything
+    /// written by `__write()`.
+    pub fn with_output<W: Write, R>(&self, output: &mut W, render: impl FnOnce() -> R) -> R {
+        let _output = self.install_output(output);
+        render()
+    }
+
+    /// Makes `output` the destination of everything written by `__write()`
+    /// until the returned guard is dropped, which also clears the state of
+    /// the render. Unlike [`RenderState::with_output()`], the render can be
+    /// suspended between polls of a future while the guard is held.
+    pub fn install_output<'a, W: Write>(&'a self, output: &'a mut W) -> OutputGuard<'a> {
+        let output: &mut dyn Write = output;
+        let output = output as *mut (dyn Write + '_);
+        // SAFETY: The native functions must be 'static, so the lifetime of the
+        // writer is erased. The pointer is only dereferenced by `write()`
+        // while the virtual machine executes, and the guard, which borrows
+        // `output`, clears it before `output` is released, even if the render
+        // panics. A leaked guard also leaks the virtual machine's borrow, so
+        // nothing executes until another render replaces the pointer.
+        let output = unsafe {
+            std::mem::transmute::<*mut (dyn Write + '_), *mut (dyn Write + 'static)>(output)
+        };
+        self.0.borrow_mut().output = Some(output);
+        OutputGuard {
+            state: self,
+            _output: PhantomData,
+        }
+    }
+
+    /// Invokes `render`, passing everything written by `__write()` to
+    /// `on_fragment` in pieces divided by the `fragment` regions of the
+    /// template.
+    pub fn with_fragments<'f, R>(
+        &self,
+        on_fragment: &'f mut FragmentFn<'f>,
+        render: impl FnOnce() -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        struct Reset<'a>(&'a RenderState);
+
+        impl Drop for Reset<'_> {
+            fn drop(&mut self) {
+                self.0 .0.borrow_mut().fragments = None;
+            }
+        }
+
+        let on_fragment = on_fragment as *mut FragmentFn<'f>;
+        // SAFETY: As with `with_output()`, the lifetime of the callback is
+        // erased, and `Reset` clears it before `on_fragment` is released.
+        let on_fragment = unsafe {
+            std::mem::transmute::<*mut FragmentFn<'f>, *mut FragmentFn<'static>>(on_fragment)
+        };
+        self.0.borrow_mut().fragments = Some(Fragments {
+            on_fragment,
+            name: None,
+            buffer: String::new(),
+        });
+        let _reset = Reset(self);
+        let result = render()?;
+        self.flush_fragment()?;
+        Ok(result)
+    }
+
+    /// Passes the output buffered since the last fragment boundary to the
+    /// callback of `with_fragments()`. Output outside of a named region is
+    /// skipped if it is empty.
+    fn flush_fragment(&self) -> Result<(), Error> {
+        let mut data = self.0.borrow_mut();
+        let Some(fragments) = &mut data.fragments else {
+            return Ok(());
+        };
+        let name = fragments.name.take();
+        if name.is_none() && fragments.buffer.is_empty() {
+            return Ok(());
+        }
+        let content = std::mem::take(&mut fragments.buffer);
+        let on_fragment = fragments.on_fragment;
+        drop(data);
+        // SAFETY: See `with_fragments()`. The state isn't borrowed while the
+        // callback executes.
+        unsafe {
+            (*on_fragment)(Fragment {
+                name: name.as_deref(),
+                content: &content,
+            })
+        }
+    }
+
+    /// Begins the `fragment` region `name`, first passing along any output
+    /// that precedes it.
+    fn begin_fragment(&self, name: String) -> Result<(), Error> {
+        self.flush_fragment()?;
+        if let Some(fragments) = &mut self.0.borrow_mut().fragments {
+            fragments.name = Some(name);
+        }
+        Ok(())
+    }
+
+    /// Returns true if output is being divided into fragments while it is
+    /// captured. Captured output is written after any fragment boundaries
+    /// within it, so they can't be honored.
+    fn fragment_in_capture(&self) -> bool {
+        let data = self.0.borrow();
+        data.fragments.is_some() && !data.captures.is_empty()
+    }
+
+    /// Invokes `render`, returning the annotations of everything it wrote.
+    pub fn with_annotations(
+        &self,
+        render: impl FnOnce() -> Result<(), Error>,
+    ) -> Result<Vec<Annotation>, Error> {
+        struct Reset<'a>(&'a RenderState);
+
+        impl Drop for Reset<'_> {
+            fn drop(&mut self) {
+                let mut data = self.0 .0.borrow_mut();
+                data.annotations = None;
+                data.annotating = None;
+            }
+        }
+
+        self.0.borrow_mut().annotations = Some(Vec::new());
+        let reset = Reset(self);
+        render()?;
+        let annotations = self.0.borrow_mut().annotations.take();
+        drop(reset);
+        Ok(annotations.unwrap_or_default())
+    }
+
+    /// Invokes `render`, returning everything it accessed.
+    pub fn with_audit(
+        &self,
+        render: impl FnOnce() -> Result<(), Error>,
+    ) -> Result<Vec<Access>, Error> {
+        struct Reset<'a>(&'a RenderState);
+
+        impl Drop for Reset<'_> {
+            fn drop(&mut self) {
+                self.0 .0.borrow_mut().accesses = None;
+            }
+        }
+
+        self.0.borrow_mut().accesses = Some(Vec::new());
+        let reset = Reset(self);
+        render()?;
+        let accesses = self.0.borrow_mut().accesses.take();
+        drop(reset);
+        Ok(accesses.unwrap_or_default())
+    }
+
+    /// Records the access returned by `access`, if the render is being
+    /// audited.
+    fn audit(&self, access: impl FnOnce() -> Access) {
+        if let Some(accesses) = &mut self.0.borrow_mut().accesses {
+            accesses.push(access());
+        }
+    }
+
+    /// Attributes the output written next to the tag at `source` within
+    /// `template`, if output is being annotated.
+    fn annotate(&self, template: Option<String>, source: Range<usize>) {
+        let mut data = self.0.borrow_mut();
+        if data.annotations.is_some() {
+            data.annotating = Some((template, source));
+        }
+    }
+
+    /// Invokes `render` with `context` available to the native functions.
+    pub fn with_context<R>(
+        &self,
+        context: Box<dyn ContextProvider>,
+        render: impl FnOnce() -> R,
+    ) -> R {
+        let previous = self.0.borrow_mut().context.replace(context);
+        let result = render();
+        self.0.borrow_mut().context = previous;
+        result
+    }
+
+    /// Invokes `render` with `options` overriding the configuration.
+    pub fn with_options<R>(&self, options: RenderOptions, render: impl FnOnce() -> R) -> R {
+        let (previous, max_output_len) = {
+            let mut data = self.0.borrow_mut();
+            let max_output_len = data.max_output_len;
+            if options.max_output_len.is_some() {
+                data.max_output_len = options.max_output_len;
+            }
+            (data.options.replace(options), max_output_len)
+        };
+        let result = render();
+        let mut data = self.0.borrow_mut();
+        data.options = previous;
+        data.max_output_len = max_output_len;
+        result
+    }
+
+    /// Returns how missing values are rendered by the current render, when
+    /// the configuration renders them according to `configured`.
+    pub fn on_missing(&self, configured: OnMissing) -> OnMissing {
+        match &self.0.borrow().options {
+            Some(options) => options.on_missing(configured),
+            None => configured,
+        }
+    }
+
+    /// Returns whether the current render pretty prints values that can't be
+    /// converted to text, when the configuration's setting is `configured`.
+    fn lenient_output(&self, configured: bool) -> bool {
+        match &self.0.borrow().options {
+            Some(options) => options.lenient_output(configured),
+            None => configured,
+        }
+    }
+
+    /// Returns the encoder overriding the configuration's during the current
+    /// render.
+    fn encoder(&self) -> Option<Arc<EncodeFn>> {
+        self.0
+            .borrow()
+            .options
+            .as_ref()
+            .and_then(|options| options.encoder.clone())
+    }
+
+    /// Returns the locale overriding the context's during the current render.
+    #[cfg_attr(not(feature = "i18n"), allow(dead_code))]
+    fn locale(&self) -> Option<String> {
+        self.0
+            .borrow()
+            .options
+            .as_ref()
+            .and_then(|options| options.locale.clone())
+    }
+
+    /// Returns the budget overriding the configuration's for the current
+    /// render.
+    pub fn budget(&self) -> Option<usize> {
+        self.0
+            .borrow()
+            .options
+            .as_ref()
+            .and_then(|options| options.budget)
+    }
+
+    /// Returns the capability of type `T` provided to the current render.
+    pub fn capability<T: std::any::Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.0.borrow().options.as_ref()?.capability()
+    }
+
+    /// Returns the value `name` from the current render's context.
+    pub fn context(&self, name: &str) -> Option<Value> {
+        self.0
+            .borrow()
+            .context
+            .as_ref()
+            .and_then(|context| context.get(name))
+    }
+
+    /// Returns whether `features` enables `feature` for the current render.
+    fn feature_enabled(&self, features: &dyn FeatureProvider, feature: &str) -> bool {
+        features.is_enabled(feature, self.0.borrow().context.as_deref())
+    }
+
+    /// Returns the entry `key` of the map `name` in the current render's
+    /// context.
+    #[cfg_attr(not(feature = "forms"), allow(dead_code))]
+    pub fn context_entry(&self, name: &str, key: &str) -> Option<Value> {
+        match Shape::of(&self.context(name)?) {
+            Shape::Map(entries) => entries
+                .into_iter()
+                .find(|(existing, _)| value::key_string(existing) == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn limit_output(&self, max_output_len: Option<usize>) {
+        self.0.borrow_mut().max_output_len = max_output_len;
+    }
+
+    pub fn seed_sampling(&self, seed: u64) {
+        // Xorshift can't leave the state of zero.
+        self.0.borrow_mut().sampler = seed | 1;
+    }
+
+    /// Returns true if the `sampled` regions with `rate` are rendered by the
+    /// current render. Each render draws one sample, so a render that
+    /// includes a region also includes those with greater rates.
+    fn sampled(&self, rate: f64) -> bool {
+        let mut data = self.0.borrow_mut();
+        let sample = match data.sample {
+            Some(sample) => sample,
+            None => {
+                let mut state = data.sampler;
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                data.sampler = state;
+                // The top 53 bits are exactly representable, giving a value
+                // uniformly distributed within [0, 1).
+                let sample = (state >> 11) as f64 / (1_u64 << 53) as f64;
+                *data.sample.insert(sample)
+            }
+        };
+        sample < rate
+    }
+
+    /// Begins capturing everything written by `__write()` until the matching
+    /// call to `end_capture()`.
+    fn begin_capture(&self) {
+        self.0.borrow_mut().captures.push(String::new());
+    }
+
+    fn end_capture(&self) -> Option<String> {
+        self.0.borrow_mut().captures.pop()
+    }
+
+    /// Writes the memoized output of `key` and returns false if it has
+    /// already been rendered. Otherwise begins capturing its output and
+    /// returns true, after which `end_memo()` must be called.
+    fn begin_memo(&self, key: Option<MemoKey>) -> Result<bool, Error> {
+        if let Some(output) = key
+            .as_ref()
+            .and_then(|key| self.0.borrow().memos.get(key).cloned())
+        {
+            self.write(&output)?;
+            return Ok(false);
+        }
+
+        if key.is_some() {
+            self.begin_capture();
+        }
+        self.0.borrow_mut().memoizing.push(Memo {
+            key,
+            stateful: false,
+        });
+        Ok(true)
+    }
+
+    /// Writes the output captured since the matching `begin_memo()`,
+    /// memoizing it unless the call used the state of the render.
+    fn end_memo(&self) -> Result<(), Error> {
+        let Some(Memo {
+            key: Some(key),
+            stateful,
+        }) = self.0.borrow_mut().memoizing.pop()
+        else {
+            return Ok(());
+        };
+        let output = self.end_capture().unwrap_or_default();
+        self.write(&output)?;
+        if !stateful {
+            self.0.borrow_mut().memos.insert(key, output);
+        }
+        Ok(())
+    }
+
+    fn push_scope(&self, template: String) {
+        self.0.borrow_mut().scopes.push(template);
+    }
+
+    fn pop_scope(&self) {
+        self.0.borrow_mut().scopes.pop();
+    }
+
+    /// Returns the next id starting with `prefix` in the current render.
+    fn unique_id(&self, prefix: &str) -> String {
+        let mut data = self.0.borrow_mut();
+        data.touch_state();
+        let count = data.ids.entry(prefix.to_string()).or_default();
+        *count += 1;
+        format!("{prefix}-{count}")
+    }
+
+    /// Stores `value` as `key` unless a value is already stored as `key`,
+    /// returning whether it was stored.
+    fn set_once(&self, key: String, value: Value) -> bool {
+        let mut data = self.0.borrow_mut();
+        data.touch_state();
+        match data.store.entry(key) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(Stored::Value(value));
+                true
+            }
+        }
+    }
+
+    /// Appends `value` to the list stored as `key`, returning false if
+    /// something other than a list is stored as `key`.
+    fn push(&self, key: String, value: Value) -> bool {
+        let mut data = self.0.borrow_mut();
+        data.touch_state();
+        match data
+            .store
+            .entry(key)
+            .or_insert_with(|| Stored::List(Vec::new()))
+        {
+            Stored::List(values) => {
+                values.push(value);
+                true
+            }
+            Stored::Value(_) | Stored::Deferred { .. } => false,
+        }
+    }
+
+    /// Adds `content` to the deferred content stored as `name`.
+    fn defer(&self, name: String, content: &str) -> Result<(), String> {
+        let mut data = self.0.borrow_mut();
+        data.touch_state();
+        match data
+            .store
+            .entry(name.clone())
+            .or_insert_with(|| Stored::Deferred {
+                content: String::new(),
+                emitted: false,
+            }) {
+            Stored::Deferred { emitted: true, .. } => {
+                Err(format!("`{name}` was deferred to after it was emitted"))
+            }
+            Stored::Deferred {
+                content: stored, ..
+            } => {
+                stored.push_str(content);
+                Ok(())
+            }
+            Stored::Value(_) | Stored::List(_) => Err(format!("`{name}` is not deferred content")),
+        }
+    }
+
+    /// Returns the content deferred as `name` so far, after which no more
+    /// content can be deferred as `name`.
+    fn emit(&self, name: String) -> Result<String, String> {
+        let mut data = self.0.borrow_mut();
+        data.touch_state();
+        match data
+            .store
+            .entry(name.clone())
+            .or_insert_with(|| Stored::Deferred {
+                content: String::new(),
+                emitted: false,
+            }) {
+            Stored::Deferred { content, emitted } => {
+                *emitted = true;
+                Ok(content.clone())
+            }
+            Stored::Value(_) | Stored::List(_) => Err(format!("`{name}` is not deferred content")),
+        }
+    }
+
+    /// Returns the value stored as `key`, or the list of values pushed to
+    /// it.
+    fn stored(&self, key: &str) -> Option<Value> {
+        let mut data = self.0.borrow_mut();
+        data.touch_state();
+        data.store.get(key).map(|stored| match stored {
+            Stored::Value(value) => value.clone(),
+            Stored::List(values) => {
+                Value::dynamic(values.iter().cloned().collect::<budlang::vm::List>())
+            }
+            Stored::Deferred { content, .. } => Value::from(SafeString::new(content.clone())),
+        })
+    }
+
+    fn scope_classes(&self, classes: &str) -> String {
+        classes::scope_classes(self.0.borrow().scopes.last().map(String::as_str), classes)
+    }
+
+    /// Returns the number of bytes written by the previous render, if it
+    /// hasn't been taken already.
+    pub fn take_last_written(&self) -> usize {
+        std::mem::take(&mut self.0.borrow_mut().last_written)
+    }
+
+    /// Returns the tags of the current render.
+    pub fn tags(&self) -> Vec<(String, String)> {
+        self.0
+            .borrow()
+            .options
+            .as_ref()
+            .map(|options| options.tags.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns true if the current render has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0
+            .borrow()
+            .options
+            .as_ref()
+            .and_then(|options| options.cancellation.as_ref())
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    fn write(&self, text: &str) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(Error::from(ErrorKind::Cancelled));
+        }
+        let mut data = self.0.borrow_mut();
+        if let Some(max_output_len) = data.max_output_len {
+            let buffered = data.captures.iter().map(String::len).sum::<usize>();
+            if data.written + buffered + text.len() > max_output_len {
+                return Err(Error::from(ErrorKind::OutputTooLarge));
+            }
+        }
+        if let Some(capture) = data.captures.last_mut() {
+            capture.push_str(text);
+            return Ok(());
+        }
+        data.annotate(text.len());
+        data.written += text.len();
+        if let Some(fragments) = &mut data.fragments {
+            fragments.buffer.push_str(text);
+            return Ok(());
+        }
+        let Some(output) = data.output else {
+            return Err(Error::from(ErrorKind::Io(std::io::Error::other(
+                "no output is being rendered",
+            ))));
+        };
+        drop(data);
+        // SAFETY: See `with_output()`.
+        unsafe { (*output).write_str(text) }.map_err(Error::from)
+    }
+}
+
+/// Reads a time argument, which is either a timestamp counting the seconds
+/// since the Unix epoch, or a date or time parsed by [`clock::parse_time`].
+fn time_argument(args: &mut Arguments<'_, '_>, name: &str) -> Result<i64, Error> {
+    match args.next(name)? {
+        Value::Integer(timestamp) => Ok(timestamp),
+        value => {
+            let time = args.string(name, &value)?;
+            clock::parse_time(&time)
+                .ok_or_else(|| args.invalid(format!("`{time}` is not a date or time")))
+        }
+    }
+}
+
+/// Returns [`ErrorKind::FloatOutput`] if `value` is a float that is about to be
+/// rendered while [`Configuration::reject_floats`] is enabled.
+fn check_float(value: &Value, reject_floats: bool) -> Result<(), Error> {
+    match value {
+        Value::Real(value) if reject_floats => Err(Error::from(ErrorKind::FloatOutput(*value))),
+        _ => Ok(()),
+    }
+}
+
+/// Converts the values of expressions into the text they are rendered as.
+#[derive(Clone)]
+struct OutputText {
+    state: RenderState,
+    pretty_printer: PrettyPrinter,
+    /// Whether [`Configuration::lenient_output`] is enabled.
+    lenient_output: bool,
+}
+
+impl OutputText {
+    /// Returns the text `value` is rendered as. Values that can't be
+    /// converted to strings, such as maps, are pretty printed when output is
+    /// lenient, and otherwise fail naming `expression`, the source of the
+    /// expression that produced the value.
+    fn convert(&self, value: &Value, expression: Option<&Value>) -> Result<String, Error> {
+        if let Ok(text) = value.try_convert_to_string(&()) {
+            Ok(text.to_string())
+        } else if self.state.lenient_output(self.lenient_output) {
+            Ok(self.pretty_printer.print(value))
+        } else {
+            Err(Error::from(ErrorKind::UnprintableValue {
+                expression: expression
+                    .and_then(|expression| expression.as_dynamic::<String>().cloned()),
+                value_type: Shape::of(value).type_name(),
+            }))
+        }
+    }
+}
+
+/// Writes the values of expressions to the output as `encode()` and
+/// `__write()` do, for templates rendered by a
+/// [`TemplateBackend`](crate::TemplateBackend).
+pub(crate) struct ExpressionWriter {
+    text: OutputText,
+    encoder: Box<ConfiguredEncoder>,
+    on_missing: OnMissing,
+    reject_floats: bool,
+}
+
+/// The encoder of a configuration, which needn't be `Send` or `Sync`.
+type ConfiguredEncoder = dyn Fn(&str, &mut String);
+
+impl ExpressionWriter {
+    pub fn new<Enc: Encoder>(configuration: &Configuration<Enc>, state: &RenderState) -> Self {
+        let encoder = configuration.encoder.clone();
+        Self {
+            text: OutputText {
+                state: state.clone(),
+                pretty_printer: configuration.pretty_printer.clone(),
+                lenient_output: configuration.lenient_output,
+            },
+            encoder: Box::new(move |input, output| encoder.encode_to_string(input, output)),
+            on_missing: configuration.on_missing,
+            reject_floats: configuration.reject_floats,
+        }
+    }
+
+    pub fn write_str(&self, text: &str) -> Result<(), Error> {
+        self.text.state.write(text)
+    }
+
+    /// Returns how missing values are rendered by the current render.
+    pub fn on_missing(&self) -> OnMissing {
+        self.text.state.on_missing(self.on_missing)
+    }
+
+    pub fn write(&self, expression: &BackendExpression<'_>, value: &Value) -> Result<(), Error> {
+        if Missing::is(value) {
+            return match self.on_missing {
+                OnMissing::Keep => self.write_str(expression.tag),
+                OnMissing::Placeholder => {
+                    self.write_str(&self.encode(&format!("⟦{}⟧", expression.code)))
+                }
+                OnMissing::Fail | OnMissing::Empty => Ok(()),
+            };
+        }
+        check_float(value, self.reject_floats)?;
+        let source = Value::from(expression.code);
+        let text = self.text.convert(value, Some(&source))?;
+        if let Some(context) = expression.context.filter(|_| !expression.safe) {
+            let mut encoded = String::with_capacity(text.len());
+            context.encode(&text, &mut encoded);
+            self.write_str(&encoded)
+        } else if expression.safe || value.as_dynamic::<SafeString>().is_some() {
+            self.write_str(&text)
+        } else {
+            self.write_str(&self.encode(&text))
+        }
+    }
+
+    /// Encodes `text` using the encoder of the current render.
+    fn encode(&self, text: &str) -> String {
+        let mut encoded = String::with_capacity(text.len());
+        match self.text.state.encoder() {
+            Some(encode) => encode(text, &mut encoded),
+            None => (self.encoder)(text, &mut encoded),
+        }
+        encoded
+    }
+}
+
+/// A function provided by the user through
+/// [`Configuration::with_function`], [`Configuration::with_helper`], or
+/// [`Configuration::with_contextual_helper`].
+#[derive(Clone)]
+pub(crate) enum CustomFunction {
+    Native(Arc<dyn NativeFunction + Send + Sync>),
+    Helper(Arc<HelperFn>),
+    Contextual(Arc<ContextualHelperFn>),
+}
+
+type HelperFn = dyn Fn(&[Value]) -> Result<Value, Error> + Send + Sync;
+pub(crate) type ContextualHelperFn =
+    dyn Fn(&HelperContext<'_>, &[Value]) -> Result<Value, Error> + Send + Sync;
+
+/// Builds the Bud virtual machine for a render, keeping track of how many
+/// native functions occupy the front of the vtable.
+pub(crate) struct Functions {
+    bud: Bud<()>,
+    count: usize,
+}
+
+impl Functions {
+    pub fn new() -> Self {
+        Self {
+            bud: Bud::empty(),
+            count: 0,
+        }
+    }
+
+    pub fn with(mut self, name: &str, function: impl NativeFunction + 'static) -> Self {
+        let name = symbols::intern(name);
+        self.bud = self.bud.with_native_function(name, function);
+        self.count += 1;
+        self
+    }
+
+    pub fn with_builtin<F>(self, state: &RenderState, name: &'static str, function: F) -> Self
+    where
+        F: Fn(&mut Arguments<'_, '_>) -> Result<Value, Error> + 'static,
+    {
+        self.with(
+            name,
+            Builtin {
+                name,
+                state: state.clone(),
+                function,
+            },
+        )
+    }
+
+    #[cfg(feature = "qr")]
+    fn with_qr(self, state: &RenderState) -> Self {
+        self.with_builtin(state, "qr", qr)
+    }
+
+    #[cfg(not(feature = "qr"))]
+    fn with_qr(self, _state: &RenderState) -> Self {
+        self
+    }
+
+    #[cfg(feature = "feeds")]
+    fn with_feeds(self, state: &RenderState) -> Self {
+        use crate::feeds;
+
+        self.with_builtin(state, "rfc822", |args| {
+            let timestamp = args.next_integer("timestamp")?;
+            args.finish()?;
+
+            Ok(Value::from(feeds::rfc822(timestamp)))
+        })
+        .with_builtin(state, "rfc3339", |args| {
+            let timestamp = args.next_integer("timestamp")?;
+            args.finish()?;
+
+            Ok(Value::from(feeds::rfc3339(timestamp)))
+        })
+        .with_builtin(state, "sitemap", |args| {
+            let pages = args.next("pages")?;
+            args.finish()?;
+
+            feeds::sitemap(&pages)
+                .map(|xml| Value::from(SafeString::new(xml)))
+                .map_err(|message| args.invalid(message))
+        })
+        .with_builtin(state, "rss", |args| {
+            let channel = args.next("channel")?;
+            let items = args.next("items")?;
+            args.finish()?;
+
+            feeds::rss(&channel, &items)
+                .map(|xml| Value::from(SafeString::new(xml)))
+                .map_err(|message| args.invalid(message))
+        })
+        .with_builtin(state, "atom", |args| {
+            let feed = args.next("feed")?;
+            let entries = args.next("entries")?;
+            args.finish()?;
+
+            feeds::atom(&feed, &entries)
+                .map(|xml| Value::from(SafeString::new(xml)))
+                .map_err(|message| args.invalid(message))
+        })
+    }
+
+    #[cfg(not(feature = "feeds"))]
+    fn with_feeds(self, _state: &RenderState) -> Self {
+        self
+    }
+
+    #[cfg(feature = "forms")]
+    fn with_forms(self, state: &RenderState) -> Self {
+        use crate::forms;
+
+        // Values and errors that aren't provided fall back to the `old_input`
+        // and `errors` maps in the render's context.
+        fn field(
+            state: &RenderState,
+            value: Option<Value>,
+            map: &str,
+            name: &str,
+        ) -> Option<Value> {
+            value
+                .filter(|value| !matches!(value, Value::Void))
+                .or_else(|| state.context_entry(map, name))
+        }
+
+        let context = state.clone();
+        let input_context = state.clone();
+        let textarea_context = state.clone();
+        self.with_builtin(state, "input", move |args| {
+            let name = args.next_string("name")?;
+            let value = field(&input_context, args.next_optional(), "old_input", &name);
+            let errors = forms::messages(
+                field(&input_context, args.next_optional(), "errors", &name).as_ref(),
+            );
+            let kind = match args.next_optional() {
+                Some(Value::Void) | None => String::from("text"),
+                Some(kind) => args.string("type", &kind)?,
+            };
+            args.finish()?;
+
+            Ok(Value::from(SafeString::new(forms::input(
+                &name,
+                &kind,
+                value.as_ref(),
+                &errors,
+            ))))
+        })
+        .with_builtin(state, "textarea", move |args| {
+            let name = args.next_string("name")?;
+            let value = field(&textarea_context, args.next_optional(), "old_input", &name);
+            let errors = forms::messages(
+                field(&textarea_context, args.next_optional(), "errors", &name).as_ref(),
+            );
+            args.finish()?;
+
+            Ok(Value::from(SafeString::new(forms::textarea(
+                &name,
+                value.as_ref(),
+                &errors,
+            ))))
+        })
+        .with_builtin(state, "select", move |args| {
+            let name = args.next_string("name")?;
+            let options = args.next("options")?;
+            let selected = field(&context, args.next_optional(), "old_input", &name);
+            let errors =
+                forms::messages(field(&context, args.next_optional(), "errors", &name).as_ref());
+            args.finish()?;
+
+            forms::select(&name, &options, selected.as_ref(), &errors)
+                .map(|html| Value::from(SafeString::new(html)))
+                .map_err(|message| args.invalid(message))
+        })
+    }
+
+    #[cfg(not(feature = "forms"))]
+    fn with_forms(self, _state: &RenderState) -> Self {
+        self
+    }
+
+    #[cfg(feature = "i18n")]
+    fn with_messages<Enc>(self, state: &RenderState, configuration: &Configuration<Enc>) -> Self {
+        let Some(catalog) = configuration.catalog.clone() else {
+            return self;
+        };
+
+        // The arguments that follow the key are pairs of names and values,
+        // which the transpiler produces from the arguments in the template.
+        fn message(
+            args: &mut Arguments<'_, '_>,
+            state: &RenderState,
+            catalog: &Catalog,
+            key: &str,
+            mut arguments: Vec<(String, Value)>,
+        ) -> Result<Value, Error> {
+            while let Some(name) = args.next_optional() {
+                let name = args.string("name", &name)?;
+                let value = args.next(&name)?;
+                arguments.push((name, value));
+            }
+            let locale = match state.locale() {
+                Some(locale) => Some(locale),
+                None => state
+                    .context("locale")
+                    .map(|locale| args.string("locale", &locale))
+                    .transpose()?,
+            };
+
+            catalog
+                .format(locale.as_deref(), key, arguments)
+                .map(Value::from)
+                .map_err(|message| args.invalid(message))
+        }
+
+        let t_context = state.clone();
+        let t_catalog = catalog.clone();
+        let plural_context = state.clone();
+        self.with_builtin(state, "t", move |args| {
+            let key = args.next_string("key")?;
+            message(args, &t_context, &t_catalog, &key, Vec::new())
+        })
+        .with_builtin(state, "plural", move |args| {
+            let key = args.next_string("key")?;
+            let count = args.next("count")?;
+            message(
+                args,
+                &plural_context,
+                &catalog,
+                &key,
+                vec![(String::from("count"), count)],
+            )
+        })
+    }
+
+    #[cfg(not(feature = "i18n"))]
+    fn with_messages<Enc>(self, _state: &RenderState, _configuration: &Configuration<Enc>) -> Self {
+        self
+    }
+
+    fn with_custom(mut self, functions: &[(Symbol, CustomFunction)], state: &RenderState) -> Self {
+        for (name, function) in functions {
+            self = match function {
+                CustomFunction::Native(function) => self.with(name, Shared(function.clone())),
+                CustomFunction::Helper(function) => self.with(
+                    name,
+                    Helper {
+                        state: state.clone(),
+                        function: function.clone(),
+                    },
+                ),
+                CustomFunction::Contextual(function) => self.with(
+                    name,
+                    ContextualHelper {
+                        state: state.clone(),
+                        function: function.clone(),
+                    },
+                ),
+            };
+        }
+        self
+    }
+
+    pub fn finish(self) -> (Bud<()>, usize) {
+        (self.bud, self.count)
+    }
+}
+
+pub(crate) fn install<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    state: &RenderState,
+) -> Functions {
+    let encoder = configuration.encoder.clone();
+    let pretty_printer = configuration.pretty_printer.clone();
+    let ansi = configuration.ansi.is_enabled();
+    let assets = configuration.assets.clone();
+    let islands = configuration.islands.clone();
+    let edge_includes = configuration.edge_includes.clone();
+    let coercion = configuration
+        .coercion
+        .clone()
+        .unwrap_or_else(|| Arc::new(StrictCoercion));
+    let add_coercion = coercion.clone();
+    let compare_coercion = coercion.clone();
+    let sri_assets = assets.clone();
+    let on_missing = configuration.on_missing;
+    let reject_floats = configuration.reject_floats;
+    let output_text = OutputText {
+        state: state.clone(),
+        pretty_printer: configuration.pretty_printer.clone(),
+        lenient_output: configuration.lenient_output,
+    };
+    let write_text = output_text.clone();
+    let encode_text = output_text.clone();
+    let encode_state = state.clone();
+    let field_state = state.clone();
+    let output = state.clone();
+    let capture = state.clone();
+    let captured = state.clone();
+    let memo_begin = state.clone();
+    let memo_end = state.clone();
+    let fragment_begin = state.clone();
+    let fragment_end = state.clone();
+    let push_scope = state.clone();
+    let pop_scope = state.clone();
+    let scoped = state.clone();
+    let ids = state.clone();
+    let set_once = state.clone();
+    let push = state.clone();
+    let stored = state.clone();
+    let defer = state.clone();
+    let once = state.clone();
+    let emit = state.clone();
+    let context = state.clone();
+    let annotate = state.clone();
+    let audit = state.clone();
+    let sampled = state.clone();
+    let features = configuration.features.clone();
+    let feature_state = state.clone();
+    let exposures = configuration.exposures.clone();
+    let variant_state = state.clone();
+    let clock: Arc<dyn Clock> = configuration
+        .clock
+        .clone()
+        .unwrap_or_else(|| Arc::new(clock::SystemClock));
+    let before_clock = clock.clone();
+    let after_clock = clock.clone();
+    let mut functions = Functions::new();
+    for encoding in ContextEncoding::ALL {
+        let output_text = output_text.clone();
+        functions = functions.with_builtin(state, encoding.function(), move |args| {
+            let value = args.next("value")?;
+            let expression = args.next_optional();
+            args.finish()?;
+
+            if Missing::is(&value) {
+                return Ok(value);
+            }
+            check_float(&value, reject_floats)?;
+            let value = output_text.convert(&value, expression.as_ref())?;
+            let mut encoded = String::with_capacity(value.len());
+            encoding.encode(&value, &mut encoded);
+            Ok(Value::from(encoded))
+        });
+    }
+    functions
+        .with_builtin(state, "__write", move |args| {
+            let value = args.next("value")?;
+            let expression = args.next_optional();
+            args.finish()?;
+
+            if Missing::is(&value) {
+                return Ok(Value::Void);
+            }
+            check_float(&value, reject_floats)?;
+            let value = write_text.convert(&value, expression.as_ref())?;
+            output.write(&value)?;
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "__capture", move |args| {
+            args.finish()?;
+
+            capture.begin_capture();
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "__captured", move |args| {
+            args.finish()?;
+
+            let captured = captured
+                .end_capture()
+                .ok_or_else(|| args.invalid("no output is being captured"))?;
+            Ok(Value::from(SafeString::new(captured)))
+        })
+        .with_builtin(state, "__memo_begin", move |args| {
+            let function = args.next_string("function")?;
+            let mut arguments = Vec::new();
+            while let Some(argument) = args.next_optional() {
+                arguments.push(argument);
+            }
+
+            let key = arguments
+                .iter()
+                .map(Fingerprint::of)
+                .collect::<Option<Vec<_>>>()
+                .map(|arguments| (function, arguments));
+            memo_begin.begin_memo(key).map(Value::Boolean)
+        })
+        .with_builtin(state, "__memo_end", move |args| {
+            args.finish()?;
+
+            memo_end.end_memo()?;
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "__fragment_begin", move |args| {
+            let name = args.next_string("name")?;
+            args.finish()?;
+
+            if fragment_begin.fragment_in_capture() {
+                return Err(args.invalid(FRAGMENT_IN_CAPTURE));
+            }
+            fragment_begin.begin_fragment(name)?;
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "__fragment_end", move |args| {
+            args.finish()?;
+
+            if fragment_end.fragment_in_capture() {
+                return Err(args.invalid(FRAGMENT_IN_CAPTURE));
+            }
+            fragment_end.flush_fragment()?;
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "encode", move |args| {
+            let value = args.next("value")?;
+            let expression = args.next_optional();
+            args.finish()?;
+
+            if value.as_dynamic::<SafeString>().is_some() || Missing::is(&value) {
+                return Ok(value);
+            }
+            check_float(&value, reject_floats)?;
+            let value = encode_text.convert(&value, expression.as_ref())?;
+            let mut encoded = String::with_capacity(value.len());
+            match encode_state.encoder() {
+                Some(encode) => encode(&value, &mut encoded),
+                None => encoder.encode(&value, &mut encoded)?,
+            }
+            Ok(Value::from(encoded))
+        })
+        .with_builtin(state, "__field", move |args| {
+            let value = args.next("value")?;
+            let field = args.next_string("field")?;
+            args.finish()?;
+
+            let on_missing = field_state.on_missing(on_missing);
+            match (Shape::of(&value), on_missing) {
+                (Shape::Map(entries), _) => lookup(entries, &field).map_or_else(
+                    || match on_missing {
+                        OnMissing::Fail => Err(args.invalid(format!("no field named `{field}`"))),
+                        OnMissing::Empty | OnMissing::Keep | OnMissing::Placeholder => {
+                            Ok(Value::dynamic(Missing))
+                        }
+                    },
+                    Ok,
+                ),
+                (_, OnMissing::Empty | OnMissing::Keep | OnMissing::Placeholder)
+                    if Missing::is(&value) =>
+                {
+                    Ok(value)
+                }
+                _ => Err(args.invalid(format!("cannot access `{field}` on a non-map value"))),
+            }
+        })
+        .with_builtin(state, "__try_field", |args| {
+            let value = args.next("value")?;
+            let field = args.next_string("field")?;
+            args.finish()?;
+
+            Ok(match Shape::of(&value) {
+                Shape::Map(entries) => lookup(entries, &field),
+                _ => None,
+            }
+            .unwrap_or_else(|| Value::dynamic(Missing)))
+        })
+        .with_builtin(state, "__missing", |args| {
+            args.finish()?;
+
+            Ok(Value::dynamic(Missing))
+        })
+        .with_builtin(state, "__default", |args| {
+            let value = args.next("value")?;
+            let fallback = args.next("fallback")?;
+            args.finish()?;
+
+            Ok(if Missing::or_void(&value) {
+                fallback
+            } else {
+                value
+            })
+        })
+        .with_builtin(state, "__truthy", move |args| {
+            let value = coerced(args.next("value")?);
+            args.finish()?;
+
+            coercion.is_truthy(&value).map(Value::Boolean)
+        })
+        .with_builtin(state, "__add", move |args| {
+            let left = coerced(args.next("left")?);
+            let right = coerced(args.next("right")?);
+            args.finish()?;
+
+            add_coercion.add(&left, &right)
+        })
+        .with_builtin(state, "__compare", move |args| {
+            let operator = args.next_string("operator")?;
+            let left = coerced(args.next("left")?);
+            let right = coerced(args.next("right")?);
+            args.finish()?;
+
+            let result = match operator.as_str() {
+                "==" => compare_coercion.equals(&left, &right)?,
+                "!=" => !compare_coercion.equals(&left, &right)?,
+                "<" => compare_coercion.compare(&left, &right)?.is_lt(),
+                "<=" => compare_coercion.compare(&left, &right)?.is_le(),
+                ">" => compare_coercion.compare(&left, &right)?.is_gt(),
+                ">=" => compare_coercion.compare(&left, &right)?.is_ge(),
+                _ => return Err(args.invalid(format!("unknown operator `{operator}`"))),
+            };
+            Ok(Value::Boolean(result))
+        })
+        .with_builtin(state, "__keep", |args| {
+            let value = args.next("value")?;
+            let tag = args.next_string("tag")?;
+            args.finish()?;
+
+            Ok(if Missing::is(&value) {
+                Value::from(SafeString::new(tag))
+            } else {
+                value
+            })
+        })
+        .with_builtin(state, "__audit", move |args| {
+            let kind = args.next_string("kind")?;
+            let kind = AccessKind::parse(&kind)
+                .ok_or_else(|| args.invalid(format!("unknown access `{kind}`")))?;
+            let name = args.next_string("name")?;
+            let start = args.next_width("start")?;
+            let end = args.next_width("end")?;
+            let template = args
+                .next_optional()
+                .map(|template| args.string("template", &template))
+                .transpose()?;
+            args.finish()?;
+
+            audit.audit(|| Access {
+                kind,
+                name,
+                template,
+                source: start..end,
+            });
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "__at", move |args| {
+            let start = args.next_width("start")?;
+            let end = args.next_width("end")?;
+            let template = args
+                .next_optional()
+                .map(|template| args.string("template", &template))
+                .transpose()?;
+            args.finish()?;
+
+            annotate.annotate(template, start..end);
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "__island", move |args| {
+            let name = args.next_string("name")?;
+            let mut props = Vec::new();
+            while let Some(prop) = args.next_optional() {
+                props.push((prop, args.next("value")?));
+            }
+            args.finish()?;
+
+            if !islands.contains(&name) {
+                return Err(args.invalid(format!("`{name}` is not a registered island")));
+            }
+            let props = formats::json(
+                &Value::dynamic(props.into_iter().collect::<budlang::vm::HashMap>()),
+                false,
+            )?;
+            Ok(Value::from(SafeString::new(html::island(&name, &props))))
+        })
+        .with_builtin(state, "__edge_include", move |args| {
+            let name = args.next_string("name")?;
+            let mut arguments = Vec::new();
+            while let Some(argument) = args.next_optional() {
+                let argument = args.string("argument", &argument)?;
+                let value = args.next("value")?;
+                // Missing values are left out of the query string.
+                if !matches!(value, Value::Void) {
+                    let value = args.string(&argument, &value)?;
+                    arguments.push((argument, value));
+                }
+            }
+            args.finish()?;
+
+            let marker = edge_includes
+                .as_ref()
+                .and_then(|edge_includes| edge_includes.marker(&name, &arguments))
+                .ok_or_else(|| args.invalid(format!("`{name}` is not an edge include")))?;
+            Ok(Value::from(SafeString::new(marker)))
+        })
+        .with_builtin(state, "__feature", move |args| {
+            let feature = args.next_string("feature")?;
+            args.finish()?;
+
+            Ok(Value::Boolean(features.as_ref().is_some_and(|features| {
+                feature_state.feature_enabled(&**features, &feature)
+            })))
+        })
+        .with_builtin(state, "variant", move |args| {
+            let experiment = args.next_string("experiment")?;
+            let variants = args.next("variants")?;
+            args.finish()?;
+
+            let Shape::List(variants) = Shape::of(&variants) else {
+                return Err(args.invalid("`variants` must be a list"));
+            };
+            if variants.is_empty() {
+                return Err(args.invalid("`variants` must not be empty"));
+            }
+            let user = variant_state
+                .context("user_id")
+                .filter(|user| !Missing::or_void(user))
+                .map(|user| args.string("user_id", &user))
+                .transpose()?;
+            let chosen =
+                variants[variant::assign(&experiment, user.as_deref(), variants.len())].clone();
+            if let Some(exposures) = &exposures {
+                exposures.record(&Exposure {
+                    experiment,
+                    variant: args.string("variant", &chosen)?,
+                    user,
+                });
+            }
+            Ok(chosen)
+        })
+        .with_builtin(state, "before", move |args| {
+            let end = time_argument(args, "time")?;
+            args.finish()?;
+
+            Ok(Value::Boolean(before_clock.now() < end))
+        })
+        .with_builtin(state, "after", move |args| {
+            let start = time_argument(args, "time")?;
+            args.finish()?;
+
+            Ok(Value::Boolean(after_clock.now() >= start))
+        })
+        .with_builtin(state, "between", move |args| {
+            let start = time_argument(args, "start")?;
+            let end = time_argument(args, "end")?;
+            args.finish()?;
+
+            Ok(Value::Boolean((start..end).contains(&clock.now())))
+        })
+        .with_builtin(state, "__sampled", move |args| {
+            let rate = match args.next("rate")? {
+                Value::Integer(rate) => rate as f64,
+                Value::Real(rate) => rate,
+                _ => return Err(args.invalid("`rate` must be a number")),
+            };
+            args.finish()?;
+
+            Ok(Value::Boolean(sampled.sampled(rate)))
+        })
+        .with_builtin(state, "defined", |args| {
+            let value = args.next("value")?;
+            args.finish()?;
+
+            Ok(Value::from(!Missing::or_void(&value)))
+        })
+        .with_builtin(state, "__entries", |args| {
+            let collection = args.next("collection")?;
+            args.finish()?;
+
+            Entries::of(&collection)
+                .map(Value::dynamic)
+                .ok_or_else(|| args.invalid("only lists and maps can be iterated"))
+        })
+        .with_builtin(state, "__entry_count", |args| {
+            let entries = args.next("entries")?;
+            args.finish()?;
+
+            let count = entries
+                .as_dynamic::<Entries>()
+                .map_or(0, |entries| entries.0.len());
+            Ok(Value::from(i64::try_from(count).unwrap_or(i64::MAX)))
+        })
+        .with_builtin(state, "__entry_key", |args| entry(args).map(|(key, _)| key))
+        .with_builtin(state, "__entry_value", |args| {
+            entry(args).map(|(_, value)| value)
+        })
+        .with_builtin(state, "context", move |args| {
+            let name = args.next_string("name")?;
+            args.finish()?;
+
+            Ok(context.context(&name).unwrap_or(Value::Void))
+        })
+        .with_builtin(state, "__push_scope", move |args| {
+            let template = args.next_string("template")?;
+            args.finish()?;
+
+            push_scope.push_scope(template);
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "__pop_scope", move |args| {
+            args.finish()?;
+
+            pop_scope.pop_scope();
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "class", move |args| {
+            let mut classes = vec![args.next_string("classes")?];
+            while let Some(class) = args.next_optional() {
+                classes.push(args.string("classes", &class)?);
+            }
+
+            Ok(Value::from(scoped.scope_classes(&classes.join(" "))))
+        })
+        .with_builtin(state, "unique_id", move |args| {
+            let prefix = args.next_string("prefix")?;
+            args.finish()?;
+
+            Ok(Value::from(ids.unique_id(&prefix)))
+        })
+        .with_builtin(state, "set_once", move |args| {
+            let key = args.next_string("key")?;
+            let value = args.next("value")?;
+            args.finish()?;
+
+            Ok(Value::Boolean(set_once.set_once(key, value)))
+        })
+        .with_builtin(state, "push", move |args| {
+            let key = args.next_string("key")?;
+            let value = args.next("value")?;
+            args.finish()?;
+
+            if push.push(key.clone(), value) {
+                Ok(Value::Void)
+            } else {
+                Err(args.invalid(format!("`{key}` is not a list")))
+            }
+        })
+        .with_builtin(state, "get", move |args| {
+            let key = args.next_string("key")?;
+            args.finish()?;
+
+            Ok(stored.stored(&key).unwrap_or(Value::Void))
+        })
+        .with_builtin(state, "__once", move |args| {
+            let region = args.next_string("region")?;
+            args.finish()?;
+
+            // Regions are stored under keys that `set_once()` can't be
+            // given by accident.
+            Ok(Value::Boolean(
+                once.set_once(format!("__once {region}"), Value::Void),
+            ))
+        })
+        .with_builtin(state, "__defer", move |args| {
+            let name = args.next_string("name")?;
+            let content = args.next_string("content")?;
+            args.finish()?;
+
+            defer
+                .defer(name, &content)
+                .map_err(|message| args.invalid(message))?;
+            Ok(Value::Void)
+        })
+        .with_builtin(state, "__emit", move |args| {
+            let name = args.next_string("name")?;
+            args.finish()?;
+
+            let content = emit.emit(name).map_err(|message| args.invalid(message))?;
+            Ok(Value::from(SafeString::new(content)))
+        })
+        .with_builtin(state, "dump", move |args| {
+            let value = args.next("value")?;
+            args.finish()?;
+
+            Ok(Value::from(pretty_printer.print(&value)))
+        })
+        .with_builtin(state, "json", |args| {
+            let value = args.next("value")?;
+            args.finish()?;
+
+            formats::json(&value, false).map(Value::from)
+        })
+        .with_builtin(state, "json_pretty", |args| {
+            let value = args.next("value")?;
+            args.finish()?;
+
+            formats::json(&value, true).map(Value::from)
+        })
+        .with_builtin(state, "toml", |args| {
+            let value = args.next("value")?;
+            args.finish()?;
+
+            formats::toml(&value).map(Value::from)
+        })
+        .with_builtin(state, "yaml", |args| {
+            let value = args.next("value")?;
+            let indent = args.next_width("indent")?;
+            args.finish()?;
+
+            formats::yaml(&value, indent).map(Value::from)
+        })
+        .with_builtin(state, "bold", move |args| {
+            ansi_style(args, ansi::BOLD, ansi)
+        })
+        .with_builtin(state, "dim", move |args| ansi_style(args, ansi::DIM, ansi))
+        .with_builtin(state, "italic", move |args| {
+            ansi_style(args, ansi::ITALIC, ansi)
+        })
+        .with_builtin(state, "underline", move |args| {
+            ansi_style(args, ansi::UNDERLINE, ansi)
+        })
+        .with_builtin(state, "wrap", |args| {
+            let text = args.next_string("text")?;
+            let width = args.next_width("width")?;
+            args.finish()?;
+
+            Ok(Value::from(text::wrap(&text, width)))
+        })
+        .with_builtin(state, "center", |args| {
+            let text = args.next_string("text")?;
+            let width = args.next_width("width")?;
+            args.finish()?;
+
+            Ok(Value::from(text::center(&text, width)))
+        })
+        .with_builtin(state, "quote", |args| {
+            let text = args.next_string("text")?;
+            let prefix = match args.next_optional() {
+                Some(prefix) => args.string("prefix", &prefix)?,
+                None => String::from("> "),
+            };
+            args.finish()?;
+
+            Ok(Value::from(text::quote(&text, &prefix)))
+        })
+        .with_builtin(state, "signature", |args| {
+            let text = args.next_string("text")?;
+            args.finish()?;
+
+            Ok(Value::from(text::signature(&text)))
+        })
+        .with_builtin(state, "table", table)
+        .with_builtin(state, "color", move |args| ansi_color(args, false, ansi))
+        .with_builtin(state, "background", move |args| {
+            ansi_color(args, true, ansi)
+        })
+        .with_builtin(state, "img", move |args| img(args, &assets))
+        .with_builtin(state, "sri", move |args| sri(args, &sri_assets))
+        .with_builtin(state, "meta_tags", meta_tags)
+        .with_builtin(state, "nav", |args| {
+            let items = args.next("items")?;
+            let active = match args.next_optional() {
+                Some(Value::Void) | None => None,
+                Some(active) => Some(args.string("active", &active)?),
+            };
+            args.finish()?;
+
+            let items = html::NavItem::list(&items).map_err(|message| args.invalid(message))?;
+            Ok(Value::from(SafeString::new(html::nav(
+                &items,
+                active.as_deref(),
+            ))))
+        })
+        .with_builtin(state, "breadcrumbs", |args| {
+            let items = args.next("items")?;
+            let active = args.next_string("active")?;
+            args.finish()?;
+
+            let items = html::NavItem::list(&items).map_err(|message| args.invalid(message))?;
+            Ok(Value::from(SafeString::new(html::breadcrumbs(
+                &items, &active,
+            ))))
+        })
+        .with_qr(state)
+        .with_feeds(state)
+        .with_forms(state)
+        .with_messages(state, configuration)
+        .with_custom(&configuration.functions, state)
+}
+
+/// Returns `value`, or `void` if it is missing, as passed to a
+/// [`CoercionPolicy`](crate::CoercionPolicy).
+fn coerced(value: Value) -> Value {
+    if Missing::is(&value) {
+        Value::Void
+    } else {
+        value
+    }
+}
+
+/// Returns the value of the entry keyed by `field` within a map's entries.
+fn lookup(entries: Vec<(Value, Value)>, field: &str) -> Option<Value> {
+    entries
+        .into_iter()
+        .find(|(key, _)| value::key_string(key) == field)
+        .map(|(_, value)| value)
+}
+
+/// Returns the entry at an index of a `for` statement's entries.
+fn entry(args: &mut Arguments<'_, '_>) -> Result<(Value, Value), Error> {
+    let entries = args.next("entries")?;
+    let index = args.next_integer("index")?;
+    args.finish()?;
+
+    entries
+        .as_dynamic::<Entries>()
+        .zip(usize::try_from(index).ok())
+        .and_then(|(entries, index)| entries.0.get(index).cloned())
+        .ok_or_else(|| args.invalid("no entry at index"))
+}
+
+fn table(args: &mut Arguments<'_, '_>) -> Result<Value, Error> {
+    let rows = args.next("rows")?;
+    let headers = args.next("headers")?;
+    let style = match args.next_optional() {
+        Some(style) => {
+            let style = args.string("style", &style)?;
+            TableStyle::named(&style)
+                .ok_or_else(|| args.invalid(format!("unknown table style `{style}`")))?
+        }
+        None => TableStyle::Ascii,
+    };
+    args.finish()?;
+
+    let Shape::List(headers) = Shape::of(&headers) else {
+        return Err(args.invalid("`headers` must be a list"));
+    };
+    let headers = headers
+        .iter()
+        .map(|header| args.string("headers", header))
+        .collect::<Result<Vec<_>, _>>()?;
+    let Shape::List(rows) = Shape::of(&rows) else {
+        return Err(args.invalid("`rows` must be a list"));
+    };
+
+    let rows = rows
+        .iter()
+        .map(|row| match Shape::of(row) {
+            Shape::List(cells) => Ok(cells.iter().map(table_cell).collect()),
+            Shape::Map(entries) => {
+                let entries = value::sorted_entries(entries);
+                Ok(headers
+                    .iter()
+                    .map(|header| {
+                        entries
+                            .iter()
+                            .find(|(key, _)| key == header)
+                            .map_or_else(|| Cell::new("", false), |(_, value)| table_cell(value))
+                    })
+                    .collect())
+            }
+            _ => Err(args.invalid("each row must be a list or a map")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Value::from(text::table(&headers, &rows, style)))
+}
+
+fn img(args: &mut Arguments<'_, '_>, assets: &AssetManifest) -> Result<Value, Error> {
+    let path = args.next_string("path")?;
+    let widths = args.next_optional();
+    let alt = match args.next_optional() {
+        Some(alt) => Some(args.string("alt", &alt)?),
+        None => None,
+    };
+    args.finish()?;
+
+    let image = assets
+        .image(&path)
+        .ok_or_else(|| args.invalid(format!("`{path}` is not in the asset manifest")))?;
+    let widths = match widths.as_ref().map(Shape::of) {
+        None | Some(Shape::Void) => image.variants().map(|(width, _)| width).collect(),
+        Some(Shape::List(widths)) => widths
+            .iter()
+            .map(|width| match width {
+                Value::Integer(width) => u32::try_from(*width).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| args.invalid("`widths` must be a list of positive integers"))?,
+        Some(_) => return Err(args.invalid("`widths` must be a list")),
+    };
+
+    let tag = assets::img_tag(image, &widths, alt.as_deref())
+        .map_err(|width| args.invalid(format!("`{path}` has no variant {width} pixels wide")))?;
+    Ok(Value::from(SafeString::new(tag)))
+}
+
+fn sri(args: &mut Arguments<'_, '_>, assets: &AssetManifest) -> Result<Value, Error> {
+    let path = args.next_string("path")?;
+    args.finish()?;
+
+    let hash = assets.integrity(&path).ok_or_else(|| {
+        args.invalid(format!(
+            "`{path}` has no integrity hash in the asset manifest"
+        ))
+    })?;
+    Ok(Value::from(SafeString::new(assets::sri_attributes(hash))))
+}
+
+fn meta_tags(args: &mut Arguments<'_, '_>) -> Result<Value, Error> {
+    // Entries from later maps replace those with the same key in earlier
+    // maps, allowing page-specific properties to override defaults.
+    let mut maps = vec![args.next("properties")?];
+    maps.extend(std::iter::from_fn(|| args.next_optional()));
+
+    let mut properties: Vec<(String, Vec<String>)> = Vec::new();
+    for map in maps {
+        let Shape::Map(entries) = Shape::of(&map) else {
+            return Err(args.invalid("properties must be maps"));
+        };
+        for (key, value) in value::sorted_entries(entries) {
+            let contents = match Shape::of(&value) {
+                Shape::Void => Vec::new(),
+                Shape::List(values) => values
+                    .iter()
+                    .map(|value| args.string(&key, value))
+                    .collect::<Result<_, _>>()?,
+                _ => vec![args.string(&key, &value)?],
+            };
+            match properties.iter_mut().find(|(existing, _)| *existing == key) {
+                Some((_, existing)) => *existing = contents,
+                None => properties.push((key, contents)),
+            }
+        }
+    }
+
+    let tags = html::meta_tags(properties.iter().flat_map(|(key, contents)| {
+        contents
+            .iter()
+            .map(move |content| (key.as_str(), content.as_str()))
+    }));
+    Ok(Value::from(SafeString::new(tags)))
+}
+
+#[cfg(feature = "qr")]
+fn qr(args: &mut Arguments<'_, '_>) -> Result<Value, Error> {
+    let data = args.next_string("data")?;
+    let size = args.next_optional_integer("size")?;
+    args.finish()?;
+
+    let code = qrcode::QrCode::new(data.as_bytes())
+        .map_err(|err| args.invalid(format!("data cannot be encoded: {err}")))?;
+    let mut renderer = code.render::<qrcode::render::svg::Color<'_>>();
+    if let Some(size) = size {
+        let size = u32::try_from(size).map_err(|_| args.invalid(format!("invalid size {size}")))?;
+        renderer.min_dimensions(size, size);
+    }
+    Ok(Value::from(SafeString::new(renderer.build())))
+}
+
+fn table_cell(value: &Value) -> Cell {
+    match Shape::of(value) {
+        Shape::Integer(value) => Cell::new(value.to_string(), true),
+        Shape::Real(value) => Cell::new(value.to_string(), true),
+        Shape::Boolean(value) => Cell::new(value.to_string(), false),
+        Shape::Void => Cell::new("", false),
+        Shape::String(value) => Cell::new(value, false),
+        Shape::List(_) | Shape::Map(_) | Shape::Other => {
+            Cell::new(PrettyPrinter::new().print(value), false)
+        }
+    }
+}
+
+fn ansi_style(args: &mut Arguments<'_, '_>, code: &str, enabled: bool) -> Result<Value, Error> {
+    let text = args.next_string("text")?;
+    args.finish()?;
+
+    Ok(Value::from(ansi::style(&text, code, enabled)))
+}
+
+fn ansi_color(
+    args: &mut Arguments<'_, '_>,
+    background: bool,
+    enabled: bool,
+) -> Result<Value, Error> {
+    let text = args.next_string("text")?;
+    let color = args.next_string("color")?;
+    args.finish()?;
+
+    let code = ansi::color_code(&color, background)
+        .ok_or_else(|| args.invalid(format!("unknown color `{color}`")))?;
+    Ok(Value::from(ansi::style(&text, &code.to_string(), enabled)))
+}
+
+struct Builtin<F> {
+    name: &'static str,
+    state: RenderState,
+    function: F,
+}
+
+impl<F> NativeFunction for Builtin<F>
+where
+    F: Fn(&mut Arguments<'_, '_>) -> Result<Value, Error> + 'static,
+{
+    fn invoke(&self, args: &mut PoppedValues<'_>) -> Result<Value, FaultKind> {
+        if self.state.is_cancelled() {
+            return Err(self.state.fail(Error::from(ErrorKind::Cancelled)));
+        }
+        let mut args = Arguments {
+            function: self.name,
+            values: args,
+        };
+        (self.function)(&mut args).map_err(|error| self.state.fail(error))
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self as *const Self as *const u8
+    }
+}
+
+struct Shared(Arc<dyn NativeFunction + Send + Sync>);
+
+impl NativeFunction for Shared {
+    fn invoke(&self, args: &mut PoppedValues<'_>) -> Result<Value, FaultKind> {
+        self.0.invoke(args)
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self.0.as_ptr()
+    }
+}
+
+struct Helper {
+    state: RenderState,
+    function: Arc<HelperFn>,
+}
+
+impl NativeFunction for Helper {
+    fn invoke(&self, args: &mut PoppedValues<'_>) -> Result<Value, FaultKind> {
+        let args = args.collect::<Vec<_>>();
+        (self.function)(&args).map_err(|error| self.state.fail(error))
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        Arc::as_ptr(&self.function).cast::<u8>()
+    }
+}
+
+struct ContextualHelper {
+    state: RenderState,
+    function: Arc<ContextualHelperFn>,
+}
+
+impl NativeFunction for ContextualHelper {
+    fn invoke(&self, args: &mut PoppedValues<'_>) -> Result<Value, FaultKind> {
+        let args = args.collect::<Vec<_>>();
+        (self.function)(&HelperContext { state: &self.state }, &args)
+            .map_err(|error| self.state.fail(error))
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        Arc::as_ptr(&self.function).cast::<u8>()
+    }
+}
+
+pub(crate) struct Arguments<'b, 'a> {
+    function: &'static str,
+    values: &'b mut PoppedValues<'a>,
+}
+
+impl Arguments<'_, '_> {
+    pub fn invalid(&self, message: impl Into<String>) -> Error {
+        Error::from(ErrorKind::InvalidArgument {
+            function: self.function.to_string(),
+            message: message.into(),
+        })
+    }
+
+    pub fn next(&mut self, name: &str) -> Result<Value, Error> {
+        self.values
+            .next()
+            .ok_or_else(|| self.invalid(format!("missing argument `{name}`")))
+    }
+
+    pub fn next_optional(&mut self) -> Option<Value> {
+        self.values.next()
+    }
+
+    pub fn next_string(&mut self, name: &str) -> Result<String, Error> {
+        let value = self.next(name)?;
+        self.string(name, &value)
+    }
+
+    pub fn next_optional_integer(&mut self, name: &str) -> Result<Option<i64>, Error> {
+        match self.next_optional() {
+            Some(Value::Integer(value)) => Ok(Some(value)),
+            Some(Value::Void) | None => Ok(None),
+            Some(_) => Err(self.invalid(format!("`{name}` must be an integer"))),
+        }
+    }
+
+    pub fn next_integer(&mut self, name: &str) -> Result<i64, Error> {
+        self.next_optional_integer(name)?
+            .ok_or_else(|| self.invalid(format!("missing argument `{name}`")))
+    }
+
+    pub fn next_width(&mut self, name: &str) -> Result<usize, Error> {
+        let width = self.next_integer(name)?;
+        usize::try_from(width)
+            .map_err(|_| self.invalid(format!("`{name}` must not be negative, found {width}")))
+    }
+
+    pub fn string(&self, name: &str, value: &Value) -> Result<String, Error> {
+        value
+            .try_convert_to_string(&())
+            .map(|value| value.to_string())
+            .map_err(|_| self.invalid(format!("`{name}` could not be converted to a string")))
+    }
+
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.values
+            .verify_empty()
+            .map_err(|_| self.invalid("too many arguments"))
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    fs,
+    io::{self, Cursor, Read},
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::SystemTime,
+};
+
+use crate::{Error, ErrorKind, MemoryFs, SignatureVerifier, Vfs, VfsEntry};
+
+/// A [`Vfs`] serving the files within a `.zip`, `.tar`, or `.tar.gz`
+/// archive, such as a theme or plugin distributed as a single file.
+///
+/// The files of the archive are read into memory when the bundle is opened,
+/// and paths are relative to the root of the archive. A bundle opened from a
+/// file is read again when the file is replaced, so a [`FileLoader`] with
+/// hot reloading enabled serves the new templates without being recreated.
+///
+/// ```rust,no_run
+/// use budplate::{BundleFs, Configuration, FileLoader};
+///
+/// let loader = FileLoader::new("", Configuration::for_html())
+///     .with_vfs(BundleFs::open("themes/dark.zip").unwrap());
+/// let rendered = loader.render_with("index", [("title", "Hello")]).unwrap();
+/// ```
+///
+/// Bundles opened using [`BundleFs::open_signed()`] are verified as a whole,
+/// using the detached signature read from the file next to the bundle with
+/// `.sig` appended to its name, such as `dark.zip.sig`. Templates within the
+/// bundle can also be signed individually, as with any [`Vfs`] read by a
+/// [`FileLoader`].
+///
+/// [`FileLoader`]: crate::FileLoader
+pub struct BundleFs {
+    state: Mutex<Bundle>,
+}
+
+struct Bundle {
+    files: MemoryFs,
+    /// The file the bundle was read from, along with its modification time
+    /// when it was read.
+    source: Option<(PathBuf, Option<SystemTime>)>,
+    verifier: Option<Box<dyn SignatureVerifier>>,
+}
+
+impl BundleFs {
+    /// Returns a bundle serving the files within `archive`, the contents of
+    /// a `.zip`, `.tar`, or `.tar.gz` file.
+    pub fn from_bytes(archive: &[u8]) -> Result<Self, Error> {
+        Ok(Self::new(Bundle {
+            files: unpack(archive)?,
+            source: None,
+            verifier: None,
+        }))
+    }
+
+    /// Returns a bundle serving the files within the archive at `path`,
+    /// which is read again when it is modified.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::open_bundle(path.into(), None)
+    }
+
+    /// Returns a bundle serving the files within the archive at `path`,
+    /// after verifying its signature using `verifier`. The bundle is named
+    /// by its file name when passed to `verifier`.
+    ///
+    /// A bundle without a signature fails to open with
+    /// [`ErrorKind::UnsignedTemplate`], and one whose signature doesn't
+    /// verify fails with [`ErrorKind::InvalidSignature`]. When the archive is
+    /// replaced, the new archive is only served once its signature verifies.
+    pub fn open_signed(
+        path: impl Into<PathBuf>,
+        verifier: impl SignatureVerifier,
+    ) -> Result<Self, Error> {
+        Self::open_bundle(path.into(), Some(Box::new(verifier)))
+    }
+
+    fn open_bundle(
+        path: PathBuf,
+        verifier: Option<Box<dyn SignatureVerifier>>,
+    ) -> Result<Self, Error> {
+        let mut bundle = Bundle {
+            files: MemoryFs::new(),
+            source: None,
+            verifier,
+        };
+        let modified = fs::metadata(&path)?.modified().ok();
+        bundle.files = bundle.read(&path)?;
+        bundle.source = Some((path, modified));
+        Ok(Self::new(bundle))
+    }
+
+    fn new(bundle: Bundle) -> Self {
+        Self {
+            state: Mutex::new(bundle),
+        }
+    }
+
+    /// Returns the files of the bundle, reading its archive again if it was
+    /// modified.
+    fn files(&self) -> io::Result<MemoryFs> {
+        let mut bundle = self.lock();
+        if let Some((path, modified)) = &bundle.source {
+            let current = fs::metadata(path)?.modified().ok();
+            if current != *modified {
+                let path = path.clone();
+                bundle.files = bundle
+                    .read(&path)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+                bundle.source = Some((path, current));
+            }
+        }
+        Ok(bundle.files.clone())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Bundle> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Bundle {
+    /// Reads the archive at `path`, verifying its signature if the bundle is
+    /// signed.
+    fn read(&self, path: &Path) -> Result<MemoryFs, Error> {
+        let archive = fs::read(path)?;
+        if let Some(verifier) = &self.verifier {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let mut signature_path = path.as_os_str().to_owned();
+            signature_path.push(".sig");
+            let signature = match fs::read(signature_path) {
+                Ok(signature) => signature,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    return Err(Error::from(ErrorKind::UnsignedTemplate(name)))
+                }
+                Err(err) => return Err(Error::from(err)),
+            };
+            if !verifier.verify(&name, &archive, &signature) {
+                return Err(Error::from(ErrorKind::InvalidSignature(name)));
+            }
+        }
+        unpack(&archive)
+    }
+}
+
+impl Vfs for BundleFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files()?.read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsEntry>> {
+        self.files()?.read_dir(path)
+    }
+}
+
+impl std::fmt::Debug for BundleFs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bundle = self.lock();
+        f.debug_struct("BundleFs")
+            .field("source", &bundle.source)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reads the files within `archive`, which is detected as a zip archive or a
+/// tar archive that may be compressed with gzip.
+fn unpack(archive: &[u8]) -> Result<MemoryFs, Error> {
+    let files = MemoryFs::new();
+    if archive.starts_with(b"PK") {
+        let mut zip = zip::ZipArchive::new(Cursor::new(archive)).map_err(invalid)?;
+        for index in 0..zip.len() {
+            let mut file = zip.by_index(index).map_err(invalid)?;
+            if file.is_dir() {
+                continue;
+            }
+            let path = file
+                .enclosed_name()
+                .map(Path::to_path_buf)
+                .ok_or_else(|| invalid(format!("invalid path `{}`", file.name())))?;
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            files.write(path, contents);
+        }
+    } else if archive.starts_with(&[0x1f, 0x8b]) {
+        unpack_tar(flate2::read::GzDecoder::new(archive), &files)?;
+    } else {
+        unpack_tar(archive, &files)?;
+    }
+    Ok(files)
+}
+
+fn unpack_tar(archive: impl Read, files: &MemoryFs) -> Result<(), Error> {
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        // Entries may not escape the root of the archive.
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(invalid(format!("invalid path `{}`", path.display())));
+        }
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        files.write(path, contents);
+    }
+    Ok(())
+}
+
+fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+fn zip_archive(files: &[(&str, &str)]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        zip.start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[cfg(test)]
+fn tar_gz_archive(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    ));
+    for (name, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, name, contents.as_bytes())
+            .unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap()
+}
+
+#[test]
+fn bundled_templates() {
+    use crate::{Configuration, FileLoader};
+
+    let files = [
+        (
+            "index.bud.html",
+            r#"<main>{{ include "partials/nav" }}</main>"#,
+        ),
+        ("partials/nav.bud.html", "<nav></nav>"),
+    ];
+    for archive in [zip_archive(&files), tar_gz_archive(&files)] {
+        let loader = FileLoader::new("", Configuration::for_html())
+            .with_vfs(BundleFs::from_bytes(&archive).unwrap());
+        assert_eq!(loader.render("index").unwrap(), "<main><nav></nav></main>");
+    }
+    assert!(BundleFs::from_bytes(b"PK not a zip").is_err());
+}
+
+#[test]
+fn swapped_signed_bundles() {
+    use crate::{Configuration, FileLoader};
+
+    let directory = std::env::temp_dir().join(format!("budplate-bundle-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("theme.zip");
+    let signature_path = directory.join("theme.zip.sig");
+    // Each signature is a copy of its bundle.
+    let verifier = |_: &str, archive: &[u8], signature: &[u8]| archive == signature;
+
+    let archive = zip_archive(&[("index.bud.html", "<h1>Light</h1>")]);
+    fs::write(&path, &archive).unwrap();
+    assert!(matches!(
+        BundleFs::open_signed(&path, verifier),
+        Err(Error { kind: ErrorKind::UnsignedTemplate(name), .. }) if name == "theme.zip"
+    ));
+    fs::write(&signature_path, &archive).unwrap();
+    let loader = FileLoader::new("", Configuration::for_html())
+        .hot_reload(true)
+        .with_vfs(BundleFs::open_signed(&path, verifier).unwrap());
+    assert_eq!(loader.render("index").unwrap(), "<h1>Light</h1>");
+
+    // A replaced bundle is only served once its signature verifies.
+    let archive = zip_archive(&[("index.bud.html", "<h1>Dark</h1>")]);
+    fs::write(&path, &archive).unwrap();
+    let file = fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(SystemTime::now() + std::time::Duration::from_secs(1))
+        .unwrap();
+    assert!(loader.render("index").is_err());
+    fs::write(&signature_path, &archive).unwrap();
+    assert_eq!(loader.render("index").unwrap(), "<h1>Dark</h1>");
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,617 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Read, Write},
+    rc::Rc,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{
+    hash, symbols, BudSource, CompiledTemplate, Configuration, Encoder, Error, ErrorKind,
+    ParameterName, Template, Templates,
+};
+
+/// The first line written by [`TemplateCache::write_to()`], which changes
+/// whenever the format does.
+const HEADER: &str = "budplate-cache 2\n";
+
+/// Templates that have been transpiled into Bud ahead of time, such as by a
+/// build step, so that an application can skip parsing and transpiling them
+/// at startup.
+///
+/// A cache is created by [`Configuration::precompile()`] and loaded by
+/// [`Configuration::compile_cached()`], which requires a configuration that
+/// transpiles templates the same way. Only the Bud source of each template
+/// is cached, so it is still compiled when it is loaded, and the startup
+/// time saved is only the time spent transpiling. The `render` benchmark
+/// measures it for the representative workloads of [`crate::bench`].
+///
+/// ```rust
+/// use budplate::{Configuration, TemplateCache, Templates};
+///
+/// let mut templates = Templates::new();
+/// templates.register("greeting", "Hello, {{= name }}!").unwrap();
+///
+/// let configuration = Configuration::for_html();
+/// let mut file = Vec::new();
+/// configuration
+///     .precompile(&templates, [("greeting", ["name"])])
+///     .unwrap()
+///     .write_to(&mut file)
+///     .unwrap();
+///
+/// let cache = TemplateCache::read_from(&file[..]).unwrap();
+/// let greeting = configuration.compile_cached(&cache, "greeting").unwrap();
+/// assert_eq!(
+///     greeting.render_with([("name", "Ada")]).unwrap(),
+///     "Hello, Ada!"
+/// );
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TemplateCache {
+    /// The options of the configuration that transpiled the templates.
+    options: String,
+    templates: Vec<CachedTemplate>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct CachedTemplate {
+    name: String,
+    parameters: Vec<String>,
+    source: String,
+    /// The number of sub-template functions defined after the template's
+    /// render function.
+    instances: usize,
+    metadata: BTreeMap<String, String>,
+}
+
+impl TemplateCache {
+    /// Returns the names of the cached templates.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.templates.iter().map(|template| template.name.as_str())
+    }
+
+    fn get(&self, name: &str) -> Option<&CachedTemplate> {
+        self.templates.iter().find(|template| template.name == name)
+    }
+
+    /// Writes this cache to `writer`, from which it can be read by
+    /// [`TemplateCache::read_from()`].
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(HEADER.as_bytes())?;
+        write_field(&mut writer, &self.options)?;
+        write_field(&mut writer, &self.templates.len().to_string())?;
+        for template in &self.templates {
+            write_field(&mut writer, &template.name)?;
+            write_field(&mut writer, &template.parameters.len().to_string())?;
+            for parameter in &template.parameters {
+                write_field(&mut writer, parameter)?;
+            }
+            write_field(&mut writer, &template.instances.to_string())?;
+            write_field(&mut writer, &template.source)?;
+            write_field(&mut writer, &template.metadata.len().to_string())?;
+            for (name, value) in &template.metadata {
+                write_field(&mut writer, name)?;
+                write_field(&mut writer, value)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Reads a cache written by [`TemplateCache::write_to()`] from `reader`.
+    pub fn read_from(mut reader: impl Read) -> Result<Self, Error> {
+        let mut contents = String::new();
+        reader
+            .read_to_string(&mut contents)
+            .map_err(ErrorKind::Io)?;
+        let mut fields = Fields(contents.strip_prefix(HEADER).ok_or_else(malformed)?);
+
+        let options = fields.next()?.to_string();
+        let count = fields.next_count()?;
+        let mut templates = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            let name = fields.next()?.to_string();
+            let parameters = (0..fields.next_count()?)
+                .map(|_| fields.next().map(String::from))
+                .collect::<Result<_, _>>()?;
+            let instances = fields.next_count()?;
+            let source = fields.next()?.to_string();
+            let metadata = (0..fields.next_count()?)
+                .map(|_| Ok((fields.next()?.to_string(), fields.next()?.to_string())))
+                .collect::<Result<_, Error>>()?;
+            templates.push(CachedTemplate {
+                name,
+                parameters,
+                source,
+                instances,
+                metadata,
+            });
+        }
+        if !fields.0.is_empty() {
+            return Err(malformed());
+        }
+
+        Ok(Self { options, templates })
+    }
+}
+
+/// Writes `field` prefixed by its length, so that it may contain any text.
+fn write_field(writer: &mut impl Write, field: &str) -> io::Result<()> {
+    writeln!(writer, "{}:{field}", field.len())
+}
+
+/// The fields remaining to be read from a cache.
+struct Fields<'a>(&'a str);
+
+impl<'a> Fields<'a> {
+    fn next(&mut self) -> Result<&'a str, Error> {
+        let (len, rest) = self.0.split_once(':').ok_or_else(malformed)?;
+        let len = len.parse::<usize>().map_err(|_| malformed())?;
+        let field = rest.get(..len).ok_or_else(malformed)?;
+        self.0 = rest[len..].strip_prefix('\n').ok_or_else(malformed)?;
+        Ok(field)
+    }
+
+    fn next_count(&mut self) -> Result<usize, Error> {
+        self.next()?.parse().map_err(|_| malformed())
+    }
+}
+
+fn malformed() -> Error {
+    Error::from(ErrorKind::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed template cache",
+    )))
+}
+
+/// Transpiled templates shared by every configuration in a process, such as
+/// those of each tenant of a multi-tenant application, so that a template
+/// registered identically by many tenants is only transpiled once.
+///
+/// Each tenant keeps its own [`Templates`] and [`Configuration`], including
+/// its loaders, functions, and execution limits, and compiles templates
+/// using [`Configuration::compile_shared()`]. Only the transpiled Bud source
+/// is shared: it is keyed by the template's name, source, and parameters,
+/// the sources of the templates it includes, and the configuration options
+/// that affect transpiling. Every compiled template still has a virtual
+/// machine of its own, so no tenant can call another tenant's functions or
+/// see its data. Clones of a cache share its templates.
+///
+/// ```rust
+/// use budplate::{CompileCache, Configuration, Templates};
+///
+/// let cache = CompileCache::new();
+/// for tenant in ["acme", "globex"] {
+///     let mut templates = Templates::new();
+///     templates.register("greeting", "Hello, {{= name }}!").unwrap();
+///     let configuration = Configuration::for_html().with_max_output_len(1024);
+///     let greeting = configuration
+///         .compile_shared(&cache, &templates, "greeting", ["name"])
+///         .unwrap();
+///     assert_eq!(
+///         greeting.render_with([("name", tenant)]).unwrap(),
+///         format!("Hello, {tenant}!")
+///     );
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct CompileCache(Arc<Mutex<HashMap<SharedKey, Vec<Arc<SharedTemplate>>>>>);
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct SharedKey {
+    options: String,
+    name: String,
+    parameters: Vec<String>,
+    source: u64,
+}
+
+struct SharedTemplate {
+    /// The names of the templates included while transpiling, along with
+    /// the hashes of their sources.
+    includes: Vec<(String, u64)>,
+    bud_source: BudSource,
+}
+
+impl SharedTemplate {
+    /// Returns true if `templates` registers the same templates that were
+    /// included when this template was transpiled.
+    fn includes_match(&self, templates: &Templates<'_>) -> bool {
+        self.includes.iter().all(|(name, hash)| {
+            templates
+                .get(name)
+                .is_some_and(|template| source_hash(template) == *hash)
+        })
+    }
+}
+
+fn source_hash(template: &Template<'_>) -> u64 {
+    hash::fnv1a(template.source.bytes())
+}
+
+impl std::fmt::Debug for CompileCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompileCache")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl CompileCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of transpiled templates in this cache.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lock().values().map(Vec::len).sum()
+    }
+
+    /// Returns true if this cache contains no transpiled templates.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every template from this cache.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SharedKey, Vec<Arc<SharedTemplate>>>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&self, key: &SharedKey, templates: &Templates<'_>) -> Option<Arc<SharedTemplate>> {
+        self.lock()
+            .get(key)?
+            .iter()
+            .find(|shared| shared.includes_match(templates))
+            .cloned()
+    }
+
+    fn insert(&self, key: SharedKey, shared: Arc<SharedTemplate>) {
+        self.lock().entry(key).or_default().push(shared);
+    }
+}
+
+impl<Enc: Encoder> Configuration<Enc> {
+    /// Transpiles each template registered in `templates` whose name is
+    /// listed in `entries` to accept the parameters listed with it, returning
+    /// a cache from which they can be compiled by
+    /// [`Configuration::compile_cached()`].
+    ///
+    /// Each template is also compiled, so that a cache is only created for
+    /// templates that load successfully.
+    pub fn precompile<'n, Entries, Params>(
+        &self,
+        templates: &Templates<'_>,
+        entries: Entries,
+    ) -> Result<TemplateCache, Error>
+    where
+        Entries: IntoIterator<Item = (&'n str, Params)>,
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let mut cache = TemplateCache {
+            options: self.cache_options(),
+            templates: Vec::new(),
+        };
+        for (name, parameters) in entries {
+            if cache.get(name).is_some() {
+                return Err(Error::from(ErrorKind::DuplicateTemplate(name.to_string())));
+            }
+            let template = templates
+                .get(name)
+                .ok_or_else(|| ErrorKind::UnknownTemplate(name.to_string()))?;
+            let parameters = parameters
+                .into_iter()
+                .map(ParameterName::into_symbol)
+                .collect::<Vec<_>>();
+            let bud_source = self.transpile(template, Some(templates), "render", &parameters, 0)?;
+            let machine = Rc::new(self.machine());
+            self.evaluate_into(&machine, &bud_source, parameters.clone(), Some(name))?;
+
+            cache.templates.push(CachedTemplate {
+                name: name.to_string(),
+                parameters: parameters.iter().map(|param| param.to_string()).collect(),
+                source: bud_source.source,
+                instances: bud_source.instances,
+                metadata: bud_source.metadata,
+            });
+        }
+        Ok(cache)
+    }
+
+    /// Compiles the template `name` from `cache`. Returns
+    /// [`ErrorKind::IncompatibleCache`] if the cache was created by a
+    /// configuration that transpiles templates differently.
+    pub fn compile_cached(
+        &self,
+        cache: &TemplateCache,
+        name: &str,
+    ) -> Result<CompiledTemplate, Error> {
+        if cache.options != self.cache_options() {
+            return Err(Error::from(ErrorKind::IncompatibleCache));
+        }
+        let cached = cache
+            .get(name)
+            .ok_or_else(|| ErrorKind::UnknownTemplate(name.to_string()))?;
+
+        let parameters = cached
+            .parameters
+            .iter()
+            .map(|param| symbols::intern(param))
+            .collect();
+        let machine = Rc::new(self.machine());
+        let bud_source = BudSource {
+            source: cached.source.clone(),
+            instances: cached.instances,
+            spans: Vec::new(),
+            templates: Vec::new(),
+            metadata: cached.metadata.clone(),
+        };
+        self.evaluate_into(&machine, &bud_source, parameters, Some(name))
+    }
+
+    /// Compiles the template registered as `name` in `templates`, reusing
+    /// the Bud source it was transpiled into by another configuration that
+    /// compiled identical templates using `cache`.
+    pub fn compile_shared<Params>(
+        &self,
+        cache: &CompileCache,
+        templates: &Templates<'_>,
+        name: &str,
+        parameters: Params,
+    ) -> Result<CompiledTemplate, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let template = templates
+            .get(name)
+            .ok_or_else(|| ErrorKind::UnknownTemplate(name.to_string()))?;
+        let parameters = parameters
+            .into_iter()
+            .map(ParameterName::into_symbol)
+            .collect::<Vec<_>>();
+        let machine = Rc::new(self.machine());
+        if let Some(compiled) =
+            self.compile_executable(&machine, template, Some(name), &parameters)?
+        {
+            return Ok(compiled);
+        }
+
+        let key = SharedKey {
+            options: self.cache_options(),
+            name: name.to_string(),
+            parameters: parameters.iter().map(|param| param.to_string()).collect(),
+            source: source_hash(template),
+        };
+        let shared = match cache.get(&key, templates) {
+            Some(shared) => shared,
+            None => {
+                let bud_source =
+                    self.transpile(template, Some(templates), "render", &parameters, 0)?;
+                let includes = bud_source.templates[1..]
+                    .iter()
+                    .flatten()
+                    .filter_map(|name| {
+                        let template = templates.get(name)?;
+                        Some((name.clone(), source_hash(template)))
+                    })
+                    .collect();
+                let shared = Arc::new(SharedTemplate {
+                    includes,
+                    bud_source,
+                });
+                cache.insert(key, shared.clone());
+                shared
+            }
+        };
+        self.evaluate_into(&machine, &shared.bud_source, parameters, Some(name))
+    }
+
+    /// Returns true if the template `name` renders differently when
+    /// registered in `new` than in `old`, such as after a deploy, or if it
+    /// isn't registered in `old`. Edits that don't change the Bud source a
+    /// template transpiles into, such as to comments or to the whitespace
+    /// within tags, don't change its layout, so output cached from the
+    /// template remains valid. Changes to the templates it includes do.
+    ///
+    /// ```rust
+    /// use budplate::{Configuration, Templates};
+    ///
+    /// let mut old = Templates::new();
+    /// old.register("page", "<h1>{{=title}}</h1>").unwrap();
+    /// let mut new = Templates::new();
+    /// new.register("page", "{{# The page's title #}}<h1>{{= title }}</h1>")
+    ///     .unwrap();
+    /// let configuration = Configuration::for_html();
+    /// assert!(!configuration.layout_changed(&old, &new, "page").unwrap());
+    /// ```
+    pub fn layout_changed(
+        &self,
+        old: &Templates<'_>,
+        new: &Templates<'_>,
+        name: &str,
+    ) -> Result<bool, Error> {
+        let template = new
+            .get(name)
+            .ok_or_else(|| ErrorKind::UnknownTemplate(name.to_string()))?;
+        let Some(previous) = old.get(name) else {
+            return Ok(true);
+        };
+        let Ok(previous) = self.transpile(previous, Some(old), "render", &[], 0) else {
+            return Ok(true);
+        };
+        let current = self.transpile(template, Some(new), "render", &[], 0)?;
+        Ok(previous.source != current.source)
+    }
+
+    /// Describes the options that affect how templates are transpiled.
+    fn cache_options(&self) -> String {
+        let mut edge_includes = self
+            .edge_includes
+            .iter()
+            .flat_map(|edge_includes| edge_includes.templates().map(|(name, _)| name))
+            .collect::<Vec<_>>();
+        edge_includes.sort_unstable();
+        format!(
+            "exact_whitespace={} auto_trim={} delimiters={:?},{:?} on_missing={:?} \
+             memoize_partials={} messages={} annotate_output={} audit_accesses={} \
+             coercion={} edge_includes={:?}",
+            self.exact_whitespace,
+            self.auto_trim,
+            self.delimiters.open,
+            self.delimiters.close,
+            self.on_missing,
+            self.memoize_partials,
+            self.has_catalog(),
+            self.annotate_output,
+            self.audit_accesses,
+            self.coercion.is_some(),
+            edge_includes,
+        )
+    }
+}
+
+#[test]
+fn layout_changes() {
+    let templates = |page: &str, nav: &str| {
+        let mut templates = Templates::new();
+        templates.register("page", page.to_string()).unwrap();
+        templates.register("nav", nav.to_string()).unwrap();
+        templates
+    };
+    let old = templates(
+        "<h1>{{= title }}</h1>{{ include \"nav\" }}",
+        "<nav>{{# empty #}}</nav>",
+    );
+    let configuration = Configuration::for_html();
+    let changed = |page: &str, nav: &str| {
+        configuration
+            .layout_changed(&old, &templates(page, nav), "page")
+            .unwrap()
+    };
+    assert!(!changed(
+        "<h1>{{=title}}</h1>{{# navigation #}}{{include \"nav\"}}",
+        "<nav>{{# empty #}}</nav>"
+    ));
+    assert!(!changed(
+        "<h1>{{= title }}</h1>{{ include \"nav\" }}",
+        "<nav>{{# no links yet #}}</nav>"
+    ));
+    assert!(changed(
+        "<h1>{{= title }}</h1> {{ include \"nav\" }}",
+        "<nav>{{# empty #}}</nav>"
+    ));
+    assert!(changed(
+        "<h1>{{= title }}</h1>{{ include \"nav\" }}",
+        "<nav>{{= title }}</nav>"
+    ));
+    assert!(configuration
+        .layout_changed(&Templates::new(), &old, "page")
+        .unwrap());
+    assert!(matches!(
+        configuration.layout_changed(&old, &Templates::new(), "page"),
+        Err(Error {
+            kind: ErrorKind::UnknownTemplate(_),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn cache_round_trip() {
+    let mut templates = Templates::new();
+    templates.register("row", "<li>{{= label }}</li>").unwrap();
+    templates
+        .register("list", "<ul>{{ include \"row\" with label }}</ul>")
+        .unwrap();
+
+    let configuration = Configuration::for_html();
+    let cache = configuration
+        .precompile(&templates, [("list", ["label"]), ("row", ["label"])])
+        .unwrap();
+    assert_eq!(cache.names().collect::<Vec<_>>(), ["list", "row"]);
+    assert!(matches!(
+        configuration.precompile(&templates, [("row", ["label"]), ("row", ["label"])]),
+        Err(Error {
+            kind: ErrorKind::DuplicateTemplate(_),
+            ..
+        })
+    ));
+
+    let mut file = Vec::new();
+    cache.write_to(&mut file).unwrap();
+    assert_eq!(TemplateCache::read_from(&file[..]).unwrap(), cache);
+    assert!(matches!(
+        TemplateCache::read_from(&file[..file.len() - 2]),
+        Err(Error {
+            kind: ErrorKind::Io(_),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Configuration::for_html()
+            .exact_whitespace()
+            .compile_cached(&cache, "list"),
+        Err(Error {
+            kind: ErrorKind::IncompatibleCache,
+            ..
+        })
+    ));
+    assert!(matches!(
+        configuration.compile_cached(&cache, "missing"),
+        Err(Error {
+            kind: ErrorKind::UnknownTemplate(_),
+            ..
+        })
+    ));
+
+    let list = configuration.compile_cached(&cache, "list").unwrap();
+    assert_eq!(list.parameters(), [budlang::vm::Symbol::from("label")]);
+    assert_eq!(
+        list.render_with([("label", "a&b")]).unwrap(),
+        "<ul><li>a&amp;b</li></ul>"
+    );
+}
+
+#[test]
+fn shared_compiles() {
+    let tenant = |nav: &str| {
+        let mut templates = Templates::new();
+        templates
+            .register("page", "<h1>{{= title }}</h1>{{ include \"nav\" }}")
+            .unwrap();
+        templates.register("nav", nav.to_string()).unwrap();
+        templates
+    };
+    let cache = CompileCache::new();
+    let compile = |configuration: Configuration<crate::HtmlEncoding>, nav: &str| {
+        configuration
+            .compile_shared(&cache, &tenant(nav), "page", ["title"])
+            .unwrap()
+    };
+    let acme = compile(Configuration::for_html(), "<nav></nav>");
+    compile(
+        Configuration::for_html().with_max_output_len(1024),
+        "<nav></nav>",
+    );
+    assert_eq!(cache.len(), 1);
+    let globex = compile(Configuration::for_html(), "<nav>Globex</nav>");
+    compile(Configuration::for_html().exact_whitespace(), "<nav></nav>");
+    assert_eq!(cache.len(), 3);
+
+    assert_eq!(
+        acme.render_with([("title", "Acme")]).unwrap(),
+        "<h1>Acme</h1><nav></nav>"
+    );
+    assert_eq!(
+        globex.render_with([("title", "Globex")]).unwrap(),
+        "<h1>Globex</h1><nav>Globex</nav>"
+    );
+    cache.clear();
+    assert!(cache.is_empty());
+}
@@ -0,0 +1,234 @@
+use crate::{
+    html::{find_closing_tag, tag_end},
+    AssetManifest, Error, OutputTransform, TransformedRender,
+};
+
+/// An [`OutputTransform`] that serves assets from a CDN by prefixing their
+/// URLs in `src`, `href`, `poster`, and `srcset` attributes with the CDN's
+/// origin.
+///
+/// Only root-relative URLs are rewritten: those of the images in the
+/// configuration's [`AssetManifest`], and those starting with a prefix added
+/// using [`CdnRewriter::with_prefix`]. Text and the content of `<script>`
+/// and `<style>` elements are left unchanged.
+///
+/// ```rust
+/// use budplate::{AssetManifest, CdnRewriter, Configuration, ImageAsset};
+///
+/// let rendered = Configuration::for_html()
+///     .with_assets(
+///         AssetManifest::new().with_image("hero", ImageAsset::new("/img/hero.jpg", 800, 600)),
+///     )
+///     .with_output_transform(CdnRewriter::new("https://cdn.example.com/").with_prefix("/static/"))
+///     .render(r#"<link href="/static/site.css"><img src="/img/hero.jpg"><a href="/about">"#)
+///     .unwrap();
+/// assert_eq!(
+///     rendered,
+///     r#"<link href="https://cdn.example.com/static/site.css"><img src="https://cdn.example.com/img/hero.jpg"><a href="/about">"#
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct CdnRewriter {
+    origin: String,
+    prefixes: Vec<String>,
+}
+
+impl CdnRewriter {
+    /// Returns a rewriter that serves the configuration's images from
+    /// `origin`, such as `https://cdn.example.com`.
+    #[must_use]
+    pub fn new(origin: impl Into<String>) -> Self {
+        let mut origin = origin.into();
+        while origin.ends_with('/') {
+            origin.pop();
+        }
+        Self {
+            origin,
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Also serves each URL starting with `prefix`, such as `/static/`, from
+    /// the CDN.
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    fn rewrites(&self, url: &str, assets: &AssetManifest) -> bool {
+        url.starts_with('/')
+            && !url.starts_with("//")
+            && (self.prefixes.iter().any(|prefix| url.starts_with(prefix))
+                || assets.contains_url(url))
+    }
+
+    fn rewrite(&self, html: &str, assets: &AssetManifest) -> String {
+        let mut rewritten = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            rewritten.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.starts_with("<!--") {
+                let end = rest.find("-->").map_or(rest.len(), |end| end + 3);
+                rewritten.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            } else if !rest[1..].starts_with(|ch: char| ch.is_ascii_alphabetic()) {
+                rewritten.push('<');
+                rest = &rest[1..];
+                continue;
+            }
+            let end = tag_end(rest);
+            let tag = &rest[..end];
+            rest = &rest[end..];
+            let name = self.rewrite_tag(tag, assets, &mut rewritten);
+            if ["script", "style"]
+                .into_iter()
+                .any(|raw| raw.eq_ignore_ascii_case(name))
+                && !tag.ends_with("/>")
+            {
+                let close = find_closing_tag(rest, name);
+                rewritten.push_str(&rest[..close]);
+                rest = &rest[close..];
+            }
+        }
+        rewritten.push_str(rest);
+        rewritten
+    }
+
+    /// Writes `tag` to `rewritten` with the URLs of its attributes
+    /// rewritten, returning the name of the element it opens.
+    fn rewrite_tag<'a>(
+        &self,
+        tag: &'a str,
+        assets: &AssetManifest,
+        rewritten: &mut String,
+    ) -> &'a str {
+        let attributes = attributes(tag);
+        let name = attributes.first().map_or("", |(name, _)| *name);
+        let mut written = 0;
+        for (name, value) in attributes.into_iter().skip(1) {
+            let Some(value) = value else {
+                continue;
+            };
+            let start = value.as_ptr() as usize - tag.as_ptr() as usize;
+            rewritten.push_str(&tag[written..start]);
+            written = start + value.len();
+            if name.eq_ignore_ascii_case("srcset") {
+                for (index, candidate) in value.split(',').enumerate() {
+                    if index > 0 {
+                        rewritten.push(',');
+                    }
+                    let leading = candidate.len() - candidate.trim_start().len();
+                    rewritten.push_str(&candidate[..leading]);
+                    self.push_url(&candidate[leading..], assets, rewritten);
+                }
+            } else if ["src", "href", "poster"]
+                .into_iter()
+                .any(|url| url.eq_ignore_ascii_case(name))
+            {
+                self.push_url(value, assets, rewritten);
+            } else {
+                rewritten.push_str(value);
+            }
+        }
+        rewritten.push_str(&tag[written..]);
+        name
+    }
+
+    /// Writes `candidate`, which is a URL optionally followed by a
+    /// descriptor such as `2x`, pointing the URL at the CDN if needed.
+    fn push_url(&self, candidate: &str, assets: &AssetManifest, rewritten: &mut String) {
+        let url = candidate
+            .split(char::is_whitespace)
+            .next()
+            .unwrap_or_default();
+        if self.rewrites(url, assets) {
+            rewritten.push_str(&self.origin);
+        }
+        rewritten.push_str(candidate);
+    }
+}
+
+impl OutputTransform for CdnRewriter {
+    fn transform(&self, output: String, render: &TransformedRender<'_>) -> Result<String, Error> {
+        Ok(self.rewrite(&output, render.assets))
+    }
+}
+
+/// Returns the name of the element `tag` opens followed by each of its
+/// attributes and their values, without quotes.
+fn attributes(tag: &str) -> Vec<(&str, Option<&str>)> {
+    let inner = tag.strip_prefix('<').unwrap_or(tag);
+    let inner = inner.strip_suffix('>').unwrap_or(inner);
+    let mut attributes = Vec::new();
+    let mut rest = inner;
+    loop {
+        rest = rest.trim_start_matches(|ch: char| ch.is_whitespace() || ch == '/');
+        if rest.is_empty() {
+            break attributes;
+        }
+        let end = rest
+            .find(|ch: char| ch.is_whitespace() || ch == '=' || ch == '/')
+            .unwrap_or(rest.len());
+        let name = &rest[..end.max(1)];
+        rest = rest[name.len()..].trim_start();
+        let Some(after_equals) = rest.strip_prefix('=').filter(|_| !attributes.is_empty()) else {
+            attributes.push((name, None));
+            continue;
+        };
+        rest = after_equals.trim_start();
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &rest[1..];
+                let end = value.find(quote).unwrap_or(value.len());
+                rest = value.get(end + 1..).unwrap_or_default();
+                &value[..end]
+            }
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let value = &rest[..end];
+                rest = &rest[end..];
+                value
+            }
+        };
+        attributes.push((name, Some(value)));
+    }
+}
+
+#[test]
+fn rewritten_urls() {
+    let assets = AssetManifest::new().with_image(
+        "hero",
+        crate::ImageAsset::new("/img/hero.jpg", 800, 600).with_variant(400, "/img/hero-400.jpg"),
+    );
+    let cdn = CdnRewriter::new("https://cdn.test").with_prefix("/static/");
+    assert_eq!(
+        cdn.rewrite(
+            "<img src=/img/hero.jpg srcset=\"/img/hero-400.jpg 400w, /img/other.jpg 800w\" \
+             alt='/static/a.png'>",
+            &assets
+        ),
+        "<img src=https://cdn.test/img/hero.jpg \
+         srcset=\"https://cdn.test/img/hero-400.jpg 400w, /img/other.jpg 800w\" \
+         alt='/static/a.png'>"
+    );
+    assert_eq!(
+        cdn.rewrite(
+            "<a HREF = '/static/x.pdf' title=\"a > b\">1 < 2</a><!-- <a href=/static/y> -->",
+            &assets
+        ),
+        "<a HREF = 'https://cdn.test/static/x.pdf' title=\"a > b\">1 < 2</a>\
+         <!-- <a href=/static/y> -->"
+    );
+    assert_eq!(
+        cdn.rewrite(
+            "<script src=\"/static/app.js\">let a = '<img src=/static/b.png>';</script>\
+             <link href=//static/x.css><video poster=/static/p.jpg controls/>",
+            &assets
+        ),
+        "<script src=\"https://cdn.test/static/app.js\">let a = '<img src=/static/b.png>';</script>\
+         <link href=//static/x.css><video poster=https://cdn.test/static/p.jpg controls/>"
+    );
+}
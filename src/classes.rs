@@ -0,0 +1,54 @@
+/// Returns the name `class` is rendered as by `class()` within the
+/// registered template `template`.
+///
+/// Scoped class names have a suffix derived from the template's name, which
+/// keeps the styles of one component from applying to another. Stylesheets
+/// can use this function to produce matching selectors.
+///
+/// ```rust
+/// let scoped = budplate::scoped_class("card", "title");
+/// assert!(scoped.starts_with("title-"));
+/// assert_ne!(scoped, budplate::scoped_class("alert", "title"));
+/// ```
+pub fn scoped_class(template: &str, class: &str) -> String {
+    format!("{class}-{:06x}", scope_hash(template) & 0xFF_FFFF)
+}
+
+/// Scopes each whitespace-separated class in `classes`, or returns them
+/// unchanged when rendering outside of a registered template.
+pub(crate) fn scope_classes(template: Option<&str>, classes: &str) -> String {
+    let mut scoped = String::with_capacity(classes.len());
+    for class in classes.split_whitespace() {
+        if !scoped.is_empty() {
+            scoped.push(' ');
+        }
+        match template {
+            Some(template) => scoped.push_str(&scoped_class(template, class)),
+            None => scoped.push_str(class),
+        }
+    }
+    scoped
+}
+
+/// A 32-bit FNV-1a hash, which is stable across builds and platforms unlike
+/// the standard library's hasher.
+fn scope_hash(template: &str) -> u32 {
+    template.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[test]
+fn scoped_classes() {
+    assert_eq!(scope_classes(None, " a  b "), "a b");
+    assert_eq!(
+        scope_classes(Some("card"), "title big"),
+        format!(
+            "{} {}",
+            scoped_class("card", "title"),
+            scoped_class("card", "big")
+        )
+    );
+    assert_eq!(scoped_class("card", "title"), scoped_class("card", "title"));
+    assert_eq!(scoped_class("card", "title").len(), "title-".len() + 6);
+}
@@ -0,0 +1,154 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Provides the current time to `before()`, `after()`, and `between()`, as
+/// registered using [`Configuration::with_clock`](crate::Configuration::with_clock).
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the number of seconds since the Unix epoch.
+    fn now(&self) -> i64;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> i64 + Send + Sync + 'static,
+{
+    fn now(&self) -> i64 {
+        self()
+    }
+}
+
+/// The system's clock, which is used unless a configuration is given
+/// another.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => i64::try_from(elapsed.as_secs()).unwrap_or(i64::MAX),
+            Err(err) => i64::try_from(err.duration().as_secs()).map_or(i64::MIN, |secs| -secs),
+        }
+    }
+}
+
+/// A clock that is stopped at a timestamp, so that templates depending on
+/// the time render the same way in tests.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FixedClock(pub i64);
+
+impl FixedClock {
+    /// Returns a clock stopped at `time`, which is a date or time accepted
+    /// by `before()`, or `None` if it can't be parsed.
+    #[must_use]
+    pub fn at(time: &str) -> Option<Self> {
+        parse_time(time).map(Self)
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.0
+    }
+}
+
+/// Parses `time` as the number of seconds since the Unix epoch. `time` is
+/// either a date such as `2024-12-01`, which is the start of that day in UTC,
+/// or an RFC 3339 time such as `2024-12-01T09:30:00Z` or
+/// `2024-12-01T09:30:00+01:00`.
+pub(crate) fn parse_time(time: &str) -> Option<i64> {
+    let (date, time) = match time.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (time, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year = number(parts.next()?, 4)?;
+    let month = number(parts.next()?, 2)?;
+    let day = number(parts.next()?, 2)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut timestamp = days_from_civil(year, month, day) * 86_400;
+
+    if let Some(time) = time {
+        let (clock, offset) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+            (clock, 0)
+        } else {
+            let split = time.rfind(['+', '-'])?;
+            let (clock, offset) = time.split_at(split);
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let offset = number(hours, 2)? * 3600 + number(minutes, 2)? * 60;
+            (
+                clock,
+                if time.as_bytes()[split] == b'-' {
+                    -offset
+                } else {
+                    offset
+                },
+            )
+        };
+        let mut parts = clock.splitn(3, ':');
+        let hour = number(parts.next()?, 2)?;
+        let minute = number(parts.next()?, 2)?;
+        let second = parts.next().map_or(Some(0), |second| number(second, 2))?;
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        timestamp += hour * 3600 + minute * 60 + second - offset;
+    }
+    Some(timestamp)
+}
+
+/// Parses `digits`, which must contain exactly `len` ASCII digits.
+fn number(digits: &str, len: usize) -> Option<i64> {
+    if digits.len() == len && digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days between the Unix epoch and a date in the
+/// proleptic Gregorian calendar, using eras of 400 years that begin on
+/// March 1st.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[test]
+fn parsed_times() {
+    assert_eq!(parse_time("1970-01-01"), Some(0));
+    assert_eq!(parse_time("2024-12-01"), Some(1_733_011_200));
+    assert_eq!(parse_time("2000-03-01"), Some(951_868_800));
+    assert_eq!(parse_time("2024-12-01T09:30:00Z"), Some(1_733_045_400));
+    assert_eq!(parse_time("2024-12-01T10:30:00+01:00"), Some(1_733_045_400));
+    assert_eq!(parse_time("2024-12-01T04:00-05:30"), Some(1_733_045_400));
+    assert_eq!(parse_time("1969-12-31T23:59:59Z"), Some(-1));
+    for invalid in [
+        "2024-13-01",
+        "2023-02-29",
+        "2024-1-01",
+        "2024-12-01T25:00Z",
+        "2024-12-01T09:30",
+        "tomorrow",
+    ] {
+        assert_eq!(parse_time(invalid), None, "{invalid}");
+    }
+    assert_eq!(
+        FixedClock::at("2024-02-29").map(|clock| clock.now()),
+        Some(1_709_164_800)
+    );
+}
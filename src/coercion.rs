@@ -0,0 +1,498 @@
+use std::{borrow::Cow, cmp::Ordering};
+
+use budlang::vm::Value;
+
+use crate::{
+    directive::{is_identifier, split_top_level},
+    expression::string_end,
+    value::{Fingerprint, Shape},
+    Error, ErrorKind, SafeString,
+};
+
+/// Decides how values are implicitly converted by the operators and
+/// conditions of templates compiled using
+/// [`Configuration::with_coercion`](crate::Configuration::with_coercion).
+///
+/// The policy evaluates `+`, the comparison operators `==`, `!=`, `<`, `<=`,
+/// `>`, and `>=`, and the conditions of `if` and `else if` statements,
+/// including each operand of `and`, `or`, and `not` within them. Other
+/// operators are evaluated by Bud. Missing values are passed to the policy
+/// as `void`.
+///
+/// Each method's default implementation is strict, which is how
+/// [`StrictCoercion`] behaves: only booleans and `void` can be conditions,
+/// strings can only be added to strings, and only numbers or strings can be
+/// ordered. A policy only needs to override the conversions it relaxes, such
+/// as those of [`LenientCoercion`].
+pub trait CoercionPolicy: Send + Sync + 'static {
+    /// Returns whether `value` passes as the condition of an `if`.
+    fn is_truthy(&self, value: &Value) -> Result<bool, Error> {
+        match Shape::of(value) {
+            Shape::Boolean(value) => Ok(value),
+            Shape::Void => Ok(false),
+            other => Err(coercion_error(format!(
+                "a condition must be a boolean, found a {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    /// Returns the result of `left + right`.
+    fn add(&self, left: &Value, right: &Value) -> Result<Value, Error> {
+        match (Shape::of(left), Shape::of(right)) {
+            (Shape::Integer(left), Shape::Integer(right)) => left
+                .checked_add(right)
+                .map(Value::Integer)
+                .ok_or_else(|| coercion_error(format!("{left} + {right} overflowed"))),
+            (Shape::String(left), Shape::String(right)) => Ok(Value::from(left + &right)),
+            (left, right) => match (number(&left), number(&right)) {
+                (Some(left), Some(right)) => Ok(Value::Real(left + right)),
+                _ => Err(coercion_error(format!(
+                    "can't add a {} to a {}",
+                    right.type_name(),
+                    left.type_name()
+                ))),
+            },
+        }
+    }
+
+    /// Returns whether `left == right`. `!=` is its negation.
+    fn equals(&self, left: &Value, right: &Value) -> Result<bool, Error> {
+        let (left_shape, right_shape) = (Shape::of(left), Shape::of(right));
+        if let (Some(left), Some(right)) = (number(&left_shape), number(&right_shape)) {
+            return Ok(left == right);
+        }
+        match (Fingerprint::of(left), Fingerprint::of(right)) {
+            (Some(left), Some(right)) => Ok(left == right),
+            _ => Err(coercion_error(format!(
+                "can't compare a {} to a {}",
+                left_shape.type_name(),
+                right_shape.type_name()
+            ))),
+        }
+    }
+
+    /// Returns how `left` is ordered relative to `right`, for `<`, `<=`,
+    /// `>`, and `>=`.
+    fn compare(&self, left: &Value, right: &Value) -> Result<Ordering, Error> {
+        let (left, right) = (Shape::of(left), Shape::of(right));
+        let ordering = match (&left, &right) {
+            (Shape::Integer(left), Shape::Integer(right)) => Some(left.cmp(right)),
+            (Shape::String(left), Shape::String(right)) => Some(left.cmp(right)),
+            _ => number(&left)
+                .zip(number(&right))
+                .and_then(|(left, right)| left.partial_cmp(&right)),
+        };
+        ordering.ok_or_else(|| {
+            coercion_error(format!(
+                "can't order a {} and a {}",
+                left.type_name(),
+                right.type_name()
+            ))
+        })
+    }
+}
+
+/// A [`CoercionPolicy`] that doesn't convert values between types.
+///
+/// ```rust
+/// use budplate::{Configuration, ErrorKind, StrictCoercion};
+///
+/// let configuration = Configuration::default().with_coercion(StrictCoercion);
+/// assert_eq!(configuration.render(r#"{{= "a" + "b" }}"#).unwrap(), "ab");
+/// assert!(matches!(
+///     configuration.render(r#"{{= "a" + 1 }}"#).unwrap_err().kind,
+///     ErrorKind::Coercion(_)
+/// ));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StrictCoercion;
+
+impl CoercionPolicy for StrictCoercion {}
+
+/// A [`CoercionPolicy`] that converts values the way engines such as PHP's
+/// do.
+///
+/// - `false`, `0`, `0.0`, `""`, `"0"`, `void`, and empty lists and maps are
+///   false as conditions, and everything else is true.
+/// - Adding a string to any other value concatenates their text, with `void`
+///   as an empty string. Numbers, booleans, and `void` are otherwise added
+///   as numbers.
+/// - Values are equal when their text is, or when both are numeric, such as
+///   `1 == "1.0"`. `void` equals `""`, `0`, and `false`.
+/// - Numbers and numeric strings are ordered as numbers, and all other
+///   values by their text.
+///
+/// ```rust
+/// use budplate::{Configuration, LenientCoercion};
+///
+/// let configuration = Configuration::default().with_coercion(LenientCoercion);
+/// assert_eq!(
+///     configuration
+///         .render(r#"{{= "Page " + 2 }}{{ if "" }}!{{ end }}{{ if 1 == "1" }}?{{ end }}"#)
+///         .unwrap(),
+///     "Page 2?"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LenientCoercion;
+
+impl CoercionPolicy for LenientCoercion {
+    fn is_truthy(&self, value: &Value) -> Result<bool, Error> {
+        Ok(match Shape::of(value) {
+            Shape::Boolean(value) => value,
+            Shape::Integer(value) => value != 0,
+            Shape::Real(value) => value != 0.,
+            Shape::String(value) => !value.is_empty() && value != "0",
+            Shape::Void => false,
+            Shape::List(values) => !values.is_empty(),
+            Shape::Map(entries) => !entries.is_empty(),
+            Shape::Other => true,
+        })
+    }
+
+    fn add(&self, left: &Value, right: &Value) -> Result<Value, Error> {
+        let (left_shape, right_shape) = (Shape::of(left), Shape::of(right));
+        if matches!(left_shape, Shape::String(_)) || matches!(right_shape, Shape::String(_)) {
+            if let (Some(left), Some(right)) = (text(left), text(right)) {
+                return Ok(Value::from(left + &right));
+            }
+        }
+        match (loose_number(&left_shape), loose_number(&right_shape)) {
+            (Some(left), Some(right)) => StrictCoercion.add(&left, &right),
+            _ => StrictCoercion.add(left, right),
+        }
+    }
+
+    fn equals(&self, left: &Value, right: &Value) -> Result<bool, Error> {
+        let (left_shape, right_shape) = (Shape::of(left), Shape::of(right));
+        if let (Some(left), Some(right)) = (numeric(&left_shape), numeric(&right_shape)) {
+            return Ok(left == right);
+        }
+        match (&left_shape, &right_shape) {
+            (Shape::Void, other) | (other, Shape::Void) => Ok(match other {
+                Shape::String(value) => value.is_empty(),
+                Shape::Integer(value) => *value == 0,
+                Shape::Real(value) => *value == 0.,
+                Shape::Boolean(value) => !value,
+                Shape::Void => true,
+                _ => false,
+            }),
+            _ => match (text(left), text(right)) {
+                (Some(left), Some(right)) => Ok(left == right),
+                _ => StrictCoercion.equals(left, right),
+            },
+        }
+    }
+
+    fn compare(&self, left: &Value, right: &Value) -> Result<Ordering, Error> {
+        let (left_shape, right_shape) = (Shape::of(left), Shape::of(right));
+        if let Some(ordering) = numeric(&left_shape)
+            .zip(numeric(&right_shape))
+            .and_then(|(left, right)| left.partial_cmp(&right))
+        {
+            return Ok(ordering);
+        }
+        match (text(left), text(right)) {
+            (Some(left), Some(right)) => Ok(left.cmp(&right)),
+            _ => StrictCoercion.compare(left, right),
+        }
+    }
+}
+
+fn coercion_error(message: String) -> Error {
+    Error::from(ErrorKind::Coercion(message))
+}
+
+/// Returns the value of a number.
+fn number(shape: &Shape) -> Option<f64> {
+    match shape {
+        Shape::Integer(value) => Some(*value as f64),
+        Shape::Real(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Returns the value of a number or a string containing one.
+fn numeric(shape: &Shape) -> Option<f64> {
+    match shape {
+        Shape::String(value) => value.trim().parse().ok(),
+        shape => number(shape),
+    }
+}
+
+/// Returns the number a number, boolean, or `void` is added as.
+fn loose_number(shape: &Shape) -> Option<Value> {
+    match shape {
+        Shape::Integer(value) => Some(Value::Integer(*value)),
+        Shape::Real(value) => Some(Value::Real(*value)),
+        Shape::Boolean(value) => Some(Value::Integer(i64::from(*value))),
+        Shape::Void => Some(Value::Integer(0)),
+        _ => None,
+    }
+}
+
+/// Returns the text of a value that isn't a collection.
+fn text(value: &Value) -> Option<String> {
+    if let Some(safe) = value.as_dynamic::<SafeString>() {
+        return Some(safe.as_str().to_string());
+    }
+    match Shape::of(value) {
+        Shape::String(value) => Some(value),
+        Shape::Integer(value) => Some(value.to_string()),
+        Shape::Real(value) => Some(value.to_string()),
+        Shape::Boolean(value) => Some(value.to_string()),
+        Shape::Void => Some(String::new()),
+        _ => None,
+    }
+}
+
+const COMPARISONS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+/// Rewrites the Bud statement `statement` so that the condition of an `if`
+/// or `else if`, or the value of an assignment, is evaluated by the
+/// configuration's [`CoercionPolicy`].
+pub(crate) fn rewrite_statement(statement: &str) -> Cow<'_, str> {
+    for keyword in ["if ", "else if "] {
+        if let Some(condition) = statement.strip_prefix(keyword) {
+            return Cow::Owned(format!("{keyword}{}", rewrite_condition(condition)));
+        }
+    }
+    if let Some((name, value)) = statement.split_once(":=") {
+        if is_identifier(name.trim()) {
+            return Cow::Owned(format!("{name}:= {}", rewrite(value)));
+        }
+    }
+    Cow::Borrowed(statement)
+}
+
+/// Rewrites the condition `source` so that the truth of each operand of its
+/// `and`, `or`, and `not` operators is decided by `__truthy()`.
+pub(crate) fn rewrite_condition(source: &str) -> String {
+    logical(source, &|operand| {
+        format!("__truthy({})", arithmetic(operand))
+    })
+}
+
+/// Rewrites the Bud expression `source` so that its `+` and comparison
+/// operators are evaluated by `__add()` and `__compare()`.
+pub(crate) fn rewrite(source: &str) -> String {
+    logical(source, &arithmetic)
+}
+
+/// Rewrites each operand of the `and`, `or`, and `not` operators in `source`
+/// using `operand`.
+fn logical(source: &str, operand: &dyn Fn(&str) -> String) -> String {
+    let source = source.trim();
+    for keyword in ["or", "and"] {
+        let operators = operators(source, &[keyword]);
+        if !operators.is_empty() {
+            let mut rewritten = String::new();
+            let mut start = 0;
+            for (index, keyword) in operators {
+                rewritten.push_str(&logical(&source[start..index], operand));
+                rewritten.push(' ');
+                rewritten.push_str(keyword);
+                rewritten.push(' ');
+                start = index + keyword.len();
+            }
+            rewritten.push_str(&logical(&source[start..], operand));
+            return rewritten;
+        }
+    }
+    if let Some(negated) = source
+        .strip_prefix("not")
+        .filter(|rest| rest.starts_with(|ch: char| ch.is_whitespace() || ch == '('))
+    {
+        return format!("not {}", logical(negated, operand));
+    }
+    if let Some(inner) = grouped(source) {
+        return format!("({})", logical(inner, operand));
+    }
+    operand(source)
+}
+
+/// Rewrites the comparison and `+` operators of `source`, which doesn't
+/// contain any `and`, `or`, or `not` operators outside of parentheses.
+fn arithmetic(source: &str) -> String {
+    let source = source.trim();
+    // Comparisons are left-associative, so the last one is evaluated last.
+    if let Some(&(index, operator)) = operators(source, &COMPARISONS).last() {
+        return format!(
+            "__compare(\"{operator}\", {}, {})",
+            arithmetic(&source[..index]),
+            arithmetic(&source[index + operator.len()..])
+        );
+    }
+
+    let terms = operators(source, &["+", "-"]);
+    if terms.is_empty() {
+        return nested(source);
+    }
+    let mut rewritten = nested(source[..terms[0].0].trim());
+    for (term, &(index, operator)) in terms.iter().enumerate() {
+        let end = terms.get(term + 1).map_or(source.len(), |(next, _)| *next);
+        let operand = nested(source[index + 1..end].trim());
+        rewritten = if operator == "+" {
+            format!("__add({rewritten}, {operand})")
+        } else {
+            format!("{rewritten} - {operand}")
+        };
+    }
+    rewritten
+}
+
+/// Rewrites the expressions within the parentheses, brackets, and braces of
+/// `source`, such as the arguments of function calls.
+fn nested(source: &str) -> String {
+    let mut rewritten = String::with_capacity(source.len());
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        let end = match ch {
+            '"' => {
+                let end = string_end(source, start);
+                rewritten.push_str(&source[start..end]);
+                end
+            }
+            '(' | '[' | '{' => {
+                let end = group_end(source, start);
+                let inner = &source[start + 1..end.saturating_sub(1).max(start + 1)];
+                rewritten.push(ch);
+                let items = split_top_level(inner, ",");
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        rewritten.push_str(", ");
+                    }
+                    rewritten.push_str(&rewrite(item));
+                }
+                rewritten.push_str(&source[start + 1 + inner.len()..end]);
+                end
+            }
+            ch => {
+                rewritten.push(ch);
+                continue;
+            }
+        };
+        while chars.next_if(|(index, _)| *index < end).is_some() {}
+    }
+    rewritten
+}
+
+/// Returns the contents of `source` if it is entirely enclosed in
+/// parentheses.
+fn grouped(source: &str) -> Option<&str> {
+    (source.starts_with('(') && group_end(source, 0) == source.len())
+        .then(|| &source[1..source.len() - 1])
+        .filter(|_| source.ends_with(')'))
+}
+
+/// Returns the offset after the bracket that closes the one at `start`.
+fn group_end(source: &str, start: usize) -> usize {
+    let mut depth = 0_usize;
+    let mut chars = source[start..].char_indices().peekable();
+    while let Some((offset, ch)) = chars.next() {
+        let index = start + offset;
+        match ch {
+            '"' => {
+                let end = string_end(source, index);
+                while chars.next_if(|(offset, _)| start + *offset < end).is_some() {}
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return index + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    source.len()
+}
+
+/// Returns the offsets of the binary operators in `source` that aren't
+/// within brackets or string literals, along with which of `candidates` each
+/// one is. Longer candidates must precede candidates they begin with.
+fn operators<'o>(source: &str, candidates: &[&'o str]) -> Vec<(usize, &'o str)> {
+    let mut operators = Vec::new();
+    let mut depth = 0_usize;
+    let mut chars = source.char_indices().peekable();
+    while let Some((index, ch)) = chars.next() {
+        let end = match ch {
+            '"' => string_end(source, index),
+            '(' | '[' | '{' => {
+                depth += 1;
+                continue;
+            }
+            ')' | ']' | '}' => {
+                depth = depth.saturating_sub(1);
+                continue;
+            }
+            _ if depth > 0 => continue,
+            ch if ch.is_alphanumeric() || ch == '_' => {
+                let end = source[index..]
+                    .find(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+                    .map_or(source.len(), |len| index + len);
+                if let Some(keyword) = candidates
+                    .iter()
+                    .find(|candidate| **candidate == &source[index..end])
+                {
+                    operators.push((index, *keyword));
+                }
+                end
+            }
+            _ => {
+                let Some(operator) = candidates
+                    .iter()
+                    .find(|candidate| source[index..].starts_with(**candidate))
+                else {
+                    continue;
+                };
+                // `+` and `-` are only binary operators when they follow an
+                // operand.
+                let binary = source[..index].trim_end().ends_with(|ch: char| {
+                    ch.is_alphanumeric() || matches!(ch, '_' | ')' | ']' | '}' | '"')
+                });
+                if binary || !matches!(*operator, "+" | "-") {
+                    operators.push((index, *operator));
+                }
+                index + operator.len()
+            }
+        };
+        while chars.next_if(|(index, _)| *index < end).is_some() {}
+    }
+    operators
+}
+
+#[test]
+fn rewritten_operators() {
+    assert_eq!(rewrite("a"), "a");
+    assert_eq!(rewrite("a + 1"), "__add(a, 1)");
+    assert_eq!(rewrite("a - b + -1"), "__add(a - b, -1)");
+    assert_eq!(rewrite("a * 2 + b / 3"), "__add(a * 2, b / 3)");
+    assert_eq!(
+        rewrite(r#"f(a + "+", [b == c]) + 1"#),
+        r#"__add(f(__add(a, "+"), [__compare("==", b, c)]), 1)"#
+    );
+    assert_eq!(
+        rewrite("a <= b and not (c != d or e)"),
+        r#"__compare("<=", a, b) and not (__compare("!=", c, d) or e)"#
+    );
+    assert_eq!(rewrite("orders + android"), "__add(orders, android)");
+    assert_eq!(
+        rewrite_condition("a and not b"),
+        "__truthy(a) and not __truthy(b)"
+    );
+    assert_eq!(
+        rewrite_condition("(a or b) and count > 1"),
+        r#"(__truthy(a) or __truthy(b)) and __truthy(__compare(">", count, 1))"#
+    );
+    assert_eq!(rewrite_statement("if x"), "if __truthy(x)");
+    assert_eq!(rewrite_statement("else if x"), "else if __truthy(x)");
+    assert_eq!(rewrite_statement("total := a + b"), "total := __add(a, b)");
+    assert_eq!(
+        rewrite_statement("loop for i := 1 to 3"),
+        "loop for i := 1 to 3"
+    );
+    assert_eq!(rewrite_statement("else"), "else");
+}
@@ -0,0 +1,111 @@
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+use budlang::vm::Value;
+
+use crate::builtins::RenderState;
+
+/// Supplies values for a single render that are available to built-in
+/// functions without being passed as template arguments.
+///
+/// Web integrations can use a provider to inject request-specific data. For
+/// example, the `forms` helpers look up `old_input` and `errors` maps to
+/// re-populate fields after a failed submission.
+pub trait ContextProvider: 'static {
+    fn get(&self, name: &str) -> Option<Value>;
+}
+
+impl ContextProvider for HashMap<String, Value> {
+    fn get(&self, name: &str) -> Option<Value> {
+        HashMap::get(self, name).cloned()
+    }
+}
+
+impl<F> ContextProvider for F
+where
+    F: Fn(&str) -> Option<Value> + 'static,
+{
+    fn get(&self, name: &str) -> Option<Value> {
+        self(name)
+    }
+}
+
+/// The render a helper registered using
+/// [`Configuration::with_contextual_helper`](crate::Configuration::with_contextual_helper)
+/// is called by.
+///
+/// Helpers look up what they may act with, such as a tenant's database
+/// connection or a user's credentials, as a capability of the render. The
+/// same compiled template can then be rendered for each tenant or request
+/// with different capabilities, and a helper called without one can refuse
+/// to act.
+///
+/// ```rust
+/// use budplate::{vm::Value, Configuration, ErrorKind, HelperContext, RenderOptions, Template};
+///
+/// struct Tenant(&'static str);
+///
+/// let configuration = Configuration::default().with_contextual_helper(
+///     "tenant_name",
+///     |context: &HelperContext<'_>, _args: &[Value]| {
+///         let tenant = context.capability::<Tenant>().ok_or_else(|| ErrorKind::InvalidArgument {
+///             function: String::from("tenant_name"),
+///             message: String::from("no tenant"),
+///         })?;
+///         Ok(Value::from(tenant.0))
+///     },
+/// );
+/// let template = Template::from("{{= tenant_name() }}")
+///     .compile(&configuration, Vec::<&str>::new())
+///     .unwrap();
+/// let acme = RenderOptions::new().with_capability(Tenant("Acme"));
+/// assert_eq!(
+///     template.render_with_options::<&str, &str, _>(&acme, []).unwrap(),
+///     "Acme"
+/// );
+/// assert!(template.render().is_err());
+/// ```
+pub struct HelperContext<'a> {
+    pub(crate) state: &'a RenderState,
+}
+
+impl HelperContext<'_> {
+    /// Returns the capability of type `T` passed to
+    /// [`RenderOptions::with_capability`](crate::RenderOptions::with_capability)
+    /// for this render, if any.
+    #[must_use]
+    pub fn capability<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.state.capability()
+    }
+
+    /// Returns the value `name` from the render's [`ContextProvider`], if
+    /// any.
+    #[must_use]
+    pub fn context(&self, name: &str) -> Option<Value> {
+        self.state.context(name)
+    }
+}
+
+#[test]
+fn render_capabilities() {
+    use crate::RenderOptions;
+
+    #[derive(Debug, PartialEq)]
+    struct Tenant(&'static str);
+
+    let state = RenderState::default();
+    let context = HelperContext { state: &state };
+    assert!(context.capability::<Tenant>().is_none());
+    let options = RenderOptions::new()
+        .with_capability(Tenant("acme"))
+        .with_capability(7_u32)
+        .with_capability(Tenant("globex"));
+    state.with_options(options, || {
+        assert_eq!(
+            context.capability::<Tenant>().as_deref(),
+            Some(&Tenant("globex"))
+        );
+        assert_eq!(context.capability::<u32>().as_deref(), Some(&7));
+        assert!(context.capability::<String>().is_none());
+    });
+    assert!(context.capability::<Tenant>().is_none());
+}
@@ -0,0 +1,199 @@
+use std::{fmt::Write, ops::Range};
+
+use crate::{formats::write_json_string, Configuration, Encoder, Error, Templates};
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Severity {
+    /// The template can't be compiled.
+    Error,
+    /// The template can be compiled, but is likely to be mistaken.
+    Warning,
+}
+
+impl Severity {
+    /// Returns the name of this severity, as written in JSON.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// The location of a [`Diagnostic`] within a template's source.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Span {
+    /// The byte range of the tag that caused the diagnostic.
+    pub range: Range<usize>,
+    /// The line the range starts on, counting from 1.
+    pub line: usize,
+    /// The column the range starts at in characters, counting from 1.
+    pub column: usize,
+}
+
+impl Span {
+    fn new(source: &str, range: Range<usize>) -> Self {
+        let before = &source[..range.start.min(source.len())];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            range,
+        }
+    }
+}
+
+/// A problem found in a template, in a form that tools such as editors and
+/// continuous integration can consume.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    /// An identifier of the kind of problem, such as `unknown_template`,
+    /// which doesn't change between releases. See [`Error::code()`].
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// The name of the registered template the problem is in.
+    pub template: Option<String>,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    /// Describes `error`, which occurred in the template registered as
+    /// `template` whose source is `source`. Located errors within other
+    /// templates are attributed to the template they occurred in, without a
+    /// span.
+    #[must_use]
+    pub fn from_error(error: &Error, template: Option<&str>, source: &str) -> Self {
+        let (template, span) = match (&error.template, &error.span) {
+            (Some(other), _) if Some(other.as_str()) != template => (Some(other.as_str()), None),
+            (_, Some(range)) => (template, Some(Span::new(source, range.clone()))),
+            _ => (template, None),
+        };
+        Self {
+            code: error.code(),
+            severity: Severity::Error,
+            message: error.kind.to_string(),
+            template: template.map(String::from),
+            span,
+        }
+    }
+
+    /// Returns this diagnostic as a JSON object. Its `span` contains the
+    /// `start` and `end` byte offsets, `line`, and `column`, or is `null`.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json);
+        json
+    }
+
+    fn write_json(&self, json: &mut String) {
+        json.push_str("{\"code\":");
+        write_json_string(self.code, json);
+        json.push_str(",\"severity\":");
+        write_json_string(self.severity.as_str(), json);
+        json.push_str(",\"message\":");
+        write_json_string(&self.message, json);
+        json.push_str(",\"template\":");
+        match &self.template {
+            Some(template) => write_json_string(template, json),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"span\":");
+        match &self.span {
+            Some(span) => write!(
+                json,
+                "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{}}}",
+                span.range.start, span.range.end, span.line, span.column
+            )
+            .expect("String does not fail to write"),
+            None => json.push_str("null"),
+        }
+        json.push('}');
+    }
+}
+
+/// Returns `diagnostics` as a JSON array.
+#[must_use]
+pub fn diagnostics_to_json(diagnostics: &[Diagnostic]) -> String {
+    let mut json = String::from("[");
+    for (index, diagnostic) in diagnostics.iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        diagnostic.write_json(&mut json);
+    }
+    json.push(']');
+    json
+}
+
+impl<Enc: Encoder> Configuration<Enc> {
+    /// Checks that every template registered in `templates` can be
+    /// transpiled, returning a diagnostic for each that can't, sorted by
+    /// template name.
+    ///
+    /// ```rust
+    /// use budplate::{diagnostics_to_json, Configuration, Templates};
+    ///
+    /// let mut templates = Templates::new();
+    /// templates.register("page", "<h1>\n{{ include \"nav\" }}").unwrap();
+    /// let diagnostics = Configuration::for_html().validate(&templates);
+    /// assert_eq!(
+    ///     diagnostics_to_json(&diagnostics),
+    ///     r#"[{"code":"unknown_template","severity":"error","message":"unknown template `nav`","template":"page","span":{"start":5,"end":24,"line":2,"column":1}}]"#
+    /// );
+    /// ```
+    pub fn validate(&self, templates: &Templates<'_>) -> Vec<Diagnostic> {
+        templates
+            .sorted()
+            .into_iter()
+            .filter_map(|(name, template)| {
+                self.transpile(template, Some(templates), "render", &[], 0)
+                    .err()
+                    .map(|error| Diagnostic::from_error(&error, Some(name), template.source()))
+            })
+            .collect()
+    }
+}
+
+impl Templates<'_> {
+    /// Checks every registered template using the default configuration.
+    /// See [`Configuration::validate()`].
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        Configuration::default().validate(self)
+    }
+}
+
+#[test]
+fn validation_diagnostics() {
+    let mut templates = Templates::new();
+    templates.register("ok", "{{= title }}").unwrap();
+    templates
+        .register("broken", "line one\n  é {{ include \"missing\" }}")
+        .unwrap();
+    templates
+        .register("includes", "{{ include \"broken\" }}")
+        .unwrap();
+
+    let diagnostics = templates.validate();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].template.as_deref(), Some("broken"));
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[0].span,
+        Some(Span {
+            range: 14..37,
+            line: 2,
+            column: 5,
+        })
+    );
+    assert_eq!(diagnostics[1].template.as_deref(), Some("broken"));
+    assert_eq!(diagnostics[1].span, None);
+    assert_eq!(diagnostics[0].code, diagnostics[1].code);
+    assert_eq!(
+        Diagnostic::from_error(&Error::new(crate::ErrorKind::BudgetExceeded), None, "").to_json(),
+        r#"{"code":"budget_exceeded","severity":"error","message":"execution budget exceeded","template":null,"span":null}"#
+    );
+}
@@ -0,0 +1,613 @@
+use std::borrow::Cow;
+
+use crate::{Error, ErrorKind};
+
+/// A statement that is interpreted by Budplate rather than passed through to
+/// Bud.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Directive<'a> {
+    Define {
+        name: Cow<'a, str>,
+    },
+    EndDefine,
+    Block {
+        name: Cow<'a, str>,
+    },
+    EndBlock,
+    Extends {
+        name: Cow<'a, str>,
+    },
+    Render {
+        name: Cow<'a, str>,
+        arguments: Vec<Argument<'a>>,
+    },
+    Include {
+        name: Cow<'a, str>,
+        arguments: Vec<Argument<'a>>,
+    },
+    Component {
+        name: Cow<'a, str>,
+        arguments: Vec<Argument<'a>>,
+    },
+    EndComponent,
+    /// A placeholder for a component rendered by a client-side framework,
+    /// whose arguments are its props.
+    Island {
+        name: Cow<'a, str>,
+        arguments: Vec<Argument<'a>>,
+    },
+    Fragment {
+        name: Cow<'a, str>,
+    },
+    EndFragment,
+    /// Content added to the named content written by `emit`.
+    Defer {
+        name: &'a str,
+    },
+    Emit {
+        name: &'a str,
+    },
+    /// A region rendered at most once per render.
+    Once,
+    /// Metadata describing the template, which renders nothing.
+    Meta {
+        entries: Vec<(&'a str, Cow<'a, str>)>,
+    },
+    Feature {
+        name: Cow<'a, str>,
+    },
+    /// A region rendered by a fraction of renders, written as a number
+    /// between 0 and 1.
+    Sampled {
+        rate: f64,
+    },
+    For {
+        key: Option<&'a str>,
+        value: &'a str,
+        collection: &'a str,
+    },
+}
+
+/// The kinds of blocks that directives open. Budplate's named blocks are
+/// closed by `end` followed by their kind, such as `end define`, and the
+/// others by a bare `end`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum BlockKind {
+    Define,
+    Block,
+    Component,
+    Fragment,
+    Defer,
+    /// A block that is transpiled into a Bud construct.
+    Bud,
+}
+
+impl Directive<'_> {
+    /// Returns the kind of block this directive opens, if it opens one.
+    pub const fn opens(&self) -> Option<BlockKind> {
+        match self {
+            Directive::Define { .. } => Some(BlockKind::Define),
+            Directive::Block { .. } => Some(BlockKind::Block),
+            Directive::Component { .. } => Some(BlockKind::Component),
+            Directive::Fragment { .. } => Some(BlockKind::Fragment),
+            Directive::Defer { .. } => Some(BlockKind::Defer),
+            Directive::Once
+            | Directive::Feature { .. }
+            | Directive::Sampled { .. }
+            | Directive::For { .. } => Some(BlockKind::Bud),
+            Directive::EndDefine
+            | Directive::EndBlock
+            | Directive::EndComponent
+            | Directive::EndFragment
+            | Directive::Extends { .. }
+            | Directive::Render { .. }
+            | Directive::Include { .. }
+            | Directive::Island { .. }
+            | Directive::Emit { .. }
+            | Directive::Meta { .. } => None,
+        }
+    }
+
+    /// Returns the kind of block this directive closes, if it closes one.
+    pub const fn closes(&self) -> Option<BlockKind> {
+        match self {
+            Directive::EndDefine => Some(BlockKind::Define),
+            Directive::EndBlock => Some(BlockKind::Block),
+            Directive::EndComponent => Some(BlockKind::Component),
+            Directive::EndFragment => Some(BlockKind::Fragment),
+            _ => None,
+        }
+    }
+}
+
+/// A named argument in a `with` clause. `with item` is shorthand for
+/// `with item = item`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct Argument<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+pub(crate) fn parse(statement: &str) -> Result<Option<Directive<'_>>, Error> {
+    let (keyword, rest) = split_keyword(statement);
+    match keyword {
+        // Directives that name a template are only recognized when followed
+        // by a string literal, leaving identifiers such as `render` usable
+        // within Bud statements.
+        "define" | "render" | "include" | "extends" | "component" | "island" | "fragment"
+        | "feature"
+            if !rest.starts_with('"') =>
+        {
+            Ok(None)
+        }
+        // Blocks may also be named by an identifier.
+        "block" => {
+            let (name, rest) = match parse_string_literal(rest) {
+                Some(literal) => literal,
+                None => {
+                    let (name, rest) = split_keyword(rest);
+                    if !is_identifier(name) {
+                        return Ok(None);
+                    }
+                    (Cow::Borrowed(name), rest)
+                }
+            };
+            expect_end(statement, rest)?;
+            Ok(Some(Directive::Block { name }))
+        }
+        "extends" => {
+            let (name, rest) = expect_string_literal(statement, rest)?;
+            expect_end(statement, rest)?;
+            Ok(Some(Directive::Extends { name }))
+        }
+        "define" => {
+            let (name, rest) = expect_string_literal(statement, rest)?;
+            expect_end(statement, rest)?;
+            Ok(Some(Directive::Define { name }))
+        }
+        "end" if split_keyword(rest) == ("define", "") => Ok(Some(Directive::EndDefine)),
+        "end" if split_keyword(rest) == ("block", "") => Ok(Some(Directive::EndBlock)),
+        "end" if split_keyword(rest) == ("component", "") => Ok(Some(Directive::EndComponent)),
+        "end" if split_keyword(rest) == ("fragment", "") => Ok(Some(Directive::EndFragment)),
+        "fragment" => {
+            let (name, rest) = expect_string_literal(statement, rest)?;
+            expect_end(statement, rest)?;
+            Ok(Some(Directive::Fragment { name }))
+        }
+        "feature" => {
+            let (name, rest) = expect_string_literal(statement, rest)?;
+            expect_end(statement, rest)?;
+            Ok(Some(Directive::Feature { name }))
+        }
+        // `defer` and `emit` are only recognized when followed by a name
+        // alone.
+        "defer" if is_identifier(rest.trim_end()) => Ok(Some(Directive::Defer {
+            name: rest.trim_end(),
+        })),
+        "emit" if is_identifier(rest.trim_end()) => Ok(Some(Directive::Emit {
+            name: rest.trim_end(),
+        })),
+        "once" if rest.trim_end().is_empty() => Ok(Some(Directive::Once)),
+        // `meta` is only recognized when followed by a name and `=`.
+        "meta"
+            if split_keyword(rest).1.starts_with('=')
+                && !split_keyword(rest).1.starts_with("==") =>
+        {
+            let entries = split_top_level(rest, ",")
+                .into_iter()
+                .map(|entry| {
+                    let (name, value) = split_keyword(entry);
+                    let value = value.strip_prefix('=').unwrap_or(value);
+                    match parse_string_literal(value) {
+                        Some((value, rest)) if is_identifier(name) && rest.is_empty() => {
+                            Ok((name, value))
+                        }
+                        _ => Err(Error::from(ErrorKind::InvalidStatement(
+                            statement.to_string(),
+                        ))),
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Some(Directive::Meta { entries }))
+        }
+        // `sampled` only begins a region when followed by its rate.
+        "sampled" if rest.starts_with(|ch: char| ch.is_ascii_digit() || ch == '.') => {
+            match rest.trim_end().parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(Some(Directive::Sampled { rate })),
+                _ => Err(Error::from(ErrorKind::InvalidStatement(
+                    statement.to_string(),
+                ))),
+            }
+        }
+        "render" => {
+            let (name, rest) = expect_string_literal(statement, rest)?;
+            let arguments = parse_with_clause(statement, rest)?;
+            Ok(Some(Directive::Render { name, arguments }))
+        }
+        "include" => {
+            let (name, rest) = expect_string_literal(statement, rest)?;
+            let arguments = parse_with_clause(statement, rest)?;
+            Ok(Some(Directive::Include { name, arguments }))
+        }
+        "component" => {
+            let (name, rest) = expect_string_literal(statement, rest)?;
+            let arguments = parse_with_clause(statement, rest)?;
+            // The slot is provided by the component's body.
+            if arguments.iter().any(|argument| argument.name == "slot") {
+                return Err(Error::from(ErrorKind::InvalidStatement(
+                    statement.to_string(),
+                )));
+            }
+            Ok(Some(Directive::Component { name, arguments }))
+        }
+        "island" => {
+            let (name, rest) = expect_string_literal(statement, rest)?;
+            let arguments = parse_with_clause(statement, rest)?;
+            Ok(Some(Directive::Island { name, arguments }))
+        }
+        // `for` only begins a Bud statement as part of `loop for`.
+        "for" if !rest.starts_with([':', '=', '.', '(']) => {
+            let (first, rest) = split_keyword(rest);
+            let (key, value, rest) = match rest.strip_prefix(',') {
+                Some(rest) => {
+                    let (value, rest) = split_keyword(rest);
+                    (Some(first), value, rest)
+                }
+                None => (None, first, rest),
+            };
+            let (keyword, collection) = split_keyword(rest);
+            if keyword != "in"
+                || !is_identifier(value)
+                || !key.is_none_or(is_identifier)
+                || collection.trim().is_empty()
+            {
+                return Err(Error::from(ErrorKind::InvalidStatement(
+                    statement.to_string(),
+                )));
+            }
+            Ok(Some(Directive::For {
+                key,
+                value,
+                collection: collection.trim(),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub(crate) fn split_keyword(statement: &str) -> (&str, &str) {
+    let statement = statement.trim_start();
+    let end = statement
+        .find(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+        .unwrap_or(statement.len());
+    (&statement[..end], statement[end..].trim_start())
+}
+
+fn expect_end(statement: &str, rest: &str) -> Result<(), Error> {
+    if rest.trim().is_empty() {
+        Ok(())
+    } else {
+        Err(Error::from(ErrorKind::InvalidStatement(
+            statement.to_string(),
+        )))
+    }
+}
+
+fn expect_string_literal<'a>(
+    statement: &str,
+    rest: &'a str,
+) -> Result<(Cow<'a, str>, &'a str), Error> {
+    parse_string_literal(rest)
+        .ok_or_else(|| Error::from(ErrorKind::InvalidStatement(statement.to_string())))
+}
+
+/// Parses a double-quoted string literal at the start of `source`, returning
+/// the unescaped contents and the remaining source. The contents are borrowed
+/// from `source` unless they contain escapes.
+pub(crate) fn parse_string_literal(source: &str) -> Option<(Cow<'_, str>, &str)> {
+    let source = source.trim_start();
+    let contents = source.strip_prefix('"')?;
+    if let Some(end) = contents.find(['"', '\\']) {
+        if contents[end..].starts_with('"') {
+            return Some((
+                Cow::Borrowed(&contents[..end]),
+                contents[end + 1..].trim_start(),
+            ));
+        }
+    }
+
+    let mut chars = source.char_indices();
+    chars.next();
+    let mut value = String::new();
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            '"' => return Some((Cow::Owned(value), source[index + 1..].trim_start())),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                other => value.push(other),
+            },
+            ch => value.push(ch),
+        }
+    }
+
+    None
+}
+
+fn parse_with_clause<'a>(statement: &str, rest: &'a str) -> Result<Vec<Argument<'a>>, Error> {
+    let (keyword, rest) = split_keyword(rest);
+    match keyword {
+        "" if rest.is_empty() => return Ok(Vec::new()),
+        "with" => {}
+        _ => {
+            return Err(Error::from(ErrorKind::InvalidStatement(
+                statement.to_string(),
+            )))
+        }
+    }
+
+    split_top_level(rest, ",")
+        .into_iter()
+        .map(|argument| {
+            let argument = argument.trim();
+            let (name, value) = split_keyword(argument);
+            let value = if value.is_empty() {
+                name
+            } else if let Some(value) = value.strip_prefix('=').filter(|v| !v.starts_with('=')) {
+                value.trim()
+            } else {
+                return Err(ErrorKind::InvalidStatement(statement.to_string()).into());
+            };
+
+            if is_identifier(name) && !value.is_empty() {
+                Ok(Argument { name, value })
+            } else {
+                Err(ErrorKind::InvalidStatement(statement.to_string()).into())
+            }
+        })
+        .collect()
+}
+
+/// Returns true if `statement` begins a Bud construct that is closed by
+/// `end`.
+pub(crate) fn opens_block(statement: &str) -> bool {
+    let (keyword, rest) = split_keyword(statement);
+    matches!(keyword, "if" | "loop" | "function") && !rest.starts_with([':', '=', '.', '('])
+}
+
+/// Returns true if `statement` assigns to or binds the variable `name`.
+pub(crate) fn declares(statement: &str, name: &str) -> bool {
+    let (keyword, rest) = split_keyword(statement);
+    match keyword {
+        "loop" => {
+            let (keyword, rest) = split_keyword(rest);
+            keyword == "for" && split_keyword(rest).0 == name
+        }
+        "for" => matches!(
+            parse(statement),
+            Ok(Some(Directive::For { key, value, .. })) if value == name || key == Some(name)
+        ),
+        keyword => keyword == name && rest.starts_with(":="),
+    }
+}
+
+/// Returns true if `statement` is a bare `end`.
+pub(crate) fn is_end(statement: &str) -> bool {
+    split_keyword(statement) == ("end", "")
+}
+
+pub(crate) fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+/// Splits `source` on `separator`, ignoring separators that appear within
+/// string literals or nested brackets.
+pub(crate) fn split_top_level<'a>(source: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut depth = 0_usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, ch) in source.char_indices() {
+        if index < start {
+            // Within a separator longer than one character.
+            continue;
+        } else if in_string {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match ch {
+            '"' => in_string = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ if depth == 0 && source[index..].starts_with(separator) => {
+                parts.push(&source[start..index]);
+                start = index + separator.len();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&source[start..]);
+    parts
+}
+
+#[test]
+fn parse_render_directives() {
+    assert_eq!(
+        parse(r#"render "row" with item, total = sum(a, b)"#).unwrap(),
+        Some(Directive::Render {
+            name: Cow::from("row"),
+            arguments: vec![
+                Argument {
+                    name: "item",
+                    value: "item"
+                },
+                Argument {
+                    name: "total",
+                    value: "sum(a, b)"
+                }
+            ]
+        })
+    );
+    assert_eq!(
+        parse(r#"define "a \"quoted\" name""#).unwrap(),
+        Some(Directive::Define {
+            name: Cow::from(r#"a "quoted" name"#)
+        })
+    );
+    assert_eq!(
+        parse(r#"include "header""#).unwrap(),
+        Some(Directive::Include {
+            name: Cow::from("header"),
+            arguments: Vec::new()
+        })
+    );
+    assert_eq!(parse("end  define").unwrap(), Some(Directive::EndDefine));
+    assert_eq!(parse("end").unwrap(), None);
+    assert_eq!(
+        parse("block content").unwrap(),
+        Some(Directive::Block {
+            name: Cow::from("content")
+        })
+    );
+    assert_eq!(parse("block := 1").unwrap(), None);
+    assert_eq!(parse("end block").unwrap(), Some(Directive::EndBlock));
+    assert_eq!(
+        parse("end component").unwrap(),
+        Some(Directive::EndComponent)
+    );
+    assert!(parse(r#"component "card" with slot = 1"#).is_err());
+    assert_eq!(
+        parse(r#"fragment "head""#).unwrap(),
+        Some(Directive::Fragment {
+            name: Cow::from("head")
+        })
+    );
+    assert_eq!(parse("end fragment").unwrap(), Some(Directive::EndFragment));
+    assert_eq!(parse("fragment := 1").unwrap(), None);
+    assert_eq!(
+        parse(r#"feature "new_checkout""#).unwrap(),
+        Some(Directive::Feature {
+            name: Cow::from("new_checkout")
+        })
+    );
+    assert_eq!(parse("feature := 1").unwrap(), None);
+    assert_eq!(
+        parse(r#"island "Counter" with count, label = "Clicks""#).unwrap(),
+        Some(Directive::Island {
+            name: Cow::from("Counter"),
+            arguments: vec![
+                Argument {
+                    name: "count",
+                    value: "count"
+                },
+                Argument {
+                    name: "label",
+                    value: "\"Clicks\""
+                }
+            ]
+        })
+    );
+    assert_eq!(parse("island(1)").unwrap(), None);
+    assert_eq!(
+        parse("defer scripts").unwrap(),
+        Some(Directive::Defer { name: "scripts" })
+    );
+    assert_eq!(
+        parse("emit scripts").unwrap(),
+        Some(Directive::Emit { name: "scripts" })
+    );
+    assert_eq!(parse("defer := 1").unwrap(), None);
+    assert_eq!(parse("once").unwrap(), Some(Directive::Once));
+    assert_eq!(parse("once := true").unwrap(), None);
+    assert_eq!(
+        parse(r#"meta title = "Invoice", owner="billing""#).unwrap(),
+        Some(Directive::Meta {
+            entries: vec![
+                ("title", Cow::from("Invoice")),
+                ("owner", Cow::from("billing"))
+            ]
+        })
+    );
+    assert!(parse("meta title = 1").is_err());
+    assert!(parse(r#"meta title = "a" "b""#).is_err());
+    assert_eq!(parse("meta := 1").unwrap(), None);
+    assert_eq!(parse("meta == 1").unwrap(), None);
+    assert_eq!(parse("emit(scripts)").unwrap(), None);
+    assert_eq!(
+        parse("sampled 0.01").unwrap(),
+        Some(Directive::Sampled { rate: 0.01 })
+    );
+    assert_eq!(
+        parse("sampled 1e-1").unwrap(),
+        Some(Directive::Sampled { rate: 0.1 })
+    );
+    assert!(parse("sampled 1.5").is_err());
+    assert!(parse("sampled 0.5 extra").is_err());
+    assert_eq!(parse("sampled := 1").unwrap(), None);
+    assert_eq!(parse("if rendering").unwrap(), None);
+    assert!(parse(r#"render "row" with a == b"#).is_err());
+    assert!(parse(r#"define "row" extra"#).is_err());
+    assert_eq!(parse("render := 1").unwrap(), None);
+    assert_eq!(
+        parse("for key, item in items.all").unwrap(),
+        Some(Directive::For {
+            key: Some("key"),
+            value: "item",
+            collection: "items.all"
+        })
+    );
+    assert_eq!(
+        parse("for item in [1, 2]").unwrap(),
+        Some(Directive::For {
+            key: None,
+            value: "item",
+            collection: "[1, 2]"
+        })
+    );
+    assert!(parse("for item of items").is_err());
+    assert!(declares("loop for i := 1 to 2", "i"));
+    assert!(declares("for key, item in items", "key"));
+    assert!(declares("total := 0", "total"));
+    assert!(!declares("total == 0", "total"));
+    assert!(parse("for item in").is_err());
+}
+
+#[test]
+fn block_kinds() {
+    let opens = |statement| parse(statement).unwrap().and_then(|d| d.opens());
+    assert_eq!(opens(r#"define "row""#), Some(BlockKind::Define));
+    assert_eq!(opens("defer scripts"), Some(BlockKind::Defer));
+    assert_eq!(opens(r#"feature "beta""#), Some(BlockKind::Bud));
+    assert_eq!(opens("sampled 0.5"), Some(BlockKind::Bud));
+    assert_eq!(opens("once"), Some(BlockKind::Bud));
+    assert_eq!(opens("emit scripts"), None);
+    assert_eq!(
+        parse("end fragment").unwrap().and_then(|d| d.closes()),
+        Some(BlockKind::Fragment)
+    );
+}
+
+#[test]
+fn borrowed_string_literals() {
+    assert!(matches!(
+        parse_string_literal(r#""row" with"#),
+        Some((Cow::Borrowed("row"), "with"))
+    ));
+    assert!(matches!(
+        parse_string_literal(r#""a\tb""#),
+        Some((Cow::Owned(name), "")) if name == "a\tb"
+    ));
+    assert_eq!(parse_string_literal(r#""unterminated"#), None);
+}
@@ -0,0 +1,163 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{Encoder, UrlEncoding};
+
+type MarkerFn = dyn Fn(&str) -> String + Send + Sync;
+
+/// Renders the includes of selected templates as markers that an edge
+/// server, such as a CDN supporting Edge Side Includes, replaces with the
+/// template rendered separately.
+///
+/// Each selected template is served from a URL, which the marker names. The
+/// arguments of the include are appended to the URL's query string, so that
+/// `{{ include "cart" with user_id }}` renders
+/// `<esi:include src="/fragments/cart?user_id=42" />` by default. The
+/// included template isn't compiled or rendered by the page, and doesn't need
+/// to be registered with the page's [`Templates`](crate::Templates).
+///
+/// ```rust
+/// use budplate::{Configuration, EdgeIncludes, Template};
+///
+/// let rendered = Configuration::for_html()
+///     .with_edge_includes(EdgeIncludes::new().with_template("cart", "/fragments/cart"))
+///     .compile(
+///         &Template::from(r#"<header>{{ include "cart" with user_id }}</header>"#),
+///         ["user_id"],
+///     )
+///     .unwrap()
+///     .render_with([("user_id", 42_i64)])
+///     .unwrap();
+/// assert_eq!(
+///     rendered,
+///     r#"<header><esi:include src="/fragments/cart?user_id=42" /></header>"#
+/// );
+/// ```
+#[derive(Clone)]
+pub struct EdgeIncludes {
+    templates: HashMap<String, String>,
+    marker: Option<Arc<MarkerFn>>,
+}
+
+impl Default for EdgeIncludes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EdgeIncludes {
+    /// Returns a set of edge includes without any templates, whose markers
+    /// are `<esi:include>` elements.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            templates: HashMap::new(),
+            marker: None,
+        }
+    }
+
+    /// Renders each include of the template `name` as a marker for `src`,
+    /// such as `/fragments/cart`, rather than rendering the template inline.
+    #[must_use]
+    pub fn with_template(mut self, name: impl Into<String>, src: impl Into<String>) -> Self {
+        self.templates.insert(name.into(), src.into());
+        self
+    }
+
+    /// Renders the marker of each include using `marker`, which receives the
+    /// URL of the included template with its arguments in the query string.
+    /// The marker is written without being encoded.
+    ///
+    /// ```rust
+    /// use budplate::{Configuration, EdgeIncludes};
+    ///
+    /// let rendered = Configuration::for_html()
+    ///     .with_edge_includes(
+    ///         EdgeIncludes::new()
+    ///             .with_template("nav", "/fragments/nav")
+    ///             .with_marker(|src| format!("<!--# include virtual=\"{src}\" -->")),
+    ///     )
+    ///     .render(r#"{{ include "nav" }}"#)
+    ///     .unwrap();
+    /// assert_eq!(rendered, r#"<!--# include virtual="/fragments/nav" -->"#);
+    /// ```
+    #[must_use]
+    pub fn with_marker(mut self, marker: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.marker = Some(Arc::new(marker));
+        self
+    }
+
+    /// Returns the URL that the template `name` is served from, if its
+    /// includes are rendered as markers.
+    #[must_use]
+    pub fn src(&self, name: &str) -> Option<&str> {
+        self.templates.get(name).map(String::as_str)
+    }
+
+    /// Returns the names of the templates whose includes are rendered as
+    /// markers, along with the URLs they are served from.
+    pub fn templates(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.templates
+            .iter()
+            .map(|(name, src)| (name.as_str(), src.as_str()))
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.templates.contains_key(name)
+    }
+
+    /// Returns the marker of an include of `name` with `arguments`, or `None`
+    /// if `name` isn't rendered at the edge.
+    pub(crate) fn marker(&self, name: &str, arguments: &[(String, String)]) -> Option<String> {
+        let mut src = self.src(name)?.to_string();
+        for (index, (name, value)) in arguments.iter().enumerate() {
+            if index == 0 && !src.contains('?') {
+                src.push('?');
+            } else {
+                src.push('&');
+            }
+            UrlEncoding.encode_to_string(name, &mut src);
+            src.push('=');
+            UrlEncoding.encode_to_string(value, &mut src);
+        }
+        Some(match &self.marker {
+            Some(marker) => marker(&src),
+            None => format!("<esi:include src=\"{src}\" />"),
+        })
+    }
+}
+
+impl std::fmt::Debug for EdgeIncludes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EdgeIncludes")
+            .field("templates", &self.templates)
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn marker_urls() {
+    let edge = EdgeIncludes::new()
+        .with_template("cart", "/fragments/cart")
+        .with_template("nav", "/fragments/nav?v=2");
+    assert_eq!(edge.marker("missing", &[]), None);
+    assert_eq!(
+        edge.marker("cart", &[]).unwrap(),
+        "<esi:include src=\"/fragments/cart\" />"
+    );
+    assert_eq!(
+        edge.marker(
+            "cart",
+            &[
+                (String::from("user"), String::from("a&b")),
+                (String::from("q"), String::from("\"x\""))
+            ]
+        )
+        .unwrap(),
+        "<esi:include src=\"/fragments/cart?user=a%26b&q=%22x%22\" />"
+    );
+    assert_eq!(
+        edge.marker("nav", &[(String::from("page"), String::from("1"))])
+            .unwrap(),
+        "<esi:include src=\"/fragments/nav?v=2&page=1\" />"
+    );
+}
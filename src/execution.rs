@@ -0,0 +1,105 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{Error, ErrorKind};
+
+/// The most instructions a render executes before its virtual machine
+/// pauses, at which point the render is checked for cancellation, and
+/// asynchronous renders yield to their executor. The
+/// virtual machine's budget is shared by every render executed by it, so
+/// granting it in slices also bounds the instructions one render can leave
+/// unused to this many.
+const PAUSE_INTERVAL: usize = 4_096;
+
+/// Grants the budget of a render to its virtual machine in slices, and
+/// counts the instructions the render executes.
+pub(crate) struct Execution {
+    /// The most instructions the render may execute, if it is limited.
+    budget: Option<usize>,
+    /// The instructions executed in the slices before the current one.
+    executed: usize,
+    /// The instructions available to the current slice.
+    granted: usize,
+}
+
+impl Execution {
+    /// Starts a render limited to `budget` instructions, on a virtual machine
+    /// whose budget has `unused` instructions left by earlier renders.
+    /// Returns the execution, along with how much to increase the virtual
+    /// machine's budget by.
+    ///
+    /// Unused instructions are counted against the render, so a render can't
+    /// execute more than its own budget.
+    pub fn start(budget: Option<usize>, unused: Option<usize>) -> (Self, usize) {
+        let unused = unused.unwrap_or(0);
+        let mut execution = Self {
+            budget,
+            executed: 0,
+            granted: unused,
+        };
+        // The budget is always increased on an unbudgeted virtual machine, so
+        // that it pauses.
+        let increase = execution.slice().max(1).saturating_sub(unused);
+        execution.granted += increase;
+        (execution, increase)
+    }
+
+    /// Returns the instructions to grant to the next slice.
+    fn slice(&self) -> usize {
+        self.budget.map_or(PAUSE_INTERVAL, |budget| {
+            budget.saturating_sub(self.executed).min(PAUSE_INTERVAL)
+        })
+    }
+
+    /// Accounts for the virtual machine pausing after executing every
+    /// instruction of the current slice. Returns how much to increase its
+    /// budget by before resuming it, or [`ErrorKind::BudgetExceeded`] if the
+    /// render's budget is spent.
+    pub fn paused(&mut self) -> Result<usize, Error> {
+        self.executed += self.granted;
+        self.granted = self.slice();
+        if self.granted == 0 {
+            return Err(Error::from(ErrorKind::BudgetExceeded));
+        }
+        Ok(self.granted)
+    }
+
+    /// Accounts for the render finishing with `remaining` instructions of
+    /// the virtual machine's budget unused, failing it if it executed more
+    /// instructions than its budget.
+    pub fn finish(&mut self, remaining: Option<usize>) -> Result<(), Error> {
+        self.executed += self.granted.saturating_sub(remaining.unwrap_or(0));
+        self.granted = 0;
+        if self.budget.is_some_and(|budget| self.executed > budget) {
+            return Err(Error::from(ErrorKind::BudgetExceeded));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of instructions executed by the render, once it
+    /// has [finished](Self::finish).
+    pub const fn executed(&self) -> usize {
+        self.executed
+    }
+}
+
+/// A future that is pending when first polled, so that the executor polls
+/// its other tasks before polling it again.
+#[derive(Default)]
+pub(crate) struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
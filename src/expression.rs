@@ -0,0 +1,472 @@
+use std::borrow::Cow;
+
+#[cfg(feature = "i18n")]
+use crate::directive::parse_string_literal;
+use crate::directive::{is_identifier, split_top_level};
+
+/// Rewrites field accesses such as `user.name` into calls to the `__field`
+/// native function, which looks up a key within a map. Method calls like
+/// `list.push(1)` and the contents of string literals are left untouched.
+///
+/// `defined(name)` checks are also resolved: a check of a variable for which
+/// `declared` returns false becomes `false`, as Bud cannot compile references
+/// to undeclared variables.
+pub(crate) fn rewrite<'s>(source: &'s str, declared: &dyn Fn(&str) -> bool) -> Cow<'s, str> {
+    rewrite_with(source, "__field", declared)
+}
+
+/// Rewrites an expression whose top level may provide fallbacks using `??`,
+/// such as `user.nickname ?? user.name`. Each operand but the last may be
+/// missing or `void`, in which case the next operand is used.
+pub(crate) fn rewrite_fallbacks<'s>(
+    source: &'s str,
+    declared: &dyn Fn(&str) -> bool,
+) -> Cow<'s, str> {
+    let operands = split_top_level(source, "??");
+    let Some((last, operands)) = operands.split_last().filter(|(_, rest)| !rest.is_empty()) else {
+        return rewrite(source, declared);
+    };
+
+    let mut rewritten = rewrite(last.trim(), declared).into_owned();
+    for operand in operands.iter().rev() {
+        let operand = operand.trim();
+        let root = operand.split('.').next().unwrap_or_default();
+        let operand = if is_path(operand) && !declared(root) {
+            Cow::Borrowed("__missing()")
+        } else {
+            rewrite_with(operand, "__try_field", declared)
+        };
+        rewritten = format!("__default({operand}, {rewritten})");
+    }
+    Cow::Owned(rewritten)
+}
+
+/// Returns true if `source` is a variable followed by any number of field
+/// accesses.
+fn is_path(source: &str) -> bool {
+    source.split('.').all(is_identifier)
+}
+
+fn rewrite_with<'s>(
+    source: &'s str,
+    field_function: &str,
+    declared: &dyn Fn(&str) -> bool,
+) -> Cow<'s, str> {
+    if !source.contains('.') && !source.contains("defined") {
+        return Cow::Borrowed(source);
+    }
+
+    let mut rewritten = String::with_capacity(source.len());
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        match ch {
+            '"' => {
+                let mut escaped = false;
+                let mut end = source.len();
+                for (index, ch) in chars.by_ref() {
+                    match ch {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                rewritten.push_str(&source[start..end]);
+            }
+            ch if ch.is_alphabetic() || ch == '_' => {
+                let mut end = identifier_end(source, start);
+                if let Some((checked, check_end)) = defined_check(source, start..end) {
+                    let root = checked.split('.').next().unwrap_or_default();
+                    if declared(root) {
+                        rewritten.push_str("defined(");
+                        rewritten.push_str(&rewrite_with(checked, "__try_field", declared));
+                        rewritten.push(')');
+                    } else {
+                        rewritten.push_str("false");
+                    }
+                    while chars.next_if(|(index, _)| *index < check_end).is_some() {}
+                    continue;
+                }
+                let mut expression = Cow::Borrowed(&source[start..end]);
+                // Consume `.field` accessors that are not method calls.
+                while source[end..].starts_with('.') {
+                    let field_start = end + 1;
+                    if !source[field_start..]
+                        .starts_with(|ch: char| ch.is_alphabetic() || ch == '_')
+                    {
+                        break;
+                    }
+                    let field_end = identifier_end(source, field_start);
+                    if source[field_end..].trim_start().starts_with('(') {
+                        break;
+                    }
+                    expression = Cow::Owned(format!(
+                        "{field_function}({expression}, \"{}\")",
+                        &source[field_start..field_end]
+                    ));
+                    end = field_end;
+                }
+                rewritten.push_str(&expression);
+                while chars.next_if(|(index, _)| *index < end).is_some() {}
+            }
+            ch if ch.is_ascii_digit() => {
+                // Numbers may contain a decimal point, which is not an
+                // accessor.
+                let end = source[start..]
+                    .find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '.'))
+                    .map_or(source.len(), |len| start + len);
+                rewritten.push_str(&source[start..end]);
+                while chars.next_if(|(index, _)| *index < end).is_some() {}
+            }
+            ch => rewritten.push(ch),
+        }
+    }
+
+    Cow::Owned(rewritten)
+}
+
+/// Returns the ranges of the variables referenced by `source`. Fields,
+/// called functions, and the contents of string literals are skipped.
+pub(crate) fn variables(source: &str) -> Vec<std::ops::Range<usize>> {
+    identifiers(source, false)
+}
+
+/// Returns the ranges of the names of the functions called by `source`.
+/// Methods and the contents of string literals are skipped.
+pub(crate) fn calls(source: &str) -> Vec<std::ops::Range<usize>> {
+    identifiers(source, true)
+}
+
+/// Returns the ranges of the identifiers in `source` that aren't fields or
+/// methods, and are called if `called` is true.
+fn identifiers(source: &str, called: bool) -> Vec<std::ops::Range<usize>> {
+    let mut variables = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        let end = match ch {
+            '"' => string_end(source, start),
+            ch if ch.is_alphabetic() || ch == '_' => {
+                let end = identifier_end(source, start);
+                let field = source[..start].trim_end().ends_with('.');
+                let call = source[end..].trim_start().starts_with('(');
+                if !field && call == called {
+                    variables.push(start..end);
+                }
+                end
+            }
+            ch if ch.is_ascii_digit() => source[start..]
+                .find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '.'))
+                .map_or(source.len(), |len| start + len),
+            _ => continue,
+        };
+        while chars.next_if(|(index, _)| *index < end).is_some() {}
+    }
+    variables
+}
+
+/// Returns the offset after the string literal that begins at `start`.
+pub(crate) fn string_end(source: &str, start: usize) -> usize {
+    let mut escaped = false;
+    for (index, ch) in source[start + 1..].char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return start + 1 + index + 1,
+            _ => {}
+        }
+    }
+    source.len()
+}
+
+/// Rewrites calls to the message functions `t()` and `plural()` so that each
+/// argument after the message key and count is preceded by its name, which
+/// is how they are passed to the message. An argument that is a variable or
+/// field, such as `user.name`, is named after its last segment, and an
+/// argument written as `name = value` is named explicitly:
+/// `t("greeting", user.name, count = 2)` becomes
+/// `t("greeting", "name", user.name, "count", 2)`.
+pub(crate) fn name_message_arguments(source: &str) -> Cow<'_, str> {
+    if !source.contains('(') {
+        return Cow::Borrowed(source);
+    }
+
+    let mut rewritten = String::with_capacity(source.len());
+    let mut position = 0;
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        let end = match ch {
+            '"' => string_end(source, start),
+            ch if ch.is_alphabetic() || ch == '_' => {
+                let end = identifier_end(source, start);
+                let unnamed = match &source[start..end] {
+                    "t" => 1,
+                    "plural" => 2,
+                    _ => 0,
+                };
+                let open = end + source[end..].len() - source[end..].trim_start().len();
+                let call = unnamed > 0
+                    && !source[..start].trim_end().ends_with('.')
+                    && source[open..].starts_with('(');
+                match call.then(|| call_end(source, open)).flatten() {
+                    Some(close) => {
+                        rewritten.push_str(&source[position..=open]);
+                        let arguments = split_top_level(&source[open + 1..close], ",");
+                        for (index, argument) in arguments.into_iter().enumerate() {
+                            if index > 0 {
+                                rewritten.push(',');
+                            }
+                            let argument = name_message_arguments(argument);
+                            let value = argument.trim();
+                            let leading = &argument[..argument.len() - argument.trim_start().len()];
+                            if index < unnamed {
+                                rewritten.push_str(&argument);
+                            } else if is_path(value) {
+                                let name = value.rsplit('.').next().unwrap_or_default();
+                                rewritten.push_str(&format!("{leading}\"{name}\", {value}"));
+                            } else if let Some((name, value)) = named_argument(value) {
+                                rewritten.push_str(&format!("{leading}\"{name}\", {value}"));
+                            } else {
+                                rewritten.push_str(&argument);
+                            }
+                        }
+                        rewritten.push(')');
+                        position = close + 1;
+                        close + 1
+                    }
+                    None => end,
+                }
+            }
+            ch if ch.is_ascii_digit() => source[start..]
+                .find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '.'))
+                .map_or(source.len(), |len| start + len),
+            _ => continue,
+        };
+        while chars.next_if(|(index, _)| *index < end).is_some() {}
+    }
+
+    if position == 0 {
+        Cow::Borrowed(source)
+    } else {
+        rewritten.push_str(&source[position..]);
+        Cow::Owned(rewritten)
+    }
+}
+
+#[cfg(feature = "i18n")]
+/// A call to `t()` or `plural()` found by `message_calls()`.
+pub(crate) struct MessageCall<'a> {
+    pub plural: bool,
+    /// The message key, if it is a string literal.
+    pub key: Option<Cow<'a, str>>,
+    /// The names of the arguments passed to the message, other than the
+    /// count, in the order they are passed. Arguments that are neither
+    /// variables nor named are skipped.
+    pub arguments: Vec<&'a str>,
+    /// The offset of the call within the source.
+    pub offset: usize,
+}
+
+#[cfg(feature = "i18n")]
+/// Returns the calls to `t()` and `plural()` within `source`, including those
+/// nested within the arguments of another call.
+pub(crate) fn message_calls(source: &str) -> Vec<MessageCall<'_>> {
+    let mut calls = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        let end = match ch {
+            '"' => string_end(source, start),
+            ch if ch.is_alphabetic() || ch == '_' => {
+                let end = identifier_end(source, start);
+                let unnamed = match &source[start..end] {
+                    "t" => 1,
+                    "plural" => 2,
+                    _ => 0,
+                };
+                let open = end + source[end..].len() - source[end..].trim_start().len();
+                let call = unnamed > 0
+                    && !source[..start].trim_end().ends_with('.')
+                    && source[open..].starts_with('(');
+                if let Some(close) = call.then(|| call_end(source, open)).flatten() {
+                    let arguments = split_top_level(&source[open + 1..close], ",");
+                    calls.push(MessageCall {
+                        plural: unnamed == 2,
+                        key: arguments
+                            .first()
+                            .and_then(|key| parse_string_literal(key))
+                            .filter(|(_, rest)| rest.is_empty())
+                            .map(|(key, _)| key),
+                        arguments: arguments
+                            .iter()
+                            .skip(unnamed)
+                            .filter_map(|argument| message_argument_name(argument.trim()))
+                            .collect(),
+                        offset: start,
+                    });
+                    // Continue within the arguments, which may contain
+                    // further calls.
+                    open + 1
+                } else {
+                    end
+                }
+            }
+            ch if ch.is_ascii_digit() => source[start..]
+                .find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '.'))
+                .map_or(source.len(), |len| start + len),
+            _ => continue,
+        };
+        while chars.next_if(|(index, _)| *index < end).is_some() {}
+    }
+    calls
+}
+
+#[cfg(feature = "i18n")]
+/// Returns the name that a message argument is passed to the message as.
+fn message_argument_name(argument: &str) -> Option<&str> {
+    if is_path(argument) {
+        argument.rsplit('.').next()
+    } else {
+        named_argument(argument).map(|(name, _)| name)
+    }
+}
+
+/// Splits an argument written as `name = value` into its name and value.
+fn named_argument(argument: &str) -> Option<(&str, &str)> {
+    let (name, value) = argument.split_once('=')?;
+    (is_identifier(name.trim()) && !value.starts_with('=')).then(|| (name.trim(), value.trim()))
+}
+
+/// Returns the offset of the `)` that closes the `(` at `open`.
+fn call_end(source: &str, open: usize) -> Option<usize> {
+    let mut depth = 0_usize;
+    let mut string_end_at = 0;
+    for (index, ch) in source.char_indices().skip_while(|(index, _)| *index < open) {
+        if index < string_end_at {
+            continue;
+        }
+        match ch {
+            '"' => string_end_at = string_end(source, index),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the checked path and the end of the call if `identifier` begins
+/// a call to `defined()` with a path, such as `defined(user.name)`.
+fn defined_check(source: &str, identifier: std::ops::Range<usize>) -> Option<(&str, usize)> {
+    if &source[identifier.clone()] != "defined" {
+        return None;
+    }
+    let arguments = source[identifier.end..].trim_start().strip_prefix('(')?;
+    let close = arguments.find(')')?;
+    let checked = arguments[..close].trim();
+    is_path(checked).then(|| {
+        let end = source.len() - arguments.len() + close + 1;
+        (checked, end)
+    })
+}
+
+fn identifier_end(source: &str, start: usize) -> usize {
+    source[start..]
+        .find(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+        .map_or(source.len(), |len| start + len)
+}
+
+#[test]
+fn field_access() {
+    let rewrite_field_access = |source| rewrite(source, &|_| true);
+    assert_eq!(rewrite_field_access("name"), "name");
+    assert_eq!(
+        rewrite_field_access("user.name"),
+        r#"__field(user, "name")"#
+    );
+    assert_eq!(
+        rewrite_field_access("post.author.name + \"a.b\""),
+        r#"__field(__field(post, "author"), "name") + "a.b""#
+    );
+    assert_eq!(
+        rewrite_field_access("items.push(user.id)"),
+        r#"items.push(__field(user, "id"))"#
+    );
+    assert_eq!(rewrite_field_access("1.5 * x"), "1.5 * x");
+}
+
+#[test]
+fn variable_references() {
+    let source = r#"user.name + count * f(total) - "user" + 1.5.floor() + defined(user)"#;
+    let variables = variables(source)
+        .into_iter()
+        .map(|range| &source[range])
+        .collect::<Vec<_>>();
+    assert_eq!(variables, ["user", "count", "total", "user"]);
+}
+
+#[test]
+fn function_calls() {
+    let source = r#"f(total) + user.g(1) + 1.5.floor() + "h(x)" + defined(user)"#;
+    let calls = calls(source)
+        .into_iter()
+        .map(|range| &source[range])
+        .collect::<Vec<_>>();
+    assert_eq!(calls, ["f", "defined"]);
+}
+
+#[test]
+fn message_arguments() {
+    assert_eq!(
+        name_message_arguments(r#"t("greeting", user.name, count = f(1, 2))"#),
+        r#"t("greeting", "name", user.name, "count", f(1, 2))"#
+    );
+    assert_eq!(
+        name_message_arguments(r#"plural("items", total, list.t(x)) + t("a", t("b", x))"#),
+        r#"plural("items", total, list.t(x)) + t("a", t("b", "x", x))"#
+    );
+    assert_eq!(
+        name_message_arguments(r#"format("t(x)", x == 1)"#),
+        r#"format("t(x)", x == 1)"#
+    );
+}
+
+#[test]
+#[cfg(feature = "i18n")]
+fn message_call_scanning() {
+    let source = r#"t("a", user.name, t("b\"", x = 1)) + plural(key, n, total) + "t(x)""#;
+    let calls = message_calls(source);
+    assert_eq!(calls.len(), 3);
+    assert_eq!(calls[0].key.as_deref(), Some("a"));
+    assert_eq!(calls[0].arguments, ["name"]);
+    assert_eq!(calls[1].key.as_deref(), Some("b\""));
+    assert_eq!(calls[1].arguments, ["x"]);
+    assert!(calls[2].plural && calls[2].key.is_none());
+    assert_eq!(calls[2].arguments, ["total"]);
+    assert_eq!(&source[calls[2].offset..calls[2].offset + 6], "plural");
+}
+
+#[test]
+fn fallbacks() {
+    let declared = |name: &str| name == "user";
+    assert_eq!(
+        rewrite_fallbacks(r#"user.nickname ?? user.name ?? "??""#, &declared),
+        r#"__default(__try_field(user, "nickname"), __default(__try_field(user, "name"), "??"))"#
+    );
+    assert_eq!(
+        rewrite_fallbacks("title ?? f(1) ?? 2", &declared),
+        "__default(__missing(), __default(f(1), 2))"
+    );
+    assert_eq!(rewrite_fallbacks("user", &declared), "user");
+    assert_eq!(
+        rewrite("if defined(user.name) and not defined ( other )", &declared),
+        r#"if defined(__try_field(user, "name")) and not false"#
+    );
+    assert_eq!(rewrite("undefined(x)", &declared), "undefined(x)");
+}
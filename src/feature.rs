@@ -0,0 +1,30 @@
+use std::collections::HashSet;
+
+use crate::ContextProvider;
+
+/// Decides whether the `{{ feature "name" }}` regions of templates are
+/// rendered, as registered using
+/// [`Configuration::with_features`](crate::Configuration::with_features).
+///
+/// Features are checked each time a region renders, with the
+/// [`ContextProvider`] of the render, if any, so that an experiment can be
+/// enabled for some requests and not others.
+pub trait FeatureProvider: Send + Sync + 'static {
+    fn is_enabled(&self, feature: &str, context: Option<&dyn ContextProvider>) -> bool;
+}
+
+impl<F> FeatureProvider for F
+where
+    F: Fn(&str, Option<&dyn ContextProvider>) -> bool + Send + Sync + 'static,
+{
+    fn is_enabled(&self, feature: &str, context: Option<&dyn ContextProvider>) -> bool {
+        self(feature, context)
+    }
+}
+
+/// Enables the features in the set for every render.
+impl FeatureProvider for HashSet<String> {
+    fn is_enabled(&self, feature: &str, _context: Option<&dyn ContextProvider>) -> bool {
+        self.contains(feature)
+    }
+}
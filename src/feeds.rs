@@ -0,0 +1,335 @@
+//! Sitemap, RSS, and Atom generation from lists of page records.
+//!
+//! Each record is a map. Timestamps are integers counting the seconds since
+//! the Unix epoch, and are formatted as RFC 822 dates in RSS feeds and as RFC
+//! 3339 dates in sitemaps and Atom feeds.
+
+use std::fmt::Write;
+
+use budlang::vm::Value;
+
+use crate::{
+    value::{self, Shape},
+    Configuration, Encoder, PrettyPrinter,
+};
+
+/// Escapes text for use within XML elements and attributes.
+#[derive(Debug, Clone)]
+pub struct XmlEncoding;
+
+impl Encoder for XmlEncoding {
+    fn encode<W: Write>(&self, input: &str, output: &mut W) -> std::fmt::Result {
+        for ch in input.chars() {
+            match ch {
+                '&' => output.write_str("&amp;"),
+                '<' => output.write_str("&lt;"),
+                '>' => output.write_str("&gt;"),
+                '"' => output.write_str("&quot;"),
+                '\'' => output.write_str("&apos;"),
+                ch => output.write_char(ch),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+impl Configuration<XmlEncoding> {
+    pub fn for_xml() -> Self {
+        Configuration::default().with_encoder(XmlEncoding)
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+struct DateTime {
+    year: i64,
+    month: usize,
+    day: i64,
+    weekday: usize,
+    hour: i64,
+    minute: i64,
+    second: i64,
+}
+
+impl DateTime {
+    fn from_timestamp(timestamp: i64) -> Self {
+        let days = timestamp.div_euclid(86_400);
+        let seconds = timestamp.rem_euclid(86_400);
+
+        // Converts days since the epoch into a proleptic Gregorian date,
+        // using eras of 400 years that begin on March 1st.
+        let shifted = days + 719_468;
+        let era = shifted.div_euclid(146_097);
+        let day_of_era = shifted.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month: usize::try_from(month).expect("month is within 1..=12"),
+            day,
+            weekday: usize::try_from(days.rem_euclid(7)).expect("weekday is within 0..7"),
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+        }
+    }
+}
+
+/// Formats `timestamp` as an RFC 822 date, as used by RSS.
+pub(crate) fn rfc822(timestamp: i64) -> String {
+    let date = DateTime::from_timestamp(timestamp);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[date.weekday],
+        date.day,
+        MONTHS[date.month - 1],
+        date.year,
+        date.hour,
+        date.minute,
+        date.second
+    )
+}
+
+/// Formats `timestamp` as an RFC 3339 date in UTC.
+pub(crate) fn rfc3339(timestamp: i64) -> String {
+    let date = DateTime::from_timestamp(timestamp);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+/// A page record's fields, keyed by name.
+struct Record(Vec<(String, Value)>);
+
+impl Record {
+    fn from_value(value: &Value, what: &str) -> Result<Self, String> {
+        match Shape::of(value) {
+            Shape::Map(entries) => Ok(Self(value::sorted_entries(entries))),
+            _ => Err(format!("each {what} must be a map")),
+        }
+    }
+
+    fn list(value: &Value, what: &str) -> Result<Vec<Self>, String> {
+        match Shape::of(value) {
+            Shape::List(values) => values
+                .iter()
+                .map(|value| Self::from_value(value, what))
+                .collect(),
+            _ => Err(format!("{what}s must be a list")),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.0
+            .iter()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, value)| value)
+            .filter(|value| !matches!(value, Value::Void))
+    }
+
+    fn text(&self, key: &str) -> Option<String> {
+        self.get(key).map(|value| match Shape::of(value) {
+            Shape::String(text) => text,
+            _ => PrettyPrinter::new().print(value),
+        })
+    }
+
+    fn required_text(&self, key: &str, what: &str) -> Result<String, String> {
+        self.text(key)
+            .ok_or_else(|| format!("each {what} must have a `{key}`"))
+    }
+
+    fn timestamp(&self, key: &str) -> Result<Option<i64>, String> {
+        match self.get(key) {
+            Some(Value::Integer(timestamp)) => Ok(Some(*timestamp)),
+            Some(_) => Err(format!("`{key}` must be a timestamp in seconds")),
+            None => Ok(None),
+        }
+    }
+}
+
+fn element(output: &mut String, indent: usize, name: &str, text: &str) {
+    write!(output, "{:indent$}<{name}>", "").expect("String does not fail to write");
+    XmlEncoding.encode_to_string(text, output);
+    writeln!(output, "</{name}>").expect("String does not fail to write");
+}
+
+/// Renders a sitemap from records with a `url` and an optional `updated`
+/// timestamp, `change_frequency`, and `priority`.
+pub(crate) fn sitemap(pages: &Value) -> Result<String, String> {
+    let mut output = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for page in Record::list(pages, "page")? {
+        output.push_str("  <url>\n");
+        element(&mut output, 4, "loc", &page.required_text("url", "page")?);
+        if let Some(updated) = page.timestamp("updated")? {
+            element(&mut output, 4, "lastmod", &rfc3339(updated));
+        }
+        if let Some(frequency) = page.text("change_frequency") {
+            element(&mut output, 4, "changefreq", &frequency);
+        }
+        if let Some(priority) = page.text("priority") {
+            element(&mut output, 4, "priority", &priority);
+        }
+        output.push_str("  </url>\n");
+    }
+    output.push_str("</urlset>");
+    Ok(output)
+}
+
+/// Renders an RSS 2.0 feed. `channel` requires a `title`, `link`, and
+/// `description`. Each item requires a `title` and `link`, and may have a
+/// `description`, `published` timestamp, and `guid`.
+pub(crate) fn rss(channel: &Value, items: &Value) -> Result<String, String> {
+    let channel = Record::from_value(channel, "channel")?;
+    let mut output = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n  <channel>\n",
+    );
+    for key in ["title", "link", "description"] {
+        element(&mut output, 4, key, &channel.required_text(key, "channel")?);
+    }
+    for item in Record::list(items, "item")? {
+        output.push_str("    <item>\n");
+        let link = item.required_text("link", "item")?;
+        element(
+            &mut output,
+            6,
+            "title",
+            &item.required_text("title", "item")?,
+        );
+        element(&mut output, 6, "link", &link);
+        if let Some(description) = item.text("description") {
+            element(&mut output, 6, "description", &description);
+        }
+        if let Some(published) = item.timestamp("published")? {
+            element(&mut output, 6, "pubDate", &rfc822(published));
+        }
+        element(&mut output, 6, "guid", &item.text("guid").unwrap_or(link));
+        output.push_str("    </item>\n");
+    }
+    output.push_str("  </channel>\n</rss>");
+    Ok(output)
+}
+
+/// Renders an Atom feed. `feed` requires a `title` and `link`, and may have
+/// an `id` and `updated` timestamp. Each entry requires a `title`, `link`,
+/// and `updated` timestamp, and may have an `id` and `summary`. Ids default
+/// to the record's link, and the feed's `updated` timestamp defaults to the
+/// most recently updated entry.
+pub(crate) fn atom(feed: &Value, entries: &Value) -> Result<String, String> {
+    let feed = Record::from_value(feed, "feed")?;
+    let entries = Record::list(entries, "entry")?;
+    let link = feed.required_text("link", "feed")?;
+    let updated = match feed.timestamp("updated")? {
+        Some(updated) => updated,
+        None => entries
+            .iter()
+            .map(|entry| entry.timestamp("updated"))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .max()
+            .ok_or("the feed must have an `updated` timestamp")?,
+    };
+
+    let mut output = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    element(
+        &mut output,
+        2,
+        "title",
+        &feed.required_text("title", "feed")?,
+    );
+    element(
+        &mut output,
+        2,
+        "id",
+        &feed.text("id").unwrap_or_else(|| link.clone()),
+    );
+    output.push_str("  <link href=\"");
+    XmlEncoding.encode_to_string(&link, &mut output);
+    output.push_str("\"/>\n");
+    element(&mut output, 2, "updated", &rfc3339(updated));
+    for entry in entries {
+        output.push_str("  <entry>\n");
+        let link = entry.required_text("link", "entry")?;
+        let updated = entry
+            .timestamp("updated")?
+            .ok_or("each entry must have an `updated` timestamp")?;
+        element(
+            &mut output,
+            4,
+            "title",
+            &entry.required_text("title", "entry")?,
+        );
+        element(
+            &mut output,
+            4,
+            "id",
+            &entry.text("id").unwrap_or_else(|| link.clone()),
+        );
+        output.push_str("    <link href=\"");
+        XmlEncoding.encode_to_string(&link, &mut output);
+        output.push_str("\"/>\n");
+        element(&mut output, 4, "updated", &rfc3339(updated));
+        if let Some(summary) = entry.text("summary") {
+            element(&mut output, 4, "summary", &summary);
+        }
+        output.push_str("  </entry>\n");
+    }
+    output.push_str("</feed>");
+    Ok(output)
+}
+
+#[test]
+fn feed_dates() {
+    assert_eq!(rfc822(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+    assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+    assert_eq!(rfc822(1_055_217_600), "Tue, 10 Jun 2003 04:00:00 GMT");
+    assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+    assert_eq!(rfc3339(-1), "1969-12-31T23:59:59Z");
+}
+
+#[test]
+fn sitemap_escapes_urls() {
+    let page = [
+        (
+            Value::from("url"),
+            Value::from("https://example.com/?a=1&b=2"),
+        ),
+        (Value::from("updated"), Value::Integer(0)),
+    ]
+    .into_iter()
+    .collect::<budlang::vm::HashMap>();
+    let pages = [Value::dynamic(page)]
+        .into_iter()
+        .collect::<budlang::vm::List>();
+    assert_eq!(
+        sitemap(&Value::dynamic(pages)).unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n  \
+         <url>\n    \
+         <loc>https://example.com/?a=1&amp;b=2</loc>\n    \
+         <lastmod>1970-01-01T00:00:00Z</lastmod>\n  \
+         </url>\n\
+         </urlset>"
+    );
+}
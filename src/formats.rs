@@ -0,0 +1,431 @@
+use std::fmt::Write;
+
+use budlang::vm::Value;
+
+use crate::{
+    value::{self, Shape},
+    Error, ErrorKind,
+};
+
+/// Serializes `value` as a YAML document fragment. Lines after the first are
+/// indented by `indent` spaces, allowing the result to be placed at the
+/// current nesting level of a YAML template.
+pub(crate) fn yaml(value: &Value, indent: usize) -> Result<String, Error> {
+    match yaml_node(value)? {
+        YamlNode::Inline(scalar) => Ok(scalar),
+        YamlNode::Block(lines) => {
+            let separator = format!("\n{:indent$}", "");
+            Ok(lines.join(&separator))
+        }
+    }
+}
+
+enum YamlNode {
+    Inline(String),
+    Block(Vec<String>),
+}
+
+fn yaml_node(value: &Value) -> Result<YamlNode, Error> {
+    match Shape::of(value) {
+        Shape::Integer(value) => Ok(YamlNode::Inline(value.to_string())),
+        Shape::Real(value) => Ok(YamlNode::Inline(if value.is_nan() {
+            String::from(".nan")
+        } else if value.is_infinite() {
+            String::from(if value.is_sign_positive() {
+                ".inf"
+            } else {
+                "-.inf"
+            })
+        } else {
+            format!("{value:?}")
+        })),
+        Shape::Boolean(value) => Ok(YamlNode::Inline(value.to_string())),
+        Shape::Void => Ok(YamlNode::Inline(String::from("null"))),
+        Shape::String(value) => Ok(YamlNode::Inline(yaml_string(&value))),
+        Shape::List(values) if values.is_empty() => Ok(YamlNode::Inline(String::from("[]"))),
+        Shape::List(values) => {
+            let mut lines = Vec::new();
+            for value in &values {
+                match yaml_node(value)? {
+                    YamlNode::Inline(scalar) => lines.push(format!("- {scalar}")),
+                    YamlNode::Block(nested) => {
+                        for (index, line) in nested.into_iter().enumerate() {
+                            let prefix = if index == 0 { "- " } else { "  " };
+                            lines.push(format!("{prefix}{line}"));
+                        }
+                    }
+                }
+            }
+            Ok(YamlNode::Block(lines))
+        }
+        Shape::Map(entries) if entries.is_empty() => Ok(YamlNode::Inline(String::from("{}"))),
+        Shape::Map(entries) => {
+            let mut lines = Vec::new();
+            for (key, value) in value::sorted_entries(entries) {
+                let key = yaml_string(&key);
+                match yaml_node(&value)? {
+                    YamlNode::Inline(scalar) => lines.push(format!("{key}: {scalar}")),
+                    YamlNode::Block(nested) => {
+                        lines.push(format!("{key}:"));
+                        lines.extend(nested.into_iter().map(|line| format!("  {line}")));
+                    }
+                }
+            }
+            Ok(YamlNode::Block(lines))
+        }
+        Shape::Other => {
+            fallback_string("yaml", value).map(|value| YamlNode::Inline(yaml_string(&value)))
+        }
+    }
+}
+
+/// Serializes `value` as JSON. When `pretty` is true, nested values are
+/// placed on their own lines and indented by two spaces.
+pub(crate) fn json(value: &Value, pretty: bool) -> Result<String, Error> {
+    let mut output = String::new();
+    write_json(value, pretty.then_some(0), &mut output)?;
+    Ok(output)
+}
+
+fn write_json(value: &Value, indent: Option<usize>, output: &mut String) -> Result<(), Error> {
+    match Shape::of(value) {
+        Shape::Integer(value) => write!(output, "{value}").expect("String does not fail to write"),
+        Shape::Real(value) if value.is_finite() => {
+            write!(output, "{value:?}").expect("String does not fail to write");
+        }
+        Shape::Real(value) => {
+            return Err(Error::from(ErrorKind::Serialization {
+                format: "json",
+                message: format!("{value} cannot be represented in JSON"),
+            }))
+        }
+        Shape::Boolean(value) => write!(output, "{value}").expect("String does not fail to write"),
+        Shape::Void => output.push_str("null"),
+        Shape::String(value) => write_json_string(&value, output),
+        Shape::List(values) => {
+            write_json_collection(output, indent, '[', ']', values, |value, indent, output| {
+                write_json(&value, indent, output)
+            })?;
+        }
+        Shape::Map(entries) => {
+            write_json_collection(
+                output,
+                indent,
+                '{',
+                '}',
+                value::sorted_entries(entries),
+                |(key, value), indent, output| {
+                    write_json_string(&key, output);
+                    output.push(':');
+                    if indent.is_some() {
+                        output.push(' ');
+                    }
+                    write_json(&value, indent, output)
+                },
+            )?;
+        }
+        Shape::Other => write_json_string(&fallback_string("json", value)?, output),
+    }
+    Ok(())
+}
+
+fn write_json_collection<T>(
+    output: &mut String,
+    indent: Option<usize>,
+    open: char,
+    close: char,
+    items: Vec<T>,
+    mut write_item: impl FnMut(T, Option<usize>, &mut String) -> Result<(), Error>,
+) -> Result<(), Error> {
+    output.push(open);
+    let is_empty = items.is_empty();
+    let nested_indent = indent.map(|indent| indent + 2);
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 {
+            output.push(',');
+        }
+        if let Some(nested_indent) = nested_indent {
+            write!(output, "\n{:nested_indent$}", "").expect("String does not fail to write");
+        }
+        write_item(item, nested_indent, output)?;
+    }
+    if let (Some(indent), false) = (indent, is_empty) {
+        write!(output, "\n{:indent$}", "").expect("String does not fail to write");
+    }
+    output.push(close);
+    Ok(())
+}
+
+/// Serializes a map `value` as a TOML document. Nested maps become tables,
+/// and lists of maps become arrays of tables.
+pub(crate) fn toml(value: &Value) -> Result<String, Error> {
+    match Shape::of(value) {
+        Shape::Map(entries) => {
+            let mut output = String::new();
+            write_toml_table(&mut Vec::new(), value::sorted_entries(entries), &mut output)?;
+            Ok(output)
+        }
+        _ => Err(toml_error(format!(
+            "a TOML document must be a map, found {}",
+            crate::PrettyPrinter::new().print(value)
+        ))),
+    }
+}
+
+fn toml_error(message: String) -> Error {
+    Error::from(ErrorKind::Serialization {
+        format: "toml",
+        message,
+    })
+}
+
+fn write_toml_table(
+    path: &mut Vec<String>,
+    entries: Vec<(String, Value)>,
+    output: &mut String,
+) -> Result<(), Error> {
+    let mut tables = Vec::new();
+    let mut arrays_of_tables = Vec::new();
+    for (key, value) in entries {
+        match Shape::of(&value) {
+            Shape::Map(entries) => tables.push((key, value::sorted_entries(entries))),
+            Shape::List(values)
+                if !values.is_empty()
+                    && values
+                        .iter()
+                        .all(|value| matches!(Shape::of(value), Shape::Map(_))) =>
+            {
+                arrays_of_tables.push((key, values));
+            }
+            _ => {
+                write_toml_key(&key, output);
+                output.push_str(" = ");
+                write_toml_inline(&value, output)?;
+                output.push('\n');
+            }
+        }
+    }
+
+    for (key, entries) in tables {
+        path.push(key);
+        write_toml_header(path, false, output);
+        write_toml_table(path, entries, output)?;
+        path.pop();
+    }
+
+    for (key, values) in arrays_of_tables {
+        path.push(key);
+        for value in values {
+            if let Shape::Map(entries) = Shape::of(&value) {
+                write_toml_header(path, true, output);
+                write_toml_table(path, value::sorted_entries(entries), output)?;
+            }
+        }
+        path.pop();
+    }
+
+    Ok(())
+}
+
+fn write_toml_header(path: &[String], is_array: bool, output: &mut String) {
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    output.push_str(if is_array { "[[" } else { "[" });
+    for (index, key) in path.iter().enumerate() {
+        if index > 0 {
+            output.push('.');
+        }
+        write_toml_key(key, output);
+    }
+    output.push_str(if is_array { "]]\n" } else { "]\n" });
+}
+
+fn write_toml_key(key: &str, output: &mut String) {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-')
+    {
+        output.push_str(key);
+    } else {
+        write_json_string(key, output);
+    }
+}
+
+fn write_toml_inline(value: &Value, output: &mut String) -> Result<(), Error> {
+    match Shape::of(value) {
+        Shape::Integer(value) => write!(output, "{value}").expect("String does not fail to write"),
+        Shape::Real(value) if value.is_nan() => output.push_str("nan"),
+        Shape::Real(value) if value.is_infinite() => {
+            output.push_str(if value.is_sign_positive() {
+                "inf"
+            } else {
+                "-inf"
+            });
+        }
+        Shape::Real(value) => write!(output, "{value:?}").expect("String does not fail to write"),
+        Shape::Boolean(value) => write!(output, "{value}").expect("String does not fail to write"),
+        Shape::Void => {
+            return Err(toml_error(String::from(
+                "TOML cannot represent void values",
+            )))
+        }
+        Shape::String(value) => write_json_string(&value, output),
+        Shape::List(values) => {
+            output.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    output.push_str(", ");
+                }
+                write_toml_inline(value, output)?;
+            }
+            output.push(']');
+        }
+        Shape::Map(entries) => {
+            output.push('{');
+            for (index, (key, value)) in value::sorted_entries(entries).into_iter().enumerate() {
+                if index > 0 {
+                    output.push_str(", ");
+                }
+                write_toml_key(&key, output);
+                output.push_str(" = ");
+                write_toml_inline(&value, output)?;
+            }
+            output.push('}');
+        }
+        Shape::Other => write_json_string(&fallback_string("toml", value)?, output),
+    }
+    Ok(())
+}
+
+fn yaml_string(value: &str) -> String {
+    let lowercase = value.to_ascii_lowercase();
+    let needs_quotes = value.is_empty()
+        || value.trim() != value
+        || matches!(
+            lowercase.as_str(),
+            "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n" | "null" | "~"
+        )
+        || value
+            .starts_with(|ch: char| ch.is_ascii_digit() || "-+.?:,[]{}#&*!|>'\"%@`".contains(ch))
+        || value.contains(": ")
+        || value.contains(" #")
+        || value.ends_with(':')
+        || value.chars().any(char::is_control);
+    if needs_quotes {
+        let mut quoted = String::with_capacity(value.len() + 2);
+        write_json_string(value, &mut quoted);
+        quoted
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes `value` as a double-quoted string using JSON escaping rules, which
+/// are also valid for YAML and TOML basic strings.
+pub(crate) fn write_json_string(value: &str, output: &mut String) {
+    output.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            ch if ch.is_control() => {
+                write!(output, "\\u{:04x}", u32::from(ch)).expect("String does not fail to write");
+            }
+            ch => output.push(ch),
+        }
+    }
+    output.push('"');
+}
+
+fn fallback_string(format: &'static str, value: &Value) -> Result<String, Error> {
+    value
+        .try_convert_to_string(&())
+        .map(|value| value.to_string())
+        .map_err(|_| {
+            Error::from(ErrorKind::Serialization {
+                format,
+                message: format!(
+                    "{} cannot be serialized",
+                    crate::PrettyPrinter::new().print(value)
+                ),
+            })
+        })
+}
+
+#[test]
+fn yaml_serialization() {
+    use budlang::vm::{HashMap, List};
+
+    let ports = [Value::Integer(80), Value::Integer(443)]
+        .into_iter()
+        .collect::<List>();
+    let container = [
+        ("name", Value::from("web")),
+        ("ports", Value::dynamic(ports)),
+        ("command", Value::from("serve: all")),
+    ]
+    .into_iter()
+    .map(|(key, value)| (Value::from(key), value))
+    .collect::<HashMap>();
+    let containers = Value::dynamic([Value::dynamic(container)].into_iter().collect::<List>());
+    assert_eq!(
+        yaml(&containers, 4).unwrap(),
+        "- command: \"serve: all\"\n      name: web\n      ports:\n        - 80\n        - 443"
+    );
+    assert_eq!(yaml(&Value::from("yes"), 0).unwrap(), "\"yes\"");
+    assert_eq!(yaml(&Value::dynamic(List::default()), 2).unwrap(), "[]");
+}
+
+#[test]
+fn json_and_toml_serialization() {
+    use budlang::vm::{HashMap, List};
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::dynamic(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Value::from(key), value))
+                .collect::<HashMap>(),
+        )
+    }
+
+    let tags = Value::dynamic(
+        [Value::from("a"), Value::from("b\"")]
+            .into_iter()
+            .collect::<List>(),
+    );
+    let server = map(vec![
+        ("port", Value::Integer(8080)),
+        ("tls", Value::Boolean(true)),
+    ]);
+    let mount = map(vec![("path", Value::from("/data"))]);
+    let config = map(vec![
+        ("name", Value::from("web")),
+        ("tags", tags),
+        ("server", server),
+        (
+            "mounts",
+            Value::dynamic([mount].into_iter().collect::<List>()),
+        ),
+    ]);
+
+    assert_eq!(
+        json(&config, false).unwrap(),
+        r#"{"mounts":[{"path":"/data"}],"name":"web","server":{"port":8080,"tls":true},"tags":["a","b\""]}"#
+    );
+    assert_eq!(json(&Value::dynamic(List::default()), true).unwrap(), "[]");
+    assert_eq!(
+        json(&map(vec![("a", Value::Real(1.5))]), true).unwrap(),
+        "{\n  \"a\": 1.5\n}"
+    );
+    assert_eq!(
+        toml(&config).unwrap(),
+        "name = \"web\"\ntags = [\"a\", \"b\\\"\"]\n\n[server]\nport = 8080\ntls = true\n\n[[mounts]]\npath = \"/data\"\n"
+    );
+    assert!(toml(&Value::Integer(1)).is_err());
+}
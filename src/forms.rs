@@ -0,0 +1,179 @@
+//! Form field helpers that re-populate submitted values and render
+//! validation errors accessibly.
+
+use budlang::vm::Value;
+
+use crate::{
+    value::{self, Shape},
+    Encoder, HtmlEncoding, PrettyPrinter,
+};
+
+fn text(value: &Value) -> String {
+    match Shape::of(value) {
+        Shape::String(text) => text,
+        Shape::Void => String::new(),
+        Shape::Integer(value) => value.to_string(),
+        Shape::Real(value) => value.to_string(),
+        Shape::Boolean(value) => value.to_string(),
+        _ => PrettyPrinter::new().print(value),
+    }
+}
+
+/// Returns the error messages in `errors`, which may be `void`, a single
+/// message, or a list of messages.
+pub(crate) fn messages(errors: Option<&Value>) -> Vec<String> {
+    match errors.map(Shape::of) {
+        None | Some(Shape::Void) => Vec::new(),
+        Some(Shape::List(errors)) => errors.iter().map(text).collect(),
+        Some(_) => errors.map(text).into_iter().collect(),
+    }
+}
+
+fn attribute(output: &mut String, name: &str, value: &str) {
+    output.push(' ');
+    output.push_str(name);
+    output.push_str("=\"");
+    HtmlEncoding.encode_to_string(value, output);
+    output.push('"');
+}
+
+fn field_attributes(output: &mut String, name: &str, errors: &[String]) {
+    attribute(output, "id", name);
+    attribute(output, "name", name);
+    if !errors.is_empty() {
+        output.push_str(" aria-invalid=\"true\"");
+        attribute(output, "aria-describedby", &format!("{name}-errors"));
+    }
+}
+
+fn error_list(output: &mut String, name: &str, errors: &[String]) {
+    if errors.is_empty() {
+        return;
+    }
+    output.push_str("<ul");
+    attribute(output, "id", &format!("{name}-errors"));
+    output.push_str(" class=\"errors\">");
+    for error in errors {
+        output.push_str("<li>");
+        HtmlEncoding.encode_to_string(error, output);
+        output.push_str("</li>");
+    }
+    output.push_str("</ul>");
+}
+
+pub(crate) fn input(name: &str, kind: &str, value: Option<&Value>, errors: &[String]) -> String {
+    let mut output = String::from("<input");
+    attribute(&mut output, "type", kind);
+    field_attributes(&mut output, name, errors);
+    // Passwords are never re-populated.
+    if let Some(value) = value.filter(|_| kind != "password") {
+        let value = text(value);
+        if !value.is_empty() {
+            attribute(&mut output, "value", &value);
+        }
+    }
+    output.push('>');
+    error_list(&mut output, name, errors);
+    output
+}
+
+pub(crate) fn textarea(name: &str, value: Option<&Value>, errors: &[String]) -> String {
+    let mut output = String::from("<textarea");
+    field_attributes(&mut output, name, errors);
+    output.push('>');
+    if let Some(value) = value {
+        HtmlEncoding.encode_to_string(&text(value), &mut output);
+    }
+    output.push_str("</textarea>");
+    error_list(&mut output, name, errors);
+    output
+}
+
+/// Renders a `<select>` element. `options` is either a list, whose entries
+/// are values or `[value, label]` pairs, or a map from values to labels.
+/// `selected` may be a single value or a list of values.
+pub(crate) fn select(
+    name: &str,
+    options: &Value,
+    selected: Option<&Value>,
+    errors: &[String],
+) -> Result<String, String> {
+    let options = match Shape::of(options) {
+        Shape::List(options) => options
+            .iter()
+            .map(|option| match Shape::of(option) {
+                Shape::List(pair) => match pair.as_slice() {
+                    [value, label] => Ok((text(value), text(label))),
+                    _ => Err(String::from(
+                        "option pairs must contain a value and a label",
+                    )),
+                },
+                _ => {
+                    let value = text(option);
+                    Ok((value.clone(), value))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        Shape::Map(entries) => value::sorted_entries(entries)
+            .into_iter()
+            .map(|(value, label)| (value, text(&label)))
+            .collect(),
+        _ => return Err(String::from("`options` must be a list or a map")),
+    };
+    let selected = match selected.map(Shape::of) {
+        None | Some(Shape::Void) => Vec::new(),
+        Some(Shape::List(values)) => values.iter().map(text).collect(),
+        Some(_) => selected.map(text).into_iter().collect(),
+    };
+
+    let mut output = String::from("<select");
+    field_attributes(&mut output, name, errors);
+    if selected.len() > 1 {
+        output.push_str(" multiple");
+    }
+    output.push('>');
+    for (value, label) in options {
+        output.push_str("<option");
+        attribute(&mut output, "value", &value);
+        if selected.contains(&value) {
+            output.push_str(" selected");
+        }
+        output.push('>');
+        HtmlEncoding.encode_to_string(&label, &mut output);
+        output.push_str("</option>");
+    }
+    output.push_str("</select>");
+    error_list(&mut output, name, errors);
+    Ok(output)
+}
+
+#[test]
+fn form_fields() {
+    let errors = vec![String::from("must contain an @")];
+    assert_eq!(
+        input("email", "email", Some(&Value::from("a\"b")), &errors),
+        "<input type=\"email\" id=\"email\" name=\"email\" aria-invalid=\"true\" \
+         aria-describedby=\"email-errors\" value=\"a&quot;b\">\
+         <ul id=\"email-errors\" class=\"errors\"><li>must contain an @</li></ul>"
+    );
+    assert_eq!(
+        input("password", "password", Some(&Value::from("hunter2")), &[]),
+        "<input type=\"password\" id=\"password\" name=\"password\">"
+    );
+    assert_eq!(
+        textarea("bio", Some(&Value::from("<b>hi</b>")), &[]),
+        "<textarea id=\"bio\" name=\"bio\">&lt;b&gt;hi&lt;/b&gt;</textarea>"
+    );
+
+    let options = Value::dynamic(
+        ["red", "blue"]
+            .into_iter()
+            .map(Value::from)
+            .collect::<budlang::vm::List>(),
+    );
+    assert_eq!(
+        select("color", &options, Some(&Value::from("blue")), &[]).unwrap(),
+        "<select id=\"color\" name=\"color\"><option value=\"red\">red</option>\
+         <option value=\"blue\" selected>blue</option></select>"
+    );
+}
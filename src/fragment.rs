@@ -0,0 +1,11 @@
+/// A piece of a template's output, produced by
+/// [`CompiledTemplate::render_fragments`](crate::CompiledTemplate::render_fragments).
+///
+/// Each `{{ fragment "name" }}...{{ end fragment }}` region produces a
+/// fragment with its name, even if it is empty. The output between regions
+/// produces unnamed fragments, skipping any that would be empty.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Fragment<'a> {
+    pub name: Option<&'a str>,
+    pub content: &'a str,
+}
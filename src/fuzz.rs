@@ -0,0 +1,360 @@
+//! Generators of templates and arguments for fuzzing, along with the
+//! invariants every template should uphold.
+//!
+//! [`Template`] implements [`Arbitrary`], generating templates that are
+//! usually well formed, and otherwise made from fragments of template syntax
+//! that exercise the parser's edge cases, such as unbalanced tags.
+//! [`ValidTemplate`] only generates templates that parse, and
+//! [`ArbitraryArguments`] generates arguments for their parameters.
+//! [`check_invariants()`] panics when a template violates an invariant, which
+//! fuzzers report like any other panic.
+//!
+//! A `cargo fuzz` target for a pipeline built on budplate can be as short as:
+//!
+//! ```rust
+//! use budplate::{
+//!     fuzz::{self, arbitrary::{Arbitrary, Unstructured}, ArbitraryArguments},
+//!     Configuration, Template,
+//! };
+//!
+//! fn fuzz_target(data: &[u8]) {
+//!     let mut input = Unstructured::new(data);
+//!     let (Ok(template), Ok(arguments)) = (
+//!         Template::arbitrary(&mut input),
+//!         ArbitraryArguments::arbitrary(&mut input),
+//!     ) else {
+//!         return;
+//!     };
+//!     let configuration = Configuration::for_html()
+//!         .with_budget(10_000)
+//!         .with_max_output_len(4096);
+//!     fuzz::check_invariants(&configuration, &template, &arguments);
+//! }
+//!
+//! fuzz_target(b"an arbitrary input");
+//! ```
+//!
+//! With the `proptest` feature, [`templates()`], [`valid_templates()`], and
+//! [`arguments()`] provide the same generators as proptest strategies.
+
+pub use arbitrary;
+use arbitrary::{Arbitrary, Unstructured};
+use budlang::vm::Value;
+
+use crate::{vm, Configuration, Encoder, Template};
+
+/// The parameters that the expressions of a [`ValidTemplate`] read, and that
+/// [`ArbitraryArguments`] provide values for.
+pub const PARAMETERS: [&str; 4] = ["a", "b", "items", "user"];
+
+/// The fragments that templates which aren't well formed are assembled from.
+const FRAGMENTS: &[&str] = &[
+    "{{",
+    "}}",
+    "{{=",
+    "{{:=",
+    "{{-",
+    "-}}",
+    "{{#",
+    "#}}",
+    "{{ raw }}",
+    "{{ end raw }}",
+    " end ",
+    "if ",
+    "else",
+    "for ",
+    " in ",
+    "define ",
+    "block ",
+    "component ",
+    "extends ",
+    "include ",
+    "render ",
+    "fragment ",
+    "with ",
+    "\"page\"",
+    "\"",
+    "\\",
+    "a",
+    "user.name",
+    "(",
+    ")",
+    ",",
+    "=",
+    ":=",
+    "??",
+    "1",
+    "-1.5",
+    " ",
+    "\n",
+    "é",
+    "😀",
+    "<p>",
+];
+
+const TEXT: &[&str] = &["<p>", "</p>", "Hello, ", " ", "\n", "&amp;", "é", "😀"];
+
+impl<'a> Arbitrary<'a> for Template<'static> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        if u.ratio(3, 4)? {
+            return ValidTemplate::arbitrary(u).map(|valid| valid.template);
+        }
+        let mut source = String::new();
+        for _ in 0..u.int_in_range(0..=32)? {
+            source.push_str(u.choose(FRAGMENTS)?);
+        }
+        Ok(Template::from(source))
+    }
+}
+
+/// A template that parses successfully, made of text, comments, `raw`
+/// blocks, expressions, and nested `if` and `for` statements. Its
+/// expressions only read the [`PARAMETERS`] and the variables of the loops
+/// containing them, although rendering may still fail, such as when a
+/// parameter isn't provided.
+#[derive(Debug, Clone)]
+pub struct ValidTemplate {
+    pub template: Template<'static>,
+}
+
+impl<'a> Arbitrary<'a> for ValidTemplate {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut source = String::new();
+        let mut scope = PARAMETERS.iter().map(|name| name.to_string()).collect();
+        nodes(u, 3, &mut scope, &mut source)?;
+        Ok(Self {
+            template: Template::from(source),
+        })
+    }
+}
+
+/// Writes a sequence of template nodes, nesting statements at most `depth`
+/// levels deep.
+fn nodes(
+    u: &mut Unstructured<'_>,
+    depth: usize,
+    scope: &mut Vec<String>,
+    source: &mut String,
+) -> arbitrary::Result<()> {
+    for _ in 0..u.int_in_range(0..=6)? {
+        match u.int_in_range(0..=if depth > 0 { 6 } else { 3 })? {
+            0 => source.push_str(u.choose(TEXT)?),
+            1 => {
+                let expression = expression(u, scope)?;
+                tag(u, source, &format!("= {expression} "))?;
+            }
+            2 => source.push_str("{{# a comment #}}"),
+            3 => {
+                source.push_str("{{ raw }}");
+                source.push_str(u.choose(&["{{= a }}", "{{# a #}}", "text"])?);
+                source.push_str("{{ end raw }}");
+            }
+            4 | 5 => {
+                let condition = expression(u, scope)?;
+                tag(u, source, &format!(" if {condition} "))?;
+                nodes(u, depth - 1, scope, source)?;
+                if u.arbitrary()? {
+                    tag(u, source, " else ")?;
+                    nodes(u, depth - 1, scope, source)?;
+                }
+                tag(u, source, " end ")?;
+            }
+            _ => {
+                let item = format!("item{}", scope.len());
+                let collection = u.choose(&["items", "user", "a"])?;
+                if u.arbitrary()? {
+                    tag(u, source, &format!(" for {item} in {collection} "))?;
+                } else {
+                    tag(u, source, &format!(" for key, {item} in {collection} "))?;
+                }
+                scope.push(item);
+                nodes(u, depth - 1, scope, source)?;
+                scope.pop();
+                tag(u, source, " end ")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a tag containing `code`, which may trim the whitespace around it.
+fn tag(u: &mut Unstructured<'_>, source: &mut String, code: &str) -> arbitrary::Result<()> {
+    source.push_str(if u.ratio(1, 8)? { "{{-" } else { "{{" });
+    source.push_str(code);
+    source.push_str(if u.ratio(1, 8)? { "-}}" } else { "}}" });
+    Ok(())
+}
+
+/// Returns an expression reading the variables in `scope`.
+fn expression(u: &mut Unstructured<'_>, scope: &[String]) -> arbitrary::Result<String> {
+    let variable = u.choose(scope)?;
+    Ok(match u.int_in_range(0..=5)? {
+        0 => variable.clone(),
+        1 => format!("{variable}.name"),
+        2 => format!("{variable}.name ?? \"anonymous\""),
+        3 => format!("defined({variable})"),
+        4 => u.int_in_range(-5_i64..=5)?.to_string(),
+        _ => String::from("\"<b>\""),
+    })
+}
+
+/// Arguments for each of the [`PARAMETERS`], some of which may be missing.
+/// Values are integers, real numbers, booleans, strings, `void`, and lists
+/// and maps of them.
+#[derive(Debug, Clone)]
+pub struct ArbitraryArguments(pub Vec<(String, Value)>);
+
+impl<'a> Arbitrary<'a> for ArbitraryArguments {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut arguments = Vec::with_capacity(PARAMETERS.len());
+        for name in PARAMETERS {
+            if u.ratio(7, 8)? {
+                arguments.push((name.to_string(), value(u, 2)?));
+            }
+        }
+        Ok(Self(arguments))
+    }
+}
+
+/// Returns a value containing collections nested at most `depth` levels deep.
+fn value(u: &mut Unstructured<'_>, depth: usize) -> arbitrary::Result<Value> {
+    Ok(match u.int_in_range(0..=if depth > 0 { 6 } else { 4 })? {
+        0 => Value::Integer(u.arbitrary()?),
+        1 => Value::Real(u.arbitrary()?),
+        2 => Value::Boolean(u.arbitrary()?),
+        3 => Value::from(u.arbitrary::<String>()?),
+        4 => Value::Void,
+        5 => {
+            let mut values = Vec::new();
+            for _ in 0..u.int_in_range(0..=4)? {
+                values.push(value(u, depth - 1)?);
+            }
+            vm::list(values)
+        }
+        _ => {
+            let mut entries = Vec::new();
+            for _ in 0..u.int_in_range(0..=4)? {
+                let key = u.choose(&["name", "id", "a"])?;
+                entries.push((*key, value(u, depth - 1)?));
+            }
+            vm::map(entries)
+        }
+    })
+}
+
+/// Checks the invariants of `template`, panicking if any are violated:
+///
+/// - Neither parsing, normalizing, transpiling, nor rendering it panics.
+/// - Normalizing the normalized source returns it unchanged.
+/// - The normalized template renders the same output as `template`.
+///
+/// `configuration` should limit the [budget](Configuration::with_budget) and
+/// [output](Configuration::with_max_output_len) of renders, so that templates
+/// with unbounded loops don't hang the fuzzer, and must render
+/// deterministically, such as by [seeding](Configuration::with_sampling_seed)
+/// the sampling of `sampled` regions.
+///
+/// # Panics
+///
+/// Panics if `template` violates an invariant.
+pub fn check_invariants<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    template: &Template<'_>,
+    arguments: &ArbitraryArguments,
+) {
+    let rendered = render(configuration, template.source(), arguments);
+    let _ = template.transpile("render", arguments.0.iter().map(|(name, _)| name.as_str()));
+    let Ok(normalized) = template.normalize() else {
+        return;
+    };
+
+    let renormalized = Template::from(normalized.as_str())
+        .normalize()
+        .unwrap_or_else(|err| panic!("normalized template failed to parse: {err}\n{normalized}"));
+    assert_eq!(
+        renormalized, normalized,
+        "normalizing a normalized template changed it"
+    );
+    assert_eq!(
+        render(configuration, &normalized, arguments),
+        rendered,
+        "the normalized template rendered differently\n{normalized}"
+    );
+}
+
+/// Renders `source` with `arguments`, returning the output or the kind of
+/// error rendering failed with.
+fn render<Enc: Encoder>(
+    configuration: &Configuration<Enc>,
+    source: &str,
+    arguments: &ArbitraryArguments,
+) -> Result<String, &'static str> {
+    configuration
+        .render_with(
+            source,
+            arguments
+                .0
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.clone())),
+        )
+        .map_err(|err| err.code())
+}
+
+/// Returns a strategy generating templates like [`Template::arbitrary()`].
+#[cfg(feature = "proptest")]
+pub fn templates() -> impl proptest::strategy::Strategy<Value = Template<'static>> {
+    from_bytes(|u| Template::arbitrary(u).unwrap_or_else(|_| Template::from(String::new())))
+}
+
+/// Returns a strategy generating [`ValidTemplate`]s.
+#[cfg(feature = "proptest")]
+pub fn valid_templates() -> impl proptest::strategy::Strategy<Value = ValidTemplate> {
+    from_bytes(|u| {
+        ValidTemplate::arbitrary(u).unwrap_or_else(|_| ValidTemplate {
+            template: Template::from(String::new()),
+        })
+    })
+}
+
+/// Returns a strategy generating [`ArbitraryArguments`].
+#[cfg(feature = "proptest")]
+pub fn arguments() -> impl proptest::strategy::Strategy<Value = ArbitraryArguments> {
+    from_bytes(|u| ArbitraryArguments::arbitrary(u).unwrap_or(ArbitraryArguments(Vec::new())))
+}
+
+/// Returns a strategy that generates values using `generate` from random
+/// bytes, so that proptest shrinks them by shrinking the bytes.
+#[cfg(feature = "proptest")]
+fn from_bytes<T: std::fmt::Debug>(
+    generate: impl Fn(&mut Unstructured<'_>) -> T,
+) -> impl proptest::strategy::Strategy<Value = T> {
+    use proptest::strategy::Strategy;
+
+    proptest::collection::vec(proptest::arbitrary::any::<u8>(), 0..1024)
+        .prop_map(move |bytes| generate(&mut Unstructured::new(&bytes)))
+}
+
+#[test]
+fn generated_templates_uphold_invariants() {
+    let configuration = Configuration::for_html()
+        .with_budget(10_000)
+        .with_max_output_len(4096);
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    for _ in 0..500 {
+        let bytes = (0..256)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state.to_le_bytes()[0]
+            })
+            .collect::<Vec<_>>();
+        let mut u = Unstructured::new(&bytes);
+        let valid = ValidTemplate::arbitrary(&mut u).unwrap();
+        assert!(valid.template.normalize().is_ok(), "{valid:?}");
+        let arguments = ArbitraryArguments::arbitrary(&mut u).unwrap();
+        check_invariants(&configuration, &valid.template, &arguments);
+        let template = Template::arbitrary(&mut u).unwrap();
+        check_invariants(&configuration, &template, &arguments);
+    }
+}
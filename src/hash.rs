@@ -0,0 +1,14 @@
+/// Hashes `bytes` using 64-bit FNV-1a, which is stable across builds,
+/// platforms, and releases unlike the standard library's hasher.
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[test]
+fn fnv1a_hashes() {
+    assert_eq!(fnv1a(*b""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(fnv1a(*b"a"), 0xaf63_dc4c_8601_ec8c);
+    assert_eq!(fnv1a(*b"foobar"), 0x8594_4171_f739_67e8);
+}
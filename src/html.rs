@@ -0,0 +1,257 @@
+use budlang::vm::Value;
+
+use crate::{
+    value::{self, Shape},
+    Encoder, HtmlEncoding,
+};
+
+/// Renders `<meta>` tags for `properties`, one per line. Open Graph style
+/// keys (`og:`, `article:`, etc.) use the `property` attribute, while all
+/// other keys, including `twitter:` cards, use `name`.
+pub(crate) fn meta_tags<'a>(properties: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let mut tags = String::new();
+    for (key, content) in properties {
+        if !tags.is_empty() {
+            tags.push('\n');
+        }
+        let attribute = if is_property(key) { "property" } else { "name" };
+        tags.push_str("<meta ");
+        tags.push_str(attribute);
+        tags.push_str("=\"");
+        HtmlEncoding.encode_to_string(key, &mut tags);
+        tags.push_str("\" content=\"");
+        HtmlEncoding.encode_to_string(content, &mut tags);
+        tags.push_str("\">");
+    }
+    tags
+}
+
+/// Renders the placeholder element of the island `name`, which a
+/// client-side framework hydrates using the JSON-encoded `props`.
+pub(crate) fn island(name: &str, props: &str) -> String {
+    let mut element = String::from("<div data-island=\"");
+    HtmlEncoding.encode_to_string(name, &mut element);
+    element.push_str("\" data-props=\"");
+    HtmlEncoding.encode_to_string(props, &mut element);
+    element.push_str("\"></div>");
+    element
+}
+
+fn is_property(key: &str) -> bool {
+    key.split_once(':').is_some_and(|(prefix, _)| {
+        matches!(
+            prefix,
+            "og" | "article" | "book" | "profile" | "music" | "video" | "fb"
+        )
+    })
+}
+
+/// Returns the length of the tag at the start of `html`, skipping over
+/// quoted attribute values.
+#[cfg_attr(not(any(feature = "minify", feature = "cdn")), allow(dead_code))]
+pub(crate) fn tag_end(html: &str) -> usize {
+    let mut quote = None;
+    for (index, ch) in html.char_indices() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(open), ch) if ch == open => quote = None,
+            (None, '>') => return index + 1,
+            _ => {}
+        }
+    }
+    html.len()
+}
+
+/// Returns the offset of the tag closing the element `name` within `html`,
+/// or the length of `html` if it isn't closed.
+#[cfg_attr(not(any(feature = "minify", feature = "cdn")), allow(dead_code))]
+pub(crate) fn find_closing_tag(html: &str, name: &str) -> usize {
+    let bytes = html.as_bytes();
+    (0..bytes.len())
+        .find(|&index| {
+            bytes[index..].starts_with(b"</")
+                && bytes
+                    .get(index + 2..index + 2 + name.len())
+                    .is_some_and(|candidate| candidate.eq_ignore_ascii_case(name.as_bytes()))
+        })
+        .unwrap_or(html.len())
+}
+
+/// An entry in a navigation tree.
+#[derive(Debug)]
+pub(crate) struct NavItem {
+    title: String,
+    url: Option<String>,
+    children: Vec<NavItem>,
+}
+
+impl NavItem {
+    /// Parses a list of maps with a `title`, an optional `url`, and optional
+    /// `children`.
+    pub fn list(value: &Value) -> Result<Vec<Self>, String> {
+        match Shape::of(value) {
+            Shape::List(items) => items.iter().map(Self::from_value).collect(),
+            _ => Err(String::from("navigation items must be a list")),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, String> {
+        let Shape::Map(entries) = Shape::of(value) else {
+            return Err(String::from("each navigation item must be a map"));
+        };
+        let mut title = None;
+        let mut url = None;
+        let mut children = Vec::new();
+        for (key, value) in value::sorted_entries(entries) {
+            match (key.as_str(), Shape::of(&value)) {
+                (_, Shape::Void) => {}
+                ("title", Shape::String(value)) => title = Some(value),
+                ("url", Shape::String(value)) => url = Some(value),
+                ("children", _) => children = Self::list(&value)?,
+                ("title" | "url", _) => return Err(format!("`{key}` must be a string")),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            title: title.ok_or("each navigation item must have a `title`")?,
+            url,
+            children,
+        })
+    }
+
+    fn contains(&self, active: &str) -> bool {
+        self.url.as_deref() == Some(active)
+            || self.children.iter().any(|child| child.contains(active))
+    }
+}
+
+/// Renders `items` as nested lists of links. The item whose url is `active`
+/// is marked with `aria-current="page"`, and it and its ancestors receive the
+/// `active` class.
+pub(crate) fn nav(items: &[NavItem], active: Option<&str>) -> String {
+    let mut output = String::new();
+    nav_list(items, active, &mut output);
+    output
+}
+
+fn nav_list(items: &[NavItem], active: Option<&str>, output: &mut String) {
+    output.push_str("<ul>");
+    for item in items {
+        let is_current = active.is_some() && item.url.as_deref() == active;
+        if active.is_some_and(|active| item.contains(active)) {
+            output.push_str("<li class=\"active\">");
+        } else {
+            output.push_str("<li>");
+        }
+        link(item, is_current, output);
+        if !item.children.is_empty() {
+            nav_list(&item.children, active, output);
+        }
+        output.push_str("</li>");
+    }
+    output.push_str("</ul>");
+}
+
+fn link(item: &NavItem, is_current: bool, output: &mut String) {
+    match &item.url {
+        Some(url) => {
+            output.push_str("<a href=\"");
+            HtmlEncoding.encode_to_string(url, output);
+            if is_current {
+                output.push_str("\" aria-current=\"page\">");
+            } else {
+                output.push_str("\">");
+            }
+            HtmlEncoding.encode_to_string(&item.title, output);
+            output.push_str("</a>");
+        }
+        None => HtmlEncoding.encode_to_string(&item.title, output),
+    }
+}
+
+/// Renders the path through `items` leading to the item whose url is
+/// `active`. Returns an empty string if no item is active.
+pub(crate) fn breadcrumbs(items: &[NavItem], active: &str) -> String {
+    let mut trail = Vec::new();
+    let mut level = items;
+    while let Some(item) = level.iter().find(|item| item.contains(active)) {
+        trail.push(item);
+        if item.url.as_deref() == Some(active) {
+            break;
+        }
+        level = &item.children;
+    }
+    if trail.is_empty() {
+        return String::new();
+    }
+
+    let mut output = String::from("<nav aria-label=\"Breadcrumb\"><ol>");
+    for (index, item) in trail.iter().enumerate() {
+        output.push_str("<li>");
+        if index == trail.len() - 1 {
+            output.push_str("<span aria-current=\"page\">");
+            HtmlEncoding.encode_to_string(&item.title, &mut output);
+            output.push_str("</span>");
+        } else {
+            link(item, false, &mut output);
+        }
+        output.push_str("</li>");
+    }
+    output.push_str("</ol></nav>");
+    output
+}
+
+#[test]
+fn meta_tag_attributes() {
+    assert_eq!(
+        meta_tags([
+            ("description", "Fish & chips"),
+            ("og:title", "\"Lunch\""),
+            ("twitter:card", "summary")
+        ]),
+        "<meta name=\"description\" content=\"Fish &amp; chips\">\n\
+         <meta property=\"og:title\" content=\"&quot;Lunch&quot;\">\n\
+         <meta name=\"twitter:card\" content=\"summary\">"
+    );
+}
+
+#[test]
+fn island_placeholders() {
+    assert_eq!(
+        island("Counter", r#"{"label":"<b>'Hi'</b>"}"#),
+        "<div data-island=\"Counter\" \
+         data-props=\"{&quot;label&quot;:&quot;&lt;b&gt;&#39;Hi&#39;&lt;/b&gt;&quot;}\"></div>"
+    );
+}
+
+#[test]
+fn navigation() {
+    let child = |title: &str, url: &str| NavItem {
+        title: title.to_string(),
+        url: Some(url.to_string()),
+        children: Vec::new(),
+    };
+    let items = [
+        child("Home", "/"),
+        NavItem {
+            title: String::from("Docs & Guides"),
+            url: None,
+            children: vec![child("Install", "/install"), child("Usage", "/usage")],
+        },
+    ];
+    assert_eq!(
+        nav(&items, Some("/usage")),
+        "<ul><li><a href=\"/\">Home</a></li>\
+         <li class=\"active\">Docs &amp; Guides<ul>\
+         <li><a href=\"/install\">Install</a></li>\
+         <li class=\"active\"><a href=\"/usage\" aria-current=\"page\">Usage</a></li>\
+         </ul></li></ul>"
+    );
+    assert_eq!(
+        breadcrumbs(&items, "/usage"),
+        "<nav aria-label=\"Breadcrumb\"><ol><li>Docs &amp; Guides</li>\
+         <li><span aria-current=\"page\">Usage</span></li></ol></nav>"
+    );
+    assert_eq!(breadcrumbs(&items, "/missing"), "");
+}
@@ -0,0 +1,429 @@
+//! Translated messages, which templates render using the `t()` and
+//! `plural()` functions.
+//!
+//! Messages are written in the [Fluent](https://projectfluent.org) syntax,
+//! which selects between plural forms using the rules of each locale:
+//!
+//! ```ftl
+//! greeting = Hello, { $name }!
+//! items = { $count ->
+//!     [one] One item
+//!    *[other] { $count } items
+//! }
+//! ```
+
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+
+use budlang::vm::Value;
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource, FluentValue};
+use unic_langid::LanguageIdentifier;
+
+use crate::{expression, Configuration, Delimiters, Error, ErrorKind, SegmentKind, Templates};
+
+/// The messages of a set of locales, one of which is used when a message
+/// hasn't been translated into the locale being rendered.
+///
+/// ```rust
+/// use budplate::{i18n::Catalog, Configuration};
+///
+/// let catalog = Catalog::new("en")
+///     .unwrap()
+///     .with_messages("en", "greeting = Hello, { $name }!")
+///     .unwrap();
+/// let configuration = Configuration::for_html().with_catalog(catalog);
+/// assert_eq!(
+///     configuration
+///         .render_with(r#"{{= t("greeting", name) }}"#, [("name", "Ada")])
+///         .unwrap(),
+///     "Hello, Ada!"
+/// );
+/// ```
+pub struct Catalog {
+    default_locale: LanguageIdentifier,
+    bundles: Vec<(LanguageIdentifier, FluentBundle<FluentResource>)>,
+}
+
+impl Catalog {
+    /// Returns an empty catalog whose messages fall back to those of
+    /// `default_locale`, such as `"en-US"`.
+    pub fn new(default_locale: &str) -> Result<Self, Error> {
+        Ok(Self {
+            default_locale: parse_locale(default_locale)?,
+            bundles: Vec::new(),
+        })
+    }
+
+    /// Adds `messages`, written in the Fluent syntax, to the messages of
+    /// `locale`. Messages that have already been added for `locale` can't be
+    /// redefined.
+    pub fn with_messages(mut self, locale: &str, messages: &str) -> Result<Self, Error> {
+        let langid = parse_locale(locale)?;
+        let invalid = |message: String| ErrorKind::Messages {
+            locale: locale.to_string(),
+            message,
+        };
+        let resource = FluentResource::try_new(messages.to_string())
+            .map_err(|(_, errors)| invalid(describe(&errors)))?;
+
+        let index = match self
+            .bundles
+            .iter()
+            .position(|(existing, _)| existing == &langid)
+        {
+            Some(index) => index,
+            None => {
+                let mut bundle = FluentBundle::new_concurrent(vec![langid.clone()]);
+                // Isolation marks keep right-to-left arguments from affecting
+                // the surrounding text, but are invisible characters that
+                // don't belong in most documents.
+                bundle.set_use_isolating(false);
+                self.bundles.push((langid, bundle));
+                self.bundles.len() - 1
+            }
+        };
+        self.bundles[index]
+            .1
+            .add_resource(resource)
+            .map_err(|errors| invalid(describe(&errors)))?;
+        Ok(self)
+    }
+
+    /// Returns the bundles to search for a message in `locale`, from the most
+    /// to the least specific: the locale itself, other locales of its
+    /// language, and the default locale.
+    fn bundles<'a>(
+        &'a self,
+        locale: Option<&'a LanguageIdentifier>,
+    ) -> impl Iterator<Item = &'a FluentBundle<FluentResource>> {
+        let locale = locale.unwrap_or(&self.default_locale);
+        let exact = self
+            .bundles
+            .iter()
+            .filter(move |(langid, _)| langid == locale);
+        let language = self
+            .bundles
+            .iter()
+            .filter(move |(langid, _)| langid != locale && langid.language == locale.language);
+        let default = self
+            .bundles
+            .iter()
+            .filter(|(langid, _)| langid == &self.default_locale);
+        exact
+            .chain(language)
+            .chain(default)
+            .map(|(_, bundle)| bundle)
+    }
+
+    /// Formats the message `key` in `locale` with `arguments`.
+    pub(crate) fn format(
+        &self,
+        locale: Option<&str>,
+        key: &str,
+        arguments: Vec<(String, Value)>,
+    ) -> Result<String, String> {
+        let locale = locale
+            .map(|locale| {
+                locale
+                    .parse::<LanguageIdentifier>()
+                    .map_err(|_| format!("invalid locale `{locale}`"))
+            })
+            .transpose()?;
+        let (bundle, pattern) = self
+            .bundles(locale.as_ref())
+            .find_map(|bundle| {
+                let message = bundle.get_message(key)?;
+                message.value().map(|pattern| (bundle, pattern))
+            })
+            .ok_or_else(|| format!("unknown message `{key}`"))?;
+
+        let mut args = FluentArgs::new();
+        for (name, value) in arguments {
+            let value = match value {
+                Value::Integer(value) => FluentValue::from(value),
+                Value::Real(value) => FluentValue::from(value),
+                value => FluentValue::from(
+                    value
+                        .try_convert_to_string(&())
+                        .map_err(|_| format!("`{name}` could not be converted to a string"))?
+                        .to_string(),
+                ),
+            };
+            args.set(name, value);
+        }
+        let mut errors = Vec::new();
+        let formatted = bundle.format_pattern(pattern, Some(&args), &mut errors);
+        match errors.first() {
+            None => Ok(formatted.into_owned()),
+            Some(error) => Err(format!("error formatting `{key}`: {error}")),
+        }
+    }
+}
+
+fn describe<E: ToString>(errors: &[E]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn parse_locale(locale: &str) -> Result<LanguageIdentifier, Error> {
+    locale.parse().map_err(|_| {
+        Error::from(ErrorKind::Messages {
+            locale: locale.to_string(),
+            message: String::from("invalid locale"),
+        })
+    })
+}
+
+impl<Enc> Configuration<Enc> {
+    /// Exposes the messages of `catalog` to templates through the `t()` and
+    /// `plural()` functions. The locale of each render is the `locale` value
+    /// of its [`ContextProvider`](crate::ContextProvider), or the catalog's
+    /// default locale if it has none.
+    pub fn with_catalog(mut self, catalog: Catalog) -> Self {
+        self.catalog = Some(Arc::new(catalog));
+        self
+    }
+}
+
+/// A message used by the templates of a collection, found by
+/// [`Templates::extract_messages()`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ExtractedMessage {
+    pub key: String,
+    /// The names of the arguments passed to the message.
+    pub arguments: Vec<String>,
+    /// Whether the message is rendered using `plural()`.
+    pub plural: bool,
+    /// The name of each template that uses the message, along with the line
+    /// it is used on.
+    pub references: Vec<(String, usize)>,
+}
+
+impl Templates<'_> {
+    /// Returns the messages used by the `t()` and `plural()` calls of every
+    /// registered template, sorted by key. Calls whose key isn't a string
+    /// literal are skipped.
+    pub fn extract_messages(&self) -> Result<Vec<ExtractedMessage>, Error> {
+        let mut messages = BTreeMap::<String, ExtractedMessage>::new();
+        for (name, template) in self.sorted() {
+            let template = template
+                .parse(Delimiters::DEFAULT)
+                .map_err(|err| err.in_template(Some(name)))?;
+            for segment in &template.segments {
+                if !matches!(
+                    segment.kind,
+                    SegmentKind::Expression { .. } | SegmentKind::Statement(_)
+                ) {
+                    continue;
+                }
+                let code = &template.source[segment.range.clone()];
+                for call in expression::message_calls(code) {
+                    let Some(key) = call.key else {
+                        continue;
+                    };
+                    let line = template.source[..segment.range.start + call.offset]
+                        .matches('\n')
+                        .count()
+                        + 1;
+                    let message =
+                        messages
+                            .entry(key.to_string())
+                            .or_insert_with(|| ExtractedMessage {
+                                key: key.to_string(),
+                                arguments: Vec::new(),
+                                plural: false,
+                                references: Vec::new(),
+                            });
+                    for argument in call.arguments {
+                        if !message
+                            .arguments
+                            .iter()
+                            .any(|existing| existing == argument)
+                        {
+                            message.arguments.push(argument.to_string());
+                        }
+                    }
+                    message.plural |= call.plural;
+                    message.references.push((name.to_string(), line));
+                }
+            }
+        }
+        Ok(messages.into_values().collect())
+    }
+}
+
+/// Returns messages written in the Fluent syntax for each of `messages`, as
+/// a starting point for translating them. Each message lists the templates
+/// that use it and each of its arguments, and plural messages select
+/// between the `one` and `other` plural forms.
+///
+/// ```rust
+/// use budplate::{i18n, Templates};
+///
+/// let mut templates = Templates::new();
+/// templates
+///     .register("inbox", r#"{{= t("welcome", user.name) }} {{= plural("unread", count) }}"#)
+///     .unwrap();
+/// let messages = templates.extract_messages().unwrap();
+/// assert_eq!(
+///     i18n::fluent_skeleton(&messages),
+///     "# Used by inbox:1\n\
+///      unread = { $count ->\n    [one] { $count }\n   *[other] { $count }\n}\n\
+///      \n\
+///      # Used by inbox:1\n\
+///      welcome = { $name }\n"
+/// );
+/// ```
+#[must_use]
+pub fn fluent_skeleton(messages: &[ExtractedMessage]) -> String {
+    let mut skeleton = String::new();
+    for (index, message) in messages.iter().enumerate() {
+        if index > 0 {
+            skeleton.push('\n');
+        }
+        let references = message
+            .references
+            .iter()
+            .map(|(template, line)| format!("{template}:{line}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut arguments = message
+            .arguments
+            .iter()
+            .map(|argument| format!("{{ ${argument} }}"))
+            .collect::<Vec<_>>();
+        if message.plural && !message.arguments.iter().any(|argument| argument == "count") {
+            arguments.insert(0, String::from("{ $count }"));
+        }
+        let value = if arguments.is_empty() {
+            message.key.clone()
+        } else {
+            arguments.join(" ")
+        };
+
+        writeln!(skeleton, "# Used by {references}").expect("writing to a string");
+        if message.plural {
+            writeln!(
+                skeleton,
+                "{} = {{ $count ->\n    [one] {value}\n   *[other] {value}\n}}",
+                message.key
+            )
+        } else {
+            writeln!(skeleton, "{} = {value}", message.key)
+        }
+        .expect("writing to a string");
+    }
+    skeleton
+}
+
+#[test]
+fn extracted_messages() {
+    let mut templates = Templates::new();
+    templates
+        .register(
+            "page",
+            "{{= t(\"title\") }}\n{{ if t(\"greeting\", user.name) }}{{ end }}\n\
+             {{ include \"nav\" with label = t(greeting_key) }}",
+        )
+        .unwrap();
+    templates
+        .register(
+            "nav",
+            r#"{{= t("greeting", name = "x", site) }}{{= plural("items", count) }}"#,
+        )
+        .unwrap();
+
+    let messages = templates.extract_messages().unwrap();
+    assert_eq!(
+        messages,
+        [
+            ExtractedMessage {
+                key: String::from("greeting"),
+                arguments: vec![String::from("name"), String::from("site")],
+                plural: false,
+                references: vec![(String::from("nav"), 1), (String::from("page"), 2)],
+            },
+            ExtractedMessage {
+                key: String::from("items"),
+                arguments: Vec::new(),
+                plural: true,
+                references: vec![(String::from("nav"), 1)],
+            },
+            ExtractedMessage {
+                key: String::from("title"),
+                arguments: Vec::new(),
+                plural: false,
+                references: vec![(String::from("page"), 1)],
+            },
+        ]
+    );
+    assert_eq!(
+        fluent_skeleton(&messages[2..]),
+        "# Used by page:1\ntitle = title\n"
+    );
+    assert_eq!(
+        fluent_skeleton(&messages[..1]),
+        "# Used by nav:1, page:2\ngreeting = { $name } { $site }\n"
+    );
+}
+
+#[test]
+fn translated_messages() {
+    use std::collections::HashMap;
+
+    let catalog = Catalog::new("en")
+        .unwrap()
+        .with_messages(
+            "en",
+            "greeting = Hello, { $name }!\n\
+             items = { $count ->\n    [one] One item\n   *[other] { $count } items\n}\n\
+             farewell = Goodbye, { $name }.",
+        )
+        .unwrap()
+        .with_messages(
+            "fr",
+            "greeting = Bonjour, { $name } !\n\
+             items = { $count ->\n    [one] { $count } article\n   *[other] { $count } articles\n}",
+        )
+        .unwrap();
+    assert!(matches!(
+        Catalog::new("en")
+            .unwrap()
+            .with_messages("en", "greeting = {"),
+        Err(Error {
+            kind: ErrorKind::Messages { .. },
+            ..
+        })
+    ));
+
+    let configuration = Configuration::for_html().with_catalog(catalog);
+    let template = r#"{{= t("greeting", user.name) }} {{= plural("items", count) }} {{= t("farewell", name = "Grace") }}"#;
+    let user = || {
+        Value::dynamic(
+            [(Value::from("name"), Value::from("Ada"))]
+                .into_iter()
+                .collect::<budlang::vm::HashMap>(),
+        )
+    };
+    assert_eq!(
+        configuration
+            .render_with(template, [("user", user()), ("count", Value::from(1))])
+            .unwrap(),
+        "Hello, Ada! One item Goodbye, Grace."
+    );
+
+    let french = HashMap::from([(String::from("locale"), Value::from("fr-CA"))]);
+    assert_eq!(
+        configuration
+            .render_with_context(
+                template,
+                french,
+                [("user", user()), ("count", Value::from(1))]
+            )
+            .unwrap(),
+        "Bonjour, Ada ! 1 article Goodbye, Grace."
+    );
+    assert!(configuration.render(r#"{{= t("missing") }}"#).is_err());
+}
@@ -1,30 +1,205 @@
 #![doc = include_str!("../README.md")]
 
-use std::{borrow::Cow, fmt::Write, ops::Range};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    ops::{Deref, Range},
+    rc::Rc,
+    sync::Arc,
+};
 
 use budlang::{
-    vm::{ir::Function, Destination, FaultKind, Instruction, NativeFunction, Symbol, Value},
+    vm::{Destination, Fault, FaultKind, FaultOrPause, Instruction, NativeFunction, Symbol, Value},
     Bud,
 };
+use builtins::{CustomFunction, ExpressionWriter, RenderState};
+use directive::{Argument, BlockKind, Directive};
+use execution::{Execution, YieldNow};
+use substitution::Substitution;
+use value::Missing;
+
+mod annotate;
+mod ansi;
+mod assets;
+mod audit;
+mod backend;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod bind;
+mod builtins;
+#[cfg(feature = "bundles")]
+mod bundle;
+mod cache;
+#[cfg(feature = "cdn")]
+mod cdn;
+mod classes;
+mod clock;
+mod coercion;
+mod context;
+mod diagnostic;
+mod directive;
+mod edge;
+mod execution;
+mod expression;
+mod feature;
+#[cfg(feature = "feeds")]
+pub mod feeds;
+mod formats;
+#[cfg(feature = "forms")]
+mod forms;
+mod fragment;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+mod hash;
+mod html;
+#[cfg(feature = "i18n")]
+pub mod i18n;
+mod loader;
+#[cfg(feature = "minify")]
+mod minify;
+mod options;
+mod pretty;
+mod queue;
+mod quota;
+mod record;
+#[cfg(feature = "remote")]
+mod remote;
+mod rename;
+mod renderer;
+mod safe;
+mod secret;
+#[cfg(feature = "serde")]
+mod serialize;
+mod shell;
+mod store;
+mod structure;
+mod substitution;
+mod symbols;
+mod templates;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod text;
+mod transform;
+mod usage;
+mod value;
+mod variant;
+mod vfs;
+pub mod vm;
+mod web;
+
+pub use annotate::{AnnotatedOutput, Annotation};
+pub use ansi::AnsiStyling;
+pub use assets::{AssetManifest, ImageAsset};
+pub use audit::{Access, AccessKind, AuditedOutput};
+pub use backend::{
+    BackendExpression, BackendRender, BackendSegment, BackendTemplate, Executable, TemplateBackend,
+};
+pub use bind::BoundArguments;
+#[cfg(feature = "macros")]
+pub use budplate_macros::template;
+#[cfg(feature = "bundles")]
+pub use bundle::BundleFs;
+pub use cache::{CompileCache, TemplateCache};
+#[cfg(feature = "cdn")]
+pub use cdn::CdnRewriter;
+pub use classes::scoped_class;
+pub use clock::{Clock, FixedClock, SystemClock};
+pub use coercion::{CoercionPolicy, LenientCoercion, StrictCoercion};
+pub use context::{ContextProvider, HelperContext};
+pub use diagnostic::{diagnostics_to_json, Diagnostic, Severity, Span};
+pub use edge::EdgeIncludes;
+pub use feature::FeatureProvider;
+pub use fragment::Fragment;
+pub use loader::{FileLoader, SignatureVerifier};
+#[cfg(feature = "minify")]
+pub use minify::HtmlMinifier;
+pub use options::{CancellationToken, RenderOptions, Strictness};
+pub use pretty::{PrettyPrinter, Redact};
+pub use queue::{Priority, QueueMetrics, RenderQueue};
+pub use quota::{Quota, QuotaUsage};
+pub use record::{JsonLinesRecorder, RecordedRender, RenderRecorder};
+#[cfg(feature = "remote")]
+pub use remote::HttpFs;
+pub use renderer::Renderer;
+pub use safe::SafeString;
+pub use secret::Secret;
+#[cfg(feature = "serde")]
+pub use serialize::to_value;
+pub use shell::{Shell, ShellTemplate};
+pub use store::{AsyncTemplateStore, StoreLoader, StoredTemplate, TemplateStore};
+pub use structure::{StructureKind, StructureNode};
+pub use symbols::ParameterName;
+pub use templates::Templates;
+pub use transform::{OutputTransform, TransformedRender};
+pub use usage::{CacheStatus, RenderUsage, UsageObserver};
+pub use variant::{Exposure, ExposureRecorder};
+pub use vfs::{MemoryFs, RealFs, Vfs, VfsEntry};
+pub use web::Html;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Template<'a> {
     source: Cow<'a, str>,
+    parts: Vec<TemplatePart>,
 }
 
 impl<'a> Template<'a> {
     pub const fn from_str(template: &'a str) -> Self {
         Self {
             source: Cow::Borrowed(template),
+            parts: Vec::new(),
         }
     }
 
     pub const fn from_string(template: String) -> Self {
         Self {
             source: Cow::Owned(template),
+            parts: Vec::new(),
         }
     }
 
+    pub fn concat<Parts, Part>(parts: Parts) -> Template<'static>
+    where
+        Parts: IntoIterator<Item = Part>,
+        Part: AsRef<str>,
+    {
+        parts
+            .into_iter()
+            .fold(TemplateBuilder::new(), |builder, part| {
+                builder.with_part(part.as_ref())
+            })
+            .build()
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the parts this template was assembled from using
+    /// [`TemplateBuilder`]. Templates created from a single source have no
+    /// parts.
+    pub fn parts(&self) -> &[TemplatePart] {
+        &self.parts
+    }
+
+    /// Returns the part containing the byte `offset` in this template's
+    /// source, if this template was assembled from parts.
+    pub fn part_at(&self, offset: usize) -> Option<&TemplatePart> {
+        self.parts
+            .iter()
+            .find(|part| part.range.contains(&offset))
+            .or_else(|| {
+                // An offset at the very end of the template, such as an
+                // unterminated tag, belongs to the last non-empty part.
+                self.parts
+                    .iter()
+                    .rev()
+                    .find(|part| !part.range.is_empty())
+                    .filter(|part| part.range.end == offset)
+            })
+    }
+
     pub fn render(&self) -> Result<String, Error> {
         self.render_with::<&'static str, Value, _>([])
     }
@@ -38,85 +213,359 @@ impl<'a> Template<'a> {
         Configuration::default().render_with(&self.source, args)
     }
 
-    fn parse(&self) -> Result<ParsedTemplate<'_>, Error> {
-        enum CodeKind {
-            SafeExpression,
-            UnsafeExpression,
-            Statement,
+    /// Renders this template with `args` into `output`. Output is written as
+    /// the template executes rather than being collected first.
+    pub fn render_to<W, Name, Arg, Args>(&self, output: &mut W, args: Args) -> Result<(), Error>
+    where
+        W: Write,
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        Configuration::default().render_to(output, &self.source, args)
+    }
+
+    /// Renders this template with `args`, passing its output to
+    /// `on_fragment` in pieces as it executes. See
+    /// [`CompiledTemplate::render_fragments`].
+    pub fn render_fragments<Name, Arg, Args, F>(
+        &self,
+        args: Args,
+        on_fragment: F,
+    ) -> Result<(), Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+        F: FnMut(Fragment<'_>) -> Result<(), Error>,
+    {
+        Configuration::default().render_fragments(&self.source, args, on_fragment)
+    }
+
+    /// Renders this template with the fields of `context` as its arguments.
+    /// `context` must serialize as a struct or map.
+    #[cfg(feature = "serde")]
+    pub fn render_context<T: serde::Serialize + ?Sized>(
+        &self,
+        context: &T,
+    ) -> Result<String, Error> {
+        Configuration::default().render_context(&self.source, context)
+    }
+
+    pub fn compile<Enc, Params>(
+        &self,
+        configuration: &Configuration<Enc>,
+        parameters: Params,
+    ) -> Result<CompiledTemplate, Error>
+    where
+        Enc: Encoder,
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        configuration.compile(self, parameters)
+    }
+
+    /// Returns the Bud source this template compiles to. The template is
+    /// rendered by a function named `name` that accepts `parameters`.
+    ///
+    /// Templates that use [`include`](Templates) must be transpiled as part
+    /// of a [`Templates`] collection when compiled, so including other
+    /// templates returns [`ErrorKind::UnknownTemplate`] from this function.
+    ///
+    /// Parameter names can be provided as strings or as [`Symbol`](vm::Symbol)s:
+    ///
+    /// ```rust
+    /// use budplate::{vm::Symbol, Template};
+    ///
+    /// let template = Template::from("Hello, {{= name }}!");
+    /// let source = template.transpile("greet", ["name"]).unwrap();
+    /// assert!(source.starts_with("function greet(name)"));
+    /// assert_eq!(
+    ///     template.transpile("greet", [Symbol::from("name")]).unwrap(),
+    ///     source
+    /// );
+    /// ```
+    pub fn transpile<Params>(&self, name: &str, parameters: Params) -> Result<String, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let parameters = parameters
+            .into_iter()
+            .map(ParameterName::into_symbol)
+            .collect::<Vec<_>>();
+        Ok(self
+            .parse(Delimiters::DEFAULT)?
+            .into_bud_source(name, &parameters, TranspileOptions::DEFAULT)?
+            .source)
+    }
+
+    /// Returns this template's source with each tag's code surrounded by a
+    /// single space, such as `{{= name }}` in place of `{{=name}}`. Raw text
+    /// and comments are unchanged, and normalizing the result returns it
+    /// unchanged.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let normalized = Template::from("<p>{{=name}}</p>{{-if   true}}{{# note #}}{{end -}}")
+    ///     .normalize()
+    ///     .unwrap();
+    /// assert_eq!(normalized, "<p>{{= name }}</p>{{- if   true }}{{# note #}}{{ end -}}");
+    /// ```
+    pub fn normalize(&self) -> Result<String, Error> {
+        Ok(self
+            .parse(Delimiters::DEFAULT)?
+            .to_source(Delimiters::DEFAULT))
+    }
+
+    /// Returns the entries of this template's `meta` statements, which
+    /// describe the template without rendering anything, such as for
+    /// building routing tables or ownership reports. Each value must be a
+    /// string literal, and each name may only be declared once.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let metadata = Template::from(r#"{{ meta title = "Invoice", owner = "billing" }}<h1>Invoice</h1>"#)
+    ///     .metadata()
+    ///     .unwrap();
+    /// assert_eq!(metadata["owner"], "billing");
+    /// ```
+    pub fn metadata(&self) -> Result<BTreeMap<String, String>, Error> {
+        Ok(self.parse(Delimiters::DEFAULT)?.outline()?.metadata)
+    }
+
+    /// Returns a hash identifying this template's content, such as for
+    /// keying caches of its output or detecting changes between builds.
+    ///
+    /// The hash is the 64-bit FNV-1a hash of the template's
+    /// [normalized](Self::normalize) source with the text of each `{{# #}}`
+    /// comment removed, so edits to comments and to the whitespace within
+    /// tags don't change it. This algorithm is stable across platforms and
+    /// releases.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let hash = Template::from("{{# TODO #}}<p>{{=name}}</p>").content_hash().unwrap();
+    /// assert_eq!(
+    ///     hash,
+    ///     Template::from("{{# Renders the name #}}<p>{{= name }}</p>")
+    ///         .content_hash()
+    ///         .unwrap()
+    /// );
+    /// assert_ne!(hash, Template::from("<p>{{= title }}</p>").content_hash().unwrap());
+    /// ```
+    pub fn content_hash(&self) -> Result<u64, Error> {
+        let mut template = self.parse(Delimiters::DEFAULT)?;
+        for segment in &mut template.segments {
+            if matches!(segment.kind, SegmentKind::Comment(_))
+                && template.source[segment.tag.start + Delimiters::DEFAULT.open.len()..]
+                    .starts_with('#')
+            {
+                segment.range.end = segment.range.start;
+            }
         }
-        let mut segments = Vec::new();
-        let source: &str = &self.source;
-        let mut parts = source.split("{{");
-        if let Some(raw_start) = parts.next() {
-            let offset = raw_start.as_ptr() as usize - source.as_ptr() as usize;
+        Ok(hash::fnv1a(
+            template.to_source(Delimiters::DEFAULT).into_bytes(),
+        ))
+    }
+
+    fn parse(&self, delimiters: Delimiters) -> Result<ParsedTemplate<'_>, Error> {
+        thread_local! {
+            /// The buffer segments are parsed into, which is reused by every
+            /// template parsed on a thread.
+            static SEGMENTS: RefCell<Vec<Segment>> = const { RefCell::new(Vec::new()) };
+        }
+
+        // Parsing into a reused buffer and then copying the segments out
+        // allocates them exactly once, without first scanning the source to
+        // count its tags.
+        let mut buffer = SEGMENTS.take();
+        let result = self.parse_into(delimiters, &mut buffer);
+        let segments = buffer.drain(..).collect::<Vec<_>>();
+        if buffer.capacity() <= MAX_REUSED_SEGMENTS {
+            SEGMENTS.set(buffer);
+        }
+        result?;
+        Ok(ParsedTemplate {
+            source: &self.source,
+            segments,
+        })
+    }
 
+    fn parse_into(&self, delimiters: Delimiters, segments: &mut Vec<Segment>) -> Result<(), Error> {
+        let Delimiters { open, close } = delimiters;
+        let source: &str = &self.source;
+        let push_raw = |segments: &mut Vec<Segment>, range: Range<usize>| {
             segments.push(Segment {
                 kind: SegmentKind::Raw,
-                range: offset..offset + raw_start.len(),
+                range: range.clone(),
+                tag: range,
             });
+        };
 
-            for after_brace_start in parts {
-                let mut command_parts = after_brace_start.split("}}");
-                let command = command_parts.next().ok_or(Error::MissingEndBraces)?;
-
-                let (code_kind, command) = if let Some(command) = command.strip_prefix('=') {
-                    (CodeKind::UnsafeExpression, command)
-                } else if let Some(command) = command.strip_prefix(":=") {
-                    (CodeKind::SafeExpression, command)
-                } else {
-                    (CodeKind::Statement, command)
-                };
+        let mut position = 0;
+        while let Some(tag_start) = find(source, position, open) {
+            if let Some(stray) = find(&source[..tag_start], position, close) {
+                return Err(ErrorKind::UnexpectedEndBrances.located(stray..stray + close.len()));
+            }
+            push_raw(segments, position..tag_start);
 
-                let (trim_before, command) = if let Some(command) = command.strip_prefix('-') {
-                    (true, command)
-                } else {
-                    (false, command)
+            let code_start = tag_start + open.len();
+            if source[code_start..].starts_with('#') {
+                // Comments may contain anything other than their terminator.
+                let Some(comment_end) = find_comment_end(source, code_start + 1, close) else {
+                    return Err(ErrorKind::MissingEndBraces.located(tag_start..source.len()));
                 };
+                let tag_end = comment_end + 1 + close.len();
+                let (trimming, range) = tag_trimming(source, code_start + 1..comment_end);
+                segments.push(Segment {
+                    kind: SegmentKind::Comment(trimming),
+                    range,
+                    tag: tag_start..tag_end,
+                });
+                position = tag_end;
+                continue;
+            }
 
-                let (trim_after, command) = if let Some(command) = command.strip_suffix('-') {
-                    (true, command)
-                } else {
-                    (false, command)
-                };
+            let code_end = match (
+                find(source, code_start, close),
+                find(source, code_start, open),
+            ) {
+                (Some(end), next) if next.is_none_or(|next| end < next) => end,
+                _ => return Err(ErrorKind::MissingEndBraces.located(tag_start..source.len())),
+            };
+            let tag_end = code_end + close.len();
+            let tag = tag_start..tag_end;
 
-                let trimming = WhitespaceTrimming {
-                    trim_before,
-                    trim_after,
-                };
+            let (safe, code) = if source[code_start..].starts_with(":=") {
+                (Some(true), code_start + 2..code_end)
+            } else if source[code_start..].starts_with('=') {
+                (Some(false), code_start + 1..code_end)
+            } else {
+                (None, code_start..code_end)
+            };
+            let (trimming, mut range) = tag_trimming(source, code);
 
-                let kind = match code_kind {
-                    CodeKind::SafeExpression => SegmentKind::Expression {
-                        trimming,
-                        safe: true,
-                    },
-                    CodeKind::UnsafeExpression => SegmentKind::Expression {
+            let kind = match safe {
+                Some(false) => {
+                    // `{{=url value }}` selects an encoding for a single
+                    // expression.
+                    let context = ContextEncoding::parse(&source[range.clone()]).map(
+                        |(context, expression)| {
+                            range.start = range.end - expression.len();
+                            context
+                        },
+                    );
+                    SegmentKind::Expression {
                         trimming,
                         safe: false,
-                    },
-                    CodeKind::Statement => SegmentKind::Statement(trimming),
-                };
-
-                let offset = command.as_ptr() as usize - source.as_ptr() as usize;
-                segments.push(Segment {
-                    kind,
-                    range: offset..offset + command.len(),
-                });
-
-                if let Some(raw_end) = command_parts.next() {
-                    let offset = raw_end.as_ptr() as usize - source.as_ptr() as usize;
+                        context,
+                    }
+                }
+                Some(true) => SegmentKind::Expression {
+                    trimming,
+                    safe: true,
+                    context: None,
+                },
+                None if source[range.clone()].trim() == "raw" => {
+                    // Everything until the matching `{{ end raw }}` is output
+                    // verbatim.
+                    let (end_tag, end_trimming) = find_end_raw(source, tag_end, delimiters)
+                        .ok_or_else(|| {
+                            ErrorKind::UnterminatedBlock(String::from("raw")).located(tag.clone())
+                        })?;
                     segments.push(Segment {
-                        kind: SegmentKind::Raw,
-                        range: offset..offset + raw_end.len(),
+                        kind: SegmentKind::Comment(trimming),
+                        range,
+                        tag,
                     });
-
-                    if command_parts.next().is_some() {
-                        return Err(Error::UnexpectedEndBrances);
-                    }
+                    push_raw(segments, tag_end..end_tag.start);
+                    segments.push(Segment {
+                        kind: SegmentKind::Comment(end_trimming),
+                        range: end_tag.clone(),
+                        tag: end_tag.clone(),
+                    });
+                    position = end_tag.end;
+                    continue;
                 }
-            }
+                None => SegmentKind::Statement(trimming),
+            };
+            segments.push(Segment { kind, range, tag });
+            position = tag_end;
+        }
+
+        if let Some(stray) = find(source, position, close) {
+            return Err(ErrorKind::UnexpectedEndBrances.located(stray..stray + close.len()));
         }
+        push_raw(segments, position..source.len());
 
-        Ok(ParsedTemplate { source, segments })
+        Ok(())
+    }
+}
+
+/// The most segments whose storage is kept for parsing the next template,
+/// so that parsing one large template doesn't hold onto its memory.
+const MAX_REUSED_SEGMENTS: usize = 4_096;
+
+/// Returns the offset of the first occurrence of `pattern` in `source` at or
+/// after `start`.
+fn find(source: &str, start: usize, pattern: &str) -> Option<usize> {
+    source[start..].find(pattern).map(|index| index + start)
+}
+
+/// Returns the offset of the `#` that begins the first comment terminator,
+/// `#` followed by `close`, in `source` at or after `start`.
+fn find_comment_end(source: &str, start: usize, close: &str) -> Option<usize> {
+    let mut position = start;
+    loop {
+        let hash = find(source, position, "#")?;
+        if source[hash + 1..].starts_with(close) {
+            return Some(hash);
+        }
+        position = hash + 1;
+    }
+}
+
+/// Strips the whitespace trimming markers from the code of a tag, returning
+/// the trimming and the range of the remaining code.
+fn tag_trimming(source: &str, mut code: Range<usize>) -> (WhitespaceTrimming, Range<usize>) {
+    let trim_before = source[code.clone()].starts_with('-');
+    if trim_before {
+        code.start += 1;
+    }
+    let trim_after = source[code.clone()].ends_with('-');
+    if trim_after {
+        code.end -= 1;
+    }
+    (
+        WhitespaceTrimming {
+            trim_before,
+            trim_after,
+        },
+        code,
+    )
+}
+
+/// Finds the `{{ end raw }}` tag that closes a raw block beginning at
+/// `start`, returning its range and trimming.
+fn find_end_raw(
+    source: &str,
+    start: usize,
+    delimiters: Delimiters,
+) -> Option<(Range<usize>, WhitespaceTrimming)> {
+    let mut position = start;
+    loop {
+        let tag_start = find(source, position, delimiters.open)?;
+        let code_start = tag_start + delimiters.open.len();
+        let code_end = find(source, code_start, delimiters.close)?;
+        let (trimming, code) = tag_trimming(source, code_start..code_end);
+        if source[code].split_whitespace().eq(["end", "raw"]) {
+            return Some((tag_start..code_end + delimiters.close.len(), trimming));
+        }
+        position = code_start;
     }
 }
 
@@ -132,316 +581,5180 @@ impl<'a> From<String> for Template<'a> {
     }
 }
 
+/// Assembles a [`Template`] from multiple sources, remembering where each
+/// source begins so that offsets into the combined template can be traced
+/// back to the part they came from.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateBuilder {
+    source: String,
+    parts: Vec<TemplatePart>,
+}
+
+impl TemplateBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_part(self, source: &str) -> Self {
+        self.push_part(None, source)
+    }
+
+    pub fn with_named_part(self, name: impl Into<String>, source: &str) -> Self {
+        self.push_part(Some(name.into()), source)
+    }
+
+    fn push_part(mut self, name: Option<String>, source: &str) -> Self {
+        let start = self.source.len();
+        self.source.push_str(source);
+        self.parts.push(TemplatePart {
+            name,
+            range: start..self.source.len(),
+        });
+        self
+    }
+
+    pub fn build(self) -> Template<'static> {
+        Template {
+            source: Cow::Owned(self.source),
+            parts: self.parts,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TemplatePart {
+    pub name: Option<String>,
+    pub range: Range<usize>,
+}
+
+impl TemplatePart {
+    /// Converts an offset in the assembled template into an offset within
+    /// this part's original source.
+    pub fn relative_offset(&self, offset: usize) -> usize {
+        offset.saturating_sub(self.range.start)
+    }
+}
+
+#[test]
+fn template_builder_offsets() {
+    let template = TemplateBuilder::new()
+        .with_named_part("header", "<h1>{{= title }}</h1>")
+        .with_part("")
+        .with_named_part("body", "{{= body }}")
+        .build();
+    assert_eq!(template.source(), "<h1>{{= title }}</h1>{{= body }}");
+
+    let body = template.part_at(24).unwrap();
+    assert_eq!(body.name.as_deref(), Some("body"));
+    assert_eq!(body.relative_offset(24), 3);
+    assert_eq!(template.part_at(3).unwrap().name.as_deref(), Some("header"));
+    assert!(template.part_at(100).is_none());
+
+    assert_eq!(template.part_at(32).unwrap().name.as_deref(), Some("body"));
+    assert_eq!(Template::concat(["a", "b"]).source(), "ab");
+}
+
 #[derive(Debug, Clone)]
 struct Segment {
     kind: SegmentKind,
     range: Range<usize>,
+    /// The range of the entire tag, including its delimiters.
+    tag: Range<usize>,
 }
 
 #[derive(Debug, Clone, Copy)]
 enum SegmentKind {
     Raw,
+    /// A tag that produces no output, such as a comment.
+    Comment(WhitespaceTrimming),
     Statement(WhitespaceTrimming),
     Expression {
         trimming: WhitespaceTrimming,
         safe: bool,
+        /// The encoding selected within the tag, which replaces the
+        /// configuration's encoder.
+        context: Option<ContextEncoding>,
     },
 }
 
-#[derive(Debug)]
-pub enum Error {
-    MissingEndBraces,
-    UnexpectedEndBrances,
+/// An encoding that can be selected for an individual expression, such as
+/// `{{=url value }}`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ContextEncoding {
+    Html,
+    JsonString,
+    Url,
+    Shell,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct WhitespaceTrimming {
-    pub trim_before: bool,
-    pub trim_after: bool,
+impl ContextEncoding {
+    const ALL: [Self; 4] = [Self::Html, Self::JsonString, Self::Url, Self::Shell];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::JsonString => "json",
+            Self::Url => "url",
+            Self::Shell => "shell",
+        }
+    }
+
+    /// The native function that applies this encoding.
+    const fn function(self) -> &'static str {
+        match self {
+            Self::Html => "__encode_html",
+            Self::JsonString => "__encode_json",
+            Self::Url => "__encode_url",
+            Self::Shell => "__encode_shell",
+        }
+    }
+
+    fn encode(self, input: &str, output: &mut String) {
+        match self {
+            Self::Html => HtmlEncoding.encode_to_string(input, output),
+            Self::JsonString => JsonStringEncoding.encode_to_string(input, output),
+            Self::Url => UrlEncoding.encode_to_string(input, output),
+            Self::Shell => ShellEncoding.encode_to_string(input, output),
+        }
+    }
+
+    /// Parses an encoding name at the start of `code`, which must be
+    /// immediately followed by whitespace and an expression. Returns the
+    /// encoding and the remaining expression.
+    ///
+    /// The name may also be a variable, such as in `{{=url + "/page" }}`, so
+    /// it is only an encoding when the rest begins an operand rather than
+    /// continuing an expression with an operator.
+    fn parse(code: &str) -> Option<(Self, &str)> {
+        Self::ALL.into_iter().find_map(|context| {
+            let expression = code.strip_prefix(context.name())?;
+            (expression.starts_with(char::is_whitespace) && begins_operand(expression))
+                .then_some((context, expression))
+        })
+    }
+}
+
+/// Returns true if `code` begins with an operand, such as a variable or
+/// literal, rather than with an operator.
+fn begins_operand(code: &str) -> bool {
+    let code = code.trim_start();
+    let (word, _) = directive::split_keyword(code);
+    if !word.is_empty() {
+        return !matches!(word, "and" | "or" | "xor");
+    }
+    code.starts_with(['"', '(', '[', '{'])
 }
 
+/// An error that occurred while parsing, compiling, or rendering a template.
+///
+/// The [`kind`](Self::kind) of an error describes what went wrong. Errors
+/// caused by a tag within a template also record where the tag is, and which
+/// registered template it is in.
 #[derive(Debug)]
-struct ParsedTemplate<'a> {
-    source: &'a str,
-    segments: Vec<Segment>,
+#[non_exhaustive]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// The name of the registered template containing the tag that caused
+    /// this error, or `None` for the template being rendered.
+    pub template: Option<String>,
+    /// The byte range of the tag that caused this error, if known.
+    pub span: Option<Range<usize>>,
+    /// The annotated Bud source that failed to compile, for compilation
+    /// errors.
+    generated_source: Option<Box<str>>,
 }
 
-impl<'a> ParsedTemplate<'a> {
-    pub fn to_bud_source(&self, name: &str, parameters: &[Symbol]) -> String {
-        let mut segments = self.segments.iter().cloned().peekable();
-        let mut source = String::with_capacity(self.source.len());
-        source.push_str("function ");
-        source.push_str(name);
-        source.push('(');
-        for (index, param) in parameters.iter().enumerate() {
-            if index > 0 {
-                source.push_str(", ");
-            }
-            source.push_str(param);
+/// The kinds of [`Error`]. More kinds may be added in the future, so matches
+/// on a kind need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    MissingEndBraces,
+    UnexpectedEndBrances,
+    MissingArgument(String),
+    UnknownArgument(String),
+    /// A template was bound using [`CompiledTemplate::bind()`] to a tuple
+    /// with a different number of arguments than it has parameters.
+    ArgumentCount {
+        parameters: usize,
+        arguments: usize,
+    },
+    InvalidStatement(String),
+    UnterminatedBlock(String),
+    UnknownTemplate(String),
+    DuplicateTemplate(String),
+    /// A [`FileLoader`] that requires signed templates read a template
+    /// without a signature, or a signed bundle of templates has no
+    /// signature.
+    UnsignedTemplate(String),
+    /// The signature of a template read by a [`FileLoader`], or of a signed
+    /// bundle of templates, is invalid.
+    InvalidSignature(String),
+    InvalidArgument {
+        function: String,
+        message: String,
+    },
+    Serialization {
+        format: &'static str,
+        message: String,
+    },
+    /// The Bud source generated for a template failed to compile.
+    Compilation(budlang::CompilationError),
+    /// A fault occurred while executing a template.
+    Runtime(FaultKind),
+    /// An error occurred writing the rendered output.
+    Io(std::io::Error),
+    /// Rendering executed more operations than allowed by
+    /// [`Configuration::with_budget`].
+    BudgetExceeded,
+    /// The rendered output grew beyond the length allowed by
+    /// [`Configuration::with_max_output_len`].
+    OutputTooLarge,
+    /// The render was cancelled using the [`CancellationToken`] of its
+    /// [`RenderOptions`].
+    Cancelled,
+    /// The template's [`Quota`] has been exhausted.
+    QuotaExceeded,
+    /// The template was rendered while a render using the same virtual
+    /// machine was in progress, such as an asynchronous render paused by
+    /// another task on the same thread.
+    Busy,
+    /// An expression rendered a floating point number while
+    /// [`Configuration::reject_floats`] is enabled.
+    FloatOutput(f64),
+    /// An operator or condition was given values that the configuration's
+    /// [`CoercionPolicy`] doesn't convert, such as a string added to a
+    /// number.
+    Coercion(String),
+    /// An expression rendered a value that can't be converted to text, such
+    /// as a map, while [`Configuration::lenient_output`] is disabled.
+    UnprintableValue {
+        /// The source of the expression, unless the value was passed to
+        /// `encode()` by Bud code.
+        expression: Option<String>,
+        /// The type of the value, such as `map`.
+        value_type: &'static str,
+    },
+    /// A [`TemplateCache`] was created using a configuration that
+    /// transpiles templates differently.
+    IncompatibleCache,
+    /// The messages added to a catalog for `locale` are invalid.
+    Messages {
+        locale: String,
+        message: String,
+    },
+}
+
+impl Error {
+    #[must_use]
+    pub const fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            template: None,
+            span: None,
+            generated_source: None,
         }
-        source.push_str(")\noutput := \"\"\n");
-        let mut trim_next_start = false;
-        let mut is_at_line_start = true;
+    }
 
-        while let Some(segment) = segments.next() {
-            match segment.kind {
-                SegmentKind::Raw => {
-                    if segment.range.is_empty() {
-                        continue;
-                    }
-                    // Render this as a string literal
-                    if is_at_line_start {
-                        is_at_line_start = false;
-                        source.push_str("output := output + ");
-                    } else {
-                        source.push_str(" + ");
-                    }
-                    let mut literal = &self.source[segment.range];
-                    if trim_next_start {
-                        literal = literal.trim_start();
-                    }
-                    if matches!(segments.peek(), Some(Segment{ kind: SegmentKind::Statement(trimming) | SegmentKind::Expression{ trimming, ..}, .. }) if trimming.trim_before)
-                    {
-                        literal = literal.trim_end();
-                    }
-                    write!(
-                        &mut source,
-                        "{}",
-                        budlang::vm::StringLiteralDisplay::new(literal)
-                    )
-                    .expect("failed to display literal");
-                }
-                SegmentKind::Statement(trimming) => {
-                    trim_next_start = trimming.trim_after;
-                    // A statement that stands on its own line.
-                    if !is_at_line_start {
-                        source.push('\n');
-                        is_at_line_start = true;
-                    }
-                    let statement = self.source[segment.range].trim();
-                    writeln!(&mut source, "{statement}").expect("failed to render statement");
-                }
-                SegmentKind::Expression { trimming, safe } => {
-                    trim_next_start = trimming.trim_after;
-                    // An inline Bud expression
-                    if is_at_line_start {
-                        is_at_line_start = false;
-                        source.push_str("output := output + ");
-                    } else {
-                        source.push_str(" + ");
-                    }
+    /// Returns this error annotated with the location in the template that
+    /// caused it. Errors that already have a location are left unchanged.
+    fn located(mut self, range: Range<usize>) -> Self {
+        if self.span.is_none() {
+            self.span = Some(range);
+        }
+        self
+    }
 
-                    let expression = self.source[segment.range].trim();
-                    if safe {
-                        write!(&mut source, "(({expression}) as String)")
-                            .expect("failed to render expression");
-                    } else {
-                        write!(&mut source, "encode(({expression}) as String)")
-                            .expect("failed to render expression");
-                    }
-                }
-            }
+    /// Records that a located error occurred within the template registered
+    /// as `name`.
+    fn in_template(mut self, name: Option<&str>) -> Self {
+        if self.span.is_some() && self.template.is_none() {
+            self.template = name.map(String::from);
         }
-        source.push_str("\noutput\nend");
+        self
+    }
 
-        println!("{source}");
+    /// Returns an identifier of the kind of this error, such as
+    /// `unknown_template`. See [`ErrorKind::code()`].
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
 
-        source
+    /// Returns the byte range of the tag within the template that caused
+    /// this error, if known.
+    pub fn source_range(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+
+    /// Returns the name of the registered template that caused this error,
+    /// if it was not the template being rendered.
+    pub fn template_name(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    /// Returns the Bud source generated for the template, if this error
+    /// occurred compiling it. The source generated for each tag is preceded
+    /// by a `//` line marking the tag's byte range and template.
+    ///
+    /// A template that fails to compile has usually been translated into
+    /// Bud incorrectly, so the generated source is what a bug report needs.
+    /// It is only kept when compilation fails, rather than being logged for
+    /// every template.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let error = Template::from("<p>{{= 1 + }}</p>").render().unwrap_err();
+    /// assert!(error.generated_source().unwrap().contains("// 3..13\n"));
+    /// ```
+    pub fn generated_source(&self) -> Option<&str> {
+        self.generated_source.as_deref()
+    }
+
+    /// Returns the line and column of this error within `source`, which
+    /// must be the source of the template that caused it.
+    pub fn location(&self, source: &str) -> Option<Location> {
+        self.source_range()
+            .map(|range| Location::of(source, range.start))
     }
 }
 
-#[test]
-fn hello_world_to_bud() {
-    let template = Template::from("Hello, {{= name }}!");
-    let rendered = template
-        .render_with([(Symbol::from("name"), Value::from("World"))])
-        .unwrap();
+impl ErrorKind {
+    /// Returns an error of this kind caused by the tag at `range`.
+    fn located(self, range: Range<usize>) -> Error {
+        Error::new(self).located(range)
+    }
 
-    assert_eq!(rendered, "Hello, World!");
+    /// Returns an identifier of this kind of error, such as
+    /// `unknown_template`. Codes don't change between releases, so tools can
+    /// rely on them to recognize errors.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::MissingEndBraces => "missing_end_braces",
+            ErrorKind::UnexpectedEndBrances => "unexpected_end_braces",
+            ErrorKind::MissingArgument(_) => "missing_argument",
+            ErrorKind::UnknownArgument(_) => "unknown_argument",
+            ErrorKind::ArgumentCount { .. } => "argument_count",
+            ErrorKind::InvalidStatement(_) => "invalid_statement",
+            ErrorKind::UnterminatedBlock(_) => "unterminated_block",
+            ErrorKind::UnknownTemplate(_) => "unknown_template",
+            ErrorKind::DuplicateTemplate(_) => "duplicate_template",
+            ErrorKind::UnsignedTemplate(_) => "unsigned_template",
+            ErrorKind::InvalidSignature(_) => "invalid_signature",
+            ErrorKind::InvalidArgument { .. } => "invalid_argument",
+            ErrorKind::Serialization { .. } => "serialization",
+            ErrorKind::Compilation(_) => "compilation",
+            ErrorKind::Runtime(_) => "runtime",
+            ErrorKind::Io(_) => "io",
+            ErrorKind::BudgetExceeded => "budget_exceeded",
+            ErrorKind::OutputTooLarge => "output_too_large",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::QuotaExceeded => "quota_exceeded",
+            ErrorKind::Busy => "busy",
+            ErrorKind::FloatOutput(_) => "float_output",
+            ErrorKind::Coercion(_) => "coercion",
+            ErrorKind::UnprintableValue { .. } => "unprintable_value",
+            ErrorKind::IncompatibleCache => "incompatible_cache",
+            ErrorKind::Messages { .. } => "invalid_messages",
+        }
+    }
 }
 
-#[test]
-fn trim_tests() {
-    assert_eq!(Template::from(r#" {{= "a" }} "#).render().unwrap(), " a ");
-    assert_eq!(Template::from(r#" {{=- "a" -}} "#).render().unwrap(), "a");
-    assert_eq!(Template::from(r#" {{=- "a" }} "#).render().unwrap(), "a ");
-    assert_eq!(Template::from(r#" {{= "a" -}} "#).render().unwrap(), " a");
-    assert_eq!(
-        Template::from(
-            r#"
-                {{- if true -}}
-                    {{= "a" -}}
-                {{ end -}}
-            "#
-        )
-        .render()
-        .unwrap(),
-        "a"
-    );
+/// A 1-based line and column within a template's source. Columns count
+/// characters rather than bytes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
 }
 
-#[test]
-fn loop_test() {
-    let template = Template::from("{{ loop for i := 1 to 5 inclusive }}{{= i }}{{ end }}");
-    let rendered = template.render().unwrap();
+impl Location {
+    pub fn of(source: &str, offset: usize) -> Self {
+        let before = source.get(..offset).unwrap_or(source);
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
 
-    assert_eq!(rendered, "12345");
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::MissingEndBraces => f.write_str("missing closing `}}`"),
+            ErrorKind::UnexpectedEndBrances => f.write_str("unexpected `}}`"),
+            ErrorKind::MissingArgument(name) => write!(f, "missing argument `{name}`"),
+            ErrorKind::UnknownArgument(name) => write!(f, "unknown argument `{name}`"),
+            ErrorKind::ArgumentCount {
+                parameters,
+                arguments,
+            } => write!(
+                f,
+                "{arguments} arguments bound to a template with {parameters} parameters"
+            ),
+            ErrorKind::InvalidStatement(statement) => write!(f, "invalid statement `{statement}`"),
+            ErrorKind::UnterminatedBlock(name) => write!(f, "`{name}` is missing its `end`"),
+            ErrorKind::UnknownTemplate(name) => write!(f, "unknown template `{name}`"),
+            ErrorKind::DuplicateTemplate(name) => write!(f, "template `{name}` is defined twice"),
+            ErrorKind::UnsignedTemplate(name) => write!(f, "template `{name}` is not signed"),
+            ErrorKind::InvalidSignature(name) => {
+                write!(f, "the signature of template `{name}` is invalid")
+            }
+            ErrorKind::InvalidArgument { function, message } => write!(f, "{function}: {message}"),
+            ErrorKind::Serialization { format, message } => {
+                write!(f, "error serializing {format}: {message}")
+            }
+            ErrorKind::Compilation(err) => write!(f, "compilation error: {err}"),
+            ErrorKind::Runtime(fault) => write!(f, "runtime error: {fault}"),
+            ErrorKind::Io(err) => write!(f, "i/o error: {err}"),
+            ErrorKind::BudgetExceeded => f.write_str("execution budget exceeded"),
+            ErrorKind::OutputTooLarge => f.write_str("rendered output exceeded the maximum length"),
+            ErrorKind::Cancelled => f.write_str("rendering was cancelled"),
+            ErrorKind::QuotaExceeded => f.write_str("rendering quota exceeded"),
+            ErrorKind::Busy => f.write_str("another render of the template is in progress"),
+            ErrorKind::FloatOutput(value) => write!(
+                f,
+                "floating point number `{value}` rendered while floats are rejected"
+            ),
+            ErrorKind::Coercion(message) => f.write_str(message),
+            ErrorKind::UnprintableValue {
+                expression: Some(expression),
+                value_type,
+            } => write!(
+                f,
+                "`{expression}` is a {value_type}, which can't be rendered as text"
+            ),
+            ErrorKind::UnprintableValue {
+                expression: None,
+                value_type,
+            } => write!(f, "a {value_type} can't be rendered as text"),
+            ErrorKind::IncompatibleCache => {
+                f.write_str("the template cache was created with a different configuration")
+            }
+            ErrorKind::Messages { locale, message } => {
+                write!(f, "invalid messages for `{locale}`: {message}")
+            }
+        }
+    }
 }
 
-pub struct CompiledTemplate(Function<budlang::Intrinsic>);
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.template, &self.span) {
+            (Some(template), Some(range)) => {
+                write!(f, "{} (in `{template}` at {range:?})", self.kind)
+            }
+            (Some(template), None) => write!(f, "{} (in `{template}`)", self.kind),
+            (None, Some(range)) => write!(f, "{} (at {range:?})", self.kind),
+            (None, None) => self.kind.fmt(f),
+        }
+    }
+}
 
-pub trait Encoder: Clone + 'static {
-    fn encode<W: Write>(&self, input: &str, output: &mut W);
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Compilation(err) => Some(err),
+            ErrorKind::Io(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct NoEncoding;
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
 
-impl Encoder for NoEncoding {
-    fn encode<W: Write>(&self, input: &str, output: &mut W) {
-        output.write_str(input).unwrap();
+impl From<std::fmt::Error> for Error {
+    fn from(err: std::fmt::Error) -> Self {
+        Self::from(ErrorKind::Io(std::io::Error::other(err)))
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::new(ErrorKind::Io(err))
+    }
+}
+
+impl<Env, Output> From<budlang::Error<'_, Env, Output>> for Error {
+    fn from(err: budlang::Error<'_, Env, Output>) -> Self {
+        Self::new(match err {
+            budlang::Error::Compilation(err) => ErrorKind::Compilation(err),
+            budlang::Error::Fault(fault) => match fault.kind {
+                FaultOrPause::Fault(kind) => ErrorKind::Runtime(kind),
+                // Paused templates are resumed until they exhaust their
+                // budget.
+                FaultOrPause::Pause(_) => ErrorKind::BudgetExceeded,
+            },
+        })
+    }
+}
+
+impl From<FaultKind> for Error {
+    fn from(fault: FaultKind) -> Self {
+        Self::new(ErrorKind::Runtime(fault))
+    }
+}
+
+/// The markers that begin and end a tag within a template.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Delimiters {
+    open: &'static str,
+    close: &'static str,
+}
+
+impl Delimiters {
+    const DEFAULT: Self = Self {
+        open: "{{",
+        close: "}}",
+    };
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WhitespaceTrimming {
+    pub trim_before: bool,
+    pub trim_after: bool,
+}
+
+/// Options controlling how a [`ParsedTemplate`] is transpiled.
+#[derive(Clone, Copy)]
+struct TranspileOptions<'a, 'r> {
+    /// The templates that can be included, if any.
+    templates: Option<&'a Templates<'r>>,
+    exact_whitespace: bool,
+    auto_trim: bool,
+    delimiters: Delimiters,
+    on_missing: OnMissing,
+    memoize_partials: bool,
+    /// Whether the arguments of `t()` and `plural()` are named for a
+    /// message catalog.
+    messages: bool,
+    /// Whether output is marked with the tags that produce it.
+    annotate_output: bool,
+    /// Whether the variables, functions, and templates each tag accesses
+    /// are recorded.
+    audit_accesses: bool,
+    /// Whether operators and conditions are evaluated by a
+    /// [`CoercionPolicy`].
+    coercion: bool,
+    /// The templates whose includes are rendered as edge markers, if any.
+    edge_includes: Option<&'a EdgeIncludes>,
+    /// The number of sub-template functions that have already been
+    /// transpiled into the same virtual machine, which the functions of this
+    /// template are numbered after.
+    first_instance: usize,
+}
+
+impl TranspileOptions<'_, '_> {
+    const DEFAULT: Self = Self {
+        templates: None,
+        exact_whitespace: false,
+        auto_trim: false,
+        delimiters: Delimiters::DEFAULT,
+        on_missing: OnMissing::Fail,
+        memoize_partials: false,
+        messages: false,
+        annotate_output: false,
+        audit_accesses: false,
+        coercion: false,
+        edge_includes: None,
+        first_instance: 0,
+    };
+}
+
+#[derive(Debug)]
+struct ParsedTemplate<'a> {
+    source: &'a str,
+    segments: Vec<Segment>,
+}
+
+impl<'a> ParsedTemplate<'a> {
+    /// Returns true if the code of any statement or expression calls the
+    /// function named `function`.
+    fn calls(&self, function: &str) -> bool {
+        self.segments.iter().any(|segment| {
+            matches!(
+                segment.kind,
+                SegmentKind::Statement(_) | SegmentKind::Expression { .. }
+            ) && {
+                let code = &self.source[segment.range.clone()];
+                expression::calls(code)
+                    .into_iter()
+                    .any(|call| &code[call] == function)
+            }
+        })
+    }
+
+    pub fn into_bud_source(
+        self,
+        name: &str,
+        parameters: &[Symbol],
+        options: TranspileOptions<'a, '_>,
+    ) -> Result<BudSource, Error> {
+        let source = String::with_capacity(self.source.len());
+        let mut transpiler = Transpiler {
+            units: vec![Rc::new(Unit::new(None, self)?)],
+            templates: options.templates,
+            exact_whitespace: options.exact_whitespace,
+            auto_trim: options.auto_trim,
+            delimiters: options.delimiters,
+            on_missing: options.on_missing,
+            memoize_partials: options.memoize_partials,
+            messages: options.messages,
+            annotate_output: options.annotate_output,
+            audit_accesses: options.audit_accesses,
+            coercion: options.coercion,
+            edge_includes: options.edge_includes,
+            first_instance: options.first_instance,
+            parameters: Vec::new(),
+            instances: Vec::new(),
+            slots: 0,
+            loops: 0,
+            memos: 0,
+            source,
+            spans: Vec::new(),
+        };
+        let body = 0..transpiler.units[0].template.segments.len();
+        transpiler.function(0, name, parameters, body)?;
+
+        // Rendering a sub-template can instantiate further sub-templates, so
+        // the list of instances may grow while it is being emitted.
+        let mut next_instance = 0;
+        while let Some(instance) = transpiler.instances.get(next_instance).cloned() {
+            let unit = transpiler.units[instance.unit].clone();
+            let body = match &instance.define {
+                Some(define) => unit.defines[define].body.clone(),
+                None => 0..unit.template.segments.len(),
+            };
+            transpiler
+                .function(
+                    instance.unit,
+                    &instance.function,
+                    &instance.parameters,
+                    body,
+                )
+                .map_err(|err| err.in_template(unit.name.as_deref()))?;
+            next_instance += 1;
+        }
+
+        Ok(BudSource {
+            source: transpiler.source,
+            instances: transpiler.instances.len(),
+            spans: transpiler.spans,
+            templates: transpiler
+                .units
+                .iter()
+                .map(|unit| unit.name.clone())
+                .collect(),
+            metadata: transpiler.units[0].metadata.clone(),
+        })
+    }
+
+    /// Reconstructs the source of this template from its segments, which
+    /// were parsed using `delimiters`. Raw text and comments are reproduced
+    /// exactly, while each tag's code is surrounded by a single space,
+    /// producing a normalized form of the original source.
+    fn to_source(&self, delimiters: Delimiters) -> String {
+        let Delimiters { open, close } = delimiters;
+        let mut source = String::with_capacity(self.source.len());
+        for segment in &self.segments {
+            let code = &self.source[segment.range.clone()];
+            let (trimming, prefix, code) = match segment.kind {
+                SegmentKind::Raw => {
+                    source.push_str(code);
+                    continue;
+                }
+                SegmentKind::Comment(trimming)
+                    if self.source[segment.tag.start + open.len()..].starts_with('#') =>
+                {
+                    source.push_str(open);
+                    source.push('#');
+                    if trimming.trim_before {
+                        source.push('-');
+                    }
+                    source.push_str(code);
+                    if trimming.trim_after {
+                        source.push('-');
+                    }
+                    source.push('#');
+                    source.push_str(close);
+                    continue;
+                }
+                // The closing tag of a raw block is recorded as a whole.
+                SegmentKind::Comment(trimming) if segment.range == segment.tag => {
+                    (trimming, "", "end raw")
+                }
+                SegmentKind::Comment(trimming) | SegmentKind::Statement(trimming) => {
+                    (trimming, "", code.trim())
+                }
+                SegmentKind::Expression {
+                    trimming,
+                    safe: true,
+                    ..
+                } => (trimming, ":=", code.trim()),
+                SegmentKind::Expression {
+                    trimming,
+                    safe: false,
+                    ..
+                } => (trimming, "=", code.trim()),
+            };
+
+            source.push_str(open);
+            source.push_str(prefix);
+            if trimming.trim_before {
+                source.push('-');
+            }
+            if let SegmentKind::Expression {
+                context: Some(context),
+                ..
+            } = segment.kind
+            {
+                source.push_str(context.name());
+            }
+            source.push(' ');
+            if !code.is_empty() {
+                source.push_str(code);
+                source.push(' ');
+            }
+            if trimming.trim_after {
+                source.push('-');
+            }
+            source.push_str(close);
+        }
+        source
+    }
+
+    fn disable_trimming(&mut self) {
+        for segment in &mut self.segments {
+            match &mut segment.kind {
+                SegmentKind::Raw => {}
+                SegmentKind::Comment(trimming)
+                | SegmentKind::Statement(trimming)
+                | SegmentKind::Expression { trimming, .. } => {
+                    *trimming = WhitespaceTrimming::default();
+                }
+            }
+        }
+    }
+
+    /// Removes the line containing each statement or comment that is alone
+    /// on its line, so that tags used only for control flow don't leave
+    /// blank lines or indentation in the output.
+    fn trim_standalone_tags(&mut self) {
+        let source = self.source;
+        for index in 0..self.segments.len() {
+            if matches!(
+                self.segments[index].kind,
+                SegmentKind::Raw | SegmentKind::Expression { .. }
+            ) {
+                continue;
+            }
+
+            let tag = self.segments[index].tag.clone();
+            let line_start = source[..tag.start]
+                .rfind('\n')
+                .map_or(0, |newline| newline + 1);
+            let line_end = source[tag.end..]
+                .find('\n')
+                .map_or(source.len(), |newline| tag.end + newline + 1);
+            if !source[line_start..tag.start].trim().is_empty()
+                || !source[tag.end..line_end].trim().is_empty()
+            {
+                continue;
+            }
+
+            if let Some(previous) = index
+                .checked_sub(1)
+                .map(|previous| &mut self.segments[previous])
+                .filter(|previous| matches!(previous.kind, SegmentKind::Raw))
+            {
+                previous.range.end = previous.range.end.min(line_start).max(previous.range.start);
+            }
+            if let Some(next) = self
+                .segments
+                .get_mut(index + 1)
+                .filter(|next| matches!(next.kind, SegmentKind::Raw))
+            {
+                next.range.start = next.range.start.max(line_end).min(next.range.end);
+            }
+        }
+    }
+
+    fn statement(&self, segment: &Segment) -> Result<Option<Directive<'_>>, Error> {
+        directive::parse(self.source[segment.range.clone()].trim())
+            .map_err(|err| err.located(segment.tag.clone()))
+    }
+
+    /// Locates the definitions, blocks, and `extends` directive within this
+    /// template.
+    fn outline(&self) -> Result<Outline, Error> {
+        enum Open {
+            Define(String, usize),
+            Block(String, usize),
+            Component(String, usize),
+            Fragment(String, usize),
+            Defer(String, usize),
+            Bud,
+        }
+
+        let mut outline = Outline::default();
+        let mut open = Vec::new();
+        let mut close = |open: Open, end: usize| -> Result<(), Error> {
+            let (name, start, map) = match open {
+                Open::Define(name, start) => (name, start, &mut outline.defines),
+                Open::Block(name, start) => (name, start, &mut outline.blocks),
+                Open::Component(_, start) => {
+                    outline.components.insert(start, start + 1..end);
+                    return Ok(());
+                }
+                Open::Defer(_, start) => {
+                    outline.defers.insert(start, start + 1..end);
+                    return Ok(());
+                }
+                Open::Fragment(..) | Open::Bud => return Ok(()),
+            };
+            let define = Define {
+                start,
+                body: start + 1..end,
+            };
+            if map.insert(name.clone(), define).is_some() {
+                return Err(
+                    ErrorKind::DuplicateTemplate(name).located(self.segments[start].tag.clone())
+                );
+            }
+            Ok(())
+        };
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            if !matches!(segment.kind, SegmentKind::Statement(_)) {
+                continue;
+            }
+            let invalid = || {
+                ErrorKind::InvalidStatement(self.source[segment.range.clone()].trim().to_string())
+                    .located(segment.tag.clone())
+            };
+            match self.statement(segment)? {
+                Some(Directive::Define { name }) => {
+                    if let Some(Open::Define(open, start)) =
+                        open.iter().find(|open| matches!(open, Open::Define(..)))
+                    {
+                        return Err(ErrorKind::UnterminatedBlock(open.clone())
+                            .located(self.segments[*start].tag.clone()));
+                    }
+                    open.push(Open::Define(name.into_owned(), index));
+                }
+                Some(Directive::Block { name }) => open.push(Open::Block(name.into_owned(), index)),
+                Some(Directive::Component { name, .. }) => {
+                    open.push(Open::Component(name.into_owned(), index));
+                }
+                Some(Directive::Fragment { name }) => {
+                    // Fragments divide the output of a render, so they can't
+                    // be nested.
+                    if let Some(Open::Fragment(open, start)) =
+                        open.iter().find(|open| matches!(open, Open::Fragment(..)))
+                    {
+                        return Err(ErrorKind::UnterminatedBlock(open.clone())
+                            .located(self.segments[*start].tag.clone()));
+                    }
+                    open.push(Open::Fragment(name.into_owned(), index));
+                }
+                Some(
+                    closing @ (Directive::EndDefine
+                    | Directive::EndBlock
+                    | Directive::EndComponent
+                    | Directive::EndFragment),
+                ) => match open.pop() {
+                    Some(innermost)
+                        if matches!(
+                            (&innermost, &closing),
+                            (Open::Define(..), Directive::EndDefine)
+                                | (Open::Block(..), Directive::EndBlock)
+                                | (Open::Component(..), Directive::EndComponent)
+                                | (Open::Fragment(..), Directive::EndFragment)
+                        ) =>
+                    {
+                        close(innermost, index)?;
+                    }
+                    Some(
+                        Open::Define(name, start)
+                        | Open::Block(name, start)
+                        | Open::Component(name, start)
+                        | Open::Fragment(name, start)
+                        | Open::Defer(name, start),
+                    ) => {
+                        return Err(ErrorKind::UnterminatedBlock(name)
+                            .located(self.segments[start].tag.clone()))
+                    }
+                    _ => return Err(invalid()),
+                },
+                Some(Directive::Extends { name }) => {
+                    if !open.is_empty() || outline.extends.is_some() {
+                        return Err(invalid());
+                    }
+                    outline.extends = Some((name.into_owned(), index));
+                }
+                Some(Directive::Defer { name }) => open.push(Open::Defer(name.to_string(), index)),
+                Some(Directive::Meta { entries }) => {
+                    for (name, value) in entries {
+                        if outline
+                            .metadata
+                            .insert(name.to_string(), value.into_owned())
+                            .is_some()
+                        {
+                            return Err(invalid());
+                        }
+                    }
+                }
+                Some(directive) => {
+                    if directive.opens() == Some(BlockKind::Bud) {
+                        open.push(Open::Bud);
+                    }
+                }
+                None => {
+                    let statement = self.source[segment.range.clone()].trim();
+                    if directive::is_end(statement) {
+                        // A bare `end` closes the innermost open block, which
+                        // may be a Bud construct such as `if` or `loop`.
+                        if let Some(innermost) = open.pop() {
+                            close(innermost, index)?;
+                        }
+                    } else if directive::opens_block(statement) {
+                        open.push(Open::Bud);
+                    }
+                }
+            }
+        }
+
+        match open
+            .into_iter()
+            .rev()
+            .find(|open| !matches!(open, Open::Bud))
+        {
+            Some(
+                Open::Define(name, start)
+                | Open::Block(name, start)
+                | Open::Component(name, start)
+                | Open::Fragment(name, start)
+                | Open::Defer(name, start),
+            ) => Err(ErrorKind::UnterminatedBlock(name).located(self.segments[start].tag.clone())),
+            _ => Ok(outline),
+        }
+    }
+}
+
+/// The structure of a template, as found by [`ParsedTemplate::outline`].
+#[derive(Debug, Default)]
+struct Outline {
+    defines: HashMap<String, Define>,
+    blocks: HashMap<String, Define>,
+    /// The bodies of the `component` statements, keyed by the index of each
+    /// statement.
+    components: HashMap<usize, Range<usize>>,
+    /// The bodies of the `defer` statements, keyed by the index of each
+    /// statement.
+    defers: HashMap<usize, Range<usize>>,
+    /// The entries of the `meta` statements.
+    metadata: BTreeMap<String, String>,
+    /// The name of the template this template extends, along with the index
+    /// of the `extends` statement.
+    extends: Option<(String, usize)>,
+}
+
 #[derive(Debug, Clone)]
-pub struct HtmlEncoding;
+struct Define {
+    start: usize,
+    body: Range<usize>,
+}
+
+/// Bud source generated from a template, along with the information needed
+/// to map locations within it back to the template.
+struct BudSource {
+    source: String,
+    /// The number of sub-template functions defined after the template's
+    /// render function.
+    instances: usize,
+    spans: Vec<SourceSpan>,
+    /// The names of the templates referenced by `spans`.
+    templates: Vec<Option<String>>,
+    /// The metadata declared by the template.
+    metadata: BTreeMap<String, String>,
+}
+
+/// Marks the start of the Bud source generated for a template tag.
+struct SourceSpan {
+    offset: usize,
+    unit: usize,
+    range: Range<usize>,
+}
+
+impl BudSource {
+    /// Annotates a compilation error with the location of the template tag
+    /// that generated the offending Bud source, and with the annotated
+    /// source itself.
+    fn locate(&self, mut error: Error) -> Error {
+        let ErrorKind::Compilation(compilation) = &error.kind else {
+            return error;
+        };
+        let offset = compilation_error_offset(compilation);
+        error.generated_source = Some(self.annotated().into_boxed_str());
+        let Some(offset) = offset else {
+            return error;
+        };
+        let index = self.spans.partition_point(|span| span.offset <= offset);
+        match index.checked_sub(1).map(|index| &self.spans[index]) {
+            Some(span) => error
+                .located(span.range.clone())
+                .in_template(self.templates[span.unit].as_deref()),
+            None => error,
+        }
+    }
+
+    /// Returns the source with a comment before the source of each tag,
+    /// naming the tag's byte range and the template containing it.
+    fn annotated(&self) -> String {
+        let mut annotated = String::with_capacity(self.source.len() + self.spans.len() * 16);
+        let mut written = 0;
+        for span in &self.spans {
+            annotated.push_str(&self.source[written..span.offset]);
+            written = span.offset;
+            if !annotated.is_empty() && !annotated.ends_with('\n') {
+                annotated.push('\n');
+            }
+            write!(annotated, "// {}..{}", span.range.start, span.range.end)
+                .expect("String does not fail to write");
+            if let Some(template) = &self.templates[span.unit] {
+                write!(annotated, " in `{template}`").expect("String does not fail to write");
+            }
+            annotated.push('\n');
+        }
+        annotated.push_str(&self.source[written..]);
+        annotated
+    }
+}
+
+/// Returns the offset within the Bud source where `error` occurred.
+fn compilation_error_offset(error: &budlang::CompilationError) -> Option<usize> {
+    error.range().map(|range| range.start)
+}
+
+/// A template being transpiled, along with the definitions it contains.
+#[derive(Debug)]
+struct Unit<'a> {
+    /// The name this template is registered under, or `None` for the
+    /// template being compiled.
+    name: Option<String>,
+    template: ParsedTemplate<'a>,
+    defines: HashMap<String, Define>,
+    blocks: HashMap<String, Define>,
+    components: HashMap<usize, Range<usize>>,
+    defers: HashMap<usize, Range<usize>>,
+    metadata: BTreeMap<String, String>,
+    extends: Option<(String, usize)>,
+}
+
+impl<'a> Unit<'a> {
+    fn new(name: Option<String>, template: ParsedTemplate<'a>) -> Result<Self, Error> {
+        let Outline {
+            defines,
+            blocks,
+            components,
+            defers,
+            metadata,
+            extends,
+        } = template.outline()?;
+        Ok(Self {
+            name,
+            template,
+            defines,
+            blocks,
+            components,
+            defers,
+            metadata,
+            extends,
+        })
+    }
+}
+
+/// A function rendering either a `define` within a unit, or the entirety of
+/// an included unit when `define` is `None`.
+#[derive(Debug, Clone)]
+struct Instance {
+    unit: usize,
+    define: Option<String>,
+    parameters: Vec<String>,
+    function: String,
+}
+
+struct Transpiler<'a, 'r> {
+    units: Vec<Rc<Unit<'a>>>,
+    templates: Option<&'a Templates<'r>>,
+    exact_whitespace: bool,
+    auto_trim: bool,
+    delimiters: Delimiters,
+    on_missing: OnMissing,
+    memoize_partials: bool,
+    messages: bool,
+    annotate_output: bool,
+    audit_accesses: bool,
+    coercion: bool,
+    edge_includes: Option<&'a EdgeIncludes>,
+    first_instance: usize,
+    /// The parameters of the function being emitted.
+    parameters: Vec<String>,
+    instances: Vec<Instance>,
+    /// The number of component slots emitted so far.
+    slots: usize,
+    /// The number of `for` statements emitted so far.
+    loops: usize,
+    /// The number of memoized sub-template calls emitted so far.
+    memos: usize,
+    source: String,
+    spans: Vec<SourceSpan>,
+}
+
+impl<'a> Transpiler<'a, '_> {
+    fn function<Param: Deref<Target = str>>(
+        &mut self,
+        unit_index: usize,
+        name: &str,
+        parameters: &[Param],
+        body: Range<usize>,
+    ) -> Result<(), Error> {
+        self.source.push_str("function ");
+        self.source.push_str(name);
+        self.source.push('(');
+        for (index, param) in parameters.iter().enumerate() {
+            if index > 0 {
+                self.source.push_str(", ");
+            }
+            self.source.push_str(param);
+        }
+        self.source.push_str(")\n");
+        self.parameters = parameters.iter().map(|param| param.to_string()).collect();
+
+        let chain = self.chain(unit_index)?;
+        let base = chain[chain.len() - 1];
+        if base != unit_index && body == (0..self.units[unit_index].template.segments.len()) {
+            // A template that extends another renders as its base template,
+            // with its own blocks taking the place of the base's.
+            let body = 0..self.units[base].template.segments.len();
+            self.emit(&chain, base, body)?;
+        } else {
+            self.emit(&chain, unit_index, body)?;
+        }
+        self.source.push_str("end\n");
+
+        Ok(())
+    }
+
+    /// Returns the chain of templates that `unit_index` extends, starting
+    /// with `unit_index` itself and ending with the base template.
+    fn chain(&mut self, unit_index: usize) -> Result<Vec<usize>, Error> {
+        let mut chain = vec![unit_index];
+        loop {
+            let unit = self.units[chain[chain.len() - 1]].clone();
+            let Some((name, statement)) = &unit.extends else {
+                return Ok(chain);
+            };
+            let segment = &unit.template.segments[*statement];
+            let parent = self
+                .include(name)
+                .map_err(|err| err.located(segment.tag.clone()))?;
+            if chain.contains(&parent) {
+                return Err(ErrorKind::InvalidStatement(
+                    unit.template.source[segment.range.clone()]
+                        .trim()
+                        .to_string(),
+                )
+                .located(segment.tag.clone())
+                .in_template(unit.name.as_deref()));
+            }
+            chain.push(parent);
+        }
+    }
+
+    /// Emits the segments in `body` of `unit_index`. Blocks are resolved
+    /// against `chain`, the most derived template's override winning.
+    fn emit(
+        &mut self,
+        chain: &[usize],
+        unit_index: usize,
+        body: Range<usize>,
+    ) -> Result<(), Error> {
+        let unit = self.units[unit_index].clone();
+        // `class()` scopes class names to the registered template whose
+        // code calls it.
+        let scope = unit.name.as_ref().filter(|_| unit.template.calls("class"));
+        if let Some(scope) = scope {
+            writeln!(
+                &mut self.source,
+                "__push_scope({})",
+                budlang::vm::StringLiteralDisplay::new(scope)
+            )?;
+        }
+        self.emit_segments(chain, unit_index, body)
+            .map_err(|err| err.in_template(unit.name.as_deref()))?;
+        if scope.is_some() {
+            self.source.push_str("__pop_scope()\n");
+        }
+        Ok(())
+    }
+
+    /// Marks the output that follows as produced by `segment` of `unit`,
+    /// when output is annotated.
+    fn annotate(&mut self, unit: &Unit<'_>, segment: &Segment) -> Result<(), Error> {
+        if !self.annotate_output {
+            return Ok(());
+        }
+        write!(
+            &mut self.source,
+            "__at({}, {}",
+            segment.tag.start, segment.tag.end
+        )?;
+        if let Some(name) = &unit.name {
+            write!(
+                &mut self.source,
+                ", {}",
+                budlang::vm::StringLiteralDisplay::new(name)
+            )?;
+        }
+        self.source.push_str(")\n");
+        Ok(())
+    }
+
+    /// Records the accesses of `segment` of `unit` when it is executed, when
+    /// accesses are audited.
+    fn audit(
+        &mut self,
+        unit: &Unit<'_>,
+        segment: &Segment,
+        accesses: Vec<(AccessKind, &str)>,
+    ) -> Result<(), Error> {
+        if !self.audit_accesses {
+            return Ok(());
+        }
+        for (kind, name) in accesses {
+            write!(
+                &mut self.source,
+                "__audit({}, {}, {}, {}",
+                budlang::vm::StringLiteralDisplay::new(kind.as_str()),
+                budlang::vm::StringLiteralDisplay::new(name),
+                segment.tag.start,
+                segment.tag.end
+            )?;
+            if let Some(name) = &unit.name {
+                write!(
+                    &mut self.source,
+                    ", {}",
+                    budlang::vm::StringLiteralDisplay::new(name)
+                )?;
+            }
+            self.source.push_str(")\n");
+        }
+        Ok(())
+    }
+
+    /// Records the accesses of the statement `segment` of `unit`, when
+    /// accesses are audited.
+    fn audit_statement(
+        &mut self,
+        unit: &Unit<'_>,
+        segment: &Segment,
+        directive: Option<&Directive<'_>>,
+    ) -> Result<(), Error> {
+        if !self.audit_accesses {
+            return Ok(());
+        }
+        let code = unit.template.source[segment.range.clone()].trim();
+        fn arguments<'a>(arguments: &[Argument<'a>], accesses: &mut Vec<(AccessKind, &'a str)>) {
+            for argument in arguments {
+                if std::ptr::eq(argument.name, argument.value) {
+                    accesses.push((AccessKind::Variable, argument.name));
+                } else {
+                    accesses.extend(audit::accesses(argument.value));
+                }
+            }
+        }
+
+        let mut accesses = Vec::new();
+        match directive {
+            Some(
+                Directive::Include {
+                    name,
+                    arguments: args,
+                }
+                | Directive::Component {
+                    name,
+                    arguments: args,
+                },
+            ) => {
+                accesses.push((AccessKind::Include, &**name));
+                arguments(args, &mut accesses);
+            }
+            Some(
+                Directive::Render {
+                    arguments: args, ..
+                }
+                | Directive::Island {
+                    arguments: args, ..
+                },
+            ) => arguments(args, &mut accesses),
+            Some(Directive::For { collection, .. }) => {
+                accesses.extend(audit::accesses(collection));
+            }
+            // Nothing can be executed between the branches of an `if`, so
+            // the accesses of an `else if` condition are recorded within its
+            // branch.
+            Some(_) | None if code.starts_with("else") => {}
+            Some(_) => {}
+            None => accesses = audit::accesses(code),
+        }
+        self.audit(unit, segment, accesses)
+    }
+
+    fn emit_segments(
+        &mut self,
+        chain: &[usize],
+        unit_index: usize,
+        body: Range<usize>,
+    ) -> Result<(), Error> {
+        let unit = self.units[unit_index].clone();
+        let segments = &unit.template.segments;
+        // A definition's body honors the trimming of its opening tag.
+        let mut trim_next_start = body.start.checked_sub(1).is_some_and(|opening| {
+            matches!(
+                segments[opening].kind,
+                SegmentKind::Statement(trimming) if trimming.trim_after
+            )
+        });
+
+        let mut index = body.start;
+        while index < body.end {
+            let segment = &segments[index];
+            index += 1;
+            self.spans.push(SourceSpan {
+                offset: self.source.len(),
+                unit: unit_index,
+                range: segment.tag.clone(),
+            });
+            match segment.kind {
+                SegmentKind::Raw => {
+                    let mut literal = &unit.template.source[segment.range.clone()];
+                    if trim_next_start {
+                        literal = literal.trim_start();
+                    }
+                    if matches!(segments.get(index), Some(Segment{ kind: SegmentKind::Comment(trimming) | SegmentKind::Statement(trimming) | SegmentKind::Expression{ trimming, ..}, .. }) if trimming.trim_before)
+                    {
+                        literal = literal.trim_end();
+                    }
+                    if literal.is_empty() {
+                        continue;
+                    }
+                    self.annotate(&unit, segment)?;
+                    // Render this as a string literal
+                    writeln!(
+                        &mut self.source,
+                        "__write({})",
+                        budlang::vm::StringLiteralDisplay::new(literal)
+                    )?;
+                }
+                SegmentKind::Statement(trimming) => {
+                    trim_next_start = trimming.trim_after;
+                    let statement = unit.template.statement(segment)?;
+                    self.audit_statement(&unit, segment, statement.as_ref())?;
+                    match statement {
+                        Some(Directive::Define { name }) => {
+                            // Definitions are emitted as their own functions.
+                            // Skip past the body, honoring the trimming of the
+                            // closing `end define`.
+                            let end = unit.defines[&*name].body.end;
+                            debug_assert_eq!(unit.defines[&*name].start, index - 1);
+                            if let SegmentKind::Statement(trimming) = segments[end].kind {
+                                trim_next_start = trimming.trim_after;
+                            }
+                            index = end + 1;
+                        }
+                        Some(Directive::Block { name }) => {
+                            // Blocks are rendered in place so that they can
+                            // access the variables in scope where they are
+                            // declared.
+                            let overriding = chain
+                                .iter()
+                                .copied()
+                                .find(|&unit| self.units[unit].blocks.contains_key(&*name))
+                                .unwrap_or(unit_index);
+                            let overriding_body =
+                                self.units[overriding].blocks[&*name].body.clone();
+                            self.emit(chain, overriding, overriding_body)?;
+
+                            let end = unit.blocks[&*name].body.end;
+                            if let SegmentKind::Statement(trimming) = segments[end].kind {
+                                trim_next_start = trimming.trim_after;
+                            }
+                            index = end + 1;
+                        }
+                        Some(Directive::Component { name, arguments }) => {
+                            // The body is rendered in place into a buffer,
+                            // which the component receives as `slot`.
+                            let body = unit.components[&(index - 1)].clone();
+                            let component = self
+                                .include(&name)
+                                .map_err(|err| err.located(segment.tag.clone()))?;
+                            let end = body.end;
+                            self.source.push_str("__capture()\n");
+                            self.emit(chain, unit_index, body)?;
+
+                            let slot = format!("__slot_{}", self.slots);
+                            self.slots += 1;
+                            writeln!(&mut self.source, "{slot} := __captured()")?;
+                            let mut arguments = arguments;
+                            arguments.push(Argument {
+                                name: "slot",
+                                value: &slot,
+                            });
+                            let call = self.instantiate(component, None, &arguments);
+                            self.source.push_str(&call);
+                            self.source.push('\n');
+
+                            if let SegmentKind::Statement(trimming) = segments[end].kind {
+                                trim_next_start = trimming.trim_after;
+                            }
+                            index = end + 1;
+                        }
+                        Some(
+                            Directive::EndDefine | Directive::EndBlock | Directive::EndComponent,
+                        ) => {
+                            // Closing statements are skipped with their
+                            // bodies, so this one has no opening statement.
+                            return Err(ErrorKind::InvalidStatement(
+                                unit.template.source[segment.range.clone()]
+                                    .trim()
+                                    .to_string(),
+                            )
+                            .located(segment.tag.clone()));
+                        }
+                        Some(Directive::Extends { .. } | Directive::Meta { .. }) => {
+                            // Handled before the template is emitted.
+                        }
+                        Some(Directive::Render { name, arguments }) => {
+                            if !unit.defines.contains_key(&*name) {
+                                return Err(ErrorKind::UnknownTemplate(name.into_owned())
+                                    .located(segment.tag.clone()));
+                            }
+                            self.call(unit_index, Some(&name), &arguments)?;
+                        }
+                        Some(Directive::Include { name, arguments })
+                            if self
+                                .edge_includes
+                                .is_some_and(|edge_includes| edge_includes.contains(&name)) =>
+                        {
+                            // The included template is rendered separately,
+                            // and its arguments are passed as alternating
+                            // names and values.
+                            let mut marker = format!(
+                                "__write(__edge_include({}",
+                                budlang::vm::StringLiteralDisplay::new(&name)
+                            );
+                            for argument in &arguments {
+                                write!(
+                                    &mut marker,
+                                    ", {}, {}",
+                                    budlang::vm::StringLiteralDisplay::new(argument.name),
+                                    self.argument(argument.value)
+                                )?;
+                            }
+                            writeln!(&mut self.source, "{marker}))")?;
+                        }
+                        Some(Directive::Include { name, arguments }) => {
+                            let included = self
+                                .include(&name)
+                                .map_err(|err| err.located(segment.tag.clone()))?;
+                            self.call(included, None, &arguments)?;
+                        }
+                        Some(Directive::Defer { name }) => {
+                            // The body is rendered in place into a buffer,
+                            // which is added to the content written by the
+                            // matching `emit`.
+                            let body = unit.defers[&(index - 1)].clone();
+                            let end = body.end;
+                            self.source.push_str("__capture()\n");
+                            self.emit(chain, unit_index, body)?;
+                            writeln!(
+                                &mut self.source,
+                                "__defer({}, __captured())",
+                                budlang::vm::StringLiteralDisplay::new(name)
+                            )?;
+
+                            if let SegmentKind::Statement(trimming) = segments[end].kind {
+                                trim_next_start = trimming.trim_after;
+                            }
+                            index = end + 1;
+                        }
+                        Some(Directive::Emit { name }) => {
+                            writeln!(
+                                &mut self.source,
+                                "__write(__emit({}))",
+                                budlang::vm::StringLiteralDisplay::new(name)
+                            )?;
+                        }
+                        Some(Directive::Island { name, arguments }) => {
+                            // The props are passed as alternating names and
+                            // values.
+                            let mut island = format!(
+                                "__write(__island({}",
+                                budlang::vm::StringLiteralDisplay::new(&name)
+                            );
+                            for argument in &arguments {
+                                write!(
+                                    &mut island,
+                                    ", {}, {}",
+                                    budlang::vm::StringLiteralDisplay::new(argument.name),
+                                    self.argument(argument.value)
+                                )?;
+                            }
+                            writeln!(&mut self.source, "{island}))")?;
+                        }
+                        Some(Directive::Fragment { name }) => {
+                            writeln!(
+                                &mut self.source,
+                                "__fragment_begin({})",
+                                budlang::vm::StringLiteralDisplay::new(&name)
+                            )?;
+                        }
+                        Some(Directive::EndFragment) => {
+                            self.source.push_str("__fragment_end()\n");
+                        }
+                        Some(Directive::Feature { name }) => {
+                            // The region, and any `else`, are closed by the
+                            // matching `end`.
+                            writeln!(
+                                &mut self.source,
+                                "if __feature({})",
+                                budlang::vm::StringLiteralDisplay::new(&name)
+                            )?;
+                        }
+                        Some(Directive::Sampled { rate }) => {
+                            writeln!(&mut self.source, "if __sampled({rate:?})")?;
+                        }
+                        Some(Directive::Once) => {
+                            // Each region is identified by its template and
+                            // location, so that it is rendered once however
+                            // many times its template is included.
+                            let region = format!(
+                                "{}@{}",
+                                unit.name.as_deref().unwrap_or_default(),
+                                segment.tag.start
+                            );
+                            writeln!(
+                                &mut self.source,
+                                "if __once({})",
+                                budlang::vm::StringLiteralDisplay::new(&region)
+                            )?;
+                        }
+                        Some(Directive::For {
+                            key,
+                            value,
+                            collection,
+                        }) => {
+                            // The body is emitted by the following segments,
+                            // and the loop is closed by the matching `end`.
+                            let collection = expression::rewrite_fallbacks(collection, &|name| {
+                                self.declared(name)
+                            });
+                            let collection = self.coerce(&collection);
+                            let entries = format!("__entries_{}", self.loops);
+                            let entry = format!("__entry_{}", self.loops);
+                            self.loops += 1;
+                            writeln!(
+                                &mut self.source,
+                                "{entries} := __entries({collection})\n\
+                                 loop for {entry} := 0 to __entry_count({entries})\n\
+                                 {value} := __entry_value({entries}, {entry})"
+                            )?;
+                            if let Some(key) = key {
+                                writeln!(
+                                    &mut self.source,
+                                    "{key} := __entry_key({entries}, {entry})"
+                                )?;
+                            }
+                        }
+                        None => {
+                            // A statement that stands on its own line.
+                            let statement =
+                                self.messages(unit.template.source[segment.range.clone()].trim());
+                            let statement =
+                                expression::rewrite(&statement, &|name| self.declared(name));
+                            let statement = if self.coercion {
+                                Cow::Owned(coercion::rewrite_statement(&statement).into_owned())
+                            } else {
+                                statement
+                            };
+                            writeln!(&mut self.source, "{statement}")?;
+                            if statement.starts_with("else") {
+                                let code = unit.template.source[segment.range.clone()].trim();
+                                self.audit(&unit, segment, audit::accesses(code))?;
+                            }
+                        }
+                    }
+                }
+                SegmentKind::Comment(trimming) => {
+                    trim_next_start = trimming.trim_after;
+                }
+                SegmentKind::Expression {
+                    trimming,
+                    safe,
+                    context,
+                } => {
+                    trim_next_start = trimming.trim_after;
+                    self.annotate(&unit, segment)?;
+                    // An inline Bud expression, written to the output as it
+                    // is evaluated.
+                    let code = unit.template.source[segment.range.clone()].trim();
+                    self.audit(&unit, segment, audit::accesses(code))?;
+                    let expression = self.messages(code);
+                    let expression =
+                        expression::rewrite_fallbacks(&expression, &|name| self.declared(name));
+                    let expression = self.coerce(&expression);
+                    // The expression's source is passed along with its value
+                    // to describe values that can't be rendered.
+                    let literal = budlang::vm::StringLiteralDisplay::new(code);
+                    let mut value = if safe {
+                        format!("{expression}, {literal}")
+                    } else if let Some(context) = context {
+                        format!("{}({expression}, {literal})", context.function())
+                    } else {
+                        format!("encode({expression}, {literal})")
+                    };
+                    match self.on_missing {
+                        OnMissing::Keep => {
+                            value = format!(
+                                "__keep({value}, {})",
+                                budlang::vm::StringLiteralDisplay::new(
+                                    &unit.template.source[segment.tag.clone()]
+                                )
+                            );
+                        }
+                        OnMissing::Placeholder => {
+                            // The expression may contain characters that
+                            // must be encoded, unlike a tag kept verbatim.
+                            let placeholder = format!("⟦{code}⟧");
+                            value = format!(
+                                "__keep({value}, encode({}))",
+                                budlang::vm::StringLiteralDisplay::new(&placeholder)
+                            );
+                        }
+                        OnMissing::Fail | OnMissing::Empty => {}
+                    }
+                    writeln!(&mut self.source, "__write({value})")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Names the arguments of the message functions called by `source`, if
+    /// they are provided by a catalog.
+    fn messages<'s>(&self, source: &'s str) -> Cow<'s, str> {
+        if self.messages {
+            expression::name_message_arguments(source)
+        } else {
+            Cow::Borrowed(source)
+        }
+    }
+
+    /// Returns true if the variable `name` may be in scope: it is a parameter
+    /// of the function being emitted, or a statement within any template
+    /// being transpiled assigns to it.
+    fn declared(&self, name: &str) -> bool {
+        self.parameters.iter().any(|param| param == name)
+            || self.units.iter().any(|unit| {
+                unit.template.segments.iter().any(|segment| {
+                    matches!(segment.kind, SegmentKind::Statement(_))
+                        && directive::declares(
+                            unit.template.source[segment.range.clone()].trim(),
+                            name,
+                        )
+                })
+            })
+    }
+
+    /// Returns the index of the unit for the registered template `name`,
+    /// parsing it the first time it is included.
+    fn include(&mut self, name: &str) -> Result<usize, Error> {
+        if let Some(index) = self
+            .units
+            .iter()
+            .position(|unit| unit.name.as_deref() == Some(name))
+        {
+            return Ok(index);
+        }
+
+        let Some(template) = self.templates.and_then(|templates| templates.get(name)) else {
+            return Err(Error::from(ErrorKind::UnknownTemplate(name.to_string())));
+        };
+        let mut template = template
+            .parse(self.delimiters)
+            .map_err(|err| err.in_template(Some(name)))?;
+        if self.exact_whitespace {
+            template.disable_trimming();
+        } else if self.auto_trim {
+            template.trim_standalone_tags();
+        }
+        let unit = Unit::new(Some(name.to_string()), template)
+            .map_err(|err| err.in_template(Some(name)))?;
+        self.units.push(Rc::new(unit));
+        Ok(self.units.len() - 1)
+    }
+
+    /// Returns a Bud expression that calls the function rendering `define`
+    /// within `unit` with `arguments`.
+    fn instantiate(
+        &mut self,
+        unit: usize,
+        define: Option<&str>,
+        arguments: &[Argument<'_>],
+    ) -> String {
+        let (function, arguments) = self.instance(unit, define, arguments);
+        format!("{function}({})", arguments.join(", "))
+    }
+
+    /// Emits a call to the function rendering `define` within `unit` with
+    /// `arguments`. When partials are memoized, the call is skipped if the
+    /// function has already been called with equal arguments during the
+    /// render, and its earlier output is written instead.
+    fn call(
+        &mut self,
+        unit: usize,
+        define: Option<&str>,
+        arguments: &[Argument<'_>],
+    ) -> Result<(), Error> {
+        let (function, arguments) = self.instance(unit, define, arguments);
+        if !self.memoize_partials {
+            writeln!(&mut self.source, "{function}({})", arguments.join(", "))?;
+            return Ok(());
+        }
+
+        // Each argument is evaluated once, for both the memo's key and the
+        // call.
+        let memo = self.memos;
+        self.memos += 1;
+        let mut variables = Vec::with_capacity(arguments.len());
+        for (index, argument) in arguments.iter().enumerate() {
+            let variable = format!("__memo_{memo}_{index}");
+            writeln!(&mut self.source, "{variable} := {argument}")?;
+            variables.push(variable);
+        }
+        let mut key = budlang::vm::StringLiteralDisplay::new(&function).to_string();
+        for variable in &variables {
+            key.push_str(", ");
+            key.push_str(variable);
+        }
+        writeln!(
+            &mut self.source,
+            "if __memo_begin({key})\n{function}({})\n__memo_end()\nend",
+            variables.join(", ")
+        )?;
+        Ok(())
+    }
+
+    /// Returns the name of the function rendering `define` within `unit`,
+    /// followed by the Bud expressions of its arguments. Each distinct set of
+    /// argument names produces its own function.
+    fn instance(
+        &mut self,
+        unit: usize,
+        define: Option<&str>,
+        arguments: &[Argument<'_>],
+    ) -> (String, Vec<String>) {
+        let mut arguments = arguments.to_vec();
+        arguments.sort_by(|a, b| a.name.cmp(b.name));
+        let parameters = arguments
+            .iter()
+            .map(|argument| argument.name.to_string())
+            .collect::<Vec<_>>();
+
+        let function = if let Some(instance) = self.instances.iter().find(|instance| {
+            instance.unit == unit
+                && instance.define.as_deref() == define
+                && instance.parameters == parameters
+        }) {
+            instance.function.clone()
+        } else {
+            let function = format!("__define_{}", self.first_instance + self.instances.len());
+            self.instances.push(Instance {
+                unit,
+                define: define.map(String::from),
+                parameters,
+                function: function.clone(),
+            });
+            function
+        };
+
+        let arguments = arguments
+            .iter()
+            .map(|argument| self.argument(argument.value))
+            .collect();
+        (function, arguments)
+    }
+
+    /// Returns the Bud expression passing `value` as an argument.
+    fn argument(&self, value: &str) -> String {
+        let value = self.messages(value);
+        let value = expression::rewrite_fallbacks(&value, &|name| self.declared(name));
+        format!("({})", self.coerce(&value))
+    }
+
+    /// Rewrites the operators of the expression `source` to be evaluated by
+    /// a coercion policy, if one is configured.
+    fn coerce<'s>(&self, source: &'s str) -> Cow<'s, str> {
+        if self.coercion {
+            Cow::Owned(coercion::rewrite(source))
+        } else {
+            Cow::Borrowed(source)
+        }
+    }
+}
+
+#[test]
+fn hello_world_to_bud() {
+    let template = Template::from("Hello, {{= name }}!");
+    let rendered = template
+        .render_with([(Symbol::from("name"), Value::from("World"))])
+        .unwrap();
+
+    assert_eq!(rendered, "Hello, World!");
+    assert_eq!(
+        template.transpile("render", ["name"]).unwrap(),
+        "function render(name)\n__write(\"Hello, \")\n__write(encode(name, \"name\"))\n__write(\"!\")\nend\n"
+    );
+}
+
+#[test]
+fn trim_tests() {
+    assert_eq!(Template::from(r#" {{= "a" }} "#).render().unwrap(), " a ");
+    assert_eq!(Template::from(r#" {{=- "a" -}} "#).render().unwrap(), "a");
+    assert_eq!(Template::from(r#" {{=- "a" }} "#).render().unwrap(), "a ");
+    assert_eq!(Template::from(r#" {{= "a" -}} "#).render().unwrap(), " a");
+    assert_eq!(
+        Template::from(
+            r#"
+                {{- if true -}}
+                    {{= "a" -}}
+                {{ end -}}
+            "#
+        )
+        .render()
+        .unwrap(),
+        "a"
+    );
+}
+
+#[test]
+fn define_and_render() {
+    let template = Template::from(
+        r#"{{ define "row" -}}
+            <li>{{= item }}</li>
+        {{- end define }}<ul>{{ render "row" with item = "a" }}{{ render "row" with item = "b" }}</ul>"#,
+    );
+    let bud = template
+        .parse(Delimiters::DEFAULT)
+        .unwrap()
+        .into_bud_source("render", &[], TranspileOptions::DEFAULT)
+        .unwrap()
+        .source;
+    // Both renders share the same argument names, so only one function is
+    // generated for the definition.
+    assert_eq!(bud.matches("function __define_").count(), 1);
+    assert_eq!(template.render().unwrap(), "<ul><li>a</li><li>b</li></ul>");
+
+    assert!(matches!(
+        Template::from(r#"{{ render "missing" }}"#).render().unwrap_err().kind,
+        ErrorKind::UnknownTemplate(name) if name == "missing"
+    ));
+    assert!(matches!(
+        Template::from(r#"{{ define "row" }}"#).render().unwrap_err().kind,
+        ErrorKind::UnterminatedBlock(name) if name == "row"
+    ));
+}
+
+#[test]
+fn loop_test() {
+    let template = Template::from("{{ loop for i := 1 to 5 inclusive }}{{= i }}{{ end }}");
+    let rendered = template.render().unwrap();
+
+    assert_eq!(rendered, "12345");
+}
+
+/// A Bud virtual machine with Budplate's native functions installed, which
+/// one or more templates are compiled into.
+struct Machine {
+    bud: RefCell<Bud<()>>,
+    state: RenderState,
+    /// The number of functions in the virtual machine's vtable.
+    functions: Cell<usize>,
+    /// The number of sub-template functions compiled into the machine.
+    instances: Cell<usize>,
+    /// Writes the output of templates compiled by a [`TemplateBackend`].
+    expressions: ExpressionWriter,
+    /// The number of instructions executed by the last render.
+    executed: Cell<usize>,
+}
+
+/// A template that has been compiled by [`Configuration::compile`] or
+/// [`Template::compile`], which can be rendered many times without being
+/// parsed or compiled again.
+pub struct CompiledTemplate {
+    machine: Rc<Machine>,
+    parameters: Vec<Symbol>,
+    vtable_index: usize,
+    budget: Option<usize>,
+    recorder: Option<Recorder>,
+    usage: Option<Usage>,
+    quota: Option<Quota>,
+    on_missing: OnMissing,
+    transforms: Option<Transforms>,
+    metadata: BTreeMap<String, String>,
+    /// The template compiled by the configuration's backend, which is
+    /// rendered in place of the function at `vtable_index`.
+    executable: Option<Box<dyn Executable>>,
+}
+
+/// The output transforms of a [`CompiledTemplate`], along with what they
+/// need to describe each render.
+struct Transforms {
+    transforms: Vec<Arc<dyn OutputTransform>>,
+    assets: AssetManifest,
+    template: Option<String>,
+}
+
+/// The usage observer of a [`CompiledTemplate`], along with what it needs
+/// to describe each render.
+struct Usage {
+    observer: Arc<dyn UsageObserver>,
+    template: Option<String>,
+    /// Whether the template has been rendered before.
+    rendered: Cell<bool>,
+}
+
+/// The recorder of a [`CompiledTemplate`], along with what it needs to
+/// describe each render.
+struct Recorder {
+    recorder: Arc<dyn RenderRecorder>,
+    printer: PrettyPrinter,
+    template: Option<String>,
+}
+
+impl CompiledTemplate {
+    pub fn parameters(&self) -> &[Symbol] {
+        &self.parameters
+    }
+
+    /// Returns the metadata declared by this template's `meta` statements.
+    /// See [`Template::metadata()`].
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    pub fn render(&self) -> Result<String, Error> {
+        self.render_with::<&'static str, Value, _>([])
+    }
+
+    /// Renders this template with `args`, which may be provided in any order
+    /// but must include a value for each parameter.
+    pub fn render_with<Name, Arg, Args>(&self, args: Args) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let mut output = String::new();
+        self.render_to(&mut output, args)?;
+        Ok(output)
+    }
+
+    /// Renders this template with `args`, making `context` available to
+    /// built-in functions.
+    pub fn render_with_context<Name, Arg, Args>(
+        &self,
+        context: impl ContextProvider,
+        args: Args,
+    ) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let values = self.arguments(args)?;
+        let mut output = String::new();
+        self.machine.state.with_context(Box::new(context), || {
+            self.render_values_to(values, &mut output)
+        })?;
+        Ok(output)
+    }
+
+    /// Renders this template with `args` into `output`. Output is written as
+    /// the template executes rather than being collected first.
+    pub fn render_to<W, Name, Arg, Args>(&self, output: &mut W, args: Args) -> Result<(), Error>
+    where
+        W: Write,
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let values = self.arguments(args)?;
+        self.render_values_to(values, output)
+    }
+
+    /// Renders this template with `args`, yielding to the executor polling
+    /// the returned future every few thousand instructions, so that a long
+    /// render doesn't keep the executor's other tasks from running.
+    ///
+    /// Compiled templates can't be sent between threads, so the future must
+    /// be run on the thread that compiled the template, such as using Tokio's
+    /// `LocalSet`.
+    ///
+    /// ```rust
+    /// use budplate::{CompiledTemplate, Error};
+    ///
+    /// async fn render_report(report: &CompiledTemplate, rows: i64) -> Result<String, Error> {
+    ///     report.render_async([("rows", rows)]).await
+    /// }
+    /// ```
+    pub async fn render_async<Name, Arg, Args>(&self, args: Args) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let values = self.arguments(args)?;
+        let mut output = String::new();
+        match self.start_observing()? {
+            Some(start) => {
+                let result = self.run_async(values, &mut output).await;
+                self.finish_observing(start, result)?;
+            }
+            None => self.run_async(values, &mut output).await?,
+        }
+        self.transform(output, None)
+    }
+
+    /// Returns a function that renders this template with a tuple of
+    /// arguments, each of which is passed to the parameter at the same
+    /// position in [`parameters()`](Self::parameters).
+    ///
+    /// The returned function doesn't look up parameters by name or check for
+    /// missing arguments, and reserves enough output for the previous render,
+    /// making it suited to rendering a small template many times, such as a
+    /// log line or a text message.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let compiled = Template::from("{{= name }} has {{= count }} new messages")
+    ///     .compile(&Default::default(), ["name", "count"])
+    ///     .unwrap();
+    /// let render = compiled.bind::<(String, i64)>().unwrap();
+    /// assert_eq!(
+    ///     render((String::from("Ferris"), 3)).unwrap(),
+    ///     "Ferris has 3 new messages"
+    /// );
+    /// ```
+    ///
+    /// An error is returned if the tuple doesn't have an element for each
+    /// parameter.
+    pub fn bind<Args: BoundArguments>(
+        &self,
+    ) -> Result<impl Fn(Args) -> Result<String, Error> + '_, Error> {
+        if Args::COUNT != self.parameters.len() {
+            return Err(Error::from(ErrorKind::ArgumentCount {
+                parameters: self.parameters.len(),
+                arguments: Args::COUNT,
+            }));
+        }
+        let capacity = Cell::new(0);
+        Ok(move |args: Args| {
+            let mut values = Vec::with_capacity(Args::COUNT);
+            args.push_values(&mut values);
+            let mut output = String::with_capacity(capacity.get());
+            self.render_values_to(values, &mut output)?;
+            capacity.set(output.len());
+            Ok(output)
+        })
+    }
+
+    /// Renders this template with `args`, passing its output to
+    /// `on_fragment` in pieces as it executes, so that a response can be sent
+    /// before slow parts of the template have rendered.
+    ///
+    /// A template divides its output using `{{ fragment "name" }}...{{ end
+    /// fragment }}` regions. Each region, and the output between regions, is
+    /// passed to `on_fragment` as soon as it has rendered:
+    ///
+    /// ```rust
+    /// use budplate::{Configuration, Template};
+    ///
+    /// let template = Template::from(
+    ///     r#"<html>{{ fragment "head" }}<head></head>{{ end fragment }}<body>{{= body }}</body></html>"#,
+    /// );
+    /// let compiled = Configuration::for_html().compile(&template, ["body"]).unwrap();
+    /// let mut fragments = Vec::new();
+    /// compiled
+    ///     .render_fragments([("body", "Hello")], |fragment| {
+    ///         fragments.push((fragment.name.map(String::from), fragment.content.to_string()));
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     fragments,
+    ///     [
+    ///         (None, String::from("<html>")),
+    ///         (Some(String::from("head")), String::from("<head></head>")),
+    ///         (None, String::from("<body>Hello</body></html>")),
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// An error returned by `on_fragment` stops rendering and is returned by
+    /// this function.
+    pub fn render_fragments<Name, Arg, Args, F>(
+        &self,
+        args: Args,
+        mut on_fragment: F,
+    ) -> Result<(), Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+        F: FnMut(Fragment<'_>) -> Result<(), Error>,
+    {
+        let values = self.arguments(args)?;
+        self.render_values_in_fragments(values, &mut on_fragment)
+    }
+
+    fn render_values_in_fragments(
+        &self,
+        values: Vec<Value>,
+        on_fragment: &mut dyn FnMut(Fragment<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut transformed = |fragment: Fragment<'_>| {
+            if self.transforms.is_none() {
+                return on_fragment(fragment);
+            }
+            let content = self.transform(fragment.content.to_string(), fragment.name)?;
+            on_fragment(Fragment {
+                name: fragment.name,
+                content: &content,
+            })
+        };
+        // While fragments are being rendered, nothing is written to the
+        // output.
+        self.observed(|| {
+            self.machine
+                .state
+                .with_fragments(&mut transformed, || self.run_to(values, &mut String::new()))
+        })
+    }
+
+    /// Renders this template with `args` into `output`. Output is written as
+    /// the template executes rather than being collected first.
+    pub fn render_to_io<W, Name, Arg, Args>(&self, output: &mut W, args: Args) -> Result<(), Error>
+    where
+        W: std::io::Write,
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let mut output = IoWriter {
+            writer: output,
+            error: None,
+        };
+        let result = self.render_to(&mut output, args);
+        match output.error {
+            Some(err) => Err(Error::from(ErrorKind::Io(err))),
+            None => result,
+        }
+    }
+
+    /// Renders this template once for each item of `batch`, such as each
+    /// recipient of a mail merge. Each item provides the arguments that are
+    /// specific to it, and `shared` provides the rest.
+    ///
+    /// Values within `shared` are reference counted, so each render receives
+    /// the same values rather than copies of them. This includes lists and
+    /// maps: a render that modifies a shared collection, such as by pushing
+    /// onto a list, changes it for the renders of every later item. An item
+    /// may override a shared argument by providing its own value.
+    ///
+    /// ```rust
+    /// use budplate::{Configuration, Template};
+    ///
+    /// let template = Template::from("Dear {{= name }}, {{= message }}")
+    ///     .compile(&Configuration::default(), ["name", "message"])
+    ///     .unwrap();
+    /// let rendered = template
+    ///     .render_batch(
+    ///         [("message", "welcome!")],
+    ///         [[("name", "Ada")], [("name", "Grace")]],
+    ///     )
+    ///     .unwrap()
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(rendered, ["Dear Ada, welcome!", "Dear Grace, welcome!"]);
+    /// ```
+    pub fn render_batch<'t, Name, Arg, Shared, Batch>(
+        &'t self,
+        shared: Shared,
+        batch: Batch,
+    ) -> Result<impl Iterator<Item = Result<String, Error>> + 't, Error>
+    where
+        Shared: IntoIterator<Item = (Name, Arg)>,
+        Batch: IntoIterator + 't,
+        Batch::Item: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let shared = self.assign(vec![None; self.parameters.len()], shared)?;
+        Ok(batch.into_iter().map(move |item| {
+            let values = self.complete(self.assign(shared.clone(), item)?)?;
+            let mut output = String::new();
+            self.render_values_to(values, &mut output)?;
+            Ok(output)
+        }))
+    }
+
+    fn arguments<Name, Arg, Args>(&self, args: Args) -> Result<Vec<Value>, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        self.complete(self.assign(vec![None; self.parameters.len()], args)?)
+    }
+
+    /// Stores each of `args` in `values` at the index of its parameter.
+    fn assign<Name, Arg, Args>(
+        &self,
+        mut values: Vec<Option<Value>>,
+        args: Args,
+    ) -> Result<Vec<Option<Value>>, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        for (name, arg) in args {
+            let name = name.into();
+            let index = self
+                .parameters
+                .iter()
+                .position(|parameter| parameter == &name)
+                .ok_or_else(|| ErrorKind::UnknownArgument(name.to_string()))?;
+            values[index] = Some(arg.into());
+        }
+        Ok(values)
+    }
+
+    /// Returns the value of every parameter, handling parameters without an
+    /// argument according to [`Configuration::on_missing()`].
+    fn complete(&self, values: Vec<Option<Value>>) -> Result<Vec<Value>, Error> {
+        values
+            .into_iter()
+            .zip(&self.parameters)
+            .map(|(value, name)| match value {
+                Some(value) => Ok(value),
+                None if self.machine.state.on_missing(self.on_missing) == OnMissing::Fail => {
+                    Err(ErrorKind::MissingArgument(name.to_string()).into())
+                }
+                None => Ok(Value::dynamic(Missing)),
+            })
+            .collect()
+    }
+
+    fn render_values_to<W: Write>(&self, values: Vec<Value>, output: &mut W) -> Result<(), Error> {
+        self.observed(|| {
+            if self.transforms.is_none() {
+                return self.run_to(values, output);
+            }
+            let mut rendered = String::new();
+            self.run_to(values, &mut rendered)?;
+            output.write_str(&self.transform(rendered, None)?)?;
+            Ok(())
+        })
+    }
+
+    /// Invokes `render`, accounting for it in the configuration's quota and
+    /// passing a summary of it to its usage observer.
+    fn observed(&self, render: impl FnOnce() -> Result<(), Error>) -> Result<(), Error> {
+        let Some(start) = self.start_observing()? else {
+            return render();
+        };
+        let result = render();
+        self.finish_observing(start, result)
+    }
+
+    /// Returns when a render that is observed started, or `None` if renders
+    /// aren't observed. Fails if the configuration's quota is exhausted.
+    fn start_observing(&self) -> Result<Option<std::time::Instant>, Error> {
+        if self.usage.is_none() && self.quota.is_none() {
+            return Ok(None);
+        }
+        if self.quota.as_ref().is_some_and(Quota::is_exhausted) {
+            return Err(Error::from(ErrorKind::QuotaExceeded));
+        }
+        Ok(Some(std::time::Instant::now()))
+    }
+
+    /// Accounts for a render that started at `start` and returned `result`.
+    fn finish_observing(
+        &self,
+        start: std::time::Instant,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        let duration = start.elapsed();
+        if let Some(quota) = &self.quota {
+            quota.record(
+                duration,
+                self.machine.executed.take(),
+                self.machine.state.take_last_written(),
+            );
+        }
+        let Some(usage) = &self.usage else {
+            return result;
+        };
+        let cache = if usage.rendered.replace(true) {
+            CacheStatus::Hit
+        } else {
+            CacheStatus::Miss
+        };
+        usage.observer.observe(&RenderUsage {
+            template: usage.template.as_deref(),
+            duration,
+            cache,
+            tags: &self.machine.state.tags(),
+            succeeded: result.is_ok(),
+        });
+        result
+    }
+
+    /// Applies each of the configuration's output transforms to `output`,
+    /// which is the fragment `fragment` when rendering fragments.
+    fn transform(&self, output: String, fragment: Option<&str>) -> Result<String, Error> {
+        let Some(transforms) = &self.transforms else {
+            return Ok(output);
+        };
+        let render = TransformedRender {
+            template: transforms.template.as_deref(),
+            parameters: &self.parameters,
+            fragment,
+            assets: &transforms.assets,
+        };
+        transforms
+            .transforms
+            .iter()
+            .try_fold(output, |output, transform| {
+                transform.transform(output, &render)
+            })
+    }
+
+    /// Renders this template with `values` into `output`, without
+    /// transforming it.
+    fn run_to<W: Write>(&self, values: Vec<Value>, output: &mut W) -> Result<(), Error> {
+        let Ok(mut bud) = self.machine.bud.try_borrow_mut() else {
+            return Err(Error::from(ErrorKind::Busy));
+        };
+        self.begin_run(&values)?;
+        if let Some(executable) = &self.executable {
+            return self.machine.state.with_output(output, || {
+                executable.render(&mut BackendRender {
+                    parameters: &self.parameters,
+                    arguments: &values,
+                    writer: &self.machine.expressions,
+                })
+            });
+        }
+
+        let arg_count = values.len();
+        let mut execution = self.start_execution(&mut bud, values)?;
+        let result = self.machine.state.with_output(output, || {
+            let mut result = bud.run::<Value>(
+                &[Instruction::Call {
+                    vtable_index: Some(self.vtable_index),
+                    arg_count,
+                    destination: Destination::Return,
+                }],
+                0,
+            );
+            loop {
+                match result {
+                    Ok(_) => break Ok(()),
+                    Err(budlang::Error::Fault(Fault {
+                        kind: FaultOrPause::Pause(mut paused),
+                        ..
+                    })) => {
+                        paused.increase_budget(self.next_slice(&mut execution)?);
+                        result = paused.resume().map_err(budlang::Error::Fault);
+                    }
+                    Err(err) => break Err(Error::from(err)),
+                }
+            }
+        });
+        self.finish_execution(&bud, execution, result)
+    }
+
+    /// Renders this template with `values` into `output`, without
+    /// transforming it, yielding whenever the virtual machine pauses.
+    async fn run_async(&self, values: Vec<Value>, output: &mut String) -> Result<(), Error> {
+        // Another asynchronous render using the same virtual machine may be
+        // paused, such as in another task on this thread.
+        let mut bud = loop {
+            match self.machine.bud.try_borrow_mut() {
+                Ok(bud) => break bud,
+                Err(_) => YieldNow::default().await,
+            }
+        };
+        self.begin_run(&values)?;
+        if let Some(executable) = &self.executable {
+            return self.machine.state.with_output(output, || {
+                executable.render(&mut BackendRender {
+                    parameters: &self.parameters,
+                    arguments: &values,
+                    writer: &self.machine.expressions,
+                })
+            });
+        }
+
+        let arg_count = values.len();
+        let mut execution = self.start_execution(&mut bud, values)?;
+        let installed = self.machine.state.install_output(output);
+        let mut result = bud.run::<Value>(
+            &[Instruction::Call {
+                vtable_index: Some(self.vtable_index),
+                arg_count,
+                destination: Destination::Return,
+            }],
+            0,
+        );
+        let result = loop {
+            match result {
+                Ok(_) => break Ok(()),
+                Err(budlang::Error::Fault(Fault {
+                    kind: FaultOrPause::Pause(mut paused),
+                    ..
+                })) => {
+                    match self.next_slice(&mut execution) {
+                        Ok(slice) => paused.increase_budget(slice),
+                        Err(err) => break Err(err),
+                    }
+                    YieldNow::default().await;
+                    result = paused.resume().map_err(budlang::Error::Fault);
+                }
+                Err(err) => break Err(Error::from(err)),
+            }
+        };
+        drop(installed);
+        self.finish_execution(&bud, execution, result)
+    }
+
+    /// Checks whether a render of `values` can begin, and records it.
+    fn begin_run(&self, values: &[Value]) -> Result<(), Error> {
+        if self.machine.state.is_cancelled() {
+            return Err(Error::from(ErrorKind::Cancelled));
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.recorder.record(&RecordedRender::new(
+                recorder.template.clone(),
+                &self.parameters,
+                values,
+                &recorder.printer,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Pushes `values` onto the stack of `bud` and grants it the first slice
+    /// of the render's budget.
+    fn start_execution(&self, bud: &mut Bud<()>, values: Vec<Value>) -> Result<Execution, Error> {
+        bud.stack.extend(values)?;
+        let (execution, increase) =
+            Execution::start(self.machine.state.budget().or(self.budget), bud.budget());
+        bud.increase_budget(increase);
+        Ok(execution)
+    }
+
+    /// Accounts for the instructions executed by a render that returned
+    /// `result`, failing it if it executed more than its budget.
+    fn finish_execution(
+        &self,
+        bud: &Bud<()>,
+        mut execution: Execution,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        let finished = execution.finish(bud.budget());
+        self.machine.executed.set(execution.executed());
+        result
+            .and(finished)
+            .map_err(|err| self.machine.state.take_error().unwrap_or(err))
+    }
+
+    /// Returns the next slice of the budget of a render paused by the
+    /// virtual machine, unless the render was cancelled or its budget is
+    /// spent.
+    fn next_slice(&self, execution: &mut Execution) -> Result<usize, Error> {
+        if self.machine.state.is_cancelled() {
+            return Err(Error::from(ErrorKind::Cancelled));
+        }
+        execution.paused()
+    }
+}
+
+/// Adapts an [`std::io::Write`] implementor to [`Write`], keeping the
+/// underlying error if writing fails.
+struct IoWriter<'a, W> {
+    writer: &'a mut W,
+    error: Option<std::io::Error>,
+}
+
+impl<W: std::io::Write> Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.writer.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            std::fmt::Error
+        })
+    }
+}
+
+#[test]
+fn compiled_template_reuse() {
+    let compiled = Template::from("{{= greeting }}, {{= name }}!")
+        .compile(&Configuration::default(), ["name", "greeting"])
+        .unwrap();
+    assert_eq!(
+        compiled
+            .render_with([("greeting", "Hello"), ("name", "World")])
+            .unwrap(),
+        "Hello, World!"
+    );
+    assert_eq!(
+        compiled
+            .render_with([("name", "Budplate"), ("greeting", "Hi")])
+            .unwrap(),
+        "Hi, Budplate!"
+    );
+    assert!(matches!(
+        compiled.render_with([("name", "Budplate")]),
+        Err(Error { kind: ErrorKind::MissingArgument(name), .. }) if name == "greeting"
+    ));
+    assert!(matches!(
+        compiled.render_with([("other", "Budplate")]),
+        Err(Error { kind: ErrorKind::UnknownArgument(name), .. }) if name == "other"
+    ));
+}
+
+#[test]
+fn bound_templates() {
+    let compiled = Configuration::for_html()
+        .compile(
+            &Template::from("{{= level }}: {{= message }}"),
+            ["level", "message"],
+        )
+        .unwrap();
+    let render = compiled.bind::<(&str, String)>().unwrap();
+    assert_eq!(
+        render(("warn", String::from("<disk> full"))).unwrap(),
+        "warn: &lt;disk&gt; full"
+    );
+    assert_eq!(render(("info", String::new())).unwrap(), "info: ");
+    assert!(matches!(
+        compiled.bind::<(&str,)>().map(|_| ()),
+        Err(Error {
+            kind: ErrorKind::ArgumentCount {
+                parameters: 2,
+                arguments: 1
+            },
+            ..
+        })
+    ));
+    assert_eq!(
+        Template::from("static")
+            .compile(&Configuration::default(), Vec::<&str>::new())
+            .unwrap()
+            .bind::<()>()
+            .unwrap()(())
+        .unwrap(),
+        "static"
+    );
+}
+
+#[test]
+fn yielding_renders() {
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    let compiled = Template::from("{{ loop for i := 1 to count }}{{ end }}{{= count }}")
+        .compile(&Configuration::default(), ["count"])
+        .unwrap();
+    let waker = Waker::from(Arc::new(Noop));
+    let mut context = Context::from_waker(&waker);
+    let mut render = pin!(compiled.render_async([("count", 100_000_i64)]));
+    let mut yields = 0;
+    let rendered = loop {
+        match render.as_mut().poll(&mut context) {
+            Poll::Ready(rendered) => break rendered.unwrap(),
+            Poll::Pending => yields += 1,
+        }
+        // The virtual machine can't be used by other renders until the
+        // paused render completes.
+        assert!(matches!(
+            compiled.render_with([("count", 1_i64)]).unwrap_err().kind,
+            ErrorKind::Busy
+        ));
+    };
+    assert_eq!(rendered, "100000");
+    assert!(yields > 10);
+    assert_eq!(compiled.render_with([("count", 1_i64)]).unwrap(), "1");
+}
+
+#[test]
+fn sendable_between_threads() {
+    fn assert_send<T: Send + Sync>() {}
+
+    // Templates are compiled on the thread that renders them, so these must
+    // be able to move to a blocking thread pool.
+    assert_send::<Templates<'static>>();
+    assert_send::<Configuration<HtmlEncoding>>();
+}
+
+pub trait Encoder: Clone + 'static {
+    /// Writes `input` to `output`, encoded. Errors returned by `output` are
+    /// returned.
+    fn encode<W: Write>(&self, input: &str, output: &mut W) -> std::fmt::Result;
+
+    /// Appends `input` to `output`, encoded. Unlike other writers, a
+    /// `String` never returns an error.
+    fn encode_to_string(&self, input: &str, output: &mut String) {
+        self.encode(input, output)
+            .expect("String does not fail to write");
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NoEncoding;
+
+impl Encoder for NoEncoding {
+    fn encode<W: Write>(&self, input: &str, output: &mut W) -> std::fmt::Result {
+        output.write_str(input)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HtmlEncoding;
+
+impl Encoder for HtmlEncoding {
+    fn encode<W: Write>(&self, input: &str, output: &mut W) -> std::fmt::Result {
+        let mut last_byte_written = 0;
+        for (index, ch) in input.char_indices() {
+            let encoded = match ch {
+                '&' => "&amp;",
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '"' => "&quot;",
+                '\'' => "&#39;",
+                _ => continue,
+            };
+            if last_byte_written < index {
+                output.write_str(&input[last_byte_written..index])?;
+            }
+            output.write_str(encoded)?;
+            last_byte_written = index + 1;
+        }
+
+        if last_byte_written < input.len() {
+            output.write_str(&input[last_byte_written..])?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn html_encoding_test() {
+    let mut encoded = String::new();
+    HtmlEncoding
+        .encode("&<>'\"unencoded", &mut encoded)
+        .unwrap();
+    assert_eq!(encoded, "&amp;&lt;&gt;&#39;&quot;unencoded");
+}
+
+/// Escapes text for use within a JSON string literal. The surrounding quotes
+/// are not written, allowing templates such as `{"name": "{{= name }}"}`.
+///
+/// `<`, `>`, and `&` are also escaped, so that the result can be embedded
+/// within an HTML `<script>` element.
+#[derive(Debug, Clone)]
+pub struct JsonStringEncoding;
+
+impl Encoder for JsonStringEncoding {
+    fn encode<W: Write>(&self, input: &str, output: &mut W) -> std::fmt::Result {
+        for ch in input.chars() {
+            match ch {
+                '"' => output.write_str("\\\""),
+                '\\' => output.write_str("\\\\"),
+                '\n' => output.write_str("\\n"),
+                '\r' => output.write_str("\\r"),
+                '\t' => output.write_str("\\t"),
+                '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                    write!(output, "\\u{:04x}", u32::from(ch))
+                }
+                ch if ch.is_control() => write!(output, "\\u{:04x}", u32::from(ch)),
+                ch => output.write_char(ch),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn json_string_encoding_test() {
+    let mut encoded = String::new();
+    JsonStringEncoding
+        .encode("\"a\\b\"\n</script>&\u{1}", &mut encoded)
+        .unwrap();
+    assert_eq!(
+        encoded,
+        "\\\"a\\\\b\\\"\\n\\u003c/script\\u003e\\u0026\\u0001"
+    );
+}
+
+/// Percent-encodes text for use within a URL's path or query string. Every
+/// byte other than ASCII letters, digits, `-`, `.`, `_`, and `~` is encoded.
+#[derive(Debug, Clone)]
+pub struct UrlEncoding;
+
+impl Encoder for UrlEncoding {
+    fn encode<W: Write>(&self, input: &str, output: &mut W) -> std::fmt::Result {
+        for byte in input.bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    output.write_char(char::from(byte))
+                }
+                byte => write!(output, "%{byte:02X}"),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn url_encoding_test() {
+    let mut encoded = String::new();
+    UrlEncoding.encode("a b/c?d=é&e~", &mut encoded).unwrap();
+    assert_eq!(encoded, "a%20b%2Fc%3Fd%3D%C3%A9%26e~");
+}
+
+/// Quotes text as a single argument to a POSIX shell command.
+///
+/// The result is always enclosed in single quotes, within which the shell
+/// doesn't interpret any characters. Single quotes within the text are written
+/// as `'\''`.
+#[derive(Debug, Clone)]
+pub struct ShellEncoding;
+
+impl Encoder for ShellEncoding {
+    fn encode<W: Write>(&self, input: &str, output: &mut W) -> std::fmt::Result {
+        output.write_char('\'')?;
+        for part in input.split_inclusive('\'') {
+            match part.strip_suffix('\'') {
+                Some(part) => {
+                    output.write_str(part)?;
+                    output.write_str("'\\''")?;
+                }
+                None => output.write_str(part)?,
+            }
+        }
+        output.write_char('\'')
+    }
+}
+
+#[test]
+fn shell_encoding_test() {
+    let mut encoded = String::new();
+    ShellEncoding.encode("it's $HOME", &mut encoded).unwrap();
+    assert_eq!(encoded, "'it'\\''s $HOME'");
+
+    encoded.clear();
+    ShellEncoding.encode("", &mut encoded).unwrap();
+    assert_eq!(encoded, "''");
+}
+
+#[test]
+fn context_encodings() {
+    assert_eq!(
+        ContextEncoding::parse("url  page.slug"),
+        Some((ContextEncoding::Url, "  page.slug"))
+    );
+    assert_eq!(ContextEncoding::parse("url"), None);
+    assert_eq!(ContextEncoding::parse("url "), None);
+    assert_eq!(ContextEncoding::parse("urls + 1"), None);
+    assert_eq!(ContextEncoding::parse("url + 1"), None);
+    assert_eq!(ContextEncoding::parse("json ?? 1"), None);
+    assert_eq!(ContextEncoding::parse("url and 1"), None);
+    assert_eq!(
+        ContextEncoding::parse(r#"json "a""#),
+        Some((ContextEncoding::JsonString, r#" "a""#))
+    );
+
+    // Variables may share the name of an encoding.
+    assert_eq!(
+        Configuration::for_html()
+            .render_with(
+                r#"<a href="{{=url + "/page" }}">{{=url ?? "" }}</a>"#,
+                [("url", "/a&b")]
+            )
+            .unwrap(),
+        r#"<a href="/a&amp;b/page">/a&amp;b</a>"#
+    );
+
+    assert_eq!(
+        Configuration::for_html()
+            .render_with(
+                r#"<a href="/search?q={{=url query }}" onclick='run({{=json query }})'>{{= query }}</a>"#,
+                [("query", "a&b")]
+            )
+            .unwrap(),
+        r#"<a href="/search?q=a%26b" onclick='run(a\u0026b)'>a&amp;b</a>"#
+    );
+}
+
+/// Escapes values for Java `.properties` files and `.ini`-style
+/// configuration files.
+///
+/// Separators (`=`, `:`), comment markers (`#`, `!`), backslashes, and leading
+/// whitespace are escaped with a backslash, and characters outside of
+/// printable ASCII are written as `\uXXXX` escapes.
+#[derive(Debug, Clone)]
+pub struct PropertiesEncoding;
+
+impl Encoder for PropertiesEncoding {
+    fn encode<W: Write>(&self, input: &str, output: &mut W) -> std::fmt::Result {
+        let mut is_leading = true;
+        for ch in input.chars() {
+            match ch {
+                ' ' if is_leading => output.write_str("\\ ")?,
+                '\\' | '=' | ':' | '#' | '!' => {
+                    output.write_char('\\')?;
+                    output.write_char(ch)?;
+                }
+                '\n' => output.write_str("\\n")?,
+                '\r' => output.write_str("\\r")?,
+                '\t' => output.write_str("\\t")?,
+                '\u{c}' => output.write_str("\\f")?,
+                ' '..='~' => output.write_char(ch)?,
+                ch => {
+                    let mut utf16 = [0; 2];
+                    for unit in ch.encode_utf16(&mut utf16) {
+                        write!(output, "\\u{unit:04X}")?;
+                    }
+                }
+            }
+            is_leading &= ch == ' ';
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn properties_encoding_test() {
+    let mut encoded = String::new();
+    PropertiesEncoding
+        .encode("  a=b:c #!\\\n\té😀 ", &mut encoded)
+        .unwrap();
+    assert_eq!(
+        encoded,
+        "\\ \\ a\\=b\\:c \\#\\!\\\\\\n\\t\\u00E9\\uD83D\\uDE00 "
+    );
+}
+
+/// How a template renders values that are missing. See
+/// [`Configuration::on_missing()`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum OnMissing {
+    /// Rendering fails with [`ErrorKind::MissingArgument`] or an error from the
+    /// field access.
+    #[default]
+    Fail,
+    /// Missing values render as nothing.
+    Empty,
+    /// Expressions that output a missing value render their tag unchanged,
+    /// such as `{{= user.nickname }}`.
+    Keep,
+    /// Expressions that output a missing value render a visible placeholder
+    /// naming the expression, such as `⟦user.nickname⟧`, so that a template
+    /// can be previewed before the data it renders is available.
+    Placeholder,
+}
+
+pub struct Configuration<Enc> {
+    pub encoder: Enc,
+    pub auto_trim: bool,
+    pub exact_whitespace: bool,
+    delimiters: Delimiters,
+    pub pretty_printer: PrettyPrinter,
+    pub ansi: AnsiStyling,
+    pub assets: AssetManifest,
+    functions: Vec<(Symbol, CustomFunction)>,
+    budget: Option<usize>,
+    max_output_len: Option<usize>,
+    recorder: Option<Arc<dyn RenderRecorder>>,
+    usage: Option<Arc<dyn UsageObserver>>,
+    quota: Option<Quota>,
+    features: Option<Arc<dyn FeatureProvider>>,
+    exposures: Option<Arc<dyn ExposureRecorder>>,
+    clock: Option<Arc<dyn Clock>>,
+    sampling_seed: Option<u64>,
+    transforms: Vec<Arc<dyn OutputTransform>>,
+    islands: Vec<String>,
+    edge_includes: Option<Arc<EdgeIncludes>>,
+    backend: Option<Arc<dyn TemplateBackend>>,
+    substitution: bool,
+    on_missing: OnMissing,
+    memoize_partials: bool,
+    reject_floats: bool,
+    coercion: Option<Arc<dyn CoercionPolicy>>,
+    lenient_output: bool,
+    annotate_output: bool,
+    audit_accesses: bool,
+    #[cfg(feature = "i18n")]
+    catalog: Option<Arc<i18n::Catalog>>,
+}
+
+impl Default for Configuration<NoEncoding> {
+    fn default() -> Self {
+        Self {
+            encoder: NoEncoding,
+            auto_trim: Default::default(),
+            exact_whitespace: false,
+            delimiters: Delimiters::DEFAULT,
+            pretty_printer: PrettyPrinter::default(),
+            ansi: AnsiStyling::Auto,
+            assets: AssetManifest::new(),
+            functions: Vec::new(),
+            budget: None,
+            max_output_len: None,
+            recorder: None,
+            usage: None,
+            quota: None,
+            features: None,
+            exposures: None,
+            clock: None,
+            sampling_seed: None,
+            transforms: Vec::new(),
+            islands: Vec::new(),
+            edge_includes: None,
+            backend: None,
+            substitution: true,
+            on_missing: OnMissing::Fail,
+            memoize_partials: false,
+            reject_floats: false,
+            coercion: None,
+            lenient_output: false,
+            annotate_output: false,
+            audit_accesses: false,
+            #[cfg(feature = "i18n")]
+            catalog: None,
+        }
+    }
+}
+
+impl Configuration<HtmlEncoding> {
+    pub const fn for_html() -> Self {
+        Self {
+            encoder: HtmlEncoding,
+            auto_trim: false,
+            exact_whitespace: false,
+            delimiters: Delimiters::DEFAULT,
+            pretty_printer: PrettyPrinter::new(),
+            ansi: AnsiStyling::Auto,
+            assets: AssetManifest::new(),
+            functions: Vec::new(),
+            budget: None,
+            max_output_len: None,
+            recorder: None,
+            usage: None,
+            quota: None,
+            features: None,
+            exposures: None,
+            clock: None,
+            sampling_seed: None,
+            transforms: Vec::new(),
+            islands: Vec::new(),
+            edge_includes: None,
+            backend: None,
+            substitution: true,
+            on_missing: OnMissing::Fail,
+            memoize_partials: false,
+            reject_floats: false,
+            coercion: None,
+            lenient_output: false,
+            annotate_output: false,
+            audit_accesses: false,
+            #[cfg(feature = "i18n")]
+            catalog: None,
+        }
+    }
+}
+
+impl Configuration<PropertiesEncoding> {
+    pub const fn for_properties() -> Self {
+        Self {
+            encoder: PropertiesEncoding,
+            auto_trim: false,
+            exact_whitespace: false,
+            delimiters: Delimiters::DEFAULT,
+            pretty_printer: PrettyPrinter::new(),
+            ansi: AnsiStyling::Auto,
+            assets: AssetManifest::new(),
+            functions: Vec::new(),
+            budget: None,
+            max_output_len: None,
+            recorder: None,
+            usage: None,
+            quota: None,
+            features: None,
+            exposures: None,
+            clock: None,
+            sampling_seed: None,
+            transforms: Vec::new(),
+            islands: Vec::new(),
+            edge_includes: None,
+            backend: None,
+            substitution: true,
+            on_missing: OnMissing::Fail,
+            memoize_partials: false,
+            reject_floats: false,
+            coercion: None,
+            lenient_output: false,
+            annotate_output: false,
+            audit_accesses: false,
+            #[cfg(feature = "i18n")]
+            catalog: None,
+        }
+    }
+}
+
+impl<Enc> Configuration<Enc>
+where
+    Enc: Encoder,
+{
+    /// Removes the line containing each statement or comment tag that is
+    /// alone on its line, including its indentation and newline. Expressions
+    /// and tags that share a line with other content are unaffected.
+    pub fn auto_trim(mut self) -> Self {
+        self.auto_trim = true;
+        self
+    }
+
+    /// Disables all whitespace handling, including explicit trimming markers
+    /// and [`auto_trim`](Self::auto_trim). Raw text is rendered byte-for-byte
+    /// as it appears in the template.
+    pub fn exact_whitespace(mut self) -> Self {
+        self.exact_whitespace = true;
+        self
+    }
+
+    /// Uses `open` and `close` in place of `{{` and `}}` to mark tags, for
+    /// output formats where braces are common. Comments become `open#` to
+    /// `#close`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either delimiter is empty.
+    pub fn with_delimiters(mut self, open: &'static str, close: &'static str) -> Self {
+        assert!(
+            !open.is_empty() && !close.is_empty(),
+            "delimiters must not be empty"
+        );
+        self.delimiters = Delimiters { open, close };
+        self
+    }
+
+    /// Limits each render to executing `operations` Bud instructions, after
+    /// which rendering fails with [`ErrorKind::BudgetExceeded`]. This
+    /// prevents templates containing unbounded loops from running forever.
+    ///
+    /// Every render starts with the full budget, and operations left unused
+    /// by a render aren't available to later renders.
+    pub fn with_budget(mut self, operations: usize) -> Self {
+        self.budget = Some(operations);
+        self
+    }
+
+    /// Limits rendered output to `bytes`, after which rendering fails with
+    /// [`ErrorKind::OutputTooLarge`]. Output buffered for components counts
+    /// towards the limit.
+    pub fn with_max_output_len(mut self, bytes: usize) -> Self {
+        self.max_output_len = Some(bytes);
+        self
+    }
+
+    /// Passes the arguments of every render to `recorder`, after redacting
+    /// them with this configuration's pretty printer.
+    pub fn with_recorder(mut self, recorder: impl RenderRecorder) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Passes a summary of every render, including its duration, to
+    /// `observer`.
+    pub fn with_usage_observer(mut self, observer: impl UsageObserver) -> Self {
+        self.usage = Some(Arc::new(observer));
+        self
+    }
+
+    /// Accounts for every render of the templates compiled with this
+    /// configuration using `quota`, failing renders with
+    /// [`ErrorKind::QuotaExceeded`] once it is exhausted.
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Decides whether each `{{ feature "name" }}` region is rendered using
+    /// `provider`. Without a provider, every feature is disabled.
+    pub fn with_features(mut self, provider: impl FeatureProvider) -> Self {
+        self.features = Some(Arc::new(provider));
+        self
+    }
+
+    /// Registers the island `name`, which templates can hand off to a
+    /// client-side framework using `{{ island "name" with props }}`. Rendering
+    /// an island that isn't registered fails.
+    pub fn with_island(mut self, name: impl Into<String>) -> Self {
+        self.islands.push(name.into());
+        self
+    }
+
+    /// Returns the names of the registered islands, such as to load the
+    /// scripts that hydrate them.
+    pub fn islands(&self) -> impl Iterator<Item = &str> {
+        self.islands.iter().map(String::as_str)
+    }
+
+    /// Renders each `{{ include "name" }}` of a template selected by
+    /// `includes` as a marker, such as `<esi:include>`, so that the page can
+    /// be assembled by an edge server from separately rendered fragments.
+    pub fn with_edge_includes(mut self, includes: EdgeIncludes) -> Self {
+        self.edge_includes = Some(Arc::new(includes));
+        self
+    }
+
+    /// Passes each variant chosen by `variant()` to `recorder`, so that
+    /// experiments can be analyzed by the users exposed to them.
+    pub fn with_exposure_recorder(mut self, recorder: impl ExposureRecorder) -> Self {
+        self.exposures = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Uses `clock` as the current time of `before()`, `after()`, and
+    /// `between()`, in place of the [`SystemClock`]. A [`FixedClock`] makes
+    /// templates that depend on the time deterministic in tests.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Decides which renders include `{{ sampled rate }}` regions using a
+    /// pseudorandom sequence starting from `seed`, rather than a random seed,
+    /// so that sampling is deterministic in tests.
+    pub fn with_sampling_seed(mut self, seed: u64) -> Self {
+        self.sampling_seed = Some(seed);
+        self
+    }
+
+    /// Passes the output of each render through `transform` before it is
+    /// returned, after any transforms that were added before it.
+    pub fn with_output_transform(mut self, transform: impl OutputTransform) -> Self {
+        self.transforms.push(Arc::new(transform));
+        self
+    }
+
+    /// Renders templates using `backend` when it supports them, rather than
+    /// the Bud virtual machine. See [`TemplateBackend`].
+    pub fn with_backend(mut self, backend: impl TemplateBackend) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Renders every template using the Bud virtual machine. Otherwise,
+    /// templates whose expressions only look up parameters and their fields,
+    /// and that contain no statements, are rendered by substituting the
+    /// values into their text without executing any Bud.
+    pub fn disable_substitution(mut self) -> Self {
+        self.substitution = false;
+        self
+    }
+
+    /// Controls how values that are missing are rendered. This applies to
+    /// parameters of a [`CompiledTemplate`] that aren't provided an argument,
+    /// and to fields that aren't present in a map. The default is
+    /// [`OnMissing::Fail`].
+    pub fn on_missing(mut self, on_missing: OnMissing) -> Self {
+        self.on_missing = on_missing;
+        self
+    }
+
+    /// Renders each sub-template at most once per render for each distinct
+    /// set of arguments, reusing its output when it is rendered again with
+    /// equal arguments. Components are always rendered.
+    ///
+    /// Output is never reused from a call that used the state of the render,
+    /// by calling `unique_id()`, `set_once()`, `push()`, or `get()`, or by
+    /// rendering a `once`, `defer`, or `emit` tag, so those are rendered each
+    /// time as if partials weren't memoized. Sub-templates must not call
+    /// other functions whose results vary between calls, since their output
+    /// is otherwise assumed to depend only on their arguments.
+    pub fn memoize_partials(mut self) -> Self {
+        self.memoize_partials = true;
+        self
+    }
+
+    /// Fails rendering with [`ErrorKind::FloatOutput`] when an expression
+    /// outputs a floating point number, so that documents that must be exact,
+    /// such as invoices, are rendered from integers or formatted decimal
+    /// strings.
+    pub fn reject_floats(mut self) -> Self {
+        self.reject_floats = true;
+        self
+    }
+
+    /// Evaluates `+`, comparisons, and the conditions of `if` statements
+    /// using `policy`, which decides how values of different types are
+    /// converted. Without a policy, they are evaluated by Bud.
+    pub fn with_coercion(mut self, policy: impl CoercionPolicy) -> Self {
+        self.coercion = Some(Arc::new(policy));
+        self
+    }
+
+    /// Renders values that can't be converted to text, such as maps, using
+    /// this configuration's pretty printer instead of failing with
+    /// [`ErrorKind::UnprintableValue`].
+    pub fn lenient_output(mut self) -> Self {
+        self.lenient_output = true;
+        self
+    }
+
+    /// Marks the output of each template tag as it is rendered, so that
+    /// [`CompiledTemplate::render_annotated()`] can map the output back to
+    /// the tags that produced it. Annotated templates render more slowly, so
+    /// this suits previews in editors rather than production.
+    pub fn annotate_output(mut self) -> Self {
+        self.annotate_output = true;
+        self
+    }
+
+    /// Records every variable read, function called, and template included
+    /// by each template tag as it is rendered, so that
+    /// [`CompiledTemplate::render_audited()`] can report what an untrusted
+    /// template accesses. Like annotated templates, audited templates render
+    /// more slowly.
+    pub fn audit_accesses(mut self) -> Self {
+        self.audit_accesses = true;
+        self
+    }
+
+    pub fn with_encoder<NewEnc>(self, encoder: NewEnc) -> Configuration<NewEnc> {
+        let Self {
+            auto_trim,
+            exact_whitespace,
+            delimiters,
+            pretty_printer,
+            ansi,
+            assets,
+            functions,
+            budget,
+            max_output_len,
+            recorder,
+            usage,
+            quota,
+            features,
+            exposures,
+            clock,
+            sampling_seed,
+            transforms,
+            islands,
+            edge_includes,
+            backend,
+            substitution,
+            on_missing,
+            memoize_partials,
+            reject_floats,
+            coercion,
+            lenient_output,
+            annotate_output,
+            audit_accesses,
+            #[cfg(feature = "i18n")]
+            catalog,
+            ..
+        } = self;
+        Configuration {
+            encoder,
+            auto_trim,
+            exact_whitespace,
+            delimiters,
+            pretty_printer,
+            ansi,
+            assets,
+            functions,
+            budget,
+            max_output_len,
+            recorder,
+            usage,
+            quota,
+            features,
+            exposures,
+            clock,
+            sampling_seed,
+            transforms,
+            islands,
+            edge_includes,
+            backend,
+            substitution,
+            on_missing,
+            memoize_partials,
+            reject_floats,
+            coercion,
+            lenient_output,
+            annotate_output,
+            audit_accesses,
+            #[cfg(feature = "i18n")]
+            catalog,
+        }
+    }
+
+    /// Returns true if `t()` and `plural()` are provided by a message
+    /// catalog.
+    fn has_catalog(&self) -> bool {
+        #[cfg(feature = "i18n")]
+        {
+            self.catalog.is_some()
+        }
+        #[cfg(not(feature = "i18n"))]
+        {
+            false
+        }
+    }
+
+    pub fn with_ansi(mut self, ansi: AnsiStyling) -> Self {
+        self.ansi = ansi;
+        self
+    }
+
+    pub fn with_pretty_printer(mut self, pretty_printer: PrettyPrinter) -> Self {
+        self.pretty_printer = pretty_printer;
+        self
+    }
+
+    /// Exposes `function` to templates as `name`.
+    pub fn with_function<F>(mut self, name: impl Into<Symbol>, function: F) -> Self
+    where
+        F: NativeFunction + Send + Sync + 'static,
+    {
+        self.functions
+            .push((name.into(), CustomFunction::Native(Arc::new(function))));
+        self
+    }
+
+    /// Exposes `function` to templates as `name`. `function` is invoked with
+    /// the arguments passed to it by the template, and any error it returns
+    /// is returned from rendering.
+    ///
+    /// ```rust
+    /// use budplate::{vm::Value, Configuration, Error, ErrorKind};
+    ///
+    /// let config = Configuration::default().with_helper("shout", |args: &[Value]| {
+    ///     match args {
+    ///         [value] => Ok(Value::from(format!("{}!", value.try_convert_to_string(&()).unwrap()))),
+    ///         _ => Err(Error::from(ErrorKind::InvalidArgument {
+    ///             function: String::from("shout"),
+    ///             message: String::from("expected one argument"),
+    ///         })),
+    ///     }
+    /// });
+    /// assert_eq!(config.render(r#"{{= shout("hi") }}"#).unwrap(), "hi!");
+    /// ```
+    pub fn with_helper<F>(mut self, name: impl Into<Symbol>, function: F) -> Self
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.functions
+            .push((name.into(), CustomFunction::Helper(Arc::new(function))));
+        self
+    }
+
+    /// Exposes `function` to templates as `name`, like
+    /// [`Configuration::with_helper`]. `function` is also passed the
+    /// [`HelperContext`] of the render calling it, from which it can look up
+    /// the capabilities provided to the render instead of capturing them.
+    pub fn with_contextual_helper<F>(mut self, name: impl Into<Symbol>, function: F) -> Self
+    where
+        F: Fn(&HelperContext<'_>, &[Value]) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.functions
+            .push((name.into(), CustomFunction::Contextual(Arc::new(function))));
+        self
+    }
+
+    /// Sets the asset metadata consulted by the `img()` and `sri()`
+    /// functions.
+    pub fn with_assets(mut self, assets: AssetManifest) -> Self {
+        self.assets = assets;
+        self
+    }
+
+    pub fn render(&self, template: &str) -> Result<String, Error> {
+        self.render_with::<&'static str, Value, _>(template, [])
+    }
+
+    pub fn render_with<Name, Arg, Args>(&self, template: &str, args: Args) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<Value>) = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .unzip();
+        let mut output = String::new();
+        self.compile(&Template::from(template), symbols)?
+            .render_values_to(values, &mut output)?;
+        Ok(output)
+    }
+
+    /// Renders `template` with `args`, making `context` available to built-in
+    /// functions.
+    pub fn render_with_context<Name, Arg, Args>(
+        &self,
+        template: &str,
+        context: impl ContextProvider,
+        args: Args,
+    ) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<Value>) = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .unzip();
+        let compiled = self.compile(&Template::from(template), symbols)?;
+        let mut output = String::new();
+        compiled.machine.state.with_context(Box::new(context), || {
+            compiled.render_values_to(values, &mut output)
+        })?;
+        Ok(output)
+    }
+
+    /// Renders `template` with `args` into `output`. Output is written as the
+    /// template executes rather than being collected first.
+    pub fn render_to<W, Name, Arg, Args>(
+        &self,
+        output: &mut W,
+        template: &str,
+        args: Args,
+    ) -> Result<(), Error>
+    where
+        W: Write,
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<Value>) = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .unzip();
+        self.compile(&Template::from(template), symbols)?
+            .render_values_to(values, output)
+    }
+
+    /// Returns the source of `template` with each tag's code surrounded by a
+    /// single space, using this configuration's delimiters. See
+    /// [`Template::normalize`].
+    pub fn normalize(&self, template: &str) -> Result<String, Error> {
+        Ok(Template::from(template)
+            .parse(self.delimiters)?
+            .to_source(self.delimiters))
+    }
+
+    /// Renders `template` with `args`, passing its output to `on_fragment`
+    /// in pieces as it executes. See [`CompiledTemplate::render_fragments`].
+    pub fn render_fragments<Name, Arg, Args, F>(
+        &self,
+        template: &str,
+        args: Args,
+        mut on_fragment: F,
+    ) -> Result<(), Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+        F: FnMut(Fragment<'_>) -> Result<(), Error>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<Value>) = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .unzip();
+        self.compile(&Template::from(template), symbols)?
+            .render_values_in_fragments(values, &mut on_fragment)
+    }
+
+    /// Renders `template` with the fields of `context` as its arguments.
+    /// `context` must serialize as a struct or map.
+    #[cfg(feature = "serde")]
+    pub fn render_context<T: serde::Serialize + ?Sized>(
+        &self,
+        template: &str,
+        context: &T,
+    ) -> Result<String, Error> {
+        let context = to_value(context)?;
+        let value::Shape::Map(entries) = value::Shape::of(&context) else {
+            return Err(serde::ser::Error::custom(
+                "the context must serialize as a struct or map",
+            ));
+        };
+        self.render_with(template, value::sorted_entries(entries))
+    }
+
+    /// Renders the template registered as `name` in `templates`, which may
+    /// include other templates from `templates`.
+    pub fn render_from<Name, Arg, Args>(
+        &self,
+        templates: &Templates<'_>,
+        name: &str,
+        args: Args,
+    ) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<Value>) = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .unzip();
+        let mut output = String::new();
+        self.compile_from(templates, name, symbols)?
+            .render_values_to(values, &mut output)?;
+        Ok(output)
+    }
+
+    /// Compiles `template` into a form that can be rendered repeatedly.
+    /// Arguments provided when rendering must match `parameters`.
+    pub fn compile<Params>(
+        &self,
+        template: &Template<'_>,
+        parameters: Params,
+    ) -> Result<CompiledTemplate, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        self.compile_template(template, None, parameters)
+    }
+
+    /// Compiles the template registered as `name` in `templates`, which may
+    /// include other templates from `templates`.
+    pub fn compile_from<Params>(
+        &self,
+        templates: &Templates<'_>,
+        name: &str,
+        parameters: Params,
+    ) -> Result<CompiledTemplate, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let template = templates
+            .get(name)
+            .ok_or_else(|| ErrorKind::UnknownTemplate(name.to_string()))?;
+        self.compile_template(template, Some((templates, name)), parameters)
+    }
+
+    /// Compiles `template`, which is registered as `name` in `templates`
+    /// when `registry` is provided.
+    fn compile_template<Params>(
+        &self,
+        template: &Template<'_>,
+        registry: Option<(&Templates<'_>, &str)>,
+        parameters: Params,
+    ) -> Result<CompiledTemplate, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let machine = Rc::new(self.machine());
+        self.compile_into(&machine, "render", template, registry, parameters)
+    }
+
+    /// Returns a new virtual machine with the native functions of this
+    /// configuration installed.
+    fn machine(&self) -> Machine {
+        let state = RenderState::default();
+        state.limit_output(self.max_output_len);
+        state.seed_sampling(self.sampling_seed.unwrap_or_else(|| {
+            std::hash::BuildHasher::hash_one(&std::collections::hash_map::RandomState::new(), 0)
+        }));
+        let (bud, functions) = builtins::install(self, &state).finish();
+        Machine {
+            bud: RefCell::new(bud),
+            expressions: ExpressionWriter::new(self, &state),
+            state,
+            functions: Cell::new(functions),
+            instances: Cell::new(0),
+            executed: Cell::new(0),
+        }
+    }
+
+    /// Compiles `template` into `machine`, rendered by the function
+    /// `function`, which must not already be defined.
+    fn compile_into<Params>(
+        &self,
+        machine: &Rc<Machine>,
+        function: &str,
+        template: &Template<'_>,
+        registry: Option<(&Templates<'_>, &str)>,
+        parameters: Params,
+    ) -> Result<CompiledTemplate, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let (templates, name) = registry.unzip();
+        let parameters = parameters
+            .into_iter()
+            .map(ParameterName::into_symbol)
+            .collect::<Vec<_>>();
+        if let Some(compiled) = self.compile_executable(machine, template, name, &parameters)? {
+            return Ok(compiled);
+        }
+        let bud_source = self.transpile(
+            template,
+            templates,
+            function,
+            &parameters,
+            machine.instances.get(),
+        )?;
+        self.evaluate_into(machine, &bud_source, parameters, name)
+    }
+
+    /// Compiles `template` using the configured backend or by substitution,
+    /// returning `None` if it must be executed by the Bud virtual machine
+    /// instead.
+    fn compile_executable(
+        &self,
+        machine: &Rc<Machine>,
+        template: &Template<'_>,
+        name: Option<&str>,
+        parameters: &[Symbol],
+    ) -> Result<Option<CompiledTemplate>, Error> {
+        // Annotations and audits are written by the Bud source of each tag,
+        // so these templates aren't substituted.
+        let substitution = self.substitution && !self.annotate_output && !self.audit_accesses;
+        if self.backend.is_none() && !substitution {
+            return Ok(None);
+        }
+        let parsed = self.parse(template)?;
+        let backend_template = BackendTemplate::new(&parsed, name, parameters);
+        let mut executable = match &self.backend {
+            Some(backend) => backend.compile(&backend_template)?,
+            None => None,
+        };
+        if executable.is_none() && substitution {
+            executable = Substitution.compile(&backend_template)?;
+        }
+        let Some(executable) = executable else {
+            return Ok(None);
+        };
+        let metadata = parsed.outline()?.metadata;
+        Ok(Some(self.compiled(
+            machine,
+            parameters.to_vec(),
+            0,
+            name,
+            metadata,
+            Some(executable),
+        )))
+    }
+
+    /// Transpiles `template` into Bud source, rendered by the function
+    /// `function`.
+    fn transpile(
+        &self,
+        template: &Template<'_>,
+        templates: Option<&Templates<'_>>,
+        function: &str,
+        parameters: &[Symbol],
+        first_instance: usize,
+    ) -> Result<BudSource, Error> {
+        self.parse(template)?.into_bud_source(
+            function,
+            parameters,
+            self.transpile_options(templates, first_instance),
+        )
+    }
+
+    /// Returns the options that templates are transpiled with by this
+    /// configuration.
+    fn transpile_options<'a, 'r>(
+        &'a self,
+        templates: Option<&'a Templates<'r>>,
+        first_instance: usize,
+    ) -> TranspileOptions<'a, 'r> {
+        TranspileOptions {
+            templates,
+            exact_whitespace: self.exact_whitespace,
+            auto_trim: self.auto_trim,
+            delimiters: self.delimiters,
+            on_missing: self.on_missing,
+            memoize_partials: self.memoize_partials,
+            messages: self.has_catalog(),
+            annotate_output: self.annotate_output,
+            audit_accesses: self.audit_accesses,
+            coercion: self.coercion.is_some(),
+            edge_includes: self.edge_includes.as_deref(),
+            first_instance,
+        }
+    }
+
+    /// Parses `template`, trimming its whitespace according to this
+    /// configuration.
+    fn parse<'t>(&self, template: &'t Template<'_>) -> Result<ParsedTemplate<'t>, Error> {
+        let mut template = template.parse(self.delimiters)?;
+        if self.exact_whitespace {
+            template.disable_trimming();
+        } else if self.auto_trim {
+            template.trim_standalone_tags();
+        }
+        Ok(template)
+    }
+
+    /// Compiles the Bud source of a template into `machine`.
+    fn evaluate_into(
+        &self,
+        machine: &Rc<Machine>,
+        bud_source: &BudSource,
+        parameters: Vec<Symbol>,
+        name: Option<&str>,
+    ) -> Result<CompiledTemplate, Error> {
+        machine
+            .bud
+            .borrow_mut()
+            .evaluate::<()>(&bud_source.source)
+            .map_err(|err| bud_source.locate(Error::from(err)))?;
+        // The render function is the first function defined after those
+        // already in the machine, followed by its sub-templates.
+        let vtable_index = machine.functions.get();
+        machine
+            .functions
+            .set(vtable_index + 1 + bud_source.instances);
+        machine
+            .instances
+            .set(machine.instances.get() + bud_source.instances);
+
+        Ok(self.compiled(
+            machine,
+            parameters,
+            vtable_index,
+            name,
+            bud_source.metadata.clone(),
+            None,
+        ))
+    }
+
+    /// Returns a template compiled into `machine`, which is rendered by the
+    /// function at `vtable_index` unless `executable` is provided.
+    fn compiled(
+        &self,
+        machine: &Rc<Machine>,
+        parameters: Vec<Symbol>,
+        vtable_index: usize,
+        name: Option<&str>,
+        metadata: BTreeMap<String, String>,
+        executable: Option<Box<dyn Executable>>,
+    ) -> CompiledTemplate {
+        CompiledTemplate {
+            machine: machine.clone(),
+            parameters,
+            vtable_index,
+            budget: self.budget,
+            on_missing: self.on_missing,
+            transforms: (!self.transforms.is_empty()).then(|| Transforms {
+                transforms: self.transforms.clone(),
+                assets: self.assets.clone(),
+                template: name.map(String::from),
+            }),
+            recorder: self.recorder.as_ref().map(|recorder| Recorder {
+                recorder: recorder.clone(),
+                printer: self.pretty_printer.clone(),
+                template: name.map(String::from),
+            }),
+            usage: self.usage.as_ref().map(|observer| Usage {
+                observer: observer.clone(),
+                template: name.map(String::from),
+                rendered: Cell::new(false),
+            }),
+            quota: self.quota.clone(),
+            metadata,
+            executable,
+        }
+    }
+}
+
+#[test]
+fn html_escaped_template() {
+    assert_eq!(
+        Configuration::for_html()
+            .render(r#"{{:= "unsafe & not encoded" }}/{{= "safe & encoded" }}"#)
+            .unwrap(),
+        "unsafe & not encoded/safe &amp; encoded"
+    );
+}
+
+#[test]
+fn dump_redacts_keys() {
+    let user = [("name", "ecton"), ("password", "hunter2")]
+        .into_iter()
+        .map(|(key, value)| (Value::from(key), Value::from(value)))
+        .collect::<budlang::vm::HashMap>();
+    assert_eq!(
+        Configuration::default()
+            .with_pretty_printer(PrettyPrinter::new().redact_keys(["password"]))
+            .render_with("{{:= dump(user) }}", [("user", Value::dynamic(user))])
+            .unwrap(),
+        r#"{name: "ecton", password: <redacted>}"#
+    );
+
+    let token = [(Value::from("API_Token"), Value::from("secret"))]
+        .into_iter()
+        .collect::<budlang::vm::HashMap>();
+    assert_eq!(
+        Configuration::default()
+            .with_pretty_printer(PrettyPrinter::new().redact_keys(["api_token"]))
+            .render_with("{{:= dump(token) }}", [("token", Value::dynamic(token))])
+            .unwrap(),
+        r#"{API_Token: <redacted>}"#
+    );
+}
+
+#[test]
+fn source_reconstruction() {
+    let sources = [
+        "plain text",
+        "{{= a }}{{:= b }}{{=url c }}{{=- d -}}{{:=- e }}{{=-json f }}",
+        "{{ if a }}x{{- else -}}y{{ end }}",
+        "{{#- a {{ comment }} -#}}{{ raw }}{{ not parsed }}{{- end raw -}}",
+        r#"{{ define "row" }}<li>{{= item }}</li>{{ end define }}{{ render "row" with item = 1 }}"#,
+    ];
+    for source in sources {
+        assert_eq!(Template::from(source).normalize().unwrap(), source);
+    }
+
+    let normalized = Template::from("{{=a}}{{  b  }}{{:=-c-}}{{raw}}{{x}}{{end   raw}}")
+        .normalize()
+        .unwrap();
+    assert_eq!(
+        normalized,
+        "{{= a }}{{ b }}{{:=- c -}}{{ raw }}{{x}}{{ end raw }}"
+    );
+    assert_eq!(
+        Template::from(normalized.as_str()).normalize().unwrap(),
+        normalized
+    );
+
+    let configuration = Configuration::default().with_delimiters("<%", "%>");
+    assert_eq!(
+        configuration.normalize("<%=x%><%# c #%>").unwrap(),
+        "<%= x %><%# c #%>"
+    );
+    assert!(Template::from("{{= a").normalize().is_err());
+}
+
+#[test]
+fn auto_trim() {
+    let source = "<ul>\n  {{ for item in items }}\n  <li>{{= item }}</li>\n  {{ end }}\n</ul>\n{{# note #}} x\n";
+    let template = Template::from(source);
+    let mut template = template.parse(Delimiters::DEFAULT).unwrap();
+    template.trim_standalone_tags();
+    let raw = template
+        .segments
+        .iter()
+        .filter(|segment| matches!(segment.kind, SegmentKind::Raw))
+        .map(|segment| &source[segment.range.clone()])
+        .collect::<Vec<_>>();
+    assert_eq!(raw, ["<ul>\n", "  <li>", "</li>\n", "</ul>\n", " x\n"]);
+
+    let items = Value::dynamic(
+        ["a", "b"]
+            .into_iter()
+            .map(Value::from)
+            .collect::<budlang::vm::List>(),
+    );
+    assert_eq!(
+        Configuration::default()
+            .auto_trim()
+            .render_with(source, [("items", items)])
+            .unwrap(),
+        "<ul>\n  <li>a</li>\n  <li>b</li>\n</ul>\n x\n"
+    );
+}
+
+#[test]
+fn exact_whitespace() {
+    let template = "a \n{{- if true -}}\n b {{=- 1 -}} c\n{{ end }}";
+    assert_eq!(Configuration::default().render(template).unwrap(), "ab1c\n");
+    assert_eq!(
+        Configuration::default()
+            .exact_whitespace()
+            .render(template)
+            .unwrap(),
+        "a \n\n b 1 c\n"
+    );
+}
+
+#[test]
+fn include_registered_templates() {
+    let mut templates = Templates::new();
+    templates
+        .register("header", "<h1>{{= title }}</h1>")
+        .unwrap();
+    templates
+        .register(
+            "page",
+            r#"{{ include "header" with title }}<p>{{= body }}</p>{{ include "footer" }}"#,
+        )
+        .unwrap();
+    templates.register("footer", "<footer></footer>").unwrap();
+    assert!(matches!(
+        templates.register("footer", ""),
+        Err(Error { kind: ErrorKind::DuplicateTemplate(name), .. }) if name == "footer"
+    ));
+
+    assert_eq!(
+        Configuration::for_html()
+            .render_from(&templates, "page", [("title", "A & B"), ("body", "Hi")])
+            .unwrap(),
+        "<h1>A &amp; B</h1><p>Hi</p><footer></footer>"
+    );
+    assert!(matches!(
+        Template::from(r#"{{ include "header" }}"#).render().unwrap_err().kind,
+        ErrorKind::UnknownTemplate(name) if name == "header"
+    ));
+}
+
+#[test]
+fn template_inheritance() {
+    let mut templates = Templates::new();
+    templates
+        .register(
+            "layout",
+            "<title>{{ block title }}Site{{ end }}</title><main>{{ block content }}{{ end block }}</main>",
+        )
+        .unwrap();
+    templates
+        .register(
+            "article",
+            r#"{{ extends "layout" }}{{ block content }}<article>{{ block body }}{{ end }}</article>{{ end }}"#,
+        )
+        .unwrap();
+    templates
+        .register(
+            "post",
+            r#"{{ extends "article" }}
+            {{- block title }}{{= title }}{{ end }}
+            {{- block body }}{{ loop for i := 1 to 2 inclusive }}{{= i }}{{ end }}{{ end }}"#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        Configuration::for_html()
+            .render_from(&templates, "article", Vec::<(&str, Value)>::new())
+            .unwrap(),
+        "<title>Site</title><main><article></article></main>"
+    );
+    assert_eq!(
+        Configuration::for_html()
+            .render_from(&templates, "post", [("title", "A & B")])
+            .unwrap(),
+        "<title>A &amp; B</title><main><article>12</article></main>"
+    );
+    assert!(matches!(
+        Template::from(r#"{{ extends "layout" }}"#).render().unwrap_err().kind,
+        ErrorKind::UnknownTemplate(name) if name == "layout"
+    ));
+    assert!(matches!(
+        Template::from("{{ block content }}").render().unwrap_err().kind,
+        ErrorKind::UnterminatedBlock(name) if name == "content"
+    ));
+}
+
+#[test]
+fn components() {
+    let mut templates = Templates::new();
+    templates
+        .register("card", "<div><h2>{{= title }}</h2>{{= slot }}</div>")
+        .unwrap();
+    templates
+        .register(
+            "page",
+            r#"{{ loop for i := 1 to 2 inclusive }}{{ component "card" with title = i -}}
+                <p>{{= i }} & {{ component "card" with title = "inner" }}{{ end }}</p>
+            {{- end component }}{{ end }}"#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        Configuration::for_html()
+            .render_from(&templates, "page", Vec::<(&str, Value)>::new())
+            .unwrap(),
+        "<div><h2>1</h2><p>1 & <div><h2>inner</h2></div></p></div>\
+         <div><h2>2</h2><p>2 & <div><h2>inner</h2></div></p></div>"
+    );
+    assert!(matches!(
+        Template::from(r#"{{ component "card" }}{{ end block }}"#)
+            .render()
+            .unwrap_err()
+            .kind,
+        ErrorKind::UnterminatedBlock(name) if name == "card"
+    ));
+}
+
+#[test]
+fn scoped_classes() {
+    let mut templates = Templates::new();
+    templates
+        .register(
+            "card",
+            r#"<div class="{{= class("card wide") }}">{{= slot }}</div>"#,
+        )
+        .unwrap();
+    templates
+        .register(
+            "page",
+            r#"{{ component "card" }}<p class="{{= class("note") }}"></p>{{ end }}"#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        Configuration::for_html()
+            .render_from(&templates, "page", Vec::<(&str, Value)>::new())
+            .unwrap(),
+        format!(
+            r#"<div class="{} {}"><p class="{}"></p></div>"#,
+            scoped_class("card", "card"),
+            scoped_class("card", "wide"),
+            scoped_class("page", "note"),
+        )
+    );
+    assert_eq!(
+        Template::from(r#"{{= class("plain") }}"#).render().unwrap(),
+        "plain"
+    );
+
+    let calls = |source: &str| {
+        Template::from(source)
+            .parse(Delimiters::DEFAULT)
+            .unwrap()
+            .calls("class")
+    };
+    assert!(calls(r#"{{ if a }}{{= class("a") }}{{ end }}"#));
+    assert!(!calls(
+        r#"<p>class("a")</p>{{= "class(" }}{{# class("b") #}}"#
+    ));
+    assert!(!calls(r#"{{= item.class("a") }}{{= subclass("b") }}"#));
+}
+
+#[test]
+fn custom_delimiters() {
+    let configuration = Configuration::default().with_delimiters("<%", "%>");
+    assert_eq!(
+        configuration
+            .render(
+                "\\frac{{a}}{{b}}: <% loop for i := 1 to 2 inclusive %><%= i %><% end %><%# no #%>"
+            )
+            .unwrap(),
+        "\\frac{{a}}{{b}}: 12"
+    );
+    assert!(matches!(
+        configuration.render("<%= a").unwrap_err().kind,
+        ErrorKind::MissingEndBraces
+    ));
+}
+
+#[test]
+fn execution_limits() {
+    let configuration = Configuration::default().with_budget(10_000);
+    assert!(matches!(
+        configuration
+            .render("{{ loop for i := 1 to 100000000 }}{{ end }}")
+            .unwrap_err()
+            .kind,
+        ErrorKind::BudgetExceeded
+    ));
+    assert_eq!(configuration.render("{{= 1 + 1 }}").unwrap(), "2");
+
+    // Operations left unused by cheap renders aren't saved for later ones.
+    let compiled = configuration
+        .compile(
+            &Template::from("{{ loop for i := 1 to count }}{{ end }}"),
+            ["count"],
+        )
+        .unwrap();
+    for _ in 0..100 {
+        compiled.render_with([("count", 1_i64)]).unwrap();
+    }
+    assert!(matches!(
+        compiled
+            .render_with([("count", 20_000_i64)])
+            .unwrap_err()
+            .kind,
+        ErrorKind::BudgetExceeded
+    ));
+    compiled.render_with([("count", 1_i64)]).unwrap();
+
+    let configuration = Configuration::default().with_max_output_len(8);
+    assert_eq!(configuration.render("12345678").unwrap(), "12345678");
+    assert!(matches!(
+        configuration
+            .render("{{ loop for i := 1 to 10 }}{{= i }}{{ end }}")
+            .unwrap_err()
+            .kind,
+        ErrorKind::OutputTooLarge
+    ));
+}
+
+#[test]
+fn rejected_floats() {
+    let configuration = Configuration::for_html().reject_floats();
+    assert_eq!(
+        configuration
+            .render("{{= 1 + 2 }} {{:= 3 }} {{=url \"1.5\" }}")
+            .unwrap(),
+        "3 3 1.5"
+    );
+    for source in ["{{= 1.5 }}", "{{:= 0.1 + 0.2 }}", "{{=json 2.0 }}"] {
+        assert!(matches!(
+            configuration.render(source).unwrap_err().kind,
+            ErrorKind::FloatOutput(_)
+        ));
+    }
+    assert_eq!(
+        Configuration::for_html().render("{{= 1.5 }}").unwrap(),
+        "1.5"
+    );
+}
+
+#[test]
+fn coercion_policies() {
+    let strict = Configuration::default().with_coercion(StrictCoercion);
+    let lenient = Configuration::default().with_coercion(LenientCoercion);
+    let source = "{{ total := a + b }}{{ if total > 2 and not (a == b) }}{{= total }}{{ else }}small{{ end }}";
+    let render = |configuration: &Configuration<NoEncoding>, a: Value, b: Value| {
+        configuration
+            .compile(&Template::from(source), ["a", "b"])
+            .unwrap()
+            .render_with([("a", a), ("b", b)])
+    };
+    assert_eq!(
+        render(&strict, Value::Integer(1), Value::Integer(2)).unwrap(),
+        "3"
+    );
+    assert_eq!(
+        render(&strict, Value::Integer(1), Value::Integer(1)).unwrap(),
+        "small"
+    );
+    assert!(matches!(
+        render(&strict, Value::from("1"), Value::Integer(2)),
+        Err(Error {
+            kind: ErrorKind::Coercion(_),
+            ..
+        })
+    ));
+    assert_eq!(
+        render(&lenient, Value::from("1"), Value::Integer(2)).unwrap(),
+        "12"
+    );
+    assert_eq!(
+        render(&lenient, Value::Void, Value::Integer(0)).unwrap(),
+        "small"
+    );
 
-impl Encoder for HtmlEncoding {
-    fn encode<W: Write>(&self, input: &str, output: &mut W) {
-        let mut last_byte_written = 0;
-        for (index, ch) in input.char_indices() {
-            let encoded = match ch {
-                '&' => "&amp;",
-                '<' => "&lt;",
-                '>' => "&gt;",
-                '"' => "&quot;",
-                '\'' => "&#39;",
-                _ => continue,
-            };
-            if last_byte_written < index {
-                output.write_str(&input[last_byte_written..index]).unwrap();
-            }
-            output.write_str(encoded).unwrap();
-            last_byte_written = index + 1;
+    for (source, expected) in [
+        ("{{ if 0 }}a{{ else }}b{{ end }}", "b"),
+        (r#"{{ if "0" }}a{{ else }}b{{ end }}"#, "b"),
+        (r#"{{ if 1 == "1.0" }}a{{ else }}b{{ end }}"#, "a"),
+        (r#"{{ if "10" > 9 }}a{{ else }}b{{ end }}"#, "a"),
+        (r#"{{= "a" + 2 + 1.5 }}"#, "a21.5"),
+    ] {
+        assert_eq!(lenient.render(source).unwrap(), expected, "{source}");
+    }
+    for source in [
+        "{{ if 0 }}a{{ end }}",
+        r#"{{ if 1 < "2" }}a{{ end }}"#,
+        r#"{{= "a" + 1 }}"#,
+    ] {
+        assert!(
+            matches!(
+                strict.render(source).unwrap_err().kind,
+                ErrorKind::Coercion(_)
+            ),
+            "{source}"
+        );
+    }
+    assert!(!Template::from("{{= a + 1 }}")
+        .transpile("render", ["a"])
+        .unwrap()
+        .contains("__add"));
+}
+
+#[test]
+fn unprintable_values() {
+    use budlang::vm::HashMap;
+
+    let user = || {
+        Value::dynamic(
+            [(Value::from("id"), Value::Integer(7))]
+                .into_iter()
+                .collect::<HashMap>(),
+        )
+    };
+    let error = Template::from("<p>{{= user }}</p>")
+        .render_with([("user", user())])
+        .unwrap_err();
+    assert!(matches!(
+        &error.kind,
+        ErrorKind::UnprintableValue {
+            expression: Some(expression),
+            value_type: "map",
+        } if expression == "user"
+    ));
+    assert_eq!(
+        error.to_string(),
+        "`user` is a map, which can't be rendered as text"
+    );
+    assert_eq!(
+        Configuration::default()
+            .lenient_output()
+            .render_with(
+                "{{= user }} {{:= user }} {{=url user }}",
+                [("user", user())]
+            )
+            .unwrap(),
+        "{id: 7} {id: 7} %7Bid%3A%207%7D"
+    );
+}
+
+#[test]
+fn collection_iteration() {
+    use budlang::vm::{HashMap, List};
+
+    let items = Value::dynamic(["a", "b"].into_iter().map(Value::from).collect::<List>());
+    let prices = Value::dynamic(
+        [("pear", 2), ("apple", 1)]
+            .into_iter()
+            .map(|(key, value)| (Value::from(key), Value::from(value)))
+            .collect::<HashMap>(),
+    );
+    let template = Template::from(
+        "{{ for item in items }}[{{= item }}]{{ end }} \
+         {{ for index, item in items }}{{= index }}={{= item }};{{ end }} \
+         {{ for name, price in prices }}{{ for item in items }}{{= name }}{{= item }}{{ end }}{{= price }}{{ end }}\
+         {{ for item in nothing }}never{{ end }}",
+    );
+    assert_eq!(
+        template
+            .render_with([
+                ("items", items.clone()),
+                ("prices", prices),
+                ("nothing", Value::Void)
+            ])
+            .unwrap(),
+        "[a][b] 0=a;1=b; appleaappleb1pearapearb2"
+    );
+    assert!(template
+        .transpile("render", ["items", "prices", "nothing"])
+        .unwrap()
+        .contains("__entries_0 := __entries(items)\nloop for __entry_0 := 0 to __entry_count(__entries_0)\nitem := __entry_value(__entries_0, __entry_0)\n"));
+    assert!(Template::from("{{ for item in items }}{{ end }}")
+        .render_with([("items", 1)])
+        .is_err());
+    assert!(matches!(
+        Template::from("{{ for item in items }}")
+            .render()
+            .unwrap_err()
+            .kind,
+        ErrorKind::UnterminatedBlock(_)
+    ));
+}
+
+#[test]
+fn missing_values() {
+    use budlang::vm::HashMap;
+
+    let user = || {
+        Value::dynamic(
+            [("name", "Ada")]
+                .into_iter()
+                .map(|(key, value)| (Value::from(key), Value::from(value)))
+                .collect::<HashMap>(),
+        )
+    };
+    assert_eq!(
+        Template::from(
+            "{{= user.nickname ?? user.name }} {{= title ?? \"untitled\" }} \
+             {{ if defined(user.name) and not defined(user.nickname) }}a{{ end }}\
+             {{ if defined(title) }}b{{ end }}"
+        )
+        .render_with([("user", user())])
+        .unwrap(),
+        "Ada untitled a"
+    );
+    assert!(Template::from("{{= user.nickname }}")
+        .render_with([("user", user())])
+        .is_err());
+
+    let template = Template::from("[{{= user.nickname }}][{{= greeting }}]");
+    let render = |on_missing| {
+        template
+            .compile(
+                &Configuration::for_html().on_missing(on_missing),
+                ["user", "greeting"],
+            )
+            .unwrap()
+            .render_with([("user", user())])
+    };
+    assert!(matches!(
+        render(OnMissing::Fail),
+        Err(Error { kind: ErrorKind::MissingArgument(name), .. }) if name == "greeting"
+    ));
+    assert_eq!(render(OnMissing::Empty).unwrap(), "[][]");
+    assert_eq!(
+        render(OnMissing::Keep).unwrap(),
+        "[{{= user.nickname }}][{{= greeting }}]"
+    );
+    assert_eq!(
+        render(OnMissing::Placeholder).unwrap(),
+        "[⟦user.nickname⟧][⟦greeting⟧]"
+    );
+}
+
+#[test]
+fn parsed_segments() {
+    let template = Template::from("a{{# # #}}b{{= c }}d{{# e #}}");
+    for _ in 0..2 {
+        let parsed = template.parse(Delimiters::DEFAULT).unwrap();
+        assert_eq!(parsed.segments.len(), 7);
+        assert_eq!(parsed.segments.capacity(), parsed.segments.len());
+        assert_eq!(&parsed.source[parsed.segments[1].tag.clone()], "{{# # #}}");
+    }
+}
+
+#[test]
+fn comments_and_raw_blocks() {
+    assert_eq!(
+        Template::from("a{{# {{ ignored }} #}}b\n{{#- trimmed -#}}\nc")
+            .render()
+            .unwrap(),
+        "abc"
+    );
+    assert_eq!(
+        Template::from("{{ raw -}}\n{{= value }} }} {{ end }}\n{{- end raw }}")
+            .render()
+            .unwrap(),
+        "{{= value }} }} {{ end }}"
+    );
+    assert!(matches!(
+        Template::from("{{ raw }}{{ end }}").render().unwrap_err().kind,
+        ErrorKind::UnterminatedBlock(name) if name == "raw"
+    ));
+    assert!(matches!(
+        Template::from("{{# unterminated }}")
+            .render()
+            .unwrap_err()
+            .kind,
+        ErrorKind::MissingEndBraces
+    ));
+}
+
+#[test]
+fn streaming_output() {
+    let compiled = Template::from("{{ loop for i := 1 to 3 inclusive }}{{= i }},{{ end }}")
+        .compile(&Configuration::default(), Vec::<Symbol>::new())
+        .unwrap();
+    let mut output = Vec::new();
+    compiled
+        .render_to_io::<_, &'static str, Value, _>(&mut output, [])
+        .unwrap();
+    assert_eq!(output, b"1,2,3,");
+
+    struct Failing;
+
+    impl std::io::Write for Failing {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("closed"))
         }
 
-        if last_byte_written < input.len() {
-            output.write_str(&input[last_byte_written..]).unwrap();
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
+
+    assert!(matches!(
+        compiled.render_to_io::<_, &'static str, Value, _>(&mut Failing, []),
+        Err(Error {
+            kind: ErrorKind::Io(_),
+            ..
+        })
+    ));
 }
 
 #[test]
-fn html_encoding_test() {
-    let mut encoded = String::new();
-    HtmlEncoding.encode("&<>'\"unencoded", &mut encoded);
-    assert_eq!(encoded, "&amp;&lt;&gt;&#39;&quot;unencoded");
+fn errors_are_returned() {
+    assert!(matches!(
+        Template::from("{{= 1 + }}").render().unwrap_err().kind,
+        ErrorKind::Compilation(_)
+    ));
+    assert!(matches!(
+        Template::from("{{= missing_variable }}")
+            .render()
+            .unwrap_err()
+            .kind,
+        ErrorKind::Compilation(_) | ErrorKind::Runtime(_)
+    ));
 }
 
-pub struct Configuration<Enc> {
-    pub encoder: Enc,
-    pub auto_trim: bool,
+#[test]
+fn generated_source_of_compilation_errors() {
+    let mut templates = Templates::new();
+    templates
+        .register("page", "<h1>{{ include \"title\" }}</h1>")
+        .unwrap();
+    templates.register("title", "{{= 1 + }}").unwrap();
+    let error = templates.render("page").unwrap_err();
+    assert!(matches!(error.kind, ErrorKind::Compilation(_)));
+    let source = error.generated_source().unwrap();
+    assert!(source.contains("// 0..4\n__write(\"<h1>\")"), "{source}");
+    assert!(source.contains("// 0..10 in `title`\n"), "{source}");
+    assert!(format!("{error:?}").contains("generated_source"));
+
+    let error = Template::from("{{= missing }}")
+        .compile(&Configuration::default(), ["missing"])
+        .unwrap()
+        .render()
+        .unwrap_err();
+    assert_eq!(error.generated_source(), None);
 }
 
-impl Default for Configuration<NoEncoding> {
-    fn default() -> Self {
-        Self {
-            encoder: NoEncoding,
-            auto_trim: Default::default(),
-        }
+#[test]
+fn fragments() {
+    let source = r#"<html>{{ fragment "head" }}<head>{{= title }}</head>{{ end fragment }}{{ fragment "empty" }}{{ end fragment }}<body></body></html>"#;
+    let bud = Template::from(source)
+        .transpile("render", ["title"])
+        .unwrap();
+    assert!(bud.contains("__fragment_begin(\"head\")\n"));
+    assert_eq!(bud.matches("__fragment_end()").count(), 2);
+    for invalid in [
+        r#"{{ fragment "a" }}{{ fragment "b" }}{{ end fragment }}{{ end fragment }}"#,
+        r#"{{ fragment "a" }}"#,
+        r#"{{ end fragment }}"#,
+    ] {
+        assert!(Template::from(invalid)
+            .transpile("render", Vec::<Symbol>::new())
+            .is_err());
     }
+
+    let mut fragments = Vec::new();
+    Template::from(source)
+        .render_fragments([("title", "Hi")], |fragment| {
+            fragments.push((
+                fragment.name.map(String::from),
+                fragment.content.to_string(),
+            ));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(
+        fragments,
+        [
+            (None, String::from("<html>")),
+            (Some(String::from("head")), String::from("<head>Hi</head>")),
+            (Some(String::from("empty")), String::new()),
+            (None, String::from("<body></body></html>")),
+        ]
+    );
+
+    // Errors returned by the callback stop rendering.
+    let mut calls = 0;
+    let result = Template::from(source).render_fragments([("title", "Hi")], |_| {
+        calls += 1;
+        Err(ErrorKind::OutputTooLarge.into())
+    });
+    assert!(matches!(
+        result,
+        Err(Error {
+            kind: ErrorKind::OutputTooLarge,
+            ..
+        })
+    ));
+    assert_eq!(calls, 1);
 }
 
-impl Configuration<HtmlEncoding> {
-    pub const fn for_html() -> Self {
-        Self {
-            encoder: HtmlEncoding,
-            auto_trim: false,
+#[test]
+fn feature_regions() {
+    let source = r#"{{ feature "new_checkout" }}new{{ else }}old{{ end }}"#;
+    let bud = Template::from(source)
+        .transpile("render", Vec::<Symbol>::new())
+        .unwrap();
+    assert!(bud.contains("if __feature(\"new_checkout\")\n"));
+    // A `feature` region inside a definition must be closed within it.
+    assert!(
+        Template::from(r#"{{ define "a" }}{{ feature "b" }}{{ end define }}{{ end }}"#)
+            .transpile("render", Vec::<Symbol>::new())
+            .is_err()
+    );
+
+    let render = |configuration: &Configuration<NoEncoding>| {
+        let mut context = HashMap::new();
+        context.insert(String::from("beta"), Value::Boolean(true));
+        configuration
+            .compile(&Template::from(source), Vec::<Symbol>::new())
+            .unwrap()
+            .render_with_context::<&str, &str, _>(context, [])
+            .unwrap()
+    };
+    assert_eq!(render(&Configuration::default()), "old");
+    let enabled = std::collections::HashSet::from([String::from("new_checkout")]);
+    assert_eq!(
+        render(&Configuration::default().with_features(enabled)),
+        "new"
+    );
+    let per_request = Configuration::default().with_features(
+        |feature: &str, context: Option<&dyn ContextProvider>| {
+            feature == "new_checkout"
+                && matches!(
+                    context.and_then(|context| context.get("beta")),
+                    Some(Value::Boolean(true))
+                )
+        },
+    );
+    assert_eq!(render(&per_request), "new");
+}
+
+#[test]
+fn edge_includes() {
+    let source = r#"<main>{{ include "cart" with user, page = 2 }}{{ include "footer" }}</main>"#;
+    let mut templates = Templates::new();
+    templates.register("page", source).unwrap();
+    templates.register("footer", "<footer></footer>").unwrap();
+    let configuration = Configuration::for_html()
+        .with_edge_includes(EdgeIncludes::new().with_template("cart", "/esi/cart"));
+    assert!(configuration
+        .transpile(
+            templates.get("page").unwrap(),
+            Some(&templates),
+            "render",
+            &[],
+            0
+        )
+        .unwrap()
+        .source
+        .contains("__write(__edge_include(\"cart\", \"user\", (user), \"page\", (2)))\n"));
+    assert_eq!(
+        configuration
+            .render_from(&templates, "page", [("user", "a b")])
+            .unwrap(),
+        "<main><esi:include src=\"/esi/cart?user=a%20b&page=2\" /><footer></footer></main>"
+    );
+    // Without edge includes, the cart must be registered to be included.
+    assert!(matches!(
+        Configuration::for_html().render_from(&templates, "page", [("user", "a b")]),
+        Err(Error {
+            kind: ErrorKind::UnknownTemplate(_),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn island_placeholders() {
+    let source = r#"{{ island "Counter" with count, label = "<Clicks>" }}"#;
+    let bud = Template::from(source)
+        .transpile("render", ["count"])
+        .unwrap();
+    assert!(bud.contains(
+        "__write(__island(\"Counter\", \"count\", (count), \"label\", (\"<Clicks>\")))\n"
+    ));
+
+    let configuration = Configuration::for_html().with_island("Counter");
+    assert_eq!(configuration.islands().collect::<Vec<_>>(), ["Counter"]);
+    let template = configuration
+        .compile(&Template::from(source), ["count"])
+        .unwrap();
+    assert_eq!(
+        template.render_with([("count", 1_i64)]).unwrap(),
+        "<div data-island=\"Counter\" \
+         data-props=\"{&quot;count&quot;:1,&quot;label&quot;:&quot;&lt;Clicks&gt;&quot;}\"></div>"
+    );
+    assert!(matches!(
+        Configuration::for_html()
+            .compile(&Template::from(source), ["count"])
+            .unwrap()
+            .render_with([("count", 1_i64)]),
+        Err(Error {
+            kind: ErrorKind::InvalidArgument { .. },
+            ..
+        })
+    ));
+}
+
+#[test]
+fn experiment_variants() {
+    let exposures = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = exposures.clone();
+    let configuration =
+        Configuration::default().with_exposure_recorder(move |exposure: &Exposure| {
+            recorded.lock().unwrap().push(exposure.clone());
+        });
+    let template = configuration
+        .compile(
+            &Template::from(r#"{{= variant("hero", ["a", "b"]) }}"#),
+            Vec::<Symbol>::new(),
+        )
+        .unwrap();
+    let render = |user: Option<&str>| {
+        let mut context = HashMap::new();
+        if let Some(user) = user {
+            context.insert(String::from("user_id"), Value::from(user));
         }
-    }
+        template
+            .render_with_context::<&str, &str, _>(context, [])
+            .unwrap()
+    };
+    assert_eq!(render(None), "a");
+    assert_eq!(render(Some("42")), "b");
+    assert_eq!(render(Some("42")), "b");
+    assert_eq!(
+        exposures.lock().unwrap()[..2],
+        [
+            Exposure {
+                experiment: String::from("hero"),
+                variant: String::from("a"),
+                user: None,
+            },
+            Exposure {
+                experiment: String::from("hero"),
+                variant: String::from("b"),
+                user: Some(String::from("42")),
+            },
+        ]
+    );
+    assert!(Configuration::default()
+        .render(r#"{{= variant("hero", []) }}"#)
+        .is_err());
 }
 
-impl<Enc> Configuration<Enc>
-where
-    Enc: Encoder,
-{
-    pub fn auto_trim(mut self) -> Self {
-        self.auto_trim = true;
-        self
-    }
+#[test]
+fn unique_ids() {
+    let template = Template::from(
+        r#"{{= unique_id("field") }} {{= unique_id("field") }} {{= unique_id("tab") }}"#,
+    )
+    .compile(&Configuration::default(), Vec::<Symbol>::new())
+    .unwrap();
+    assert_eq!(template.render().unwrap(), "field-1 field-2 tab-1");
+    // Ids are counted from the start of each render.
+    assert_eq!(template.render().unwrap(), "field-1 field-2 tab-1");
+}
 
-    pub fn with_encoder<NewEnc>(self, encoder: NewEnc) -> Configuration<NewEnc> {
-        let Self { auto_trim, .. } = self;
-        Configuration { encoder, auto_trim }
-    }
+#[test]
+fn render_store() {
+    let configuration = Configuration::default();
+    let template = configuration
+        .compile(
+            &Template::from(
+                r#"{{ if set_once("title", "First") }}1{{ end }}{{ if set_once("title", "Second") }}2{{ end }}
+                   {{- push("scripts", "a.js") }}{{ push("scripts", "b.js") -}}
+                   [{{= get("title") }}]{{ for src in get("scripts") }} {{= src }}{{ end }}
+                   {{- if defined(get("missing")) }} missing{{ else }} none{{ end }}"#,
+            ),
+            Vec::<Symbol>::new(),
+        )
+        .unwrap();
+    assert_eq!(template.render().unwrap(), "1[First] a.js b.js none");
+    // The store is emptied after each render.
+    assert_eq!(template.render().unwrap(), "1[First] a.js b.js none");
+    assert!(matches!(
+        configuration.render(r#"{{ set_once("a", 1) }}{{ push("a", 2) }}"#),
+        Err(Error {
+            kind: ErrorKind::InvalidArgument { .. },
+            ..
+        })
+    ));
+}
 
-    pub fn render(&self, template: &str) -> Result<String, Error> {
-        self.render_with::<&'static str, Value, _>(template, [])
+#[test]
+fn deferred_content() {
+    let bud = Template::from("{{ defer scripts }}<script></script>{{ end }}{{ emit scripts }}")
+        .transpile("render", Vec::<Symbol>::new())
+        .unwrap();
+    assert!(bud.contains(
+        "__capture()\n__write(\"<script></script>\")\n__defer(\"scripts\", __captured())\n\
+         __write(__emit(\"scripts\"))\n"
+    ));
+    assert!(matches!(
+        Template::from("{{ defer scripts }}").transpile("render", Vec::<Symbol>::new()),
+        Err(Error {
+            kind: ErrorKind::UnterminatedBlock(name),
+            ..
+        }) if name == "scripts"
+    ));
+
+    let mut templates = Templates::new();
+    templates
+        .register(
+            "chart",
+            r#"<canvas></canvas>{{ defer scripts }}<script src="{{= src }}"></script>{{ end }}"#,
+        )
+        .unwrap();
+    templates
+        .register(
+            "page",
+            r#"{{ include "chart" with src = "a.js" }}{{ if true }}{{ defer scripts }}<b>{{ end }}{{ end }}
+               {{- include "chart" with src = "b.js" }}|{{ emit scripts }}"#,
+        )
+        .unwrap();
+    assert_eq!(
+        Configuration::for_html()
+            .render_from(&templates, "page", Vec::<(&str, &str)>::new())
+            .unwrap(),
+        r#"<canvas></canvas><canvas></canvas>|<script src="a.js"></script><b><script src="b.js"></script>"#
+    );
+    assert!(matches!(
+        Configuration::for_html().render("{{ emit scripts }}{{ defer scripts }}x{{ end }}"),
+        Err(Error {
+            kind: ErrorKind::InvalidArgument { .. },
+            ..
+        })
+    ));
+}
+
+#[test]
+fn once_regions() {
+    let mut templates = Templates::new();
+    templates
+        .register(
+            "button",
+            "{{ once }}<style>.button {}</style>{{ end }}<button>{{= label }}</button>",
+        )
+        .unwrap();
+    templates
+        .register(
+            "page",
+            r#"{{ for label in ["a", "b"] }}{{ include "button" with label }}{{ end }}"#,
+        )
+        .unwrap();
+    let configuration = Configuration::for_html();
+    let bud = configuration
+        .transpile(
+            templates.get("page").unwrap(),
+            Some(&templates),
+            "render",
+            &[],
+            0,
+        )
+        .unwrap();
+    assert!(bud.source.contains("if __once(\"button@0\")\n"));
+    let renderer = configuration.renderer(&templates);
+    for _ in 0..2 {
+        assert_eq!(
+            renderer
+                .render_with("page", Vec::<(&str, &str)>::new())
+                .unwrap(),
+            "<style>.button {}</style><button>a</button><button>b</button>"
+        );
     }
+}
 
-    pub fn render_with<Name, Arg, Args>(&self, template: &str, args: Args) -> Result<String, Error>
-    where
-        Args: IntoIterator<Item = (Name, Arg)>,
-        Name: Into<Symbol>,
-        Arg: Into<Value>,
-    {
-        let template = Template::from(template);
-        let template = template.parse()?;
-        let args = args.into_iter();
-        let (symbols, values): (Vec<_>, Vec<_>) =
-            args.map(|(name, arg)| (name.into(), arg.into())).unzip();
-        let bud_source = template.to_bud_source("render", &symbols);
-
-        let mut bud =
-            Bud::empty().with_native_function("encode", EncodeFunction(self.encoder.clone()));
-        bud.evaluate::<()>(&bud_source).unwrap();
-
-        // Push
-        let arg_count = values.len();
-        bud.stack.extend(values).unwrap();
+#[test]
+fn template_metadata() {
+    let template = Template::from(
+        r#"{{ meta title = "Invoice" }}{{ meta owner = "billing", route = "/invoices/{id}" }}{{= id }}"#,
+    );
+    let expected = BTreeMap::from([
+        (String::from("owner"), String::from("billing")),
+        (String::from("route"), String::from("/invoices/{id}")),
+        (String::from("title"), String::from("Invoice")),
+    ]);
+    assert_eq!(template.metadata().unwrap(), expected);
+    assert!(!template
+        .transpile("render", ["id"])
+        .unwrap()
+        .contains("meta"));
+    assert!(matches!(
+        Template::from(r#"{{ meta a = "1" }}{{ meta a = "2" }}"#).metadata(),
+        Err(Error {
+            kind: ErrorKind::InvalidStatement(_),
+            ..
+        })
+    ));
 
-        Ok(bud
-            .run(
-                &[Instruction::Call {
-                    vtable_index: Some(1),
-                    arg_count,
-                    destination: Destination::Return,
-                }],
-                0,
+    let mut templates = Templates::new();
+    templates.register("invoice", template.source()).unwrap();
+    let configuration = Configuration::for_html();
+    let compiled = configuration
+        .renderer(&templates)
+        .compile("invoice", ["id"])
+        .unwrap();
+    assert_eq!(compiled.metadata(), &expected);
+    let mut file = Vec::new();
+    configuration
+        .precompile(&templates, [("invoice", ["id"])])
+        .unwrap()
+        .write_to(&mut file)
+        .unwrap();
+    let cache = TemplateCache::read_from(&file[..]).unwrap();
+    assert_eq!(
+        configuration
+            .compile_cached(&cache, "invoice")
+            .unwrap()
+            .metadata(),
+        &expected
+    );
+}
+
+#[test]
+fn content_hashes() {
+    let hash = |source: &str| Template::from(source).content_hash().unwrap();
+    assert_eq!(hash("a {{#- note #}} b"), hash("a {{#- other note #}} b"));
+    assert_ne!(hash("a {{#- note #}} b"), hash("a {{# note #}} b"));
+    assert_ne!(
+        hash("{{ raw }}a{{ end raw }}"),
+        hash("{{ raw }}b{{ end raw }}")
+    );
+    // Hashes must not change between releases.
+    assert_eq!(hash("<p>{{= name }}</p>"), 0x51d4_de07_b97e_3992);
+
+    let mut templates = Templates::new();
+    templates.register("a", "{{= a }}").unwrap();
+    templates.register("b", "{{# b #}}").unwrap();
+    let bundle = templates.content_hash().unwrap();
+    let mut renamed = Templates::new();
+    renamed.register("a", "{{=a}}").unwrap();
+    renamed.register("c", "{{# b #}}").unwrap();
+    assert_ne!(renamed.content_hash().unwrap(), bundle);
+    renamed.rename("c", "b").unwrap();
+    assert_eq!(renamed.content_hash().unwrap(), bundle);
+}
+
+#[test]
+fn batches_share_collections() {
+    let template = Template::from("{{ seen.push(name) }}{{= name }}")
+        .compile(&Configuration::default(), ["seen", "name"])
+        .unwrap();
+    let seen = vm::list(Vec::<Value>::new());
+    let rendered = template
+        .render_batch(
+            [("seen", seen.clone())],
+            [
+                [("name", Value::from("Ada"))],
+                [("name", Value::from("Grace"))],
+            ],
+        )
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(rendered, ["Ada", "Grace"]);
+    // Both renders pushed onto the same list rather than onto copies of it.
+    let seen = Template::from("{{ for name in seen }}{{= name }} {{ end }}")
+        .render_with([("seen", seen)])
+        .unwrap();
+    assert_eq!(seen, "Ada Grace ");
+}
+
+#[test]
+fn shared_symbols() {
+    let template = Template::from("{{= shared_name }}");
+    let first = template
+        .compile(&Configuration::default(), ["shared_name"])
+        .unwrap();
+    let second = template
+        .compile(
+            &Configuration::default().with_budget(1_000),
+            [String::from("shared_name")],
+        )
+        .unwrap();
+    assert!(std::ptr::eq(
+        first.parameters()[0].as_ptr(),
+        second.parameters()[0].as_ptr()
+    ));
+}
+
+#[test]
+fn time_windows() {
+    let render = |now: &str| {
+        Configuration::default()
+            .with_clock(FixedClock::at(now).unwrap())
+            .render(
+                r#"{{ if between("2024-12-01", "2024-12-26") }}holidays{{ end }}
+                   {{- if before("2024-12-01T09:00:00Z") }} soon{{ end }}
+                   {{- if after(1735689600) }} new year{{ end }}"#,
             )
-            .unwrap())
-    }
+            .unwrap()
+    };
+    assert_eq!(render("2024-11-30T23:59:59Z"), " soon");
+    assert_eq!(render("2024-12-01"), "holidays soon");
+    assert_eq!(render("2024-12-25T23:59:59Z"), "holidays");
+    assert_eq!(render("2025-01-01"), " new year");
+    assert!(Configuration::default()
+        .render(r#"{{ if after("someday") }}{{ end }}"#)
+        .is_err());
 }
 
-struct EncodeFunction<Enc>(Enc);
+#[test]
+fn sampled_regions() {
+    let source = "{{ sampled 0.25 }}<!-- debug -->{{ end }}{{ sampled 1 }}beacon{{ end }}";
+    let bud = Template::from(source)
+        .transpile("render", Vec::<Symbol>::new())
+        .unwrap();
+    assert!(bud.contains("if __sampled(0.25)\n"));
+    assert!(bud.contains("if __sampled(1.0)\n"));
+    let bud = Template::from("{{ sampled 1e-1 }}a{{ end }}{{ sampled .5 }}b{{ end }}")
+        .transpile("render", Vec::<Symbol>::new())
+        .unwrap();
+    assert!(bud.contains("if __sampled(0.1)\n"));
+    assert!(bud.contains("if __sampled(0.5)\n"));
 
-impl<Enc> NativeFunction for EncodeFunction<Enc>
-where
-    Enc: Encoder,
-{
-    fn invoke(&self, args: &mut budlang::vm::PoppedValues<'_>) -> Result<Value, FaultKind> {
-        let arg = args
-            .next()
-            .ok_or_else(|| FaultKind::ArgumentMissing(Symbol::from("value")))?;
-        args.verify_empty()?;
+    let renders = |seed| {
+        let template = Configuration::default()
+            .with_sampling_seed(seed)
+            .compile(&Template::from(source), Vec::<Symbol>::new())
+            .unwrap();
+        (0..1000)
+            .map(|_| template.render().unwrap())
+            .collect::<Vec<_>>()
+    };
+    let sampled = renders(7);
+    assert!(sampled.iter().all(|render| render.ends_with("beacon")));
+    let debug = sampled
+        .iter()
+        .filter(|render| render.starts_with("<!--"))
+        .count();
+    assert!((150..350).contains(&debug), "{debug}");
+    assert_eq!(renders(7), sampled);
+}
 
-        let as_string = arg.try_convert_to_string(&())?;
-        let mut encoded = String::with_capacity(as_string.len());
-        self.0.encode(&as_string, &mut encoded);
-        Ok(Value::from(encoded))
-    }
+#[test]
+fn memoized_partials() {
+    let source = r#"{{ define "icon" }}<i>{{= counted(name) }}</i>{{ end define }}
+        {{- loop for i := 1 to 3 inclusive }}{{ render "icon" with name = "star" }}{{ end -}}
+        {{ render "icon" with name = "moon" }}"#;
+    let bud = Template::from(source)
+        .parse(Delimiters::DEFAULT)
+        .unwrap()
+        .into_bud_source(
+            "render",
+            &[],
+            TranspileOptions {
+                memoize_partials: true,
+                ..TranspileOptions::DEFAULT
+            },
+        )
+        .unwrap()
+        .source;
+    assert!(bud.contains("__memo_0_0 := (\"star\")\nif __memo_begin(\"__define_0\", __memo_0_0)"));
 
-    fn as_ptr(&self) -> *const u8 {
-        self as *const Self as *const u8
-    }
+    let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let configuration = |memoize: bool| {
+        let calls = calls.clone();
+        let configuration = Configuration::for_html().with_helper("counted", move |args| {
+            calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(args[0].clone())
+        });
+        if memoize {
+            configuration.memoize_partials()
+        } else {
+            configuration
+        }
+    };
+    let expected = "<i>star</i><i>star</i><i>star</i><i>moon</i>";
+    assert_eq!(configuration(false).render(source).unwrap(), expected);
+    assert_eq!(calls.swap(0, std::sync::atomic::Ordering::Relaxed), 4);
+    // Memoized output doesn't carry over into the next render.
+    let compiled = configuration(true)
+        .compile(&Template::from(source), Vec::<Symbol>::new())
+        .unwrap();
+    assert_eq!(compiled.render().unwrap(), expected);
+    assert_eq!(compiled.render().unwrap(), expected);
+    assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 4);
 }
 
 #[test]
-fn html_escaped_template() {
+fn stateful_memoized_partials() {
+    let source = r#"{{ define "field" -}}
+        <input id="{{= unique_id("field") }}">
+        {{- once }}<style></style>{{ end }}
+        {{- defer scripts }}<script>{{= name }}</script>{{ end }}
+        {{- end define -}}
+        {{ render "field" with name = "a" }}{{ render "field" with name = "a" }}{{ emit scripts }}"#;
+    let expected = r#"<input id="field-1"><style></style><input id="field-2"><script>a</script><script>a</script>"#;
+    assert_eq!(Configuration::for_html().render(source).unwrap(), expected);
     assert_eq!(
         Configuration::for_html()
-            .render(r#"{{:= "unsafe & not encoded" }}/{{= "safe & encoded" }}"#)
+            .memoize_partials()
+            .render(source)
             .unwrap(),
-        "unsafe & not encoded/safe &amp; encoded"
+        expected
+    );
+}
+
+#[test]
+fn error_locations() {
+    let source = "<ul>\n  {{ if true }}\n  {{ render \"missing\" }}\n{{ end }}";
+    let error = Template::from(source)
+        .parse(Delimiters::DEFAULT)
+        .and_then(|template| {
+            template
+                .into_bud_source("render", &[], TranspileOptions::DEFAULT)
+                .map(|_| ())
+        });
+    let error = error.unwrap_err();
+    assert_eq!(error.source_range(), Some(23..45));
+    assert_eq!(
+        error.location(source),
+        Some(Location { line: 3, column: 3 })
+    );
+
+    let source = "a\nb {{= c ";
+    let error = Template::from(source)
+        .parse(Delimiters::DEFAULT)
+        .unwrap_err();
+    assert!(matches!(error.kind, ErrorKind::MissingEndBraces));
+    assert_eq!(
+        error.location(source),
+        Some(Location { line: 2, column: 3 })
     );
+
+    let mut templates = Templates::new();
+    templates
+        .register("page", r#"{{ include "nav" }}"#)
+        .unwrap();
+    templates.register("nav", "{{ define \"x\" }}").unwrap();
+    let error = Configuration::default()
+        .compile_from(&templates, "page", Vec::<Symbol>::new())
+        .err()
+        .unwrap();
+    assert_eq!(error.template_name(), Some("nav"));
+    assert_eq!(error.source_range(), Some(0..16));
+}
+
+#[test]
+fn arbitrary_templates_do_not_panic() {
+    // Templates are assembled from fragments that exercise the parser's edge
+    // cases, so that unbalanced tags and partial statements are common.
+    const FRAGMENTS: &[&str] = &[
+        "{{",
+        "}}",
+        "{{=",
+        "{{:=",
+        "{{-",
+        "-}}",
+        "{{#",
+        "#}}",
+        "{{ raw }}",
+        " end ",
+        "if ",
+        "else",
+        "for ",
+        " in ",
+        "loop ",
+        "define ",
+        "block ",
+        "component ",
+        "slot ",
+        "extends ",
+        "include ",
+        "render ",
+        "fragment ",
+        "with ",
+        "html ",
+        "url ",
+        "\"page\"",
+        "\"part\"",
+        "\"",
+        "\\",
+        "x",
+        "a.b",
+        "(",
+        ")",
+        ",",
+        "=",
+        ":=",
+        "1",
+        "-1.5",
+        "=>",
+        " ",
+        "\n",
+        "é",
+        "😀",
+        "<p>",
+        "t(",
+        "plural(",
+    ];
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        usize::try_from(state % bound as u64).unwrap()
+    };
+    let mut generate = || {
+        (0..next(16))
+            .map(|_| FRAGMENTS[next(FRAGMENTS.len())])
+            .collect::<String>()
+    };
+
+    let configuration = Configuration::default()
+        .with_budget(10_000)
+        .with_max_output_len(1024);
+    for _ in 0..2_000 {
+        let page = generate();
+        let part = generate();
+        let _ = Template::from(page.as_str()).transpile("render", ["x"]);
+        let _ = Template::from(page.as_str()).normalize();
+        let _ = Template::from(page.as_str()).rename_variable("x", "y");
+        let _ = configuration.render(&page);
+
+        let mut templates = Templates::new();
+        if templates.register("page", page.as_str()).is_err()
+            || templates.register("part", part.as_str()).is_err()
+        {
+            continue;
+        }
+        let _ = templates.validate();
+        let _ = configuration.render_from(&templates, "page", [("x", "a")]);
+        let _ = templates.rename_include("part", "other");
+    }
 }
@@ -0,0 +1,386 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{
+    CompiledTemplate, Configuration, Encoder, Error, ErrorKind, ParameterName, RealFs, Templates,
+    Vfs,
+};
+
+/// Verifies the detached signatures of the templates read by a
+/// [`FileLoader`], such as using the public key of the party that
+/// distributed them.
+///
+/// The signature of a template is read from the file next to it with `.sig`
+/// appended to its name, such as `page.bud.html.sig`. A template whose
+/// signature doesn't verify fails to load with
+/// [`ErrorKind::InvalidSignature`], as does every template if any one fails.
+pub trait SignatureVerifier: 'static {
+    /// Returns true if `signature` is a valid signature of `source`, the
+    /// contents of the template registered as `name`.
+    fn verify(&self, name: &str, source: &[u8], signature: &[u8]) -> bool;
+}
+
+impl<F> SignatureVerifier for F
+where
+    F: Fn(&str, &[u8], &[u8]) -> bool + 'static,
+{
+    fn verify(&self, name: &str, source: &[u8], signature: &[u8]) -> bool {
+        self(name, source, signature)
+    }
+}
+
+/// Loads templates from files within a directory.
+///
+/// Each file ending in the loader's extension is registered using its path
+/// relative to the directory, without the extension. For example,
+/// `partials/nav.bud.html` can be included using `{{ include "partials/nav"
+/// }}`.
+///
+/// Compiled templates are cached. When hot reloading is enabled, which is the
+/// default in debug builds, the directory is checked for changes before each
+/// render and the templates are read again if any was added, removed, or
+/// modified. Only the cached templates whose layout changed are compiled
+/// again. See [`Configuration::layout_changed()`].
+///
+/// Templates distributed by third parties can be verified before they are
+/// compiled using [`FileLoader::with_signature_verifier()`]. Hot reloading
+/// then also checks signatures for changes, so replacing only a signature
+/// is verified before the next render. See [`SignatureVerifier`].
+///
+/// Files are read from the operating system's filesystem unless another
+/// [`Vfs`] is provided using [`FileLoader::with_vfs()`].
+pub struct FileLoader<Enc> {
+    vfs: Box<dyn Vfs>,
+    directory: PathBuf,
+    extension: String,
+    hot_reload: bool,
+    verifier: Option<Box<dyn SignatureVerifier>>,
+    require_signed: bool,
+    configuration: Configuration<Enc>,
+    loaded: RefCell<Option<Loaded>>,
+}
+
+struct Loaded {
+    templates: Templates<'static>,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    compiled: HashMap<(String, Vec<String>), Rc<CompiledTemplate>>,
+}
+
+impl<Enc: Encoder> FileLoader<Enc> {
+    pub fn new(directory: impl Into<PathBuf>, configuration: Configuration<Enc>) -> Self {
+        Self {
+            vfs: Box::new(RealFs),
+            directory: directory.into(),
+            extension: String::from("bud.html"),
+            hot_reload: cfg!(debug_assertions),
+            verifier: None,
+            require_signed: false,
+            configuration,
+            loaded: RefCell::new(None),
+        }
+    }
+
+    /// Sets the extension of the files to load, without a leading period.
+    /// The default is `bud.html`.
+    #[must_use]
+    pub fn with_extension(mut self, extension: impl Into<String>) -> Self {
+        self.extension = extension.into();
+        self
+    }
+
+    /// Reads the directory and its templates from `vfs` rather than from the
+    /// operating system's filesystem.
+    #[must_use]
+    pub fn with_vfs(mut self, vfs: impl Vfs) -> Self {
+        self.vfs = Box::new(vfs);
+        self
+    }
+
+    /// Sets whether changes to the directory are picked up without creating a
+    /// new loader.
+    #[must_use]
+    pub const fn hot_reload(mut self, enabled: bool) -> Self {
+        self.hot_reload = enabled;
+        self
+    }
+
+    /// Verifies the signature of each template that has one using
+    /// `verifier`.
+    #[must_use]
+    pub fn with_signature_verifier(mut self, verifier: impl SignatureVerifier) -> Self {
+        self.verifier = Some(Box::new(verifier));
+        self
+    }
+
+    /// Refuses to load templates without a signature, failing with
+    /// [`ErrorKind::UnsignedTemplate`]. Signatures are verified by the
+    /// loader's [`SignatureVerifier`], without which no signature is valid.
+    #[must_use]
+    pub const fn require_signed(mut self) -> Self {
+        self.require_signed = true;
+        self
+    }
+
+    pub fn render(&self, name: &str) -> Result<String, Error> {
+        self.render_with::<&'static str, Value, _>(name, [])
+    }
+
+    pub fn render_with<Name, Arg, Args>(&self, name: &str, args: Args) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<Value>) = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .unzip();
+        let mut output = String::new();
+        self.compile(name, symbols)?
+            .render_values_to(values, &mut output)?;
+        Ok(output)
+    }
+
+    /// Returns the compiled form of the template `name`, compiling it if it
+    /// isn't already cached.
+    pub fn compile<Params>(
+        &self,
+        name: &str,
+        parameters: Params,
+    ) -> Result<Rc<CompiledTemplate>, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let parameters = parameters
+            .into_iter()
+            .map(ParameterName::into_symbol)
+            .collect::<Vec<_>>();
+        let key = (
+            name.to_string(),
+            parameters.iter().map(|param| param.to_string()).collect(),
+        );
+
+        let mut loaded = self.loaded.borrow_mut();
+        let stale = match &*loaded {
+            Some(loaded) => self.hot_reload && loaded.files != self.files()?,
+            None => true,
+        };
+        let loaded = match &mut *loaded {
+            Some(loaded) if !stale => loaded,
+            loaded => {
+                let mut reloaded = self.load()?;
+                if let Some(previous) = loaded.take() {
+                    self.retain_unchanged(previous, &mut reloaded);
+                }
+                loaded.insert(reloaded)
+            }
+        };
+
+        if let Some(compiled) = loaded.compiled.get(&key) {
+            return Ok(compiled.clone());
+        }
+        let compiled = Rc::new(self.configuration.compile_from(
+            &loaded.templates,
+            name,
+            parameters,
+        )?);
+        loaded.compiled.insert(key, compiled.clone());
+        Ok(compiled)
+    }
+
+    /// Moves the templates compiled from `previous` whose layout is the same
+    /// in `reloaded` into its cache.
+    fn retain_unchanged(&self, previous: Loaded, reloaded: &mut Loaded) {
+        let mut unchanged = HashMap::new();
+        for (key, compiled) in previous.compiled {
+            let same = *unchanged.entry(key.0.clone()).or_insert_with(|| {
+                matches!(
+                    self.configuration.layout_changed(
+                        &previous.templates,
+                        &reloaded.templates,
+                        &key.0
+                    ),
+                    Ok(false)
+                )
+            });
+            if same {
+                reloaded.compiled.insert(key, compiled);
+            }
+        }
+    }
+
+    /// Reads every template within the directory into a new collection,
+    /// such as to extract their messages.
+    pub fn templates(&self) -> Result<Templates<'static>, Error> {
+        self.read(&self.files()?)
+    }
+
+    /// Reads every template within the directory.
+    fn load(&self) -> Result<Loaded, Error> {
+        let files = self.files()?;
+        Ok(Loaded {
+            templates: self.read(&files)?,
+            files,
+            compiled: HashMap::new(),
+        })
+    }
+
+    fn read(&self, files: &[(PathBuf, Option<SystemTime>)]) -> Result<Templates<'static>, Error> {
+        let mut templates = Templates::new();
+        for (path, _) in files.iter().filter(|(path, _)| self.has_extension(path)) {
+            let source = String::from_utf8(self.vfs.read(path).map_err(ErrorKind::Io)?)
+                .map_err(|err| ErrorKind::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+            let name = self.template_name(path);
+            self.verify(path, &name, &source)?;
+            templates.register(name, source)?;
+        }
+        Ok(templates)
+    }
+
+    /// Verifies the signature of the template `name`, read from `path`.
+    fn verify(&self, path: &Path, name: &str, source: &str) -> Result<(), Error> {
+        if self.verifier.is_none() && !self.require_signed {
+            return Ok(());
+        }
+        let mut signature_path = path.as_os_str().to_owned();
+        signature_path.push(".sig");
+        let signature = match self.vfs.read(Path::new(&signature_path)) {
+            Ok(signature) => signature,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !self.require_signed => {
+                return Ok(())
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::from(ErrorKind::UnsignedTemplate(name.to_string())))
+            }
+            Err(err) => return Err(Error::from(ErrorKind::Io(err))),
+        };
+        match &self.verifier {
+            Some(verifier) if verifier.verify(name, source.as_bytes(), &signature) => Ok(()),
+            _ => Err(Error::from(ErrorKind::InvalidSignature(name.to_string()))),
+        }
+    }
+
+    /// Returns the paths of the templates within the directory, and of their
+    /// signatures when signatures are verified, along with their
+    /// modification times, sorted by path.
+    fn files(&self) -> Result<Vec<(PathBuf, Option<SystemTime>)>, Error> {
+        let signed = self.verifier.is_some() || self.require_signed;
+        let mut files = Vec::new();
+        let mut directories = vec![self.directory.clone()];
+        while let Some(directory) = directories.pop() {
+            for entry in self.vfs.read_dir(&directory).map_err(ErrorKind::Io)? {
+                if entry.is_dir {
+                    directories.push(entry.path);
+                } else if self.has_extension(&entry.path)
+                    || (signed && self.is_signature(&entry.path))
+                {
+                    files.push((entry.path, entry.modified));
+                }
+            }
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(files)
+    }
+
+    fn has_extension(&self, path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(self.extension.as_str()))
+            .is_some_and(|stem| stem.len() > 1 && stem.ends_with('.'))
+    }
+
+    /// Returns true if `path` is the signature of a template.
+    fn is_signature(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|extension| extension == "sig")
+            && self.has_extension(&path.with_extension(""))
+    }
+
+    /// Returns the name a file is registered as: its path relative to the
+    /// directory, separated by `/`, without the extension.
+    fn template_name(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.directory).unwrap_or(path);
+        let mut name = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        name.truncate(name.len() - self.extension.len() - 1);
+        name
+    }
+}
+
+#[test]
+fn file_names() {
+    let loader = FileLoader::new("templates", Configuration::for_html());
+    assert!(loader.has_extension(Path::new("templates/page.bud.html")));
+    assert!(!loader.has_extension(Path::new("templates/.bud.html")));
+    assert!(!loader.has_extension(Path::new("templates/page.html")));
+    assert_eq!(
+        loader.template_name(Path::new("templates/partials/nav.bud.html")),
+        "partials/nav"
+    );
+}
+
+#[test]
+fn signed_templates() {
+    use crate::MemoryFs;
+
+    let files = MemoryFs::new()
+        .with_file("templates/page.bud.html", "<h1>{{= title }}</h1>")
+        .with_file("templates/page.bud.html.sig", "<h1>{{= title }}</h1>")
+        .with_file("templates/nav.bud.html", "<nav></nav>");
+
+    // Each signature is a copy of its template.
+    let loader = || {
+        FileLoader::new("templates", Configuration::for_html())
+            .with_vfs(files.clone())
+            .with_signature_verifier(|_: &str, source: &[u8], signature: &[u8]| source == signature)
+    };
+    assert!(loader().templates().unwrap().get("nav").is_some());
+    assert!(matches!(
+        loader().require_signed().templates(),
+        Err(Error { kind: ErrorKind::UnsignedTemplate(name), .. }) if name == "nav"
+    ));
+
+    files.write("templates/nav.bud.html.sig", "<nav></nav>");
+    assert!(loader().require_signed().templates().is_ok());
+    files.write("templates/page.bud.html", "<h1>{{:= title }}</h1>");
+    assert!(matches!(
+        loader().templates(),
+        Err(Error { kind: ErrorKind::InvalidSignature(name), .. }) if name == "page"
+    ));
+    assert!(FileLoader::new("templates", Configuration::for_html())
+        .with_vfs(files.clone())
+        .templates()
+        .is_ok());
+}
+
+#[test]
+fn reloaded_signatures() {
+    use crate::MemoryFs;
+
+    let files = MemoryFs::new()
+        .with_file("templates/nav.bud.html", "<nav></nav>")
+        .with_file("templates/nav.bud.html.sig", "<nav></nav>");
+    let loader = FileLoader::new("templates", Configuration::for_html())
+        .with_vfs(files.clone())
+        .hot_reload(true)
+        .with_signature_verifier(|_: &str, source: &[u8], signature: &[u8]| source == signature);
+    assert_eq!(loader.render("nav").unwrap(), "<nav></nav>");
+
+    // Only the signature changes, which must still be noticed.
+    files.write("templates/nav.bud.html.sig", "<nav>forged</nav>");
+    assert!(matches!(
+        loader.render("nav"),
+        Err(Error { kind: ErrorKind::InvalidSignature(name), .. }) if name == "nav"
+    ));
+}
@@ -0,0 +1,111 @@
+use crate::{
+    html::{find_closing_tag, tag_end},
+    Error, OutputTransform, TransformedRender,
+};
+
+/// An [`OutputTransform`] that minifies HTML by collapsing each run of
+/// whitespace between and within text into a single space and removing
+/// comments.
+///
+/// Conditional comments, such as `<!--[if IE]>...<![endif]-->`, are kept.
+/// Tags are written unchanged, as is the content of `<pre>`, `<textarea>`,
+/// `<script>`, and `<style>` elements, whose whitespace is significant.
+///
+/// ```rust
+/// use budplate::{Configuration, HtmlMinifier};
+///
+/// let rendered = Configuration::for_html()
+///     .with_output_transform(HtmlMinifier)
+///     .render("<ul>\n  <li>{{= 1 }}</li> <!-- items -->\n</ul>\n<pre>\n  kept\n</pre>")
+///     .unwrap();
+/// assert_eq!(rendered, "<ul> <li>1</li> </ul> <pre>\n  kept\n</pre>");
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HtmlMinifier;
+
+impl OutputTransform for HtmlMinifier {
+    fn transform(&self, output: String, _render: &TransformedRender<'_>) -> Result<String, Error> {
+        Ok(minify(&output))
+    }
+}
+
+/// The elements whose content is written unchanged.
+const PRESERVED: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+fn minify(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    let mut space = false;
+    let mut rest = html;
+    while let Some(ch) = rest.chars().next() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment.find("-->").map_or(rest.len(), |end| end + 7);
+            if comment.starts_with('[') || comment.starts_with("<![") {
+                push_space(&mut minified, &mut space);
+                minified.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+        } else if ch == '<'
+            && rest[1..].starts_with(|ch: char| ch.is_ascii_alphabetic() || "/!?".contains(ch))
+        {
+            let end = tag_end(rest);
+            let tag = &rest[..end];
+            push_space(&mut minified, &mut space);
+            minified.push_str(tag);
+            rest = &rest[end..];
+            if let Some(name) = preserved_element(tag) {
+                let close = find_closing_tag(rest, name);
+                minified.push_str(&rest[..close]);
+                rest = &rest[close..];
+            }
+        } else if ch.is_whitespace() {
+            space = true;
+            rest = &rest[ch.len_utf8()..];
+        } else {
+            push_space(&mut minified, &mut space);
+            minified.push(ch);
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+    minified
+}
+
+/// Writes the space that collapsed whitespace is replaced by, except at the
+/// start of the document.
+fn push_space(minified: &mut String, space: &mut bool) {
+    if std::mem::take(space) && !minified.is_empty() {
+        minified.push(' ');
+    }
+}
+
+/// Returns the name of the element `tag` opens if its content must be
+/// preserved.
+fn preserved_element(tag: &str) -> Option<&'static str> {
+    let name = tag[1..]
+        .split(|ch: char| ch.is_whitespace() || ch == '>' || ch == '/')
+        .next()?;
+    if tag.ends_with("/>") {
+        return None;
+    }
+    PRESERVED
+        .into_iter()
+        .find(|preserved| preserved.eq_ignore_ascii_case(name))
+}
+
+#[test]
+fn minified_html() {
+    assert_eq!(
+        minify("\n  <p class=\"a  b\">\n    Hello,\n    world!\n  </p>\n"),
+        "<p class=\"a  b\"> Hello, world! </p>"
+    );
+    assert_eq!(
+        minify("a <!-- hidden --> b<!--[if IE]><p>IE</p><![endif]--><!--<![endif]-->"),
+        "a b<!--[if IE]><p>IE</p><![endif]--><!--<![endif]-->"
+    );
+    assert_eq!(
+        minify("<TEXTAREA>\n a\n</textarea>  <script>\n// x\nlet a = 1;\n</SCRIPT>"),
+        "<TEXTAREA>\n a\n</textarea> <script>\n// x\nlet a = 1;\n</SCRIPT>"
+    );
+    assert_eq!(minify("1 < 2 <br/>  <pre/> x"), "1 < 2 <br/> <pre/> x");
+    assert_eq!(minify("<pre>unclosed\n "), "<pre>unclosed\n ");
+    assert_eq!(minify("<a title='>'>é  ü</a>"), "<a title='>'>é ü</a>");
+}
@@ -0,0 +1,388 @@
+use std::{
+    any::Any,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{CompiledTemplate, Encoder, Error, OnMissing, Renderer};
+
+/// Settings for a single render that override those of the
+/// [`Configuration`](crate::Configuration) a template was compiled with, such as the locale of the
+/// user a page is rendered for.
+///
+/// Options are cheap to create for each request, unlike a configuration,
+/// whose templates must be compiled again when it changes.
+///
+/// ```rust
+/// use budplate::{Configuration, RenderOptions, Strictness, Template};
+///
+/// let template = Template::from("{{= title }}")
+///     .compile(&Configuration::default(), ["title"])
+///     .unwrap();
+/// let preview = RenderOptions::new().with_strictness(Strictness::Lenient);
+/// assert_eq!(
+///     template
+///         .render_with_options::<&str, &str, _>(&preview, [])
+///         .unwrap(),
+///     ""
+/// );
+/// assert!(template.render().is_err());
+/// ```
+#[derive(Clone, Default)]
+pub struct RenderOptions {
+    pub(crate) locale: Option<String>,
+    pub(crate) encoder: Option<Arc<EncodeFn>>,
+    pub(crate) strictness: Option<Strictness>,
+    pub(crate) budget: Option<usize>,
+    pub(crate) max_output_len: Option<usize>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    pub(crate) tags: Vec<(String, String)>,
+    pub(crate) capabilities: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+pub(crate) type EncodeFn = dyn Fn(&str, &mut String) + Send + Sync;
+
+impl RenderOptions {
+    /// Returns options that don't override any settings.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Formats messages for `locale`, in place of the `locale` value
+    /// provided by a [`ContextProvider`](crate::ContextProvider).
+    #[must_use]
+    pub fn with_locale(mut self, locale: impl Into<String>) -> Self {
+        self.locale = Some(locale.into());
+        self
+    }
+
+    /// Encodes the values of expressions that don't select an encoding using
+    /// `encoder`, in place of the configuration's encoder.
+    #[must_use]
+    pub fn with_encoder<Enc: Encoder + Send + Sync>(mut self, encoder: Enc) -> Self {
+        self.encoder = Some(Arc::new(move |input, output| {
+            encoder.encode_to_string(input, output);
+        }));
+        self
+    }
+
+    /// Overrides how missing values and values that can't be converted to
+    /// text are handled.
+    #[must_use]
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = Some(strictness);
+        self
+    }
+
+    /// Limits the render to executing `operations` Bud instructions. See
+    /// [`Configuration::with_budget`](crate::Configuration::with_budget).
+    #[must_use]
+    pub fn with_budget(mut self, operations: usize) -> Self {
+        self.budget = Some(operations);
+        self
+    }
+
+    /// Limits the rendered output to `bytes`. See
+    /// [`Configuration::with_max_output_len`](crate::Configuration::with_max_output_len).
+    #[must_use]
+    pub fn with_max_output_len(mut self, bytes: usize) -> Self {
+        self.max_output_len = Some(bytes);
+        self
+    }
+
+    /// Fails the render with [`ErrorKind::Cancelled`](crate::ErrorKind::Cancelled)
+    /// once `token` is cancelled, such as when the client a page is being
+    /// rendered for disconnects. The token is checked before output is
+    /// written, before each built-in function is called, and every few
+    /// thousand instructions, so that templates looping without output can
+    /// also be cancelled.
+    #[must_use]
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Adds the tag `name` with `value` to the render, which is passed to the
+    /// configuration's
+    /// [`UsageObserver`](crate::UsageObserver), such as the route or tenant a
+    /// page is rendered for.
+    #[must_use]
+    pub fn with_tag(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((name.into(), value.into()));
+        self
+    }
+
+    /// Provides `capability` to the helpers registered using
+    /// [`Configuration::with_contextual_helper`](crate::Configuration::with_contextual_helper)
+    /// for this render, replacing any capability of the same type. A
+    /// capability can be anything a helper needs to act on behalf of the
+    /// tenant or user a page is rendered for, such as a database handle or
+    /// credentials.
+    #[must_use]
+    pub fn with_capability<T: Any + Send + Sync>(mut self, capability: T) -> Self {
+        self.capabilities.retain(|existing| !existing.is::<T>());
+        self.capabilities.push(Arc::new(capability));
+        self
+    }
+
+    /// Returns the capability of type `T`, if one was provided.
+    pub(crate) fn capability<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.capabilities
+            .iter()
+            .find_map(|capability| capability.clone().downcast().ok())
+    }
+
+    /// Returns how missing values are rendered when the configuration
+    /// renders them according to `configured`.
+    pub(crate) fn on_missing(&self, configured: OnMissing) -> OnMissing {
+        match (self.strictness, configured) {
+            (Some(Strictness::Strict), _) => OnMissing::Fail,
+            (Some(Strictness::Lenient), OnMissing::Fail) => OnMissing::Empty,
+            (_, configured) => configured,
+        }
+    }
+
+    /// Returns whether values that can't be converted to text are pretty
+    /// printed when the configuration's setting is `configured`.
+    pub(crate) fn lenient_output(&self, configured: bool) -> bool {
+        match self.strictness {
+            Some(Strictness::Strict) => false,
+            Some(Strictness::Lenient) => true,
+            None => configured,
+        }
+    }
+}
+
+impl Debug for RenderOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderOptions")
+            .field("locale", &self.locale)
+            .field("encoder", &self.encoder.is_some())
+            .field("strictness", &self.strictness)
+            .field("budget", &self.budget)
+            .field("max_output_len", &self.max_output_len)
+            .field("cancellation", &self.cancellation)
+            .field("tags", &self.tags)
+            .field("capabilities", &self.capabilities.len())
+            .finish()
+    }
+}
+
+/// Cancels the renders it is passed to using
+/// [`RenderOptions::with_cancellation`]. Clones of a token share its state,
+/// so a render can be cancelled from another thread.
+///
+/// ```rust
+/// use budplate::{CancellationToken, Configuration, ErrorKind, RenderOptions, Template};
+///
+/// let template = Template::from("<p>{{= body }}</p>")
+///     .compile(&Configuration::default(), ["body"])
+///     .unwrap();
+/// let token = CancellationToken::new();
+/// let options = RenderOptions::new().with_cancellation(token.clone());
+/// token.cancel();
+/// let result = template.render_with_options(&options, [("body", "Hello")]);
+/// assert!(matches!(result.unwrap_err().kind, ErrorKind::Cancelled));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every render using this token, including those that start
+    /// later.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How strictly a render treats values that are missing or can't be
+/// rendered. See [`RenderOptions::with_strictness`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Strictness {
+    /// Missing arguments and fields fail rendering, as do values that can't
+    /// be converted to text, regardless of the configuration. This suits
+    /// previewing a template before it is published.
+    Strict,
+    /// Missing arguments and fields render as nothing, unless the
+    /// configuration keeps their tags, and values that can't be converted to
+    /// text are pretty printed.
+    Lenient,
+}
+
+impl CompiledTemplate {
+    /// Renders this template with `args`, overriding the settings of its
+    /// configuration with `options`.
+    pub fn render_with_options<Name, Arg, Args>(
+        &self,
+        options: &RenderOptions,
+        args: Args,
+    ) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let mut output = String::new();
+        self.machine.state.with_options(options.clone(), || {
+            let values = self.arguments(args)?;
+            self.render_values_to(values, &mut output)
+        })?;
+        Ok(output)
+    }
+}
+
+impl<Enc: Encoder> Renderer<'_, Enc> {
+    /// Renders the template `name` with `args`, overriding the settings of
+    /// the renderer's configuration with `options`.
+    pub fn render_with_options<Name, Arg, Args>(
+        &self,
+        name: &str,
+        options: &RenderOptions,
+        args: Args,
+    ) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<(Symbol, Value)>) = args
+            .into_iter()
+            .map(|(name, arg)| {
+                let name = name.into();
+                (name.clone(), (name, arg.into()))
+            })
+            .unzip();
+        self.compile(name, symbols)?
+            .render_with_options(options, values)
+    }
+}
+
+#[test]
+fn render_options() {
+    use crate::{Configuration, ErrorKind, NoEncoding, Template};
+
+    let compiled = Template::from("<p>{{= greeting }}, {{= user.name }}</p>")
+        .compile(&Configuration::for_html(), ["greeting", "user"])
+        .unwrap();
+    let user = || {
+        Value::dynamic(
+            [(Value::from("id"), Value::Integer(1))]
+                .into_iter()
+                .collect::<budlang::vm::HashMap>(),
+        )
+    };
+    assert!(compiled.render_with([("user", user())]).is_err());
+    let lenient = RenderOptions::new().with_strictness(Strictness::Lenient);
+    assert_eq!(
+        compiled
+            .render_with_options(&lenient, [("user", user())])
+            .unwrap(),
+        "<p>, </p>"
+    );
+
+    let optional = Template::from("[{{= nickname }}]")
+        .compile(
+            &Configuration::default().on_missing(OnMissing::Empty),
+            ["nickname"],
+        )
+        .unwrap();
+    assert_eq!(optional.render().unwrap(), "[]");
+    let strict = RenderOptions::new().with_strictness(Strictness::Strict);
+    assert!(matches!(
+        optional.render_with_options::<&str, &str, _>(&strict, []),
+        Err(Error {
+            kind: ErrorKind::MissingArgument(_),
+            ..
+        })
+    ));
+
+    let compiled = Template::from("<p>{{= greeting }}</p>")
+        .compile(&Configuration::for_html(), ["greeting"])
+        .unwrap();
+    let raw = RenderOptions::new().with_encoder(NoEncoding);
+    assert_eq!(
+        compiled
+            .render_with_options(&raw, [("greeting", "<b>Hi</b>")])
+            .unwrap(),
+        "<p><b>Hi</b></p>"
+    );
+    let limited = RenderOptions::new().with_max_output_len(4);
+    assert!(matches!(
+        compiled.render_with_options(&limited, [("greeting", "Hello")]),
+        Err(Error {
+            kind: ErrorKind::OutputTooLarge,
+            ..
+        })
+    ));
+    assert_eq!(
+        compiled.render_with([("greeting", "Hello")]).unwrap(),
+        "<p>Hello</p>"
+    );
+}
+
+#[test]
+fn cancelled_renders() {
+    use crate::{Configuration, ErrorKind, Template};
+
+    let token = CancellationToken::new();
+    let options = RenderOptions::new().with_cancellation(token.clone());
+    assert!(!token.is_cancelled());
+    token.clone().cancel();
+    assert!(token.is_cancelled());
+
+    let compiled = Template::from("<p>{{= greeting }}</p>")
+        .compile(&Configuration::default(), ["greeting"])
+        .unwrap();
+    assert!(matches!(
+        compiled.render_with_options(&options, [("greeting", "Hello")]),
+        Err(Error {
+            kind: ErrorKind::Cancelled,
+            ..
+        })
+    ));
+    assert_eq!(
+        compiled.render_with([("greeting", "Hello")]).unwrap(),
+        "<p>Hello</p>"
+    );
+}
+
+#[test]
+fn cancelled_loops() {
+    use std::{thread, time::Duration};
+
+    use crate::{Configuration, ErrorKind, Template};
+
+    let token = CancellationToken::new();
+    let options = RenderOptions::new().with_cancellation(token.clone());
+    let compiled = Template::from("{{ loop for i := 1 to 1000000000000 }}{{ end }}")
+        .compile(&Configuration::default(), Vec::<&str>::new())
+        .unwrap();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        token.cancel();
+    });
+    assert!(matches!(
+        compiled.render_with_options::<&str, Value, _>(&options, []),
+        Err(Error {
+            kind: ErrorKind::Cancelled,
+            ..
+        })
+    ));
+    canceller.join().unwrap();
+}
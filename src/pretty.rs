@@ -0,0 +1,273 @@
+use std::{fmt::Write, sync::Arc};
+
+use budlang::vm::{HashMap, List, StringLiteralDisplay, Value};
+
+use crate::Secret;
+
+/// Formats [`Value`]s for diagnostic output.
+///
+/// The output format is stable: scalars print as Bud literals, lists print as
+/// `[a, b]`, and maps print as `{key: value}` with entries sorted by key.
+/// Nested collections deeper than [`max_depth`](Self::max_depth) are elided,
+/// and any [`Secret`] or value matched by a [`Redact`] hook is replaced by
+/// `<redacted>`.
+#[derive(Clone)]
+pub struct PrettyPrinter {
+    max_depth: usize,
+    max_items: usize,
+    max_string_length: usize,
+    redactors: Vec<Arc<dyn Redact>>,
+}
+
+impl Default for PrettyPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for PrettyPrinter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrettyPrinter")
+            .field("max_depth", &self.max_depth)
+            .field("max_items", &self.max_items)
+            .field("max_string_length", &self.max_string_length)
+            .field("redactors", &self.redactors.len())
+            .finish()
+    }
+}
+
+impl PrettyPrinter {
+    pub const REDACTED: &'static str = "<redacted>";
+
+    pub const fn new() -> Self {
+        Self {
+            max_depth: 4,
+            max_items: 32,
+            max_string_length: 256,
+            redactors: Vec::new(),
+        }
+    }
+
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    pub fn max_items(mut self, items: usize) -> Self {
+        self.max_items = items;
+        self
+    }
+
+    pub fn max_string_length(mut self, length: usize) -> Self {
+        self.max_string_length = length;
+        self
+    }
+
+    /// Redacts the values of any map entries whose key matches one of
+    /// `keys`, ignoring ASCII case.
+    pub fn redact_keys<Keys, Key>(self, keys: Keys) -> Self
+    where
+        Keys: IntoIterator<Item = Key>,
+        Key: Into<String>,
+    {
+        let keys = keys.into_iter().map(Into::into).collect::<Vec<String>>();
+        self.with_redactor(move |key: Option<&str>, _: &Value| {
+            key.is_some_and(|key| {
+                keys.iter()
+                    .any(|redacted| redacted.eq_ignore_ascii_case(key))
+            })
+        })
+    }
+
+    pub fn with_redactor<R: Redact>(mut self, redactor: R) -> Self {
+        self.redactors.push(Arc::new(redactor));
+        self
+    }
+
+    pub fn print(&self, value: &Value) -> String {
+        let mut output = String::new();
+        self.print_to(value, &mut output)
+            .expect("String does not fail to write");
+        output
+    }
+
+    pub fn print_to<W: Write>(&self, value: &Value, output: &mut W) -> std::fmt::Result {
+        self.print_value(None, value, 0, output)
+    }
+
+    /// Returns a copy of `value` with each redacted value, including those
+    /// nested within collections, replaced by [`Self::REDACTED`].
+    pub(crate) fn redact(&self, key: Option<&str>, value: &Value) -> Value {
+        if self.is_redacted(key, value) {
+            return Value::from(Self::REDACTED);
+        }
+
+        if let Some(list) = value.as_dynamic::<List>() {
+            Value::dynamic(
+                list.to_vec()
+                    .iter()
+                    .map(|value| self.redact(None, value))
+                    .collect::<List>(),
+            )
+        } else if let Some(map) = value.as_dynamic::<HashMap>() {
+            Value::dynamic(
+                map.to_vec()
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let redacted =
+                            self.redact(key.as_dynamic::<String>().map(String::as_str), &value);
+                        (key, redacted)
+                    })
+                    .collect::<HashMap>(),
+            )
+        } else {
+            value.clone()
+        }
+    }
+
+    fn is_redacted(&self, key: Option<&str>, value: &Value) -> bool {
+        value.as_dynamic::<Secret>().is_some()
+            || self
+                .redactors
+                .iter()
+                .any(|redactor| redactor.redact(key, value))
+    }
+
+    fn print_value<W: Write>(
+        &self,
+        key: Option<&str>,
+        value: &Value,
+        depth: usize,
+        output: &mut W,
+    ) -> std::fmt::Result {
+        if self.is_redacted(key, value) {
+            return output.write_str(Self::REDACTED);
+        }
+
+        match value {
+            Value::Integer(value) => write!(output, "{value}"),
+            Value::Real(value) => write!(output, "{value:?}"),
+            Value::Boolean(value) => write!(output, "{value}"),
+            Value::Void => output.write_str("void"),
+            Value::Dynamic(_) => {
+                if let Some(string) = value.as_dynamic::<String>() {
+                    self.print_string(string, output)
+                } else if let Some(list) = value.as_dynamic::<List>() {
+                    self.print_list(&list.to_vec(), depth, output)
+                } else if let Some(map) = value.as_dynamic::<HashMap>() {
+                    self.print_map(map.to_vec(), depth, output)
+                } else {
+                    write!(output, "{value:?}")
+                }
+            }
+        }
+    }
+
+    fn print_string<W: Write>(&self, string: &str, output: &mut W) -> std::fmt::Result {
+        match string.char_indices().nth(self.max_string_length) {
+            Some((end, _)) => write!(output, "{}…", StringLiteralDisplay::new(&string[..end])),
+            None => write!(output, "{}", StringLiteralDisplay::new(string)),
+        }
+    }
+
+    fn print_list<W: Write>(
+        &self,
+        values: &[Value],
+        depth: usize,
+        output: &mut W,
+    ) -> std::fmt::Result {
+        if values.is_empty() {
+            return output.write_str("[]");
+        } else if depth >= self.max_depth {
+            return output.write_str("[…]");
+        }
+
+        output.write_char('[')?;
+        for (index, value) in values.iter().take(self.max_items).enumerate() {
+            if index > 0 {
+                output.write_str(", ")?;
+            }
+            self.print_value(None, value, depth + 1, output)?;
+        }
+        self.print_remaining(values.len(), output)?;
+        output.write_char(']')
+    }
+
+    fn print_map<W: Write>(
+        &self,
+        entries: Vec<(Value, Value)>,
+        depth: usize,
+        output: &mut W,
+    ) -> std::fmt::Result {
+        if entries.is_empty() {
+            return output.write_str("{}");
+        } else if depth >= self.max_depth {
+            return output.write_str("{…}");
+        }
+
+        let mut entries = entries
+            .into_iter()
+            .map(|(key, value)| {
+                let printed_key = match key.as_dynamic::<String>() {
+                    Some(key) => key.clone(),
+                    None => self.print(&key),
+                };
+                (printed_key, value)
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        output.write_char('{')?;
+        for (index, (key, value)) in entries.iter().take(self.max_items).enumerate() {
+            if index > 0 {
+                output.write_str(", ")?;
+            }
+            write!(output, "{key}: ")?;
+            self.print_value(Some(key), value, depth + 1, output)?;
+        }
+        self.print_remaining(entries.len(), output)?;
+        output.write_char('}')
+    }
+
+    fn print_remaining<W: Write>(&self, total: usize, output: &mut W) -> std::fmt::Result {
+        if total > self.max_items {
+            write!(output, ", … {} more", total - self.max_items)?;
+        }
+        Ok(())
+    }
+}
+
+/// A hook that decides whether a value should be hidden from diagnostic
+/// output. `key` is the map key the value is stored under, if any.
+pub trait Redact: Send + Sync + 'static {
+    fn redact(&self, key: Option<&str>, value: &Value) -> bool;
+}
+
+impl<F> Redact for F
+where
+    F: Fn(Option<&str>, &Value) -> bool + Send + Sync + 'static,
+{
+    fn redact(&self, key: Option<&str>, value: &Value) -> bool {
+        self(key, value)
+    }
+}
+
+#[test]
+fn pretty_scalars() {
+    let printer = PrettyPrinter::new();
+    assert_eq!(printer.print(&Value::Integer(42)), "42");
+    assert_eq!(printer.print(&Value::Real(1.)), "1.0");
+    assert_eq!(printer.print(&Value::Boolean(true)), "true");
+    assert_eq!(printer.print(&Value::Void), "void");
+}
+
+#[test]
+fn pretty_limits() {
+    let list = Value::dynamic((1..=5).map(Value::Integer).collect::<List>());
+    assert_eq!(
+        PrettyPrinter::new().max_items(3).print(&list),
+        "[1, 2, 3, … 2 more]"
+    );
+    let nested = Value::dynamic([list].into_iter().collect::<List>());
+    assert_eq!(PrettyPrinter::new().max_depth(1).print(&nested), "[[…]]");
+}
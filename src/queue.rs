@@ -0,0 +1,211 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+/// Limits how many renders run at once, such as on the threads of a blocking
+/// thread pool, so that a burst of requests or batch jobs can't exhaust them.
+///
+/// Renders wait for a free slot in the order they were queued, except that
+/// [`Priority::Interactive`] renders are started before any
+/// [`Priority::Batch`] render that is waiting. Clones of a queue share its
+/// slots.
+///
+/// ```rust
+/// use budplate::{Priority, RenderQueue};
+///
+/// let queue = RenderQueue::new(4);
+/// let page = queue.run(Priority::Interactive, || "<h1>Home</h1>".to_string());
+/// assert_eq!(page, "<h1>Home</h1>");
+/// assert_eq!(queue.metrics().completed, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RenderQueue(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<QueueState>,
+    released: Condvar,
+    max_concurrent: usize,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    running: usize,
+    completed: u64,
+    next_ticket: u64,
+    /// The tickets of the renders waiting for a slot, by priority.
+    interactive: VecDeque<u64>,
+    batch: VecDeque<u64>,
+}
+
+impl QueueState {
+    fn waiting(&mut self, priority: Priority) -> &mut VecDeque<u64> {
+        match priority {
+            Priority::Interactive => &mut self.interactive,
+            Priority::Batch => &mut self.batch,
+        }
+    }
+}
+
+/// The priority of a render waiting in a [`RenderQueue`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Priority {
+    /// A render that someone is waiting for, such as a page being requested.
+    Interactive,
+    /// A render whose result isn't needed right away, such as a report or a
+    /// mail merge.
+    Batch,
+}
+
+/// A snapshot of the activity of a [`RenderQueue`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct QueueMetrics {
+    /// The number of renders that may run at once.
+    pub max_concurrent: usize,
+    /// The number of renders running.
+    pub running: usize,
+    /// The number of interactive renders waiting for a slot.
+    pub waiting_interactive: usize,
+    /// The number of batch renders waiting for a slot.
+    pub waiting_batch: usize,
+    /// The number of renders that have finished.
+    pub completed: u64,
+}
+
+impl RenderQueue {
+    /// Returns a queue that runs up to `max_concurrent` renders at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_concurrent` is 0.
+    #[must_use]
+    pub fn new(max_concurrent: usize) -> Self {
+        assert!(max_concurrent > 0, "max_concurrent must not be 0");
+        Self(Arc::new(Shared {
+            state: Mutex::default(),
+            released: Condvar::new(),
+            max_concurrent,
+        }))
+    }
+
+    /// Invokes `render` once a slot is free, blocking the current thread
+    /// until then, and returns its result.
+    pub fn run<R>(&self, priority: Priority, render: impl FnOnce() -> R) -> R {
+        let _slot = self.acquire(priority);
+        render()
+    }
+
+    /// Returns the current activity of this queue.
+    #[must_use]
+    pub fn metrics(&self) -> QueueMetrics {
+        let state = self.lock();
+        QueueMetrics {
+            max_concurrent: self.0.max_concurrent,
+            running: state.running,
+            waiting_interactive: state.interactive.len(),
+            waiting_batch: state.batch.len(),
+            completed: state.completed,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.0.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn acquire(&self, priority: Priority) -> Slot<'_> {
+        let mut state = self.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting(priority).push_back(ticket);
+        loop {
+            let next = match priority {
+                Priority::Interactive => state.interactive.front(),
+                Priority::Batch if state.interactive.is_empty() => state.batch.front(),
+                Priority::Batch => None,
+            };
+            if state.running < self.0.max_concurrent && next == Some(&ticket) {
+                state.waiting(priority).pop_front();
+                state.running += 1;
+                // Another render may be able to start in a slot that is
+                // still free.
+                self.0.released.notify_all();
+                return Slot(self);
+            }
+            state = self
+                .0
+                .released
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+/// A running render's slot in a [`RenderQueue`], which is released when it
+/// is dropped, even if the render panics.
+struct Slot<'a>(&'a RenderQueue);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.running -= 1;
+        state.completed += 1;
+        self.0 .0.released.notify_all();
+    }
+}
+
+#[test]
+fn queued_renders() {
+    use std::{sync::mpsc, thread};
+
+    let queue = RenderQueue::new(1);
+    let (started, starts) = mpsc::channel();
+    let (finish, finishing) = mpsc::channel::<()>();
+    let blocker = {
+        let queue = queue.clone();
+        let started = started.clone();
+        thread::spawn(move || {
+            queue.run(Priority::Batch, || {
+                started.send("blocker").unwrap();
+                finishing.recv().unwrap();
+            });
+        })
+    };
+    assert_eq!(starts.recv().unwrap(), "blocker");
+
+    let waiter = |priority, name| {
+        let queue = queue.clone();
+        let started = started.clone();
+        thread::spawn(move || queue.run(priority, || started.send(name).unwrap()))
+    };
+    let batch = waiter(Priority::Batch, "batch");
+    while queue.metrics().waiting_batch == 0 {
+        thread::yield_now();
+    }
+    let interactive = waiter(Priority::Interactive, "interactive");
+    while queue.metrics().waiting_interactive == 0 {
+        thread::yield_now();
+    }
+    assert_eq!(
+        queue.metrics(),
+        QueueMetrics {
+            max_concurrent: 1,
+            running: 1,
+            waiting_interactive: 1,
+            waiting_batch: 1,
+            completed: 0,
+        }
+    );
+
+    finish.send(()).unwrap();
+    for handle in [blocker, batch, interactive] {
+        handle.join().unwrap();
+    }
+    assert_eq!(
+        starts.try_iter().collect::<Vec<_>>(),
+        ["interactive", "batch"]
+    );
+    assert_eq!(queue.metrics().completed, 3);
+    assert_eq!(queue.metrics().running, 0);
+}
@@ -0,0 +1,221 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+type ExhaustedFn = dyn Fn(&QuotaUsage) + Send + Sync;
+
+/// Accounts for the renders of every template compiled with
+/// [`Configuration::with_quota`](crate::Configuration::with_quota), such as
+/// all of a tenant's templates, so that a platform rendering templates on
+/// behalf of its users can bill them and throttle them fairly.
+///
+/// Once any limit has been reached, further renders fail with
+/// [`ErrorKind::QuotaExceeded`](crate::ErrorKind::QuotaExceeded) until the
+/// quota is [reset](Self::reset), such as at the start of each billing
+/// period. A render that is already running when the limit is reached is
+/// allowed to finish. Clones of a quota share its usage.
+///
+/// The instructions each render may execute can also be limited using
+/// [`Configuration::with_budget`](crate::Configuration::with_budget).
+///
+/// ```rust
+/// use budplate::{Configuration, Error, ErrorKind, Quota, Template};
+///
+/// let quota = Quota::new().with_max_output_bytes(10);
+/// let configuration = Configuration::default().with_quota(quota.clone());
+/// let template = Template::from("Hello, {{= name }}!")
+///     .compile(&configuration, ["name"])
+///     .unwrap();
+/// assert_eq!(template.render_with([("name", "Ada")]).unwrap(), "Hello, Ada!");
+/// assert_eq!(quota.usage().output_bytes, 11);
+/// assert!(matches!(
+///     template.render_with([("name", "Grace")]),
+///     Err(Error {
+///         kind: ErrorKind::QuotaExceeded,
+///         ..
+///     })
+/// ));
+/// ```
+#[derive(Clone, Default)]
+pub struct Quota {
+    limits: QuotaUsage,
+    usage: Arc<Mutex<QuotaUsage>>,
+    on_exhausted: Option<Arc<ExhaustedFn>>,
+}
+
+/// The renders accounted for by a [`Quota`].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct QuotaUsage {
+    /// The number of renders, including those that failed.
+    pub renders: u64,
+    /// The total duration of the renders.
+    pub render_time: Duration,
+    /// The total number of Bud instructions executed by the renders.
+    /// Templates rendered by a [`TemplateBackend`](crate::TemplateBackend)
+    /// don't execute instructions.
+    pub instructions: u64,
+    /// The total number of bytes rendered.
+    pub output_bytes: u64,
+}
+
+impl QuotaUsage {
+    /// Returns true if `self` has reached any of the nonzero limits in
+    /// `limits`.
+    fn reached(&self, limits: &Self) -> bool {
+        (limits.renders > 0 && self.renders >= limits.renders)
+            || (!limits.render_time.is_zero() && self.render_time >= limits.render_time)
+            || (limits.instructions > 0 && self.instructions >= limits.instructions)
+            || (limits.output_bytes > 0 && self.output_bytes >= limits.output_bytes)
+    }
+}
+
+impl Quota {
+    /// Returns a quota without any limits, which only accounts for renders.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the number of renders.
+    #[must_use]
+    pub fn with_max_renders(mut self, renders: u64) -> Self {
+        self.limits.renders = renders;
+        self
+    }
+
+    /// Limits the total duration of the renders.
+    #[must_use]
+    pub fn with_max_render_time(mut self, render_time: Duration) -> Self {
+        self.limits.render_time = render_time;
+        self
+    }
+
+    /// Limits the total number of Bud instructions executed by the renders.
+    #[must_use]
+    pub fn with_max_instructions(mut self, instructions: u64) -> Self {
+        self.limits.instructions = instructions;
+        self
+    }
+
+    /// Limits the total number of bytes rendered.
+    #[must_use]
+    pub fn with_max_output_bytes(mut self, bytes: u64) -> Self {
+        self.limits.output_bytes = bytes;
+        self
+    }
+
+    /// Invokes `on_exhausted` with the usage of this quota after the render
+    /// that reaches one of its limits.
+    #[must_use]
+    pub fn on_exhausted(
+        mut self,
+        on_exhausted: impl Fn(&QuotaUsage) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_exhausted = Some(Arc::new(on_exhausted));
+        self
+    }
+
+    /// Returns the renders accounted for since this quota was created or
+    /// last reset.
+    #[must_use]
+    pub fn usage(&self) -> QuotaUsage {
+        *self.lock()
+    }
+
+    /// Returns true if this quota has reached one of its limits.
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.lock().reached(&self.limits)
+    }
+
+    /// Forgets the renders accounted for, returning their usage.
+    pub fn reset(&self) -> QuotaUsage {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QuotaUsage> {
+        self.usage.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Accounts for a render, invoking the exhaustion callback if it reached
+    /// one of this quota's limits.
+    pub(crate) fn record(&self, render_time: Duration, instructions: usize, output_bytes: usize) {
+        let (usage, exhausted) = {
+            let mut usage = self.lock();
+            let was_exhausted = usage.reached(&self.limits);
+            usage.renders += 1;
+            usage.render_time += render_time;
+            usage.instructions = usage
+                .instructions
+                .saturating_add(u64::try_from(instructions).unwrap_or(u64::MAX));
+            usage.output_bytes = usage
+                .output_bytes
+                .saturating_add(u64::try_from(output_bytes).unwrap_or(u64::MAX));
+            (*usage, !was_exhausted && usage.reached(&self.limits))
+        };
+        if let (true, Some(on_exhausted)) = (exhausted, &self.on_exhausted) {
+            on_exhausted(&usage);
+        }
+    }
+}
+
+impl std::fmt::Debug for Quota {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Quota")
+            .field("limits", &self.limits)
+            .field("usage", &self.usage())
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn exhausted_quotas() {
+    let exhausted = Arc::new(Mutex::new(Vec::new()));
+    let quota = Quota::new()
+        .with_max_renders(3)
+        .with_max_render_time(Duration::from_secs(1))
+        .on_exhausted({
+            let exhausted = exhausted.clone();
+            move |usage: &QuotaUsage| exhausted.lock().unwrap().push(*usage)
+        });
+    let tenant = quota.clone();
+    tenant.record(Duration::from_millis(600), 100, 10);
+    assert!(!quota.is_exhausted());
+    tenant.record(Duration::from_millis(600), 200, 20);
+    assert!(quota.is_exhausted());
+    tenant.record(Duration::from_millis(1), 50, 5);
+
+    let usage = QuotaUsage {
+        renders: 2,
+        render_time: Duration::from_millis(1200),
+        instructions: 300,
+        output_bytes: 30,
+    };
+    assert_eq!(*exhausted.lock().unwrap(), [usage]);
+    assert_eq!(quota.reset().renders, 3);
+    assert!(!quota.is_exhausted());
+    assert_eq!(quota.usage(), QuotaUsage::default());
+}
+
+#[test]
+fn instruction_quotas() {
+    use crate::{Configuration, Template};
+
+    let quota = Quota::new().with_max_instructions(50_000);
+    let compiled = Template::from("{{ loop for i := 1 to count }}{{ end }}")
+        .compile(
+            &Configuration::default().with_quota(quota.clone()),
+            ["count"],
+        )
+        .unwrap();
+    compiled.render_with([("count", 10_i64)]).unwrap();
+    let short = quota.usage().instructions;
+    assert!(short > 10);
+    compiled.render_with([("count", 1_000_i64)]).unwrap();
+    assert!(quota.usage().instructions - short > 10 * short);
+    assert!(!quota.is_exhausted());
+    compiled.render_with([("count", 100_000_i64)]).unwrap();
+    assert!(quota.is_exhausted());
+}
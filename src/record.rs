@@ -0,0 +1,128 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use budlang::vm::{HashMap, Symbol, Value};
+
+use crate::{formats, Error, PrettyPrinter};
+
+/// Receives the arguments of each render of templates compiled with
+/// [`Configuration::with_recorder`](crate::Configuration::with_recorder).
+///
+/// Recorded arguments are a realistic corpus for differential testing and
+/// benchmarking. Values are redacted using the configuration's
+/// [`PrettyPrinter`] before they are recorded, so [`Secret`](crate::Secret)s
+/// and redacted keys never reach the recorder.
+pub trait RenderRecorder: Send + Sync + 'static {
+    fn record(&self, render: &RecordedRender);
+}
+
+impl<F> RenderRecorder for F
+where
+    F: Fn(&RecordedRender) + Send + Sync + 'static,
+{
+    fn record(&self, render: &RecordedRender) {
+        self(render);
+    }
+}
+
+/// The arguments of a single render.
+#[derive(Debug, Clone)]
+pub struct RecordedRender {
+    /// The name of the registered template, or `None` for a template that
+    /// was compiled directly.
+    pub template: Option<String>,
+    pub arguments: Vec<(Symbol, Value)>,
+}
+
+impl RecordedRender {
+    pub(crate) fn new(
+        template: Option<String>,
+        parameters: &[Symbol],
+        values: &[Value],
+        printer: &PrettyPrinter,
+    ) -> Self {
+        Self {
+            template,
+            arguments: parameters
+                .iter()
+                .zip(values)
+                .map(|(name, value)| (name.clone(), printer.redact(Some(name), value)))
+                .collect(),
+        }
+    }
+
+    /// Serializes this render as a single line of JSON with the fields
+    /// `template` and `arguments`.
+    pub fn to_json(&self) -> Result<String, Error> {
+        let arguments = self
+            .arguments
+            .iter()
+            .map(|(name, value)| (Value::from(name.to_string()), value.clone()))
+            .collect::<HashMap>();
+        let render = [
+            (
+                Value::from("template"),
+                self.template.clone().map_or(Value::Void, Value::from),
+            ),
+            (Value::from("arguments"), Value::dynamic(arguments)),
+        ]
+        .into_iter()
+        .collect::<HashMap>();
+        formats::json(&Value::dynamic(render), false)
+    }
+}
+
+/// Records each render as a line of JSON written to `W`.
+///
+/// Recording never causes a render to fail: renders whose arguments can't be
+/// serialized and errors writing to `W` are ignored.
+#[derive(Debug, Clone)]
+pub struct JsonLinesRecorder<W>(Arc<Mutex<W>>);
+
+impl<W> JsonLinesRecorder<W> {
+    pub fn new(writer: W) -> Self {
+        Self(Arc::new(Mutex::new(writer)))
+    }
+
+    /// Returns the writer that renders are recorded to.
+    pub fn writer(&self) -> &Mutex<W> {
+        &self.0
+    }
+}
+
+impl<W: Write + Send + 'static> RenderRecorder for JsonLinesRecorder<W> {
+    fn record(&self, render: &RecordedRender) {
+        let Ok(mut line) = render.to_json() else {
+            return;
+        };
+        line.push('\n');
+        if let Ok(mut writer) = self.0.lock() {
+            let _ = writer.write_all(line.as_bytes());
+        }
+    }
+}
+
+#[test]
+fn recorded_renders_are_redacted() {
+    use crate::Secret;
+
+    let printer = PrettyPrinter::new().redact_keys(["password"]);
+    let user = [
+        (Value::from("name"), Value::from("ecton")),
+        (Value::from("password"), Value::from("hunter2")),
+    ]
+    .into_iter()
+    .collect::<HashMap>();
+    let render = RecordedRender::new(
+        Some(String::from("profile")),
+        &[Symbol::from("user"), Symbol::from("token")],
+        &[Value::dynamic(user), Value::dynamic(Secret::new("abc"))],
+        &printer,
+    );
+    assert_eq!(
+        render.to_json().unwrap(),
+        r#"{"arguments":{"token":"<redacted>","user":{"name":"ecton","password":"<redacted>"}},"template":"profile"}"#
+    );
+}
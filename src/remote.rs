@@ -0,0 +1,437 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::{Component, Path},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{Encoder, UrlEncoding, Vfs, VfsEntry};
+
+/// A [`Vfs`] that fetches files over HTTP(S), such as templates kept in a
+/// CMS or object storage rather than deployed with the application.
+///
+/// Each path is fetched from the base URL joined with the path, so with the
+/// base URL `https://cms.example.com/templates`, `partials/nav.bud.html` is
+/// fetched from `https://cms.example.com/templates/partials/nav.bud.html`.
+/// HTTP servers don't list directories, so the files that exist are read
+/// from a manifest listing the path of each file on its own line, fetched
+/// from `manifest.txt` unless changed using [`HttpFs::with_manifest()`].
+///
+/// ```rust,no_run
+/// use budplate::{Configuration, FileLoader, HttpFs};
+///
+/// let loader = FileLoader::new("", Configuration::for_html())
+///     .with_vfs(HttpFs::new("https://cms.example.com/templates"))
+///     .hot_reload(true);
+/// let rendered = loader.render_with("index", [("title", "Hello")]).unwrap();
+/// ```
+///
+/// Fetched files are cached, and are revalidated once they are older than
+/// the [maximum age](HttpFs::with_max_age) using the `ETag` the server
+/// returned, so unchanged files aren't downloaded again. A file's
+/// modification time is when its contents were last seen to change, which
+/// is how a [`FileLoader`](crate::FileLoader) with hot reloading enabled
+/// notices changed templates.
+pub struct HttpFs {
+    base_url: String,
+    manifest: String,
+    timeout: Duration,
+    max_size: u64,
+    max_age: Duration,
+    agent: ureq::Agent,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+/// The last response for a URL.
+struct Cached {
+    /// The body of the file, or `None` if it wasn't found.
+    body: Option<Vec<u8>>,
+    etag: Option<String>,
+    /// When the body was last seen to change.
+    modified: SystemTime,
+    /// When the response was last fetched or revalidated.
+    validated: Instant,
+}
+
+/// The response to a request.
+enum Response {
+    /// The file, whose body is `None` if it wasn't found.
+    Fetched {
+        body: Option<Vec<u8>>,
+        etag: Option<String>,
+    },
+    /// The file wasn't modified since the `ETag` it was revalidated with.
+    NotModified,
+}
+
+impl HttpFs {
+    /// Returns a filesystem fetching files from `base_url`.
+    ///
+    /// By default, requests time out after 30 seconds, files may be up to 1
+    /// MiB, and cached files are revalidated after 10 seconds.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let mut base_url = base_url.into();
+        while base_url.ends_with('/') {
+            base_url.pop();
+        }
+        let timeout = Duration::from_secs(30);
+        Self {
+            base_url,
+            manifest: String::from("manifest.txt"),
+            timeout,
+            max_size: 1024 * 1024,
+            max_age: Duration::from_secs(10),
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the path of the manifest listing the files that exist, relative
+    /// to the base URL. Empty lines and lines starting with `#` are ignored.
+    #[must_use]
+    pub fn with_manifest(mut self, path: impl Into<String>) -> Self {
+        self.manifest = path.into();
+        self
+    }
+
+    /// Sets how long a request may take, including reading its body, before
+    /// failing with [`io::ErrorKind::TimedOut`].
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.agent = ureq::AgentBuilder::new().timeout(timeout).build();
+        self
+    }
+
+    /// Sets the largest file that may be fetched, in bytes. Larger files
+    /// fail to read with [`io::ErrorKind::InvalidData`].
+    #[must_use]
+    pub const fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Sets how long a fetched file is used before it is revalidated.
+    #[must_use]
+    pub const fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Returns the URL `path` is fetched from.
+    fn url(&self, path: &Path) -> io::Result<String> {
+        let mut url = self.base_url.clone();
+        for component in path.components() {
+            match component {
+                Component::Normal(component) => {
+                    url.push('/');
+                    let component = component.to_str().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "paths must be UTF-8")
+                    })?;
+                    UrlEncoding.encode_to_string(component, &mut url);
+                }
+                Component::CurDir => {}
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("`{}` isn't relative to the base URL", path.display()),
+                    ))
+                }
+            }
+        }
+        Ok(url)
+    }
+
+    /// Passes the cached response for `path` to `read`, fetching or
+    /// revalidating it first if needed. Missing files return an error of the
+    /// kind [`io::ErrorKind::NotFound`].
+    ///
+    /// The cache isn't locked while requesting, so a slow server only delays
+    /// the reads waiting on it.
+    fn fetch<T>(&self, path: &Path, read: impl FnOnce(&[u8], SystemTime) -> T) -> io::Result<T> {
+        let url = self.url(path)?;
+        let etag = {
+            let cache = self.lock();
+            match cache.get(&url) {
+                Some(cached) if cached.validated.elapsed() < self.max_age => {
+                    return read_cached(&url, cached, read);
+                }
+                cached => cached.and_then(|cached| cached.etag.clone()),
+            }
+        };
+        let validated = Instant::now();
+        let response = self.request(&url, etag.as_deref())?;
+
+        let mut cache = self.lock();
+        match response {
+            Response::Fetched { body, etag } => {
+                // Servers without `ETag`s return every file again, which
+                // doesn't mean it changed.
+                let modified = match cache.get(&url) {
+                    Some(previous) if previous.body == body => previous.modified,
+                    _ => SystemTime::now(),
+                };
+                cache.insert(
+                    url.clone(),
+                    Cached {
+                        body,
+                        etag,
+                        modified,
+                        validated,
+                    },
+                );
+            }
+            Response::NotModified => {
+                if let Some(cached) = cache.get_mut(&url) {
+                    cached.validated = validated;
+                }
+            }
+        }
+        match cache.get(&url) {
+            Some(cached) => read_cached(&url, cached, read),
+            None => Err(not_found(&url)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Cached>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Requests `url`, revalidating it using `etag` if it is set.
+    fn request(&self, url: &str, etag: Option<&str>) -> io::Result<Response> {
+        let mut request = self.agent.get(url);
+        if let Some(etag) = etag {
+            request = request.set("If-None-Match", etag);
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404 | 410, _)) => {
+                return Ok(Response::Fetched {
+                    body: None,
+                    etag: None,
+                })
+            }
+            Err(ureq::Error::Status(status, _)) => {
+                return Err(io::Error::other(format!(
+                    "fetching `{url}` failed with status {status}"
+                )))
+            }
+            Err(ureq::Error::Transport(err)) => {
+                // Timeouts are reported by ureq as I/O errors.
+                let kind = std::error::Error::source(&err)
+                    .and_then(|source| source.downcast_ref::<io::Error>())
+                    .map_or(io::ErrorKind::Other, io::Error::kind);
+                return Err(io::Error::new(
+                    kind,
+                    format!("fetching `{url}` failed: {err}"),
+                ));
+            }
+        };
+
+        if response.status() == 304 && etag.is_some() {
+            return Ok(Response::NotModified);
+        }
+        let new_etag = response.header("ETag").map(String::from);
+        let too_large = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("`{url}` is larger than {} bytes", self.max_size),
+            )
+        };
+        if response
+            .header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .is_some_and(|length| length > self.max_size)
+        {
+            return Err(too_large());
+        }
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(self.max_size + 1)
+            .read_to_end(&mut body)?;
+        if body.len() as u64 > self.max_size {
+            return Err(too_large());
+        }
+
+        Ok(Response::Fetched {
+            body: Some(body),
+            etag: new_etag,
+        })
+    }
+}
+
+/// Passes the body of `cached` to `read`, or returns an error of the kind
+/// [`io::ErrorKind::NotFound`] if it wasn't found.
+fn read_cached<T>(
+    url: &str,
+    cached: &Cached,
+    read: impl FnOnce(&[u8], SystemTime) -> T,
+) -> io::Result<T> {
+    match &cached.body {
+        Some(body) => Ok(read(body, cached.modified)),
+        None => Err(not_found(url)),
+    }
+}
+
+fn not_found(url: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("`{url}` was not found"))
+}
+
+impl Vfs for HttpFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.fetch(path, |body, _| body.to_vec())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsEntry>> {
+        let directory = path
+            .components()
+            .filter(|component| !matches!(component, Component::CurDir))
+            .collect::<std::path::PathBuf>();
+        let manifest = self.fetch(Path::new(&self.manifest), |body, _| {
+            String::from_utf8_lossy(body).into_owned()
+        })?;
+        let mut entries: Vec<VfsEntry> = Vec::new();
+        for line in manifest.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Ok(relative) = Path::new(line).strip_prefix(&directory) else {
+                continue;
+            };
+            let mut components = relative.components();
+            let Some(name) = components.next() else {
+                continue;
+            };
+            let path = directory.join(name);
+            if components.next().is_some() {
+                if !entries.iter().any(|entry| entry.path == path) {
+                    entries.push(VfsEntry {
+                        path,
+                        is_dir: true,
+                        modified: None,
+                    });
+                }
+            } else {
+                let modified = self.fetch(&path, |_, modified| modified)?;
+                entries.push(VfsEntry {
+                    path,
+                    is_dir: false,
+                    modified: Some(modified),
+                });
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl std::fmt::Debug for HttpFs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpFs")
+            .field("base_url", &self.base_url)
+            .field("manifest", &self.manifest)
+            .field("timeout", &self.timeout)
+            .field("max_size", &self.max_size)
+            .field("max_age", &self.max_age)
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn fetched_templates() {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::Arc,
+    };
+
+    use crate::{Configuration, FileLoader};
+
+    // Serves each file with the hash of its contents as its `ETag`, counting
+    // the responses that weren't modified.
+    let files = Arc::new(Mutex::new(HashMap::from([
+        (
+            String::from("/t/manifest.txt"),
+            String::from("index.bud.html\npartials/nav.bud.html\n"),
+        ),
+        (
+            String::from("/t/index.bud.html"),
+            String::from(r#"<main>{{ include "partials/nav" }}</main>"#),
+        ),
+        (
+            String::from("/t/partials/nav.bud.html"),
+            String::from("<nav></nav>"),
+        ),
+    ])));
+    let not_modified = Arc::new(Mutex::new(0));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn({
+        let files = files.clone();
+        let not_modified = not_modified.clone();
+        move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines();
+                let request = lines.next().unwrap().unwrap();
+                let path = request.split(' ').nth(1).unwrap().to_string();
+                let mut etag = None;
+                for line in lines {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("If-None-Match: ") {
+                        etag = Some(value.to_string());
+                    }
+                }
+                let files = files.lock().unwrap();
+                let body = files.get(&path);
+                let tag = body.map(|body| format!("\"{:x}\"", crate::hash::fnv1a(body.bytes())));
+                let response = match body {
+                    Some(_) if etag.is_some() && etag == tag => {
+                        *not_modified.lock().unwrap() += 1;
+                        String::from("HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n")
+                    }
+                    Some(body) => format!(
+                        "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        tag.as_deref().unwrap_or_default(),
+                        body.len()
+                    ),
+                    None => String::from(
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    ),
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        }
+    });
+
+    let http = || HttpFs::new(format!("http://{address}/t/")).with_max_age(Duration::ZERO);
+    let loader = FileLoader::new("", Configuration::for_html())
+        .hot_reload(true)
+        .with_vfs(http());
+    assert_eq!(loader.render("index").unwrap(), "<main><nav></nav></main>");
+    assert_eq!(loader.render("index").unwrap(), "<main><nav></nav></main>");
+    assert!(*not_modified.lock().unwrap() > 0);
+
+    files.lock().unwrap().insert(
+        String::from("/t/partials/nav.bud.html"),
+        String::from("<nav>Home</nav>"),
+    );
+    assert_eq!(
+        loader.render("index").unwrap(),
+        "<main><nav>Home</nav></main>"
+    );
+
+    let limited = FileLoader::new("", Configuration::for_html()).with_vfs(http().with_max_size(16));
+    assert!(limited.render("index").is_err());
+    assert_eq!(
+        http()
+            .read(Path::new("missing.bud.html"))
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::NotFound
+    );
+}
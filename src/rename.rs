@@ -0,0 +1,262 @@
+use std::ops::Range;
+
+use crate::{
+    directive::{self, is_identifier, Directive},
+    expression, Delimiters, Error, ErrorKind, SegmentKind, Template, Templates,
+};
+
+impl Template<'_> {
+    /// Returns this template's source with each reference to the variable
+    /// `old` replaced by `new`. Fields, functions, and text outside of tags
+    /// that share the name are left unchanged, as is the rest of the source.
+    ///
+    /// Variables passed to a sub-template using the `with name` shorthand
+    /// are passed as `with name = new`, so the sub-template's variable keeps
+    /// its name.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let renamed = Template::from(
+    ///     r#"{{ if user.admin }}{{= user.name }}{{ end }}{{ include "card" with user }}"#,
+    /// )
+    /// .rename_variable("user", "account")
+    /// .unwrap();
+    /// assert_eq!(
+    ///     renamed,
+    ///     r#"{{ if account.admin }}{{= account.name }}{{ end }}{{ include "card" with user = account }}"#
+    /// );
+    /// ```
+    pub fn rename_variable(&self, old: &str, new: &str) -> Result<String, Error> {
+        if !is_identifier(new) {
+            return Err(Error::from(ErrorKind::InvalidArgument {
+                function: String::from("rename_variable"),
+                message: format!("`{new}` is not a valid variable name"),
+            }));
+        }
+
+        let template = self.parse(Delimiters::DEFAULT)?;
+        let source = template.source;
+        let mut edits = Vec::new();
+        let rename_in = |code: &str, edits: &mut Vec<(Range<usize>, String)>| {
+            let offset = offset_in(source, code);
+            for variable in expression::variables(code) {
+                if &code[variable.clone()] == old {
+                    edits.push((
+                        offset + variable.start..offset + variable.end,
+                        new.to_string(),
+                    ));
+                }
+            }
+        };
+        for segment in &template.segments {
+            let code = &source[segment.range.clone()];
+            match segment.kind {
+                SegmentKind::Raw | SegmentKind::Comment(_) => {}
+                SegmentKind::Expression { .. } => rename_in(code, &mut edits),
+                SegmentKind::Statement(_) => match directive::parse(code.trim())
+                    .map_err(|err| err.located(segment.tag.clone()))?
+                {
+                    Some(
+                        Directive::Render { arguments, .. }
+                        | Directive::Include { arguments, .. }
+                        | Directive::Component { arguments, .. }
+                        | Directive::Island { arguments, .. },
+                    ) => {
+                        for argument in arguments {
+                            if std::ptr::eq(argument.name, argument.value) {
+                                if argument.name == old {
+                                    let start = offset_in(source, argument.name);
+                                    edits.push((
+                                        start..start + argument.name.len(),
+                                        format!("{old} = {new}"),
+                                    ));
+                                }
+                            } else {
+                                rename_in(argument.value, &mut edits);
+                            }
+                        }
+                    }
+                    Some(Directive::For {
+                        key,
+                        value,
+                        collection,
+                    }) => {
+                        for declared in key.into_iter().chain([value]) {
+                            if declared == old {
+                                let start = offset_in(source, declared);
+                                edits.push((start..start + declared.len(), new.to_string()));
+                            }
+                        }
+                        rename_in(collection, &mut edits);
+                    }
+                    Some(_) => {}
+                    None => rename_in(code, &mut edits),
+                },
+            }
+        }
+
+        Ok(apply(source, edits))
+    }
+
+    /// Returns this template's source with each `include`, `component`, and
+    /// `extends` statement that names the template `old` naming `new`
+    /// instead.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let renamed = Template::from(r#"{{ extends "base" }}{{ include "nav" }}"#)
+    ///     .rename_include("nav", "partials/nav")
+    ///     .unwrap();
+    /// assert_eq!(renamed, r#"{{ extends "base" }}{{ include "partials/nav" }}"#);
+    /// ```
+    pub fn rename_include(&self, old: &str, new: &str) -> Result<String, Error> {
+        let template = self.parse(Delimiters::DEFAULT)?;
+        let source = template.source;
+        let mut edits = Vec::new();
+        for segment in &template.segments {
+            if !matches!(segment.kind, SegmentKind::Statement(_)) {
+                continue;
+            }
+            let code = source[segment.range.clone()].trim();
+            let name =
+                match directive::parse(code).map_err(|err| err.located(segment.tag.clone()))? {
+                    Some(
+                        Directive::Include { name, .. }
+                        | Directive::Component { name, .. }
+                        | Directive::Extends { name },
+                    ) => name,
+                    _ => continue,
+                };
+            if name != old {
+                continue;
+            }
+            // The name is the first string literal in the statement.
+            let start = offset_in(source, code) + code.find('"').unwrap_or_default();
+            let end = expression::string_end(source, start);
+            edits.push((start..end, string_literal(new)));
+        }
+
+        Ok(apply(source, edits))
+    }
+}
+
+impl Templates<'_> {
+    /// Renames the variable `old` to `new` within every registered template.
+    /// See [`Template::rename_variable`].
+    pub fn rename_variable(&mut self, old: &str, new: &str) -> Result<(), Error> {
+        self.rewrite(|name, template| {
+            template
+                .rename_variable(old, new)
+                .map_err(|err| err.in_template(Some(name)))
+        })
+    }
+
+    /// Registers the template `old` as `new` instead, updating every
+    /// registered template that includes, extends, or uses it as a
+    /// component. See [`Template::rename_include`].
+    pub fn rename_include(&mut self, old: &str, new: &str) -> Result<(), Error> {
+        if self.get(old).is_none() {
+            return Err(Error::from(ErrorKind::UnknownTemplate(old.to_string())));
+        } else if self.get(new).is_some() {
+            return Err(Error::from(ErrorKind::DuplicateTemplate(new.to_string())));
+        }
+        self.rewrite(|name, template| {
+            template
+                .rename_include(old, new)
+                .map_err(|err| err.in_template(Some(name)))
+        })?;
+        self.rename(old, new)
+    }
+}
+
+/// Returns the offset of `part` within `source`, which it must be a slice of.
+fn offset_in(source: &str, part: &str) -> usize {
+    part.as_ptr() as usize - source.as_ptr() as usize
+}
+
+/// Returns a string literal that a directive parses as `value`.
+fn string_literal(value: &str) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' | '\\' => {
+                literal.push('\\');
+                literal.push(ch);
+            }
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            ch => literal.push(ch),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Replaces each range of `source` in `edits`, which must not overlap.
+fn apply(source: &str, mut edits: Vec<(Range<usize>, String)>) -> String {
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut edited = String::with_capacity(source.len());
+    let mut position = 0;
+    for (range, replacement) in edits {
+        edited.push_str(&source[position..range.start]);
+        edited.push_str(&replacement);
+        position = range.end;
+    }
+    edited.push_str(&source[position..]);
+    edited
+}
+
+#[test]
+fn renamed_variables() {
+    let template = Template::from(
+        "{{# user #}}user: {{= user.name ?? \"user\" }}\
+         {{ for index, user in users }}{{= index }}{{ end }}\
+         {{ render \"row\" with user, other = f(user) }}{{ user := 1 }}",
+    );
+    assert_eq!(
+        template.rename_variable("user", "member").unwrap(),
+        "{{# user #}}user: {{= member.name ?? \"user\" }}\
+         {{ for index, member in users }}{{= index }}{{ end }}\
+         {{ render \"row\" with user = member, other = f(member) }}{{ member := 1 }}"
+    );
+    assert!(matches!(
+        template.rename_variable("user", "not valid"),
+        Err(Error {
+            kind: ErrorKind::InvalidArgument { .. },
+            ..
+        })
+    ));
+}
+
+#[test]
+fn renamed_includes() {
+    let mut templates = Templates::new();
+    templates.register("nav", "<nav></nav>").unwrap();
+    templates
+        .register(
+            "page",
+            r#"{{ include "nav" }}{{ component "nav" with a }}{{ end component }}{{= "nav" }}"#,
+        )
+        .unwrap();
+    templates.rename_include("nav", "menu \"main\"").unwrap();
+    assert!(templates.get("nav").is_none());
+    assert_eq!(
+        templates.get("menu \"main\"").unwrap().source(),
+        "<nav></nav>"
+    );
+    assert_eq!(
+        templates.get("page").unwrap().source(),
+        r#"{{ include "menu \"main\"" }}{{ component "menu \"main\"" with a }}{{ end component }}{{= "nav" }}"#
+    );
+    assert!(matches!(
+        templates.rename_include("page", "menu \"main\""),
+        Err(Error {
+            kind: ErrorKind::DuplicateTemplate(_),
+            ..
+        })
+    ));
+}
@@ -0,0 +1,241 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{
+    CompiledTemplate, Configuration, Encoder, Error, ErrorKind, Machine, ParameterName, Templates,
+};
+
+/// A template name along with the names of the arguments it was compiled to
+/// accept.
+type CompiledKey = (String, Vec<Symbol>);
+
+struct Compiled {
+    template: Rc<CompiledTemplate>,
+    last_used: Cell<Instant>,
+}
+
+/// Renders the templates of a [`Templates`] collection using a single Bud
+/// virtual machine.
+///
+/// Each [`CompiledTemplate`] normally has a virtual machine of its own, which
+/// every native function must be registered with. A renderer registers them
+/// once, and compiles each template into the same machine the first time it
+/// is rendered with a given set of argument names.
+///
+/// ```rust
+/// use budplate::{Configuration, Templates};
+///
+/// let mut templates = Templates::new();
+/// templates.register("greeting", "Hello, {{= name }}!").unwrap();
+///
+/// let configuration = Configuration::for_html();
+/// let renderer = configuration.renderer(&templates);
+/// for name in ["Ada", "Grace"] {
+///     assert_eq!(
+///         renderer.render_with("greeting", [("name", name)]).unwrap(),
+///         format!("Hello, {name}!")
+///     );
+/// }
+/// ```
+pub struct Renderer<'a, Enc> {
+    configuration: &'a Configuration<Enc>,
+    templates: &'a Templates<'a>,
+    machine: RefCell<Option<Rc<Machine>>>,
+    compiled: RefCell<HashMap<CompiledKey, Compiled>>,
+    pinned: HashSet<String>,
+}
+
+impl<'a, Enc: Encoder> Renderer<'a, Enc> {
+    pub fn new(configuration: &'a Configuration<Enc>, templates: &'a Templates<'a>) -> Self {
+        Self {
+            configuration,
+            templates,
+            machine: RefCell::new(None),
+            compiled: RefCell::new(HashMap::new()),
+            pinned: HashSet::new(),
+        }
+    }
+
+    /// Keeps the templates named `names` compiled when
+    /// [`Renderer::evict_unused()`] is called, such as the layouts that every
+    /// page includes.
+    #[must_use]
+    pub fn with_pinned<Names>(mut self, names: Names) -> Self
+    where
+        Names: IntoIterator,
+        Names::Item: Into<String>,
+    {
+        self.pinned.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Drops the compiled templates that haven't been rendered or compiled
+    /// within `since`, except for pinned templates, and returns how many were
+    /// dropped. A dropped template is compiled again the next time it is
+    /// rendered.
+    ///
+    /// This keeps a long-running process that renders many templates, such as
+    /// one per tenant, from holding on to templates that are no longer used.
+    /// Once every template has been dropped, the shared virtual machine is
+    /// dropped as well, and later templates are compiled into a new one.
+    pub fn evict_unused(&self, since: Duration) -> usize {
+        let mut compiled = self.compiled.borrow_mut();
+        let before = compiled.len();
+        compiled.retain(|(name, _), entry| {
+            self.pinned.contains(name) || entry.last_used.get().elapsed() < since
+        });
+        if compiled.is_empty() {
+            self.machine.borrow_mut().take();
+        }
+        before - compiled.len()
+    }
+
+    pub fn render(&self, name: &str) -> Result<String, Error> {
+        self.render_with::<&'static str, Value, _>(name, [])
+    }
+
+    pub fn render_with<Name, Arg, Args>(&self, name: &str, args: Args) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<Value>) = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .unzip();
+        let mut output = String::new();
+        self.compile(name, symbols)?
+            .render_values_to(values, &mut output)?;
+        Ok(output)
+    }
+
+    /// Returns the template `name` compiled to accept `parameters`, compiling
+    /// it if it hasn't been already.
+    pub fn compile<Params>(
+        &self,
+        name: &str,
+        parameters: Params,
+    ) -> Result<Rc<CompiledTemplate>, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let parameters = parameters
+            .into_iter()
+            .map(ParameterName::into_symbol)
+            .collect::<Vec<_>>();
+        let key = (name.to_string(), parameters);
+        if let Some(compiled) = self.compiled.borrow().get(&key) {
+            compiled.last_used.set(Instant::now());
+            return Ok(compiled.template.clone());
+        }
+
+        let template = self
+            .templates
+            .get(name)
+            .ok_or_else(|| ErrorKind::UnknownTemplate(name.to_string()))?;
+        let machine = self
+            .machine
+            .borrow_mut()
+            .get_or_insert_with(|| Rc::new(self.configuration.machine()))
+            .clone();
+        let function = format!("__render_{}", machine.functions.get());
+        let compiled = self
+            .configuration
+            .compile_into(
+                &machine,
+                &function,
+                template,
+                Some((self.templates, name)),
+                key.1.iter().cloned(),
+            )
+            .inspect_err(|_| {
+                // A template that fails to compile may have been partially
+                // evaluated, so later templates are compiled into a new
+                // machine.
+                self.machine.borrow_mut().take();
+                self.compiled.borrow_mut().clear();
+            })?;
+
+        let compiled = Rc::new(compiled);
+        self.compiled.borrow_mut().insert(
+            key,
+            Compiled {
+                template: compiled.clone(),
+                last_used: Cell::new(Instant::now()),
+            },
+        );
+        Ok(compiled)
+    }
+}
+
+impl<Enc: Encoder> Configuration<Enc> {
+    /// Returns a [`Renderer`] that renders `templates` with this
+    /// configuration.
+    pub fn renderer<'a>(&'a self, templates: &'a Templates<'a>) -> Renderer<'a, Enc> {
+        Renderer::new(self, templates)
+    }
+}
+
+#[test]
+fn shared_machine() {
+    let mut templates = Templates::new();
+    templates.register("row", "<li>{{= label }}</li>").unwrap();
+    templates
+        .register("list", "<ul>{{ include \"row\" with label }}</ul>")
+        .unwrap();
+
+    let configuration = Configuration::for_html();
+    let renderer = configuration.renderer(&templates);
+    assert_eq!(
+        renderer.render_with("row", [("label", "a&b")]).unwrap(),
+        "<li>a&amp;b</li>"
+    );
+    assert_eq!(
+        renderer.render_with("list", [("label", "c")]).unwrap(),
+        "<ul><li>c</li></ul>"
+    );
+    assert_eq!(
+        renderer.render_with("row", [("label", "d")]).unwrap(),
+        "<li>d</li>"
+    );
+    assert!(Rc::ptr_eq(
+        &renderer.compile("row", ["label"]).unwrap(),
+        &renderer.compile("row", ["label"]).unwrap()
+    ));
+    assert!(matches!(
+        renderer.render("missing"),
+        Err(Error { kind: ErrorKind::UnknownTemplate(name), .. }) if name == "missing"
+    ));
+}
+
+#[test]
+fn evicted_templates() {
+    let mut templates = Templates::new();
+    templates
+        .register("layout", "<main>{{= body }}</main>")
+        .unwrap();
+    templates.register("tenant", "{{= name }}").unwrap();
+
+    let configuration = Configuration::for_html();
+    let renderer = configuration.renderer(&templates).with_pinned(["layout"]);
+    let tenant = renderer.compile("tenant", ["name"]).unwrap();
+    renderer.compile("layout", ["body"]).unwrap();
+    assert_eq!(renderer.evict_unused(Duration::from_secs(3600)), 0);
+    assert_eq!(renderer.evict_unused(Duration::ZERO), 1);
+    assert!(!Rc::ptr_eq(
+        &tenant,
+        &renderer.compile("tenant", ["name"]).unwrap()
+    ));
+    assert_eq!(
+        renderer.render_with("layout", [("body", "a&b")]).unwrap(),
+        "<main>a&amp;b</main>"
+    );
+}
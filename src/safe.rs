@@ -0,0 +1,44 @@
+use budlang::vm::{DynamicValue, Symbol, Value};
+
+/// A string that has already been encoded for the output format, such as
+/// markup produced by a helper function. Inline expressions render safe
+/// strings without encoding them again.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SafeString(String);
+
+impl SafeString {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl DynamicValue for SafeString {
+    fn is_truthy(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    fn kind(&self) -> Symbol {
+        Symbol::from("SafeString")
+    }
+
+    fn convert(&self, kind: &Symbol) -> Option<Value> {
+        match &**kind {
+            "String" => Some(Value::from(self.0.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl From<SafeString> for Value {
+    fn from(value: SafeString) -> Self {
+        Value::dynamic(value)
+    }
+}
@@ -0,0 +1,71 @@
+use std::fmt::Debug;
+
+use budlang::vm::{DynamicValue, Symbol, Value};
+
+/// A value that renders normally within templates but is always masked in
+/// diagnostic output, including [`PrettyPrinter`](crate::PrettyPrinter)
+/// output and `Debug` formatting.
+///
+/// ```rust
+/// use budplate::{Secret, Template};
+///
+/// let rendered = Template::from("key={{= api_key }}")
+///     .render_with([("api_key", Secret::new("hunter2"))])
+///     .unwrap();
+/// assert_eq!(rendered, "key=hunter2");
+/// ```
+#[derive(Clone)]
+pub struct Secret(Value);
+
+impl Secret {
+    pub fn new(value: impl Into<Value>) -> Self {
+        Self(value.into())
+    }
+
+    pub const fn expose(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Secret").field(&"<redacted>").finish()
+    }
+}
+
+impl DynamicValue for Secret {
+    fn is_truthy(&self) -> bool {
+        self.0.is_truthy()
+    }
+
+    fn kind(&self) -> Symbol {
+        Symbol::from("Secret")
+    }
+
+    fn convert(&self, kind: &Symbol) -> Option<Value> {
+        match &**kind {
+            "String" => self
+                .0
+                .try_convert_to_string(&())
+                .ok()
+                .map(|value| Value::from(value.to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl From<Secret> for Value {
+    fn from(secret: Secret) -> Self {
+        Value::dynamic(secret)
+    }
+}
+
+#[test]
+fn secrets_are_redacted() {
+    let secret = Value::from(Secret::new("hunter2"));
+    assert_eq!(crate::PrettyPrinter::new().print(&secret), "<redacted>");
+    assert_eq!(
+        format!("{:?}", Secret::new("hunter2")),
+        r#"Secret("<redacted>")"#
+    );
+}
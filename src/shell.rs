@@ -0,0 +1,416 @@
+use std::ops::Range;
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{
+    audit::{self, AccessKind},
+    directive::{self, Directive},
+    CompiledTemplate, Configuration, Encoder, Error, ErrorKind, ParameterName, ParsedTemplate,
+    Segment, SegmentKind, Template, Templates, WhitespaceTrimming,
+};
+
+/// A template divided into the regions that only depend on its static
+/// parameters and the holes that depend on its dynamic parameters.
+///
+/// A page whose layout is shared by every request, but whose cart or user
+/// differs, can be [rendered into a shell](Self::render_shell) once, and the
+/// shell [filled](Shell::fill) for each request, which only renders the
+/// holes.
+///
+/// Top-level tags and blocks are holes when they read a dynamic parameter,
+/// and `feature` and `sampled` regions are always holes. A template that
+/// can't be divided, because it defines sub-templates, uses components,
+/// fragments, `defer`, or `emit`, or executes statements outside of a block,
+/// such as assignments, is compiled as a single hole. Helpers that are
+/// called by the static regions, such as `unique_id()` or `context()`, are
+/// only called when the shell is rendered, and the store of `put()` and
+/// `get()` isn't shared between regions.
+pub struct ShellTemplate {
+    parts: Vec<ShellPart>,
+}
+
+struct ShellPart {
+    template: CompiledTemplate,
+    dynamic: bool,
+}
+
+impl<Enc: Encoder> Configuration<Enc> {
+    /// Compiles `template` into a [`ShellTemplate`], whose holes are the
+    /// regions that read any of `dynamic_parameters`.
+    ///
+    /// ```rust
+    /// use budplate::{Configuration, Template};
+    ///
+    /// let template = Configuration::default()
+    ///     .compile_shell(
+    ///         &Template::from("<h1>{{= site }}</h1><p>{{= user }}</p>"),
+    ///         ["site"],
+    ///         ["user"],
+    ///     )
+    ///     .unwrap();
+    /// let shell = template.render_shell([("site", "Khonsu")]).unwrap();
+    /// assert_eq!(shell.holes(), 1);
+    /// assert_eq!(
+    ///     shell.fill([("user", "ecton")]).unwrap(),
+    ///     "<h1>Khonsu</h1><p>ecton</p>"
+    /// );
+    /// ```
+    pub fn compile_shell<Static, Dynamic>(
+        &self,
+        template: &Template<'_>,
+        static_parameters: Static,
+        dynamic_parameters: Dynamic,
+    ) -> Result<ShellTemplate, Error>
+    where
+        Static: IntoIterator,
+        Static::Item: ParameterName,
+        Dynamic: IntoIterator,
+        Dynamic::Item: ParameterName,
+    {
+        self.shell(template, None, static_parameters, dynamic_parameters)
+    }
+
+    /// Compiles the template registered as `name` in `templates` into a
+    /// [`ShellTemplate`], whose holes are the regions that read any of
+    /// `dynamic_parameters`.
+    pub fn compile_shell_from<Static, Dynamic>(
+        &self,
+        templates: &Templates<'_>,
+        name: &str,
+        static_parameters: Static,
+        dynamic_parameters: Dynamic,
+    ) -> Result<ShellTemplate, Error>
+    where
+        Static: IntoIterator,
+        Static::Item: ParameterName,
+        Dynamic: IntoIterator,
+        Dynamic::Item: ParameterName,
+    {
+        let template = templates
+            .get(name)
+            .ok_or_else(|| ErrorKind::UnknownTemplate(name.to_string()))?;
+        self.shell(
+            template,
+            Some((templates, name)),
+            static_parameters,
+            dynamic_parameters,
+        )
+        .map_err(|err| err.in_template(Some(name)))
+    }
+
+    fn shell<Static, Dynamic>(
+        &self,
+        template: &Template<'_>,
+        registry: Option<(&Templates<'_>, &str)>,
+        static_parameters: Static,
+        dynamic_parameters: Dynamic,
+    ) -> Result<ShellTemplate, Error>
+    where
+        Static: IntoIterator,
+        Static::Item: ParameterName,
+        Dynamic: IntoIterator,
+        Dynamic::Item: ParameterName,
+    {
+        let (templates, name) = registry.unzip();
+        let static_parameters = static_parameters
+            .into_iter()
+            .map(ParameterName::into_symbol)
+            .collect::<Vec<_>>();
+        let dynamic_parameters = dynamic_parameters
+            .into_iter()
+            .map(ParameterName::into_symbol)
+            .collect::<Vec<_>>();
+        let all_parameters = static_parameters
+            .iter()
+            .chain(&dynamic_parameters)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let parsed = self.parse(template)?;
+        let dynamic_names = dynamic_parameters
+            .iter()
+            .map(|param| &**param)
+            .collect::<Vec<_>>();
+        let Some(regions) = divide(&parsed, &dynamic_names) else {
+            let template = self.compile_template(template, registry, all_parameters)?;
+            return Ok(ShellTemplate {
+                parts: vec![ShellPart {
+                    template,
+                    dynamic: true,
+                }],
+            });
+        };
+
+        let mut parts = Vec::with_capacity(regions.len());
+        for (range, dynamic) in regions {
+            let region = ParsedTemplate {
+                source: parsed.source,
+                segments: region_segments(&parsed, range),
+            };
+            let parameters = if dynamic {
+                all_parameters.clone()
+            } else {
+                static_parameters.clone()
+            };
+            // Each region is compiled into its own machine, since every
+            // region is rendered by a function named `render`.
+            let machine = std::rc::Rc::new(self.machine());
+            let bud_source = region.into_bud_source(
+                "render",
+                &parameters,
+                self.transpile_options(templates, 0),
+            )?;
+            let template = self.evaluate_into(&machine, &bud_source, parameters, name)?;
+            parts.push(ShellPart { template, dynamic });
+        }
+        Ok(ShellTemplate { parts })
+    }
+}
+
+/// Divides the segments of `template` into the ranges of consecutive
+/// top-level tags and blocks that do or don't read any of `dynamic`,
+/// returning `None` if the template can't be divided.
+fn divide(template: &ParsedTemplate<'_>, dynamic: &[&str]) -> Option<Vec<(Range<usize>, bool)>> {
+    let reads_dynamic = |code: &str| {
+        audit::accesses(code)
+            .into_iter()
+            .any(|(kind, name)| kind == AccessKind::Variable && dynamic.contains(&name))
+    };
+
+    let segments = &template.segments;
+    let mut regions: Vec<(Range<usize>, bool)> = Vec::new();
+    let mut index = 0;
+    while index < segments.len() {
+        let start = index;
+        let mut depth = 0_usize;
+        let mut is_dynamic = false;
+        loop {
+            let segment = segments.get(index)?;
+            index += 1;
+            let code = template.source[segment.range.clone()].trim();
+            match segment.kind {
+                SegmentKind::Raw | SegmentKind::Comment(_) => {}
+                SegmentKind::Expression { .. } => is_dynamic |= reads_dynamic(code),
+                SegmentKind::Statement(_) => match directive::parse(code).ok()? {
+                    Some(
+                        Directive::Include { arguments, .. }
+                        | Directive::Render { arguments, .. }
+                        | Directive::Island { arguments, .. },
+                    ) => {
+                        is_dynamic |= arguments
+                            .iter()
+                            .any(|argument| reads_dynamic(argument.value));
+                    }
+                    Some(Directive::For { collection, .. }) => {
+                        depth += 1;
+                        is_dynamic |= reads_dynamic(collection);
+                    }
+                    // Features and samples are decided by each render.
+                    Some(Directive::Feature { .. } | Directive::Sampled { .. }) => {
+                        depth += 1;
+                        is_dynamic = true;
+                    }
+                    Some(Directive::Once) => depth += 1,
+                    Some(Directive::Meta { .. }) => {}
+                    Some(_) => return None,
+                    None if directive::is_end(code) => depth = depth.checked_sub(1)?,
+                    None if directive::opens_block(code) => {
+                        // Functions defined at the top level may be called
+                        // by any region.
+                        if depth == 0 && code.starts_with("function") {
+                            return None;
+                        }
+                        depth += 1;
+                        is_dynamic |= reads_dynamic(code);
+                    }
+                    // Statements at the top level may assign variables that
+                    // are read by another region.
+                    None if depth == 0 => return None,
+                    None => is_dynamic |= reads_dynamic(code),
+                },
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+
+        match regions.last_mut() {
+            Some((range, dynamic)) if *dynamic == is_dynamic => range.end = index,
+            _ => regions.push((start..index, is_dynamic)),
+        }
+    }
+    Some(regions)
+}
+
+/// Returns the segments of `template` in `range`, trimming the raw text at
+/// either end that is trimmed by a tag in a neighboring region.
+fn region_segments(template: &ParsedTemplate<'_>, range: Range<usize>) -> Vec<Segment> {
+    fn trimming(segment: &Segment) -> WhitespaceTrimming {
+        match segment.kind {
+            SegmentKind::Raw => WhitespaceTrimming::default(),
+            SegmentKind::Comment(trimming)
+            | SegmentKind::Statement(trimming)
+            | SegmentKind::Expression { trimming, .. } => trimming,
+        }
+    }
+
+    let before = range
+        .start
+        .checked_sub(1)
+        .map(|index| &template.segments[index]);
+    let after = template.segments.get(range.end);
+    let mut segments = template.segments[range].to_vec();
+    if let Some(first) = segments
+        .first_mut()
+        .filter(|first| matches!(first.kind, SegmentKind::Raw))
+    {
+        if before.is_some_and(|before| trimming(before).trim_after) {
+            let raw = &template.source[first.range.clone()];
+            first.range.start += raw.len() - raw.trim_start().len();
+        }
+    }
+    if let Some(last) = segments
+        .last_mut()
+        .filter(|last| matches!(last.kind, SegmentKind::Raw))
+    {
+        if after.is_some_and(|after| trimming(after).trim_before) {
+            let raw = &template.source[last.range.clone()];
+            last.range.end -= raw.len() - raw.trim_end().len();
+        }
+    }
+    segments
+}
+
+impl ShellTemplate {
+    /// Renders the static regions of this template using `args`, which are
+    /// its static arguments.
+    pub fn render_shell<Name, Arg, Args>(&self, args: Args) -> Result<Shell<'_>, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let arguments = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .collect::<Vec<(Symbol, Value)>>();
+        let rendered = self
+            .parts
+            .iter()
+            .map(|part| {
+                if part.dynamic {
+                    return Ok(None);
+                }
+                let values = part.template.arguments(arguments.iter().cloned())?;
+                let mut output = String::new();
+                part.template.render_values_to(values, &mut output)?;
+                Ok(Some(output))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Shell {
+            template: self,
+            arguments,
+            rendered,
+        })
+    }
+}
+
+/// A [`ShellTemplate`] whose static regions have been rendered, which can be
+/// filled in with dynamic arguments many times.
+pub struct Shell<'a> {
+    template: &'a ShellTemplate,
+    arguments: Vec<(Symbol, Value)>,
+    /// The output of each static region, or `None` for each hole.
+    rendered: Vec<Option<String>>,
+}
+
+impl Shell<'_> {
+    /// Returns the number of regions that are rendered by each call to
+    /// [`fill()`](Self::fill).
+    #[must_use]
+    pub fn holes(&self) -> usize {
+        self.rendered
+            .iter()
+            .filter(|output| output.is_none())
+            .count()
+    }
+
+    /// Renders the holes of this shell using `args`, which are the dynamic
+    /// arguments of the template, returning the complete output.
+    pub fn fill<Name, Arg, Args>(&self, args: Args) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let dynamic = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .collect::<Vec<(Symbol, Value)>>();
+        let mut output = String::new();
+        for (part, rendered) in self.template.parts.iter().zip(&self.rendered) {
+            match rendered {
+                Some(rendered) => output.push_str(rendered),
+                None => {
+                    let values = part
+                        .template
+                        .arguments(self.arguments.iter().chain(&dynamic).cloned())?;
+                    part.template.render_values_to(values, &mut output)?;
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[test]
+fn shell_regions() {
+    use crate::Delimiters;
+
+    let regions = |source: &str| {
+        let template = Template::from(source);
+        let parsed = template.parse(Delimiters::DEFAULT).unwrap();
+        divide(&parsed, &["cart", "user"]).map(|regions| {
+            regions
+                .into_iter()
+                .map(|(range, dynamic)| (range.start, range.end, dynamic))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    assert_eq!(
+        regions(
+            "<nav>{{= site }}</nav>{{ for item in cart }}{{= item }}{{ end }}<footer>{{ if site.footer }}{{= site.footer }}{{ end }}</footer>{{= user }}"
+        ),
+        Some(vec![
+            (0, 3, false),
+            (3, 8, true),
+            (8, 15, false),
+            (15, 16, true),
+            (16, 17, false)
+        ])
+    );
+    assert_eq!(
+        regions("{{ feature \"beta\" }}new{{ end }}{{ include \"nav\" with site }}"),
+        Some(vec![(0, 1, false), (1, 4, true), (4, 7, false)])
+    );
+    assert_eq!(regions("{{ count := 1 }}{{= count }}"), None);
+    assert_eq!(regions("{{ define \"nav\" }}{{= site }}{{ end }}"), None);
+    assert_eq!(regions("{{ if user }}"), None);
+}
+
+#[test]
+fn trimmed_regions() {
+    use crate::Delimiters;
+
+    let template = Template::from("<p>\n  {{- user -}}\n</p>");
+    let parsed = template.parse(Delimiters::DEFAULT).unwrap();
+    let raw = |segments: Vec<Segment>| {
+        segments
+            .iter()
+            .map(|segment| &parsed.source[segment.range.clone()])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(raw(region_segments(&parsed, 0..1)), ["<p>"]);
+    assert_eq!(raw(region_segments(&parsed, 2..3)), ["</p>"]);
+}
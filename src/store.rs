@@ -0,0 +1,382 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    future::Future,
+    rc::Rc,
+};
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{
+    CompileCache, CompiledTemplate, Configuration, Encoder, Error, ParameterName, Templates,
+};
+
+/// A template read from a [`TemplateStore`] or [`AsyncTemplateStore`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StoredTemplate {
+    /// The name the template is registered as, which other templates include
+    /// it by.
+    pub name: String,
+    /// The revision of the template's source, which must change whenever its
+    /// source does, such as a version column incremented by each update.
+    pub revision: u64,
+    pub source: String,
+}
+
+impl StoredTemplate {
+    pub fn new(name: impl Into<String>, revision: u64, source: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            revision,
+            source: source.into(),
+        }
+    }
+}
+
+/// A collection of templates kept outside of the filesystem, such as in the
+/// rows of a database table, which a [`StoreLoader`] loads templates from.
+///
+/// Errors from the underlying storage can be returned as
+/// [`ErrorKind::Io`](crate::ErrorKind::Io) using [`std::io::Error::other()`].
+pub trait TemplateStore {
+    /// Returns the name and current revision of every template, such as
+    /// using `SELECT name, revision FROM templates`.
+    fn revisions(&self) -> Result<Vec<(String, u64)>, Error>;
+
+    /// Returns the templates named `names` at their current revisions.
+    fn load(&self, names: &[&str]) -> Result<Vec<StoredTemplate>, Error>;
+}
+
+/// A [`TemplateStore`] accessed asynchronously, such as using an async
+/// database driver. Templates are loaded from it using
+/// [`StoreLoader::refresh_async()`].
+pub trait AsyncTemplateStore {
+    /// Returns the name and current revision of every template.
+    fn revisions(&self) -> impl Future<Output = Result<Vec<(String, u64)>, Error>>;
+
+    /// Returns the templates named `names` at their current revisions.
+    fn load(&self, names: &[&str]) -> impl Future<Output = Result<Vec<StoredTemplate>, Error>>;
+}
+
+/// Loads templates from a [`TemplateStore`] or [`AsyncTemplateStore`],
+/// such as a CMS storing its templates in a database, compiling each on
+/// first use and caching it.
+///
+/// The loader doesn't contain any templates until it is
+/// [refreshed](Self::refresh). Each refresh reads the revision of every
+/// template, loads only the templates whose revision changed, and discards
+/// the compiled templates whose layout changed as a result, returning the
+/// names of the templates that render differently so that output cached
+/// from them can be invalidated. See [`Configuration::layout_changed()`].
+///
+/// ```rust
+/// use budplate::{Configuration, Error, StoreLoader, StoredTemplate, TemplateStore};
+///
+/// struct Rows(Vec<StoredTemplate>);
+///
+/// impl TemplateStore for Rows {
+///     fn revisions(&self) -> Result<Vec<(String, u64)>, Error> {
+///         Ok(self.0.iter().map(|row| (row.name.clone(), row.revision)).collect())
+///     }
+///
+///     fn load(&self, names: &[&str]) -> Result<Vec<StoredTemplate>, Error> {
+///         Ok(self
+///             .0
+///             .iter()
+///             .filter(|row| names.contains(&row.name.as_str()))
+///             .cloned()
+///             .collect())
+///     }
+/// }
+///
+/// let loader = StoreLoader::new(Configuration::for_html());
+/// let mut rows = Rows(vec![
+///     StoredTemplate::new("page", 1, r#"<main>{{ include "nav" }}</main>"#),
+///     StoredTemplate::new("nav", 1, "<nav></nav>"),
+/// ]);
+/// loader.refresh(&rows).unwrap();
+/// assert_eq!(loader.render("page").unwrap(), "<main><nav></nav></main>");
+///
+/// rows.0[1] = StoredTemplate::new("nav", 2, "<nav>Home</nav>");
+/// assert_eq!(loader.refresh(&rows).unwrap(), ["nav", "page"]);
+/// assert_eq!(loader.render("page").unwrap(), "<main><nav>Home</nav></main>");
+/// ```
+///
+/// Loaders compiling the same templates, such as one per tenant of a
+/// service, can share the Bud source their templates transpile into using
+/// [`StoreLoader::with_compile_cache()`].
+pub struct StoreLoader<Enc> {
+    configuration: Configuration<Enc>,
+    cache: Option<CompileCache>,
+    loaded: RefCell<Loaded>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    templates: Templates<'static>,
+    revisions: HashMap<String, u64>,
+    compiled: HashMap<(String, Vec<String>), Rc<CompiledTemplate>>,
+}
+
+impl<Enc: Encoder> StoreLoader<Enc> {
+    pub fn new(configuration: Configuration<Enc>) -> Self {
+        Self {
+            configuration,
+            cache: None,
+            loaded: RefCell::new(Loaded::default()),
+        }
+    }
+
+    /// Compiles templates using `cache`, reusing the Bud source of templates
+    /// transpiled by other loaders or configurations sharing it. See
+    /// [`Configuration::compile_shared()`].
+    #[must_use]
+    pub fn with_compile_cache(mut self, cache: CompileCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Loads the templates of `store` whose revisions changed since the last
+    /// refresh, returning the names of the templates whose layout changed,
+    /// sorted by name. This includes templates that were added or removed,
+    /// and templates that include a changed template.
+    ///
+    /// If loading fails, the templates loaded by the previous refresh remain
+    /// in use.
+    pub fn refresh(&self, store: &impl TemplateStore) -> Result<Vec<String>, Error> {
+        let revisions = store.revisions()?;
+        let stale = self.stale(&revisions);
+        if stale.is_empty() && revisions.len() == self.loaded.borrow().revisions.len() {
+            return Ok(Vec::new());
+        }
+        let loaded = store.load(&stale.iter().map(String::as_str).collect::<Vec<_>>())?;
+        self.apply(revisions, loaded)
+    }
+
+    /// Loads the templates of `store` whose revisions changed since the last
+    /// refresh. See [`StoreLoader::refresh()`].
+    pub async fn refresh_async(
+        &self,
+        store: &impl AsyncTemplateStore,
+    ) -> Result<Vec<String>, Error> {
+        let revisions = store.revisions().await?;
+        let stale = self.stale(&revisions);
+        if stale.is_empty() && revisions.len() == self.loaded.borrow().revisions.len() {
+            return Ok(Vec::new());
+        }
+        let loaded = store
+            .load(&stale.iter().map(String::as_str).collect::<Vec<_>>())
+            .await?;
+        self.apply(revisions, loaded)
+    }
+
+    /// Returns the revision of the template `name` loaded by the last
+    /// refresh, if any.
+    pub fn revision(&self, name: &str) -> Option<u64> {
+        self.loaded.borrow().revisions.get(name).copied()
+    }
+
+    /// Returns the names of the templates in `revisions` that aren't loaded
+    /// at the same revision.
+    fn stale(&self, revisions: &[(String, u64)]) -> Vec<String> {
+        let loaded = self.loaded.borrow();
+        revisions
+            .iter()
+            .filter(|(name, revision)| loaded.revisions.get(name) != Some(revision))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Replaces the loaded templates with those in `revisions`, taking the
+    /// sources of the stale templates from `loaded`.
+    fn apply(
+        &self,
+        revisions: Vec<(String, u64)>,
+        loaded: Vec<StoredTemplate>,
+    ) -> Result<Vec<String>, Error> {
+        let mut current = self.loaded.borrow_mut();
+        let mut sources = loaded
+            .into_iter()
+            .map(|template| (template.name.clone(), template))
+            .collect::<HashMap<_, _>>();
+        let mut templates = Templates::new();
+        let mut loaded_revisions = HashMap::new();
+        for (name, revision) in revisions {
+            let source = match sources.remove(&name) {
+                Some(template) => {
+                    loaded_revisions.insert(name.clone(), template.revision);
+                    template.source
+                }
+                None => match current.templates.get(&name) {
+                    Some(template) if current.revisions.get(&name) == Some(&revision) => {
+                        loaded_revisions.insert(name.clone(), revision);
+                        template.source().to_string()
+                    }
+                    // Templates removed between reading the revisions and
+                    // loading them are left out.
+                    _ => continue,
+                },
+            };
+            templates.register(name, source)?;
+        }
+
+        let names = current
+            .revisions
+            .keys()
+            .chain(loaded_revisions.keys())
+            .collect::<HashSet<_>>();
+        let mut changed = names
+            .into_iter()
+            .filter(|name| {
+                templates.get(name).is_none()
+                    || !matches!(
+                        self.configuration
+                            .layout_changed(&current.templates, &templates, name),
+                        Ok(false)
+                    )
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        changed.sort_unstable();
+
+        let compiled = std::mem::take(&mut current.compiled)
+            .into_iter()
+            .filter(|((name, _), _)| changed.binary_search(name).is_err())
+            .collect();
+        *current = Loaded {
+            templates,
+            revisions: loaded_revisions,
+            compiled,
+        };
+        Ok(changed)
+    }
+
+    pub fn render(&self, name: &str) -> Result<String, Error> {
+        self.render_with::<&'static str, Value, _>(name, [])
+    }
+
+    pub fn render_with<Name, Arg, Args>(&self, name: &str, args: Args) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        let (symbols, values): (Vec<Symbol>, Vec<Value>) = args
+            .into_iter()
+            .map(|(name, arg)| (name.into(), arg.into()))
+            .unzip();
+        let mut output = String::new();
+        self.compile(name, symbols)?
+            .render_values_to(values, &mut output)?;
+        Ok(output)
+    }
+
+    /// Returns the compiled form of the template `name`, compiling it if it
+    /// isn't already cached.
+    pub fn compile<Params>(
+        &self,
+        name: &str,
+        parameters: Params,
+    ) -> Result<Rc<CompiledTemplate>, Error>
+    where
+        Params: IntoIterator,
+        Params::Item: ParameterName,
+    {
+        let parameters = parameters
+            .into_iter()
+            .map(ParameterName::into_symbol)
+            .collect::<Vec<_>>();
+        let key = (
+            name.to_string(),
+            parameters.iter().map(|param| param.to_string()).collect(),
+        );
+        let mut loaded = self.loaded.borrow_mut();
+        if let Some(compiled) = loaded.compiled.get(&key) {
+            return Ok(compiled.clone());
+        }
+        let compiled = Rc::new(match &self.cache {
+            Some(cache) => {
+                self.configuration
+                    .compile_shared(cache, &loaded.templates, name, parameters)?
+            }
+            None => self
+                .configuration
+                .compile_from(&loaded.templates, name, parameters)?,
+        });
+        loaded.compiled.insert(key, compiled.clone());
+        Ok(compiled)
+    }
+
+    /// Returns the templates loaded by the last refresh, such as to extract
+    /// their messages.
+    pub fn templates(&self) -> Templates<'static> {
+        self.loaded.borrow().templates.clone()
+    }
+}
+
+#[test]
+fn refreshed_revisions() {
+    use std::cell::Cell;
+
+    struct Rows {
+        rows: Vec<StoredTemplate>,
+        loads: Cell<usize>,
+    }
+
+    impl TemplateStore for Rows {
+        fn revisions(&self) -> Result<Vec<(String, u64)>, Error> {
+            Ok(self
+                .rows
+                .iter()
+                .map(|row| (row.name.clone(), row.revision))
+                .collect())
+        }
+
+        fn load(&self, names: &[&str]) -> Result<Vec<StoredTemplate>, Error> {
+            self.loads.set(self.loads.get() + names.len());
+            Ok(self
+                .rows
+                .iter()
+                .filter(|row| names.contains(&row.name.as_str()))
+                .cloned()
+                .collect())
+        }
+    }
+
+    let mut store = Rows {
+        rows: vec![
+            StoredTemplate::new("page", 1, r#"<main>{{ include "nav" }}</main>"#),
+            StoredTemplate::new("nav", 1, "<nav></nav>"),
+            StoredTemplate::new("footer", 1, "<footer></footer>"),
+        ],
+        loads: Cell::new(0),
+    };
+    let loader =
+        StoreLoader::new(Configuration::for_html()).with_compile_cache(CompileCache::new());
+    assert_eq!(loader.refresh(&store).unwrap(), ["footer", "nav", "page"]);
+    assert_eq!(store.loads.get(), 3);
+    assert_eq!(loader.render("page").unwrap(), "<main><nav></nav></main>");
+    assert_eq!(loader.refresh(&store).unwrap(), Vec::<String>::new());
+    assert_eq!(store.loads.get(), 3);
+
+    // Only the changed template is loaded again, and edits that don't change
+    // the layout keep the compiled template.
+    let page = loader.compile("page", Vec::<Symbol>::new()).unwrap();
+    store.rows[1] = StoredTemplate::new("nav", 2, "{{# Links #}}<nav></nav>");
+    assert_eq!(loader.refresh(&store).unwrap(), Vec::<String>::new());
+    assert_eq!(store.loads.get(), 4);
+    assert_eq!(loader.revision("nav"), Some(2));
+    assert!(Rc::ptr_eq(
+        &page,
+        &loader.compile("page", Vec::<Symbol>::new()).unwrap()
+    ));
+
+    store.rows[1] = StoredTemplate::new("nav", 3, "<nav>Home</nav>");
+    store.rows.pop();
+    assert_eq!(loader.refresh(&store).unwrap(), ["footer", "nav", "page"]);
+    assert_eq!(
+        loader.render("page").unwrap(),
+        "<main><nav>Home</nav></main>"
+    );
+    assert!(loader.render("footer").is_err());
+}
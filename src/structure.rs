@@ -0,0 +1,394 @@
+use std::{fmt::Write, ops::Range};
+
+use crate::{
+    directive::{self, Directive},
+    formats::write_json_string,
+    Delimiters, Error, SegmentKind, Template,
+};
+
+/// The kind of a [`StructureNode`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StructureKind {
+    /// The template itself, which is the root of its structure.
+    Template,
+    /// An `if` statement, whose `else` branches are its last children.
+    Condition,
+    /// An `else` or `else if` branch of a condition.
+    Else,
+    /// A `for` or `loop` statement.
+    Loop,
+    /// A function defined by a `function` statement.
+    Function,
+    /// A sub-template defined by a `define` statement.
+    Define,
+    /// A `block`, which templates extending this one may override.
+    Block,
+    /// The template this template extends.
+    Extends,
+    /// A template rendered by an `include` statement.
+    Include,
+    /// A template rendered by a `render` statement.
+    Render,
+    /// A component, whose children are its slot content.
+    Component,
+    /// A placeholder for a client-side component.
+    Island,
+    /// A `fragment` of the output of a render.
+    Fragment,
+    /// Content deferred to a named `emit`.
+    Defer,
+    /// The output of the `defer` statements of a name.
+    Emit,
+    /// A region rendered when a feature is enabled.
+    Feature,
+    /// A region rendered by a fraction of renders.
+    Sampled,
+    /// A region rendered at most once per render.
+    Once,
+}
+
+impl StructureKind {
+    /// Returns the name of this kind, as written in JSON.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            StructureKind::Template => "template",
+            StructureKind::Condition => "condition",
+            StructureKind::Else => "else",
+            StructureKind::Loop => "loop",
+            StructureKind::Function => "function",
+            StructureKind::Define => "define",
+            StructureKind::Block => "block",
+            StructureKind::Extends => "extends",
+            StructureKind::Include => "include",
+            StructureKind::Render => "render",
+            StructureKind::Component => "component",
+            StructureKind::Island => "island",
+            StructureKind::Fragment => "fragment",
+            StructureKind::Defer => "defer",
+            StructureKind::Emit => "emit",
+            StructureKind::Feature => "feature",
+            StructureKind::Sampled => "sampled",
+            StructureKind::Once => "once",
+        }
+    }
+
+    /// Returns the Graphviz shape nodes of this kind are drawn with.
+    const fn shape(self) -> &'static str {
+        match self {
+            StructureKind::Template => "doubleoctagon",
+            StructureKind::Condition | StructureKind::Else => "diamond",
+            StructureKind::Loop => "hexagon",
+            StructureKind::Extends
+            | StructureKind::Include
+            | StructureKind::Render
+            | StructureKind::Island
+            | StructureKind::Emit => "box",
+            StructureKind::Function
+            | StructureKind::Define
+            | StructureKind::Block
+            | StructureKind::Component
+            | StructureKind::Fragment
+            | StructureKind::Defer
+            | StructureKind::Feature
+            | StructureKind::Sampled
+            | StructureKind::Once => "folder",
+        }
+    }
+}
+
+/// A statement that affects the control flow of a template, as returned by
+/// [`Template::structure()`]. Statements that open a block, such as `if`,
+/// contain the statements within the block as their children, while text,
+/// expressions, and other statements are left out.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StructureNode {
+    pub kind: StructureKind,
+    /// The code of the statement, such as `if user.admin`, or an empty
+    /// string for the template itself.
+    pub label: String,
+    /// The byte range of the statement's tag within the template, or of the
+    /// entire template for its root.
+    pub range: Range<usize>,
+    pub children: Vec<StructureNode>,
+}
+
+impl StructureNode {
+    fn new(kind: StructureKind, label: &str, range: Range<usize>) -> Self {
+        Self {
+            kind,
+            label: label.to_string(),
+            range,
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns this node and its descendants as a Graphviz `digraph`, with
+    /// an edge from each node to each of its children.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let dot = Template::from(r#"{{ if admin }}{{ include "tools" }}{{ end }}"#)
+    ///     .structure()
+    ///     .unwrap()
+    ///     .to_dot();
+    /// assert_eq!(
+    ///     dot,
+    ///     "digraph template {\n  \
+    ///        n0 [label=\"template\", shape=doubleoctagon];\n  \
+    ///        n1 [label=\"if admin\", shape=diamond];\n  \
+    ///        n0 -> n1;\n  \
+    ///        n2 [label=\"include \\\"tools\\\"\", shape=box];\n  \
+    ///        n1 -> n2;\n\
+    ///      }\n"
+    /// );
+    /// ```
+    #[must_use]
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph template {\n");
+        self.write_dot(None, &mut 0, &mut dot);
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn write_dot(&self, parent: Option<usize>, next_id: &mut usize, dot: &mut String) {
+        let id = *next_id;
+        *next_id += 1;
+        let label = if self.label.is_empty() {
+            self.kind.as_str()
+        } else {
+            &self.label
+        };
+        dot.push_str("  n");
+        dot.push_str(&id.to_string());
+        dot.push_str(" [label=\"");
+        for ch in label.chars() {
+            match ch {
+                '"' | '\\' => {
+                    dot.push('\\');
+                    dot.push(ch);
+                }
+                '\n' => dot.push_str("\\n"),
+                ch => dot.push(ch),
+            }
+        }
+        dot.push_str("\", shape=");
+        dot.push_str(self.kind.shape());
+        dot.push_str("];\n");
+        if let Some(parent) = parent {
+            writeln!(dot, "  n{parent} -> n{id};").expect("String does not fail to write");
+        }
+        for child in &self.children {
+            child.write_dot(Some(id), next_id, dot);
+        }
+    }
+
+    /// Returns this node and its descendants as a JSON object containing its
+    /// `kind`, `label`, the `start` and `end` byte offsets of its range, and
+    /// its `children`.
+    ///
+    /// ```rust
+    /// use budplate::Template;
+    ///
+    /// let json = Template::from("{{ for item in items }}{{= item }}{{ end }}")
+    ///     .structure()
+    ///     .unwrap()
+    ///     .to_json();
+    /// assert_eq!(
+    ///     json,
+    ///     r#"{"kind":"template","label":"","start":0,"end":43,"children":[{"kind":"loop","label":"for item in items","start":0,"end":23,"children":[]}]}"#
+    /// );
+    /// ```
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json);
+        json
+    }
+
+    fn write_json(&self, json: &mut String) {
+        json.push_str("{\"kind\":");
+        write_json_string(self.kind.as_str(), json);
+        json.push_str(",\"label\":");
+        write_json_string(&self.label, json);
+        write!(
+            json,
+            ",\"start\":{},\"end\":{},\"children\":[",
+            self.range.start, self.range.end
+        )
+        .expect("String does not fail to write");
+        for (index, child) in self.children.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            child.write_json(json);
+        }
+        json.push_str("]}");
+    }
+}
+
+impl Template<'_> {
+    /// Returns the control flow of this template: its conditions, loops,
+    /// includes, blocks, and the other statements that structure it, for
+    /// visualizing complex templates using [`StructureNode::to_dot()`] or
+    /// [`StructureNode::to_json()`].
+    ///
+    /// ```rust
+    /// use budplate::{StructureKind, Template};
+    ///
+    /// let structure = Template::from(
+    ///     r#"{{ extends "layout" }}{{ block body }}{{ if items }}{{ for item in items }}{{ render "row" with item }}{{ end }}{{ else }}Empty{{ end }}{{ end block }}"#,
+    /// )
+    /// .structure()
+    /// .unwrap();
+    /// let [extends, block] = &structure.children[..] else {
+    ///     unreachable!()
+    /// };
+    /// assert_eq!(extends.kind, StructureKind::Extends);
+    /// assert_eq!(block.label, "block body");
+    /// let condition = &block.children[0];
+    /// assert_eq!(
+    ///     condition.children.iter().map(|node| node.kind).collect::<Vec<_>>(),
+    ///     [StructureKind::Loop, StructureKind::Else]
+    /// );
+    /// assert_eq!(condition.children[0].children[0].label, r#"render "row" with item"#);
+    /// ```
+    ///
+    /// An error is returned if the template can't be parsed, or its blocks
+    /// aren't terminated.
+    pub fn structure(&self) -> Result<StructureNode, Error> {
+        let template = self.parse(Delimiters::DEFAULT)?;
+        template.outline()?;
+
+        // The nodes of the open blocks, innermost last, beneath the root.
+        let mut open = vec![StructureNode::new(
+            StructureKind::Template,
+            "",
+            0..template.source.len(),
+        )];
+        let close = |open: &mut Vec<StructureNode>| {
+            if open.len() > 1 {
+                let node = open.pop().expect("more than one node is open");
+                open.last_mut()
+                    .expect("the root is open")
+                    .children
+                    .push(node);
+            }
+        };
+
+        for segment in &template.segments {
+            if !matches!(segment.kind, SegmentKind::Statement(_)) {
+                continue;
+            }
+            let code = template.source[segment.range.clone()].trim();
+            let tag = segment.tag.clone();
+            let (kind, opens) = match template.statement(segment)? {
+                Some(Directive::Define { .. }) => (StructureKind::Define, true),
+                Some(Directive::Block { .. }) => (StructureKind::Block, true),
+                Some(Directive::Component { .. }) => (StructureKind::Component, true),
+                Some(Directive::Fragment { .. }) => (StructureKind::Fragment, true),
+                Some(Directive::Defer { .. }) => (StructureKind::Defer, true),
+                Some(Directive::Feature { .. }) => (StructureKind::Feature, true),
+                Some(Directive::Sampled { .. }) => (StructureKind::Sampled, true),
+                Some(Directive::Once) => (StructureKind::Once, true),
+                Some(Directive::For { .. }) => (StructureKind::Loop, true),
+                Some(Directive::Extends { .. }) => (StructureKind::Extends, false),
+                Some(Directive::Include { .. }) => (StructureKind::Include, false),
+                Some(Directive::Render { .. }) => (StructureKind::Render, false),
+                Some(Directive::Island { .. }) => (StructureKind::Island, false),
+                Some(Directive::Emit { .. }) => (StructureKind::Emit, false),
+                Some(
+                    Directive::EndDefine
+                    | Directive::EndBlock
+                    | Directive::EndComponent
+                    | Directive::EndFragment,
+                ) => {
+                    close(&mut open);
+                    continue;
+                }
+                Some(Directive::Meta { .. }) => continue,
+                None if directive::is_end(code) => {
+                    // An `end` closes both the last branch of a condition
+                    // and the condition itself.
+                    if open
+                        .last()
+                        .is_some_and(|node| node.kind == StructureKind::Else)
+                    {
+                        close(&mut open);
+                    }
+                    close(&mut open);
+                    continue;
+                }
+                None if code == "else" || code.starts_with("else ") => {
+                    match open.last().map(|node| node.kind) {
+                        Some(StructureKind::Else) => close(&mut open),
+                        Some(StructureKind::Condition) => {}
+                        // An `else` outside of a condition is left for Bud
+                        // to report.
+                        _ => continue,
+                    }
+                    (StructureKind::Else, true)
+                }
+                None if directive::opens_block(code) => match directive::split_keyword(code).0 {
+                    "if" => (StructureKind::Condition, true),
+                    "loop" => (StructureKind::Loop, true),
+                    _ => (StructureKind::Function, true),
+                },
+                None => continue,
+            };
+            let node = StructureNode::new(kind, code, tag);
+            if opens {
+                open.push(node);
+            } else {
+                open.last_mut()
+                    .expect("the root is open")
+                    .children
+                    .push(node);
+            }
+        }
+
+        // Blocks opened by Bud statements may be left open for Bud to report.
+        while open.len() > 1 {
+            close(&mut open);
+        }
+        Ok(open.pop().expect("the root is open"))
+    }
+}
+
+#[test]
+fn nested_structure() {
+    let structure = Template::from(
+        "{{ if a }}{{ loop for i in 1..3 }}{{ include \"x\" }}{{ end }}\
+         {{ else if b }}{{ function f() }}{{ end }}\
+         {{ else }}{{ once }}{{ emit \"scripts\" }}{{ end }}{{ end }}\
+         {{ component \"card\" }}{{ feature \"beta\" }}{{ end }}{{ end component }}",
+    )
+    .structure()
+    .unwrap();
+
+    fn kinds(node: &StructureNode) -> String {
+        let mut kinds = String::from(node.kind.as_str());
+        if !node.children.is_empty() {
+            kinds.push('(');
+            for (index, child) in node.children.iter().enumerate() {
+                if index > 0 {
+                    kinds.push(' ');
+                }
+                kinds.push_str(&kinds(child));
+            }
+            kinds.push(')');
+        }
+        kinds
+    }
+
+    assert_eq!(
+        kinds(&structure),
+        "template(condition(loop(include) else(function) else(once(emit))) component(feature))"
+    );
+    assert_eq!(structure.children[0].children[1].label, "else if b");
+    assert!(matches!(
+        Template::from("{{ block body }}").structure(),
+        Err(err) if err.code() == "unterminated_block"
+    ));
+}
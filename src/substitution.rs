@@ -0,0 +1,158 @@
+use budlang::vm::{Symbol, Value};
+
+use crate::{
+    backend::{BackendExpression, BackendRender, BackendSegment, BackendTemplate},
+    directive::is_identifier,
+    value::{key_string, Missing, Shape},
+    ContextEncoding, Error, ErrorKind, Executable, OnMissing, TemplateBackend,
+};
+
+/// Renders templates whose expressions only look up parameters and their
+/// fields, such as `{{= user.name }}`, by writing their text and the values
+/// they look up in turn, without executing any Bud.
+///
+/// Templates containing statements or other expressions are declined, so
+/// they are compiled into the Bud virtual machine instead.
+pub(crate) struct Substitution;
+
+impl TemplateBackend for Substitution {
+    fn compile(
+        &self,
+        template: &BackendTemplate<'_>,
+    ) -> Result<Option<Box<dyn Executable>>, Error> {
+        let mut parts = Vec::with_capacity(template.segments.len());
+        for segment in &template.segments {
+            match segment {
+                BackendSegment::Text(text) => parts.push(Part::Text(text.to_string())),
+                BackendSegment::Expression(expression) => {
+                    match Lookup::parse(expression, template.parameters) {
+                        Some(lookup) => parts.push(Part::Lookup(lookup)),
+                        None => return Ok(None),
+                    }
+                }
+                BackendSegment::Statement(_) => return Ok(None),
+            }
+        }
+        Ok(Some(Box::new(Substituted(parts))))
+    }
+}
+
+struct Substituted(Vec<Part>);
+
+enum Part {
+    Text(String),
+    Lookup(Lookup),
+}
+
+/// An expression that looks up a parameter, followed by a field of each
+/// value in turn.
+struct Lookup {
+    parameter: usize,
+    fields: Vec<String>,
+    code: String,
+    tag: String,
+    safe: bool,
+    context: Option<ContextEncoding>,
+}
+
+impl Lookup {
+    fn parse(expression: &BackendExpression<'_>, parameters: &[Symbol]) -> Option<Self> {
+        let mut path = expression.code.split('.');
+        let name = path.next()?;
+        let parameter = parameters
+            .iter()
+            .position(|parameter| &**parameter == name)?;
+        let fields = path
+            .map(|field| is_identifier(field).then(|| field.to_string()))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            parameter,
+            fields,
+            code: expression.code.to_string(),
+            tag: expression.tag.to_string(),
+            safe: expression.safe,
+            context: expression.context,
+        })
+    }
+
+    /// Returns the value looked up, handling missing fields as `__field()`
+    /// does.
+    fn value(&self, render: &BackendRender<'_>) -> Result<Value, Error> {
+        let mut value = render.arguments()[self.parameter].clone();
+        for field in &self.fields {
+            let on_missing = render.writer.on_missing();
+            value = match Shape::of(&value) {
+                Shape::Map(entries) => match entries
+                    .into_iter()
+                    .find(|(key, _)| key_string(key) == *field)
+                {
+                    Some((_, value)) => value,
+                    None if on_missing == OnMissing::Fail => {
+                        return Err(invalid_field(format!("no field named `{field}`")));
+                    }
+                    None => Value::dynamic(Missing),
+                },
+                _ if on_missing != OnMissing::Fail && Missing::is(&value) => value,
+                _ => {
+                    return Err(invalid_field(format!(
+                        "cannot access `{field}` on a non-map value"
+                    )))
+                }
+            };
+        }
+        Ok(value)
+    }
+}
+
+fn invalid_field(message: String) -> Error {
+    Error::from(ErrorKind::InvalidArgument {
+        function: String::from("__field"),
+        message,
+    })
+}
+
+impl Executable for Substituted {
+    fn render(&self, render: &mut BackendRender<'_>) -> Result<(), Error> {
+        for part in &self.0 {
+            match part {
+                Part::Text(text) => render.write(text)?,
+                Part::Lookup(lookup) => {
+                    let value = lookup.value(render)?;
+                    render.write_expression(
+                        &BackendExpression {
+                            code: &lookup.code,
+                            tag: &lookup.tag,
+                            safe: lookup.safe,
+                            context: lookup.context,
+                        },
+                        &value,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn substituted_templates() {
+    use crate::{Delimiters, Template};
+
+    let parameters = [Symbol::from("user"), Symbol::from("title")];
+    let substituted = |source: &str| {
+        let template = Template::from(source);
+        let parsed = template.parse(Delimiters::DEFAULT).unwrap();
+        Substitution
+            .compile(&BackendTemplate::new(&parsed, None, &parameters))
+            .unwrap()
+            .is_some()
+    };
+    assert!(substituted(
+        "Hi {{= user.name }}, {{=url title }}{{# note #}}"
+    ));
+    assert!(substituted("plain text"));
+    assert!(!substituted("{{= missing }}"));
+    assert!(!substituted("{{= user.name + 1 }}"));
+    assert!(!substituted("{{= upper(title) }}"));
+    assert!(!substituted("{{ if title }}{{= title }}{{ end }}"));
+}
@@ -0,0 +1,94 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+use budlang::vm::Symbol;
+
+/// The most names kept in the symbol table. Once it is full, names that
+/// haven't been interned are allocated each time they are used, so that a
+/// process compiling templates with generated names doesn't grow the table
+/// without bound.
+const MAX_SYMBOLS: usize = 4_096;
+
+/// The symbols shared by every compilation, regardless of its configuration
+/// or template collection, so that identifiers appearing in many templates,
+/// such as parameter and function names, are only allocated once.
+static SYMBOLS: OnceLock<Mutex<HashMap<Box<str>, Symbol>>> = OnceLock::new();
+
+/// Returns the symbol for `name`, creating it if it hasn't been interned
+/// yet.
+pub(crate) fn intern(name: &str) -> Symbol {
+    let mut symbols = SYMBOLS
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(symbol) = symbols.get(name) {
+        return symbol.clone();
+    }
+    let symbol = Symbol::from(name);
+    if symbols.len() < MAX_SYMBOLS {
+        symbols.insert(Box::from(name), symbol.clone());
+    }
+    symbol
+}
+
+/// The name of a template parameter. Parameter names are interned when a
+/// template is compiled, so templates compiled with the same parameter
+/// names share their symbols rather than allocating their own.
+///
+/// Implemented for `&str`, `String`, `&String`, `Cow<str>`, and
+/// [`Symbol`](crate::vm::Symbol), which are interned without allocating
+/// when they have been interned before.
+pub trait ParameterName {
+    /// Returns the interned symbol for this name.
+    fn into_symbol(self) -> Symbol;
+}
+
+impl ParameterName for &str {
+    fn into_symbol(self) -> Symbol {
+        intern(self)
+    }
+}
+
+impl ParameterName for String {
+    fn into_symbol(self) -> Symbol {
+        intern(&self)
+    }
+}
+
+impl ParameterName for &String {
+    fn into_symbol(self) -> Symbol {
+        intern(self)
+    }
+}
+
+impl ParameterName for Cow<'_, str> {
+    fn into_symbol(self) -> Symbol {
+        intern(&self)
+    }
+}
+
+impl ParameterName for Symbol {
+    fn into_symbol(self) -> Symbol {
+        intern(&self)
+    }
+}
+
+impl ParameterName for &Symbol {
+    fn into_symbol(self) -> Symbol {
+        intern(self)
+    }
+}
+
+#[test]
+fn interning() {
+    assert_eq!(intern("name"), "name".into_symbol());
+    assert_eq!(&*intern("other"), "other");
+    let name = intern("name");
+    assert!(std::ptr::eq(
+        name.as_ptr(),
+        String::from("name").into_symbol().as_ptr()
+    ));
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{hash, Configuration, Error, ErrorKind, Template};
+
+/// A collection of named templates that can include one another using
+/// `{{ include "name" }}`.
+#[derive(Debug, Clone, Default)]
+pub struct Templates<'a> {
+    templates: HashMap<String, Template<'a>>,
+}
+
+impl<'a> Templates<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `template` as `name`. Returns an error if another template
+    /// is already registered with the same name.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        template: impl Into<Template<'a>>,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        if self.templates.contains_key(&name) {
+            return Err(Error::from(ErrorKind::DuplicateTemplate(name)));
+        }
+        self.templates.insert(name, template.into());
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Template<'a>> {
+        self.templates.get(name)
+    }
+
+    /// Returns a hash identifying the content of every registered template
+    /// along with its name, such as for keying caches of output rendered
+    /// from the bundle.
+    ///
+    /// The hash is the 64-bit FNV-1a hash of, for each template sorted by
+    /// name, its name, a zero byte, and the little-endian bytes of its
+    /// [`Template::content_hash()`]. This algorithm is stable across
+    /// platforms and releases.
+    pub fn content_hash(&self) -> Result<u64, Error> {
+        let mut bytes = Vec::new();
+        for (name, template) in self.sorted() {
+            let hash = template
+                .content_hash()
+                .map_err(|err| err.in_template(Some(name)))?;
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.push(0);
+            bytes.extend_from_slice(&hash.to_le_bytes());
+        }
+        Ok(hash::fnv1a(bytes))
+    }
+
+    /// Returns the registered templates along with their names, sorted by
+    /// name.
+    pub(crate) fn sorted(&self) -> Vec<(&str, &Template<'a>)> {
+        let mut templates = self
+            .templates
+            .iter()
+            .map(|(name, template)| (name.as_str(), template))
+            .collect::<Vec<_>>();
+        templates.sort_by_key(|(name, _)| *name);
+        templates
+    }
+
+    /// Replaces the source of every registered template with the result of
+    /// `rewrite`. No template is replaced if any rewrite fails.
+    pub(crate) fn rewrite(
+        &mut self,
+        mut rewrite: impl FnMut(&str, &Template<'a>) -> Result<String, Error>,
+    ) -> Result<(), Error> {
+        let rewritten = self
+            .templates
+            .iter()
+            .map(|(name, template)| Ok((name.clone(), rewrite(name, template)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        for (name, source) in rewritten {
+            self.templates.insert(name, Template::from(source));
+        }
+        Ok(())
+    }
+
+    /// Registers the template `old` as `new` instead.
+    pub(crate) fn rename(&mut self, old: &str, new: &str) -> Result<(), Error> {
+        if self.templates.contains_key(new) {
+            return Err(Error::from(ErrorKind::DuplicateTemplate(new.to_string())));
+        }
+        let template = self
+            .templates
+            .remove(old)
+            .ok_or_else(|| ErrorKind::UnknownTemplate(old.to_string()))?;
+        self.templates.insert(new.to_string(), template);
+        Ok(())
+    }
+
+    pub fn render(&self, name: &str) -> Result<String, Error> {
+        self.render_with::<&'static str, Value, _>(name, [])
+    }
+
+    pub fn render_with<Name, Arg, Args>(&self, name: &str, args: Args) -> Result<String, Error>
+    where
+        Args: IntoIterator<Item = (Name, Arg)>,
+        Name: Into<Symbol>,
+        Arg: Into<Value>,
+    {
+        Configuration::default().render_from(self, name, args)
+    }
+}
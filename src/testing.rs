@@ -0,0 +1,1040 @@
+//! Assertions for testing the output of templates.
+//!
+//! String comparisons catch changes in rendered output, but not markup that
+//! is only broken for some arguments, such as an element that is opened
+//! within a conditional and closed outside of it. The checks in this module
+//! parse rendered HTML and report the problems they find: [`check_html()`]
+//! for markup that isn't well formed, [`AccessibilityChecks`] for images,
+//! links, and form controls that can't be used with assistive technology, and
+//! [`LinkChecker`] for links between pages that don't resolve.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+};
+
+use budlang::vm::{Symbol, Value};
+
+use crate::{AnnotatedOutput, Annotation, Configuration, Encoder, Template};
+
+/// A problem found within rendered HTML.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HtmlProblem {
+    /// The byte offset of the problem within the HTML.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for HtmlProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at byte {})", self.message, self.offset)
+    }
+}
+
+/// Checks that every element in `html` is closed and properly nested.
+///
+/// End tags that HTML allows to be omitted, such as those of `<li>` and
+/// `<p>`, are not required.
+///
+/// ```rust
+/// use budplate::testing::check_html;
+///
+/// assert!(check_html("<ul><li>One<li>Two</ul>").is_ok());
+/// let problems = check_html("<div><span></div>").unwrap_err();
+/// assert_eq!(problems[0].message, "`<span>` is not closed before `</div>`");
+/// ```
+pub fn check_html(html: &str) -> Result<(), Vec<HtmlProblem>> {
+    let mut problems = Vec::new();
+    let mut open: Vec<(String, usize)> = Vec::new();
+    for token in tokenize(html) {
+        match token {
+            Token::Start {
+                name,
+                offset,
+                self_closing,
+                ..
+            } => {
+                // Starting some elements ends the current one implicitly.
+                while open
+                    .last()
+                    .is_some_and(|(current, _)| closed_by_start(current, &name))
+                {
+                    open.pop();
+                }
+                if NOT_NESTABLE.contains(&name.as_str())
+                    && open.iter().any(|(existing, _)| existing == &name)
+                {
+                    problems.push(HtmlProblem {
+                        offset,
+                        message: format!("`<{name}>` cannot be nested within another `<{name}>`"),
+                    });
+                }
+                if !self_closing && !VOID_ELEMENTS.contains(&name.as_str()) {
+                    open.push((name, offset));
+                }
+            }
+            Token::End { name, offset } => {
+                let Some(index) = open.iter().rposition(|(existing, _)| existing == &name) else {
+                    problems.push(HtmlProblem {
+                        offset,
+                        message: if VOID_ELEMENTS.contains(&name.as_str()) {
+                            format!("`<{name}>` cannot have an end tag")
+                        } else {
+                            format!("`</{name}>` does not close an open element")
+                        },
+                    });
+                    continue;
+                };
+                for (unclosed, unclosed_offset) in open.drain(index + 1..) {
+                    if !OPTIONAL_END_TAGS.contains(&unclosed.as_str()) {
+                        problems.push(HtmlProblem {
+                            offset: unclosed_offset,
+                            message: format!("`<{unclosed}>` is not closed before `</{name}>`"),
+                        });
+                    }
+                }
+                open.pop();
+            }
+            Token::Text { .. } => {}
+        }
+    }
+
+    for (unclosed, offset) in open {
+        if !OPTIONAL_END_TAGS.contains(&unclosed.as_str()) {
+            problems.push(HtmlProblem {
+                offset,
+                message: format!("`<{unclosed}>` is never closed"),
+            });
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Panics with a description of each problem if `html` isn't well formed,
+/// as checked by [`check_html()`].
+#[track_caller]
+pub fn assert_valid_html(html: &str) {
+    if let Err(problems) = check_html(html) {
+        panic!("{}", report("invalid HTML", &problems, html));
+    }
+}
+
+/// Checks rendered HTML for common accessibility problems. Each check is
+/// enabled by default and can be disabled individually.
+///
+/// ```rust
+/// use budplate::testing::AccessibilityChecks;
+///
+/// let checks = AccessibilityChecks::default();
+/// assert!(checks.check(r#"<a href="/"><img src="home.png" alt="Home"></a>"#).is_ok());
+/// assert!(checks.check(r#"<img src="home.png">"#).is_err());
+/// assert!(checks.without_alt_text().check(r#"<img src="home.png">"#).is_ok());
+/// ```
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[must_use]
+pub struct AccessibilityChecks {
+    alt_text: bool,
+    accessible_names: bool,
+    form_labels: bool,
+}
+
+impl Default for AccessibilityChecks {
+    fn default() -> Self {
+        Self {
+            alt_text: true,
+            accessible_names: true,
+            form_labels: true,
+        }
+    }
+}
+
+impl AccessibilityChecks {
+    /// Disables checking that images have an `alt` attribute. An empty `alt`
+    /// attribute, which marks an image as decorative, is always allowed.
+    pub const fn without_alt_text(mut self) -> Self {
+        self.alt_text = false;
+        self
+    }
+
+    /// Disables checking that links and buttons have text or a label.
+    pub const fn without_accessible_names(mut self) -> Self {
+        self.accessible_names = false;
+        self
+    }
+
+    /// Disables checking that form controls have a label.
+    pub const fn without_form_labels(mut self) -> Self {
+        self.form_labels = false;
+        self
+    }
+
+    pub fn check(&self, html: &str) -> Result<(), Vec<HtmlProblem>> {
+        let tokens = tokenize(html);
+        let labelled = tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Start {
+                    name, attributes, ..
+                } if name == "label" => attribute(attributes, "for"),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut problems = Vec::new();
+        // Links and buttons that are open, and whether they have a name.
+        let mut named: Vec<(&str, usize, bool)> = Vec::new();
+        let mut label_depth = 0_usize;
+        for token in &tokens {
+            match token {
+                Token::Start {
+                    name,
+                    attributes,
+                    offset,
+                    self_closing,
+                } => {
+                    let alt = attribute(attributes, "alt");
+                    if self.alt_text && matches!(name.as_str(), "img" | "area") && alt.is_none() {
+                        problems.push(HtmlProblem {
+                            offset: *offset,
+                            message: format!("`<{name}>` is missing an `alt` attribute"),
+                        });
+                    }
+
+                    let has_label =
+                        ["aria-label", "aria-labelledby", "title"]
+                            .iter()
+                            .any(|label| {
+                                attribute(attributes, label).is_some_and(|l| !l.trim().is_empty())
+                            });
+                    if has_label || alt.is_some_and(|alt| !alt.trim().is_empty()) {
+                        for open in &mut named {
+                            open.2 = true;
+                        }
+                    }
+                    if matches!(name.as_str(), "a" | "button") && !self_closing {
+                        named.push((name, *offset, has_label));
+                    }
+
+                    if name == "label" && !self_closing {
+                        label_depth += 1;
+                    }
+                    if self.form_labels
+                        && label_depth == 0
+                        && !has_label
+                        && needs_label(name, attributes)
+                        && !attribute(attributes, "id").is_some_and(|id| labelled.contains(&id))
+                    {
+                        problems.push(HtmlProblem {
+                            offset: *offset,
+                            message: format!("`<{name}>` does not have a label"),
+                        });
+                    }
+                }
+                Token::End { name, .. } => {
+                    if name == "label" {
+                        label_depth = label_depth.saturating_sub(1);
+                    }
+                    if let Some(index) = named.iter().rposition(|open| open.0 == name) {
+                        let (name, offset, has_name) = named.remove(index);
+                        if self.accessible_names && !has_name {
+                            problems.push(HtmlProblem {
+                                offset,
+                                message: format!("`<{name}>` has no text or label"),
+                            });
+                        }
+                    }
+                }
+                Token::Text { text, .. } => {
+                    if !text.trim().is_empty() {
+                        for open in &mut named {
+                            open.2 = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Panics with a description of each problem found by
+    /// [`check()`](Self::check).
+    #[track_caller]
+    pub fn assert(&self, html: &str) {
+        if let Err(problems) = self.check(html) {
+            panic!("{}", report("inaccessible HTML", &problems, html));
+        }
+    }
+}
+
+/// Panics if `html` fails any of the [`AccessibilityChecks`].
+#[track_caller]
+pub fn assert_accessible(html: &str) {
+    AccessibilityChecks::default().assert(html);
+}
+
+fn attribute<'a>(attributes: &'a [(String, Option<String>)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(attribute, _)| attribute == name)
+        .map(|(_, value)| value.as_deref().unwrap_or(""))
+}
+
+/// Returns true if the form control `name` must be labelled.
+fn needs_label(name: &str, attributes: &[(String, Option<String>)]) -> bool {
+    match name {
+        "select" | "textarea" => true,
+        "input" => !matches!(
+            attribute(attributes, "type")
+                .map(str::to_ascii_lowercase)
+                .as_deref(),
+            Some("hidden" | "submit" | "reset" | "button" | "image")
+        ),
+        _ => false,
+    }
+}
+
+fn report(summary: &str, problems: &[HtmlProblem], html: &str) -> String {
+    let mut report = format!("{summary}:");
+    for problem in problems {
+        report.push_str("\n  ");
+        report.push_str(&problem.to_string());
+    }
+    report.push_str("\nin:\n");
+    report.push_str(html);
+    report
+}
+
+/// Checks that the internal links and anchors of a set of rendered pages,
+/// such as the output of a static site build, resolve to one of the pages.
+///
+/// Pages are added with the path they are served from. A path ending in
+/// `index.html` is also served from its directory, and a link to a path
+/// without a trailing `/` also resolves to its directory's index. Links with
+/// a scheme, such as `https:` or `mailto:`, and protocol-relative links are
+/// not checked. Only the `href` attributes of `<a>` and `<area>` elements are
+/// checked, so links to assets from other elements are not reported.
+///
+/// Pages rendered using
+/// [`CompiledTemplate::render_annotated()`](crate::CompiledTemplate::render_annotated)
+/// can be added with [`add_annotated_page()`](Self::add_annotated_page), so
+/// that each broken link is reported with the template tag that produced it.
+///
+/// ```rust
+/// use budplate::testing::LinkChecker;
+///
+/// let mut links = LinkChecker::new();
+/// links.add_page("/index.html", r#"<a href="about">About</a> <a href="/blog/#latest">Blog</a>"#);
+/// links.add_page("/about/index.html", r#"<h1 id="team">Team</h1><a href="../#top">Home</a>"#);
+/// let broken = links.check().unwrap_err();
+/// assert_eq!(
+///     broken.iter().map(|link| link.link.as_str()).collect::<Vec<_>>(),
+///     ["/blog/#latest", "../#top"]
+/// );
+/// assert_eq!(broken[1].message, "`/` has no element with the id `top`");
+/// ```
+#[derive(Debug, Default)]
+#[must_use]
+pub struct LinkChecker {
+    pages: Vec<Page>,
+}
+
+#[derive(Debug)]
+struct Page {
+    path: String,
+    html: String,
+    annotations: Vec<Annotation>,
+}
+
+/// A link found by a [`LinkChecker`] that doesn't resolve.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BrokenLink {
+    /// The path of the page containing the link.
+    pub page: String,
+    /// The byte offset of the link within the page.
+    pub offset: usize,
+    pub link: String,
+    pub message: String,
+    /// The annotation of the output containing the link, if the page was
+    /// added with [`LinkChecker::add_annotated_page()`].
+    pub annotation: Option<Annotation>,
+}
+
+impl Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (at byte {} of {}",
+            self.message, self.offset, self.page
+        )?;
+        if let Some(annotation) = &self.annotation {
+            write!(
+                f,
+                ", from bytes {}..{} of {}",
+                annotation.source.start,
+                annotation.source.end,
+                annotation.template.as_deref().unwrap_or("its template")
+            )?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl LinkChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the page served from `path` whose contents are `html`.
+    pub fn add_page(&mut self, path: impl Into<String>, html: impl Into<String>) {
+        self.pages.push(Page {
+            path: path.into(),
+            html: html.into(),
+            annotations: Vec::new(),
+        });
+    }
+
+    /// Adds the page served from `path` that was rendered as `output`.
+    /// Annotations without a template refer to the template rendered for
+    /// the page.
+    pub fn add_annotated_page(&mut self, path: impl Into<String>, output: AnnotatedOutput) {
+        self.pages.push(Page {
+            path: path.into(),
+            html: output.output,
+            annotations: output.annotations,
+        });
+    }
+
+    pub fn check(&self) -> Result<(), Vec<BrokenLink>> {
+        let mut anchors = HashMap::new();
+        for page in &self.pages {
+            let ids = anchors
+                .entry(normalize_page(&page.path))
+                .or_insert_with(HashSet::new);
+            for token in tokenize(&page.html) {
+                if let Token::Start {
+                    name, attributes, ..
+                } = token
+                {
+                    for (attribute, value) in attributes {
+                        if attribute == "id" || (attribute == "name" && name == "a") {
+                            ids.extend(value);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut broken = Vec::new();
+        for page in &self.pages {
+            for token in tokenize(&page.html) {
+                let Token::Start {
+                    name,
+                    attributes,
+                    offset,
+                    ..
+                } = token
+                else {
+                    continue;
+                };
+                let Some(link) = (matches!(name.as_str(), "a" | "area"))
+                    .then(|| attribute(&attributes, "href"))
+                    .flatten()
+                else {
+                    continue;
+                };
+                let Some(message) = unresolved(&page.path, link, &anchors) else {
+                    continue;
+                };
+                let offset = offset + page.html[offset..].find(link).unwrap_or_default();
+                broken.push(BrokenLink {
+                    page: page.path.clone(),
+                    offset,
+                    link: link.to_string(),
+                    message,
+                    annotation: AnnotatedOutput::annotation_at(&page.annotations, offset).cloned(),
+                });
+            }
+        }
+
+        if broken.is_empty() {
+            Ok(())
+        } else {
+            Err(broken)
+        }
+    }
+
+    /// Panics with a description of each broken link found by
+    /// [`check()`](Self::check).
+    #[track_caller]
+    pub fn assert(&self) {
+        if let Err(broken) = self.check() {
+            let mut report = format!("{} links are broken:", broken.len());
+            for link in &broken {
+                report.push_str("\n  ");
+                report.push_str(&link.to_string());
+            }
+            panic!("{report}");
+        }
+    }
+}
+
+/// Returns why `link` on the page served from `page` doesn't resolve to one
+/// of the pages in `anchors`, or `None` if it does.
+fn unresolved(
+    page: &str,
+    link: &str,
+    anchors: &HashMap<String, HashSet<String>>,
+) -> Option<String> {
+    let (path, fragment) = link.split_once('#').unwrap_or((link, ""));
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let scheme = path
+        .find([':', '/'])
+        .is_some_and(|end| path[end..].starts_with(':'));
+    if scheme || path.starts_with("//") {
+        return None;
+    }
+
+    let target = if path.is_empty() {
+        normalize_page(page)
+    } else if path.starts_with('/') {
+        normalize_page(path)
+    } else {
+        let directory = &page[..page.rfind('/').map_or(0, |end| end + 1)];
+        normalize_page(&format!("{directory}{path}"))
+    };
+    let (target, ids) = match anchors.get(&target) {
+        Some(ids) => (target, ids),
+        None => {
+            let directory = format!("{target}/");
+            let Some(ids) = anchors.get(&directory) else {
+                return Some(format!("`{target}` is not a page"));
+            };
+            (directory, ids)
+        }
+    };
+    (!fragment.is_empty() && !ids.contains(fragment))
+        .then(|| format!("`{target}` has no element with the id `{fragment}`"))
+}
+
+/// Returns `path` with a leading `/`, without `.` and `..` segments, and with
+/// a trailing `index.html` removed.
+fn normalize_page(path: &str) -> String {
+    let mut segments = Vec::new();
+    let directory = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    if !directory && segments.last() == Some(&"index.html") {
+        segments.pop();
+    } else if !directory {
+        return format!("/{}", segments.join("/"));
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    if !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// A context for which two versions of a template rendered differently.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RenderDifference {
+    /// The index of the context within the corpus.
+    pub index: usize,
+    /// The output of the old template, or a description of its error.
+    pub old: Result<String, String>,
+    /// The output of the new template, or a description of its error.
+    pub new: Result<String, String>,
+}
+
+impl RenderDifference {
+    /// Returns a line-by-line comparison of the two outputs. Lines only in
+    /// the old output begin with `-`, and lines only in the new output begin
+    /// with `+`.
+    pub fn diff(&self) -> String {
+        let describe = |outcome: &Result<String, String>| match outcome {
+            Ok(output) => output.clone(),
+            Err(err) => format!("<error: {err}>"),
+        };
+        diff_lines(&describe(&self.old), &describe(&self.new))
+    }
+}
+
+impl Display for RenderDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "context {}:\n{}", self.index, self.diff())
+    }
+}
+
+/// Renders `old` and `new` with each set of arguments in `contexts`,
+/// returning the contexts for which the output differs.
+///
+/// ```rust
+/// use budplate::{testing, vm::Value, Configuration, Template};
+///
+/// let differences = testing::compare_renders(
+///     &Configuration::for_html(),
+///     &Template::from("{{ if admin }}<b>Admin</b>{{ end }}"),
+///     &Template::from("{{ if admin }}<strong>Admin</strong>{{ end }}"),
+///     [[("admin", Value::from(false))], [("admin", Value::from(true))]],
+/// );
+/// assert_eq!(differences.len(), 1);
+/// assert_eq!(differences[0].index, 1);
+/// ```
+pub fn compare_renders<Enc, Name, Arg, Args>(
+    configuration: &Configuration<Enc>,
+    old: &Template<'_>,
+    new: &Template<'_>,
+    contexts: impl IntoIterator<Item = Args>,
+) -> Vec<RenderDifference>
+where
+    Enc: Encoder,
+    Args: IntoIterator<Item = (Name, Arg)>,
+    Name: Into<Symbol>,
+    Arg: Into<Value>,
+{
+    let render = |template: &Template<'_>, arguments: &[(Symbol, Value)]| {
+        configuration
+            .compile(template, arguments.iter().map(|(name, _)| name.clone()))
+            .and_then(|compiled| compiled.render_with(arguments.iter().cloned()))
+            .map_err(|err| err.to_string())
+    };
+
+    contexts
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, arguments)| {
+            let arguments = arguments
+                .into_iter()
+                .map(|(name, arg)| (name.into(), arg.into()))
+                .collect::<Vec<_>>();
+            let old = render(old, &arguments);
+            let new = render(new, &arguments);
+            (old != new).then_some(RenderDifference { index, old, new })
+        })
+        .collect()
+}
+
+/// Panics with a diff of each context for which `old` and `new` render
+/// differently, as compared by [`compare_renders()`].
+#[track_caller]
+pub fn assert_renders_match<Enc, Name, Arg, Args>(
+    configuration: &Configuration<Enc>,
+    old: &Template<'_>,
+    new: &Template<'_>,
+    contexts: impl IntoIterator<Item = Args>,
+) where
+    Enc: Encoder,
+    Args: IntoIterator<Item = (Name, Arg)>,
+    Name: Into<Symbol>,
+    Arg: Into<Value>,
+{
+    let differences = compare_renders(configuration, old, new, contexts);
+    if !differences.is_empty() {
+        let mut report = format!("{} contexts rendered differently:", differences.len());
+        for difference in &differences {
+            report.push('\n');
+            report.push_str(&difference.to_string());
+        }
+        panic!("{report}");
+    }
+}
+
+/// Compares `old` and `new` line by line using their longest common
+/// subsequence.
+fn diff_lines(old: &str, new: &str) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let mut common = vec![vec![0_usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        let (prefix, line) = if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+            (' ', old[i - 1])
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            j += 1;
+            ('+', new[j - 1])
+        } else {
+            i += 1;
+            ('-', old[i - 1])
+        };
+        diff.push(prefix);
+        diff.push(' ');
+        diff.push_str(line);
+        diff.push('\n');
+    }
+    diff
+}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements whose end tags may be omitted.
+const OPTIONAL_END_TAGS: &[&str] = &[
+    "body", "colgroup", "dd", "dt", "head", "html", "li", "optgroup", "option", "p", "rp", "rt",
+    "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+
+/// Elements that may not contain another element of the same kind.
+const NOT_NESTABLE: &[&str] = &["a", "button", "form", "label"];
+
+/// Elements whose contents are text rather than markup.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+/// Returns true if starting a `started` element implicitly closes an open
+/// `current` element.
+fn closed_by_start(current: &str, started: &str) -> bool {
+    match current {
+        "p" => matches!(
+            started,
+            "address"
+                | "article"
+                | "aside"
+                | "blockquote"
+                | "details"
+                | "div"
+                | "dl"
+                | "fieldset"
+                | "figure"
+                | "footer"
+                | "form"
+                | "h1"
+                | "h2"
+                | "h3"
+                | "h4"
+                | "h5"
+                | "h6"
+                | "header"
+                | "hr"
+                | "main"
+                | "nav"
+                | "ol"
+                | "p"
+                | "pre"
+                | "section"
+                | "table"
+                | "ul"
+        ),
+        "li" => started == "li",
+        "dt" | "dd" => matches!(started, "dt" | "dd"),
+        "option" => matches!(started, "option" | "optgroup"),
+        "tr" => started == "tr",
+        "td" | "th" => matches!(started, "td" | "th" | "tr"),
+        "thead" | "tbody" => matches!(started, "tbody" | "tfoot"),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Token {
+    Start {
+        name: String,
+        attributes: Vec<(String, Option<String>)>,
+        self_closing: bool,
+        offset: usize,
+    },
+    End {
+        name: String,
+        offset: usize,
+    },
+    Text {
+        text: String,
+        offset: usize,
+    },
+}
+
+/// Splits `html` into tags and text. Comments, doctypes, and processing
+/// instructions are skipped.
+pub(crate) fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < html.len() {
+        let Some(tag_start) = html[position..].find('<').map(|index| index + position) else {
+            push_text(&mut tokens, html, position..html.len());
+            break;
+        };
+        push_text(&mut tokens, html, position..tag_start);
+
+        let rest = &html[tag_start..];
+        if rest.starts_with("<!--") {
+            position = html[tag_start + 4..]
+                .find("-->")
+                .map_or(html.len(), |end| tag_start + 4 + end + 3);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            position = rest.find('>').map_or(html.len(), |end| tag_start + end + 1);
+        } else if let Some(end_tag) = rest.strip_prefix("</") {
+            let name_len = name_length(end_tag);
+            if name_len == 0 {
+                push_text(&mut tokens, html, tag_start..tag_start + 2);
+                position = tag_start + 2;
+                continue;
+            }
+            tokens.push(Token::End {
+                name: end_tag[..name_len].to_ascii_lowercase(),
+                offset: tag_start,
+            });
+            position = rest.find('>').map_or(html.len(), |end| tag_start + end + 1);
+        } else {
+            let name_len = name_length(&rest[1..]);
+            if name_len == 0 {
+                // A `<` that doesn't begin a tag is text.
+                push_text(&mut tokens, html, tag_start..tag_start + 1);
+                position = tag_start + 1;
+                continue;
+            }
+            let name = rest[1..=name_len].to_ascii_lowercase();
+            let (attributes, self_closing, length) = attributes(&rest[1 + name_len..]);
+            position = tag_start + 1 + name_len + length;
+
+            if RAW_TEXT_ELEMENTS.contains(&name.as_str()) && !self_closing {
+                let closing = format!("</{name}");
+                let end = find_ignoring_case(&html[position..], &closing)
+                    .map_or(html.len(), |end| position + end);
+                tokens.push(Token::Start {
+                    name,
+                    attributes,
+                    self_closing,
+                    offset: tag_start,
+                });
+                push_text(&mut tokens, html, position..end);
+                position = end;
+            } else {
+                tokens.push(Token::Start {
+                    name,
+                    attributes,
+                    self_closing,
+                    offset: tag_start,
+                });
+            }
+        }
+    }
+    tokens
+}
+
+fn push_text(tokens: &mut Vec<Token>, html: &str, range: std::ops::Range<usize>) {
+    if range.is_empty() {
+        return;
+    }
+    if let Some(Token::Text { text, .. }) = tokens.last_mut() {
+        text.push_str(&html[range]);
+    } else {
+        tokens.push(Token::Text {
+            text: html[range.clone()].to_string(),
+            offset: range.start,
+        });
+    }
+}
+
+fn name_length(source: &str) -> usize {
+    if !source.starts_with(|ch: char| ch.is_ascii_alphabetic()) {
+        return 0;
+    }
+    source
+        .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '-' || ch == ':'))
+        .unwrap_or(source.len())
+}
+
+fn find_ignoring_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Parses the attributes following a tag's name, returning them along with
+/// whether the tag is self-closing and the length of the source consumed,
+/// including the closing `>`.
+fn attributes(source: &str) -> (Vec<(String, Option<String>)>, bool, usize) {
+    let mut attributes = Vec::new();
+    let mut position = 0;
+    loop {
+        position += whitespace_length(&source[position..]);
+        let rest = &source[position..];
+        if rest.is_empty() {
+            return (attributes, false, source.len());
+        } else if rest.starts_with("/>") {
+            return (attributes, true, position + 2);
+        } else if rest.starts_with('>') {
+            return (attributes, false, position + 1);
+        } else if rest.starts_with('/') {
+            position += 1;
+            continue;
+        }
+
+        let name_end = rest
+            .find(|ch: char| ch.is_whitespace() || matches!(ch, '=' | '>' | '/'))
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        position += name_end;
+
+        let after_name = position + whitespace_length(&source[position..]);
+        let value = if source[after_name..].starts_with('=') {
+            let start = after_name + 1 + whitespace_length(&source[after_name + 1..]);
+            match source[start..].chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let end = source[start + 1..]
+                        .find(quote)
+                        .map_or(source.len(), |end| start + 1 + end);
+                    position = (end + 1).min(source.len());
+                    Some(source[start + 1..end].to_string())
+                }
+                _ => {
+                    let end = source[start..]
+                        .find(|ch: char| ch.is_whitespace() || ch == '>')
+                        .map_or(source.len(), |end| start + end);
+                    position = end;
+                    Some(source[start..end].to_string())
+                }
+            }
+        } else {
+            None
+        };
+        attributes.push((name, value));
+    }
+}
+
+fn whitespace_length(source: &str) -> usize {
+    source.len() - source.trim_start().len()
+}
+
+#[test]
+fn html_validity() {
+    assert!(check_html(
+        "<!DOCTYPE html><html><head><title>a < b</title></head>\
+         <body><p>One<p>Two<br><img src=\"a.png\" alt='x'/>\
+         <!-- <div> --><script>if (a < b) { document.write('</p>') }</script>\
+         <table><tr><td>1<td>2<tr><td>3</table></body></html>"
+    )
+    .is_ok());
+
+    assert_eq!(
+        check_html("<div><span>").unwrap_err(),
+        vec![
+            HtmlProblem {
+                offset: 0,
+                message: String::from("`<div>` is never closed")
+            },
+            HtmlProblem {
+                offset: 5,
+                message: String::from("`<span>` is never closed")
+            },
+        ]
+    );
+    assert_eq!(
+        check_html("<p><div></div></p>").unwrap_err()[0].message,
+        "`</p>` does not close an open element"
+    );
+    assert_eq!(
+        check_html("<a href=\"/\"><a>x</a></a>").unwrap_err()[0].message,
+        "`<a>` cannot be nested within another `<a>`"
+    );
+    assert_eq!(
+        check_html("<br></br>").unwrap_err()[0].message,
+        "`<br>` cannot have an end tag"
+    );
+}
+
+#[test]
+fn accessibility() {
+    let checks = AccessibilityChecks::default();
+    assert!(checks
+        .check(
+            r#"<img src="a.png" alt=""><a href="/">Home</a><button aria-label="Close"></button>
+            <label for="name">Name</label><input id="name"><label>Bio <textarea></textarea></label>
+            <input type="hidden" name="token"><input type="submit">"#
+        )
+        .is_ok());
+
+    let messages = |html| {
+        checks
+            .check(html)
+            .unwrap_err()
+            .into_iter()
+            .map(|problem| problem.message)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        messages(r#"<a href="/"> <img src="x.png"> </a><button></button><select></select>"#),
+        [
+            "`<img>` is missing an `alt` attribute",
+            "`<a>` has no text or label",
+            "`<button>` has no text or label",
+            "`<select>` does not have a label",
+        ]
+    );
+    assert!(checks
+        .without_accessible_names()
+        .without_form_labels()
+        .check("<button></button><input>")
+        .is_ok());
+}
+
+#[test]
+fn line_diffs() {
+    assert_eq!(diff_lines("a\nb\nc", "a\nc\nd"), "  a\n- b\n  c\n+ d\n");
+    assert_eq!(diff_lines("", "x"), "+ x\n");
+}
+
+#[test]
+fn link_checks() {
+    assert_eq!(normalize_page("index.html"), "/");
+    assert_eq!(normalize_page("/blog/./a/../post.html"), "/blog/post.html");
+    assert_eq!(normalize_page("/blog/index.html"), "/blog/");
+    assert_eq!(normalize_page("/blog/.."), "/");
+
+    let mut links = LinkChecker::new();
+    links.add_page(
+        "/index.html",
+        "<a href=\"/blog/post.html#intro\">1</a><a href='blog'>2</a>\
+         <a href=\"https://example.com/missing\">3</a><a href=\"mailto:a@example.com\">4</a>\
+         <a href=\"//cdn.example.com/x\">5</a><a href=\"#\">6</a><link href=\"/site.css\">\
+         <a href=\"?page=2\">7</a>",
+    );
+    links.add_page("/blog/index.html", "<a href=\"post.html#outro\">Post</a>");
+    links.add_page(
+        "/blog/post.html",
+        "<p id=intro></p><a name=\"outro\"></a><area href=\"../drafts/\">",
+    );
+    let broken = links.check().unwrap_err();
+    assert_eq!(
+        broken
+            .iter()
+            .map(|link| (link.page.as_str(), link.message.as_str()))
+            .collect::<Vec<_>>(),
+        [("/blog/post.html", "`/drafts/` is not a page")]
+    );
+    assert_eq!(broken[0].offset, 48);
+    assert_eq!(broken[0].annotation, None);
+}
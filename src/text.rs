@@ -0,0 +1,221 @@
+//! Helpers for laying out plain text, such as the text part of an email.
+
+use std::fmt::Write;
+
+/// Wraps each paragraph of `text` so that lines are no longer than `width`
+/// characters. Words longer than `width`, such as URLs, are never split.
+pub(crate) fn wrap(text: &str, width: usize) -> String {
+    let mut output = String::with_capacity(text.len());
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            output.push('\n');
+        }
+
+        let mut line_length = 0;
+        for word in line.split_whitespace() {
+            let word_length = word.chars().count();
+            if line_length > 0 {
+                if line_length + 1 + word_length > width {
+                    output.push('\n');
+                    line_length = 0;
+                } else {
+                    output.push(' ');
+                    line_length += 1;
+                }
+            }
+            output.push_str(word);
+            line_length += word_length;
+        }
+    }
+    output
+}
+
+/// Centers each line of `text` within `width` characters.
+pub(crate) fn center(text: &str, width: usize) -> String {
+    text.split('\n')
+        .map(|line| {
+            let line = line.trim();
+            let padding = width.saturating_sub(line.chars().count()) / 2;
+            format!("{:padding$}{line}", "")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Prefixes each line of `text` with `prefix`, as done when quoting a reply.
+/// Lines that are already quoted are nested without adding a space.
+pub(crate) fn quote(text: &str, prefix: &str) -> String {
+    let marker = prefix.trim_end();
+    text.split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                marker.to_string()
+            } else if !marker.is_empty() && line.starts_with(marker) {
+                format!("{marker}{line}")
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Formats `text` as a signature block, preceded by the standard `-- `
+/// delimiter line that mail clients recognize.
+pub(crate) fn signature(text: &str) -> String {
+    format!("-- \n{}", text.trim_end())
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum TableStyle {
+    Ascii,
+    Unicode,
+}
+
+impl TableStyle {
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "ascii" => Some(Self::Ascii),
+            "unicode" => Some(Self::Unicode),
+            _ => None,
+        }
+    }
+
+    /// Returns the characters for the left, middle, and right junctions of a
+    /// horizontal rule, followed by the horizontal line character.
+    const fn rule(self, position: RulePosition) -> [char; 4] {
+        match (self, position) {
+            (TableStyle::Ascii, _) => ['+', '+', '+', '-'],
+            (TableStyle::Unicode, RulePosition::Top) => ['┌', '┬', '┐', '─'],
+            (TableStyle::Unicode, RulePosition::Middle) => ['├', '┼', '┤', '─'],
+            (TableStyle::Unicode, RulePosition::Bottom) => ['└', '┴', '┘', '─'],
+        }
+    }
+
+    const fn vertical(self) -> char {
+        match self {
+            TableStyle::Ascii => '|',
+            TableStyle::Unicode => '│',
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum RulePosition {
+    Top,
+    Middle,
+    Bottom,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Cell {
+    pub text: String,
+    pub align_right: bool,
+}
+
+impl Cell {
+    pub fn new(text: impl Into<String>, align_right: bool) -> Self {
+        Self {
+            text: text.into().replace('\n', " "),
+            align_right,
+        }
+    }
+}
+
+/// Renders `rows` as a table with a header row, sizing each column to fit its
+/// widest cell.
+pub(crate) fn table(headers: &[String], rows: &[Vec<Cell>], style: TableStyle) -> String {
+    let mut widths = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.text.chars().count());
+        }
+    }
+
+    let mut output = String::new();
+    push_rule(&mut output, &widths, style.rule(RulePosition::Top));
+    let headers = headers
+        .iter()
+        .map(|header| Cell::new(header.clone(), false))
+        .collect::<Vec<_>>();
+    push_row(&mut output, &widths, &headers, style.vertical());
+    push_rule(&mut output, &widths, style.rule(RulePosition::Middle));
+    for row in rows {
+        push_row(&mut output, &widths, row, style.vertical());
+    }
+    push_rule(&mut output, &widths, style.rule(RulePosition::Bottom));
+    output.pop();
+    output
+}
+
+fn push_rule(output: &mut String, widths: &[usize], [left, middle, right, line]: [char; 4]) {
+    output.push(left);
+    for (index, width) in widths.iter().enumerate() {
+        if index > 0 {
+            output.push(middle);
+        }
+        output.push_str(&line.to_string().repeat(width + 2));
+    }
+    output.push(right);
+    output.push('\n');
+}
+
+fn push_row(output: &mut String, widths: &[usize], cells: &[Cell], vertical: char) {
+    output.push(vertical);
+    for (index, width) in widths.iter().copied().enumerate() {
+        let (text, align_right) = cells
+            .get(index)
+            .map_or(("", false), |cell| (cell.text.as_str(), cell.align_right));
+        if align_right {
+            write!(output, " {text:>width$} {vertical}")
+        } else {
+            write!(output, " {text:<width$} {vertical}")
+        }
+        .expect("String does not fail to write");
+    }
+    output.push('\n');
+}
+
+#[test]
+fn tables() {
+    let headers = [String::from("Item"), String::from("Qty")];
+    let rows = [
+        vec![Cell::new("Tea", false), Cell::new("2", true)],
+        vec![Cell::new("Scones", false), Cell::new("12", true)],
+    ];
+    assert_eq!(
+        table(&headers, &rows, TableStyle::Ascii),
+        "+--------+-----+\n\
+         | Item   | Qty |\n\
+         +--------+-----+\n\
+         | Tea    |   2 |\n\
+         | Scones |  12 |\n\
+         +--------+-----+"
+    );
+    assert_eq!(
+        table(&headers[..1], &rows[..1], TableStyle::Unicode),
+        "┌──────┐\n│ Item │\n├──────┤\n│ Tea  │\n└──────┘"
+    );
+}
+
+#[test]
+fn wrapping() {
+    assert_eq!(
+        wrap("The quick brown fox jumps over the lazy dog", 10),
+        "The quick\nbrown fox\njumps over\nthe lazy\ndog"
+    );
+    assert_eq!(
+        wrap("see https://example.com/a/long/path\n\nthanks", 10),
+        "see\nhttps://example.com/a/long/path\n\nthanks"
+    );
+}
+
+#[test]
+fn layout() {
+    assert_eq!(center("Receipt\nOrder #5", 12), "  Receipt\n  Order #5");
+    assert_eq!(quote("hi\n\n> earlier", "> "), "> hi\n>\n>> earlier");
+    assert_eq!(signature("Budplate\n"), "-- \nBudplate");
+}
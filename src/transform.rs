@@ -0,0 +1,38 @@
+use budlang::vm::Symbol;
+
+use crate::{AssetManifest, Error};
+
+/// Rewrites the output of each render of templates compiled with
+/// [`Configuration::with_output_transform`](crate::Configuration::with_output_transform),
+/// such as to minify it, inline critical CSS, or point asset URLs at a CDN.
+///
+/// Output is collected before it is transformed, so templates with
+/// transforms don't stream their output to writers as it renders. When
+/// rendering fragments, each fragment is transformed separately.
+pub trait OutputTransform: Send + Sync + 'static {
+    fn transform(&self, output: String, render: &TransformedRender<'_>) -> Result<String, Error>;
+}
+
+impl<F> OutputTransform for F
+where
+    F: Fn(String, &TransformedRender<'_>) -> Result<String, Error> + Send + Sync + 'static,
+{
+    fn transform(&self, output: String, render: &TransformedRender<'_>) -> Result<String, Error> {
+        self(output, render)
+    }
+}
+
+/// Describes the render whose output an [`OutputTransform`] is rewriting.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct TransformedRender<'a> {
+    /// The name of the registered template, or `None` for a template that
+    /// was compiled directly.
+    pub template: Option<&'a str>,
+    pub parameters: &'a [Symbol],
+    /// The name of the fragment being transformed, when rendering
+    /// fragments. See [`Fragment`](crate::Fragment).
+    pub fragment: Option<&'a str>,
+    /// The assets of the configuration the template was compiled with.
+    pub assets: &'a AssetManifest,
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+/// Receives a summary of each render of templates compiled with
+/// [`Configuration::with_usage_observer`](crate::Configuration::with_usage_observer),
+/// such as to count renders and time them in a metrics system.
+///
+/// Observers are invoked after each render finishes, including renders that
+/// fail, so unused templates and slow ones can be found across a large set
+/// of templates.
+pub trait UsageObserver: Send + Sync + 'static {
+    fn observe(&self, usage: &RenderUsage<'_>);
+}
+
+impl<F> UsageObserver for F
+where
+    F: Fn(&RenderUsage<'_>) + Send + Sync + 'static,
+{
+    fn observe(&self, usage: &RenderUsage<'_>) {
+        self(usage);
+    }
+}
+
+/// A summary of a single render, passed to a [`UsageObserver`].
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct RenderUsage<'a> {
+    /// The name of the registered template, or `None` for a template that
+    /// was compiled directly.
+    pub template: Option<&'a str>,
+    /// How long the render took, including its output transforms.
+    pub duration: Duration,
+    /// Whether the compiled template had been rendered before.
+    pub cache: CacheStatus,
+    /// The tags of the render, as added using
+    /// [`RenderOptions::with_tag`](crate::RenderOptions::with_tag).
+    pub tags: &'a [(String, String)],
+    /// Whether the render succeeded.
+    pub succeeded: bool,
+}
+
+/// Whether a render reused a compiled template.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum CacheStatus {
+    /// The template had been rendered before, such as by an earlier request
+    /// to a [`Renderer`](crate::Renderer), so its compilation was reused.
+    Hit,
+    /// This is the first render of the compiled template.
+    Miss,
+}
+
+#[test]
+fn observed_renders() {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{Configuration, Error, ErrorKind, RenderOptions, Template};
+
+    let renders = Arc::new(Mutex::new(Vec::new()));
+    let configuration = Configuration::default().with_usage_observer({
+        let renders = renders.clone();
+        move |usage: &RenderUsage<'_>| {
+            renders.lock().unwrap().push((
+                usage.template.map(String::from),
+                usage.cache,
+                usage.tags.to_vec(),
+                usage.succeeded,
+            ));
+        }
+    });
+    let template = Template::from("{{= user.name }}")
+        .compile(&configuration, ["user"])
+        .unwrap();
+    let options = RenderOptions::new().with_tag("tenant", "khonsu");
+    assert!(matches!(
+        template.render_with_options(&options, [("user", "ecton")]),
+        Err(Error {
+            kind: ErrorKind::InvalidArgument { .. },
+            ..
+        })
+    ));
+    template
+        .render_with([("user", crate::vm::map([("name", "ecton")]))])
+        .unwrap();
+    assert_eq!(
+        *renders.lock().unwrap(),
+        [
+            (
+                None,
+                CacheStatus::Miss,
+                vec![(String::from("tenant"), String::from("khonsu"))],
+                false
+            ),
+            (None, CacheStatus::Hit, Vec::new(), true),
+        ]
+    );
+}
@@ -0,0 +1,191 @@
+use budlang::vm::{DynamicValue, HashMap, List, Symbol, Value};
+
+use crate::{SafeString, Secret};
+
+/// A view of a [`Value`] that distinguishes the collection types Budplate
+/// understands.
+#[derive(Debug)]
+pub(crate) enum Shape {
+    Integer(i64),
+    Real(f64),
+    Boolean(bool),
+    Void,
+    String(String),
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Other,
+}
+
+impl Shape {
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Integer(value) => Self::Integer(*value),
+            Value::Real(value) => Self::Real(*value),
+            Value::Boolean(value) => Self::Boolean(*value),
+            Value::Void => Self::Void,
+            Value::Dynamic(_) => {
+                if let Some(string) = value.as_dynamic::<String>() {
+                    Self::String(string.clone())
+                } else if let Some(list) = value.as_dynamic::<List>() {
+                    Self::List(list.to_vec())
+                } else if let Some(map) = value.as_dynamic::<HashMap>() {
+                    Self::Map(map.to_vec())
+                } else if let Some(secret) = value.as_dynamic::<Secret>() {
+                    Self::of(secret.expose())
+                } else {
+                    Self::Other
+                }
+            }
+        }
+    }
+
+    /// Returns the name of this shape's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Integer(_) => "integer",
+            Self::Real(_) => "real number",
+            Self::Boolean(_) => "boolean",
+            Self::Void => "void value",
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Other => "native value",
+        }
+    }
+}
+
+/// A value that wasn't provided, such as an omitted argument or a field that
+/// isn't present in a map.
+#[derive(Debug)]
+pub(crate) struct Missing;
+
+impl Missing {
+    pub fn is(value: &Value) -> bool {
+        value.as_dynamic::<Self>().is_some()
+    }
+
+    /// Returns true if `value` is missing or `void`.
+    pub fn or_void(value: &Value) -> bool {
+        matches!(value, Value::Void) || Self::is(value)
+    }
+}
+
+impl DynamicValue for Missing {
+    fn is_truthy(&self) -> bool {
+        false
+    }
+
+    fn kind(&self) -> Symbol {
+        Symbol::from("Missing")
+    }
+}
+
+/// The keys and values of a collection being iterated by a `for` statement.
+#[derive(Debug)]
+pub(crate) struct Entries(pub Vec<(Value, Value)>);
+
+impl Entries {
+    /// Returns the entries of a list keyed by index, or of a map keyed by the
+    /// string form of each key in sorted order. Void has no entries.
+    pub fn of(collection: &Value) -> Option<Self> {
+        match Shape::of(collection) {
+            Shape::List(values) => Some(Self(
+                values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        (Value::from(i64::try_from(index).unwrap_or(i64::MAX)), value)
+                    })
+                    .collect(),
+            )),
+            Shape::Map(entries) => Some(Self(
+                sorted_entries(entries)
+                    .into_iter()
+                    .map(|(key, value)| (Value::from(key), value))
+                    .collect(),
+            )),
+            Shape::Void => Some(Self(Vec::new())),
+            _ => None,
+        }
+    }
+}
+
+impl DynamicValue for Entries {
+    fn is_truthy(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    fn kind(&self) -> Symbol {
+        Symbol::from("Entries")
+    }
+}
+
+/// An exact, comparable copy of a [`Value`], which identifies the arguments
+/// a sub-template is rendered with.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub(crate) enum Fingerprint {
+    Void,
+    Missing,
+    Boolean(bool),
+    Integer(i64),
+    Real(u64),
+    String(String),
+    Safe(String),
+    List(Vec<Fingerprint>),
+    Map(Vec<(Fingerprint, Fingerprint)>),
+}
+
+impl Fingerprint {
+    /// Returns the fingerprint of `value`, or `None` if `value` contains a
+    /// value that can't be compared, such as a [`Secret`] or a dynamic value
+    /// Budplate doesn't know.
+    pub fn of(value: &Value) -> Option<Self> {
+        if let Some(safe) = value.as_dynamic::<SafeString>() {
+            return Some(Self::Safe(safe.as_str().to_string()));
+        } else if Missing::is(value) {
+            return Some(Self::Missing);
+        } else if value.as_dynamic::<Secret>().is_some() {
+            return None;
+        }
+
+        match Shape::of(value) {
+            Shape::Void => Some(Self::Void),
+            Shape::Boolean(value) => Some(Self::Boolean(value)),
+            Shape::Integer(value) => Some(Self::Integer(value)),
+            Shape::Real(value) => Some(Self::Real(value.to_bits())),
+            Shape::String(value) => Some(Self::String(value)),
+            Shape::List(values) => values
+                .iter()
+                .map(Self::of)
+                .collect::<Option<_>>()
+                .map(Self::List),
+            Shape::Map(entries) => entries
+                .iter()
+                .map(|(key, value)| Some((Self::of(key)?, Self::of(value)?)))
+                .collect::<Option<_>>()
+                .map(Self::Map),
+            Shape::Other => None,
+        }
+    }
+}
+
+/// Returns the entries of a map keyed by the string form of each key,
+/// sorted by key so that output is stable.
+pub(crate) fn sorted_entries(entries: Vec<(Value, Value)>) -> Vec<(String, Value)> {
+    let mut entries = entries
+        .into_iter()
+        .map(|(key, value)| (key_string(&key), value))
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+pub(crate) fn key_string(key: &Value) -> String {
+    match Shape::of(key) {
+        Shape::String(key) => key,
+        Shape::Integer(key) => key.to_string(),
+        Shape::Real(key) => key.to_string(),
+        Shape::Boolean(key) => key.to_string(),
+        _ => crate::PrettyPrinter::new().print(key),
+    }
+}
@@ -0,0 +1,53 @@
+use crate::hash;
+
+/// Receives each exposure of a user to an experiment's variant, as chosen by
+/// `variant()` in templates compiled with
+/// [`Configuration::with_exposure_recorder`](crate::Configuration::with_exposure_recorder).
+pub trait ExposureRecorder: Send + Sync + 'static {
+    fn record(&self, exposure: &Exposure);
+}
+
+impl<F> ExposureRecorder for F
+where
+    F: Fn(&Exposure) + Send + Sync + 'static,
+{
+    fn record(&self, exposure: &Exposure) {
+        self(exposure);
+    }
+}
+
+/// A variant of an experiment that was rendered.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Exposure {
+    pub experiment: String,
+    pub variant: String,
+    /// The `user_id` from the render's context, or `None` if it wasn't
+    /// provided.
+    pub user: Option<String>,
+}
+
+/// Returns the index of the variant of `experiment` that `user` is assigned
+/// to, out of `count` variants. Users without an id see the first variant.
+///
+/// Assignments are made using FNV-1a, which is stable across platforms and
+/// releases, so a user sees the same variant wherever a page is rendered.
+pub(crate) fn assign(experiment: &str, user: Option<&str>, count: usize) -> usize {
+    let Some(user) = user else {
+        return 0;
+    };
+    let hash = hash::fnv1a(experiment.bytes().chain([0]).chain(user.bytes()));
+    usize::try_from(hash % count as u64).unwrap_or_default()
+}
+
+#[test]
+fn stable_assignments() {
+    assert_eq!(assign("hero", None, 3), 0);
+    let assigned = (0..100)
+        .map(|user| assign("hero", Some(&user.to_string()), 2))
+        .collect::<Vec<_>>();
+    assert!(assigned.contains(&0) && assigned.contains(&1));
+    // Assignments must not change between releases.
+    assert_eq!(assign("hero", Some("42"), 2), 1);
+    assert_eq!(assign("hero", Some("42"), 3), 0);
+    assert_eq!(assign("hero", Some("42"), 1), 0);
+}
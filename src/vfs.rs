@@ -0,0 +1,223 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+/// A filesystem that Budplate reads files from, such as the templates read
+/// by a [`FileLoader`](crate::FileLoader).
+///
+/// [`RealFs`] reads the operating system's filesystem, while [`MemoryFs`]
+/// holds files in memory, for tests and for environments without a
+/// filesystem, such as WebAssembly or serverless functions. Other sources,
+/// such as archives or object stores, can be read by implementing this
+/// trait.
+pub trait Vfs: 'static {
+    /// Returns the contents of the file at `path`. A missing file returns an
+    /// error of the kind [`io::ErrorKind::NotFound`].
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Returns the files and directories directly within the directory at
+    /// `path`, in any order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsEntry>>;
+}
+
+/// A file or directory returned by [`Vfs::read_dir()`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VfsEntry {
+    /// The path of the entry, which is the path of its directory joined with
+    /// its name.
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// When the file was last modified, if known. Changes to this time are
+    /// how hot reloading notices that a file changed.
+    pub modified: Option<SystemTime>,
+}
+
+/// The operating system's filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Vfs for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsEntry>> {
+        fs::read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                Ok(VfsEntry {
+                    path: entry.path(),
+                    is_dir: metadata.is_dir(),
+                    modified: metadata.modified().ok(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A filesystem held in memory.
+///
+/// Clones of a `MemoryFs` share the same files, so files written to one
+/// clone are read by the others, such as by a
+/// [`FileLoader`](crate::FileLoader) reading a clone.
+///
+/// ```rust
+/// use budplate::{Configuration, FileLoader, MemoryFs};
+///
+/// let files = MemoryFs::new()
+///     .with_file("templates/index.bud.html", r#"<main>{{ include "partials/nav" }}</main>"#)
+///     .with_file("templates/partials/nav.bud.html", "<nav></nav>");
+/// let loader = FileLoader::new("templates", Configuration::for_html())
+///     .with_vfs(files.clone())
+///     .hot_reload(true);
+/// assert_eq!(loader.render("index").unwrap(), "<main><nav></nav></main>");
+///
+/// files.write("templates/partials/nav.bud.html", "<nav>Home</nav>");
+/// assert_eq!(loader.render("index").unwrap(), "<main><nav>Home</nav></main>");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: Arc<Mutex<MemoryFiles>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryFiles {
+    files: BTreeMap<PathBuf, (Vec<u8>, SystemTime)>,
+    /// The modification time of the most recently written file.
+    last_modified: Option<SystemTime>,
+}
+
+impl MemoryFs {
+    /// Returns an empty filesystem.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `contents` to the file at `path`, returning this filesystem.
+    #[must_use]
+    pub fn with_file(self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> Self {
+        self.write(path, contents);
+        self
+    }
+
+    /// Writes `contents` to the file at `path`, replacing the file if it
+    /// exists. Directories exist while they contain a file.
+    pub fn write(&self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        let mut files = self.lock();
+        // Each write is given a later modification time than the last, even
+        // if the clock hasn't advanced, so that every change is noticed.
+        let modified = match files.last_modified {
+            Some(last) if last >= SystemTime::now() => last + Duration::from_nanos(1),
+            _ => SystemTime::now(),
+        };
+        files.last_modified = Some(modified);
+        files
+            .files
+            .insert(normalize(path.as_ref()), (contents.into(), modified));
+    }
+
+    /// Removes the file at `path`, returning true if it existed.
+    pub fn remove(&self, path: impl AsRef<Path>) -> bool {
+        self.lock()
+            .files
+            .remove(&normalize(path.as_ref()))
+            .is_some()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryFiles> {
+        self.files.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.lock()
+            .files
+            .get(&normalize(path))
+            .map(|(contents, _)| contents.clone())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsEntry>> {
+        let directory = normalize(path);
+        let files = self.lock();
+        let mut entries: Vec<VfsEntry> = Vec::new();
+        for (file, (_, modified)) in files.files.range(directory.clone()..) {
+            let Ok(relative) = file.strip_prefix(&directory) else {
+                break;
+            };
+            let mut components = relative.components();
+            let Some(name) = components.next() else {
+                // The directory is itself a file.
+                return Err(io::Error::from(io::ErrorKind::InvalidInput));
+            };
+            let path = directory.join(name);
+            if components.next().is_none() {
+                entries.push(VfsEntry {
+                    path,
+                    is_dir: false,
+                    modified: Some(*modified),
+                });
+            } else if entries.last().is_none_or(|last| last.path != path) {
+                entries.push(VfsEntry {
+                    path,
+                    is_dir: true,
+                    modified: None,
+                });
+            }
+        }
+        if entries.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        Ok(entries)
+    }
+}
+
+/// Returns `path` without `.` components or redundant separators, so that
+/// equivalent paths name the same file.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+#[test]
+fn memory_directories() {
+    let files = MemoryFs::new()
+        .with_file("a/b.txt", "b")
+        .with_file("./a/c/d.txt", "d")
+        .with_file("a/c/e.txt", "e")
+        .with_file("ab.txt", "ab");
+    assert_eq!(files.read(Path::new("a/./b.txt")).unwrap(), b"b");
+    assert_eq!(
+        files.read(Path::new("a/x.txt")).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+
+    let entries = files.read_dir(Path::new("a")).unwrap();
+    assert_eq!(
+        entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.is_dir))
+            .collect::<Vec<_>>(),
+        [
+            (PathBuf::from("a/b.txt"), false),
+            (PathBuf::from("a/c"), true)
+        ]
+    );
+    assert_eq!(files.read_dir(Path::new("a/c")).unwrap().len(), 2);
+    assert_eq!(files.read_dir(Path::new("")).unwrap().len(), 2);
+    assert!(files.read_dir(Path::new("x")).is_err());
+
+    let modified = files.read_dir(Path::new("a")).unwrap()[0].modified;
+    files.write("a/b.txt", "changed");
+    assert!(files.read_dir(Path::new("a")).unwrap()[0].modified > modified);
+    assert!(files.remove("a/b.txt"));
+    assert_eq!(files.read_dir(Path::new("a")).unwrap().len(), 1);
+}
@@ -0,0 +1,53 @@
+//! The Bud types used by budplate's API.
+//!
+//! Budplate depends on a specific version of [budlang], whose types may
+//! change between its releases. Using them through this module, rather than
+//! depending on budlang directly, ensures they are always the types budplate
+//! was built with, so upgrading budplate doesn't require upgrading budlang to
+//! a matching version. [`list()`] and [`map()`] create collections without
+//! relying on how the VM represents them.
+//!
+//! ```rust
+//! use budplate::{vm, Template};
+//!
+//! let rendered = Template::from("{{ for fruit in fruits }}{{= fruit }} {{ end }}")
+//!     .render_with([("fruits", vm::list(["apple", "pear"]))])
+//!     .unwrap();
+//! assert_eq!(rendered, "apple pear ");
+//! ```
+//!
+//! [budlang]: https://github.com/khonsulabs/budlang
+
+pub use budlang::vm::{
+    DynamicValue, FaultKind, HashMap, List, NativeFunction, PoppedValues, Symbol, Value,
+};
+
+/// Returns a list containing `values`.
+pub fn list<V>(values: impl IntoIterator<Item = V>) -> Value
+where
+    V: Into<Value>,
+{
+    Value::dynamic(values.into_iter().map(Into::into).collect::<List>())
+}
+
+/// Returns a map containing each key and value of `entries`.
+pub fn map<K, V>(entries: impl IntoIterator<Item = (K, V)>) -> Value
+where
+    K: Into<Value>,
+    V: Into<Value>,
+{
+    Value::dynamic(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect::<HashMap>(),
+    )
+}
+
+#[test]
+fn collections() {
+    let fruits = list(["apple", "pear"]);
+    assert!(fruits.as_dynamic::<List>().is_some());
+    let user = map([("name", Value::from("Ada")), ("id", Value::Integer(1))]);
+    assert!(user.as_dynamic::<HashMap>().is_some());
+}
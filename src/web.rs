@@ -0,0 +1,125 @@
+use crate::{Error, ErrorKind};
+
+/// A rendered HTML document, which is sent as a response with the content
+/// type `text/html; charset=utf-8`.
+///
+/// With the `axum` feature enabled, `Html` implements `IntoResponse`, and
+/// with the `actix-web` feature enabled it implements `Responder`. [`Error`]
+/// is converted into a response with the status from
+/// [`Error::status_code()`] by each, so a handler can return the result of
+/// rendering a template directly:
+///
+/// ```rust
+/// use budplate::{Html, Templates};
+///
+/// let mut templates = Templates::new();
+/// templates.register("index", "<h1>{{= title }}</h1>").unwrap();
+///
+/// let page = templates.render_with("index", [("title", "Home")]).map(Html);
+/// assert_eq!(page.unwrap(), Html(String::from("<h1>Home</h1>")));
+/// assert_eq!(templates.render("missing").unwrap_err().status_code(), 404);
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Html<T = String>(pub T);
+
+impl<T> From<T> for Html<T> {
+    fn from(body: T) -> Self {
+        Self(body)
+    }
+}
+
+impl Error {
+    /// Returns the HTTP status code that best describes this error when it
+    /// occurs while rendering a response.
+    ///
+    /// Requests for templates that aren't registered are `404 Not Found`, and
+    /// every other error is `500 Internal Server Error`.
+    #[must_use]
+    pub fn status_code(&self) -> u16 {
+        match self.kind {
+            ErrorKind::UnknownTemplate(_) => 404,
+            _ => 500,
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+mod axum_impls {
+    use axum::{
+        http::StatusCode,
+        response::{IntoResponse, Response},
+    };
+
+    use super::Html;
+    use crate::Error;
+
+    impl<T: Into<String>> IntoResponse for Html<T> {
+        fn into_response(self) -> Response {
+            axum::response::Html(self.0.into()).into_response()
+        }
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> Response {
+            // The error's message describes the template, which shouldn't be
+            // revealed to clients.
+            StatusCode::from_u16(self.status_code())
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response()
+        }
+    }
+}
+
+#[cfg(feature = "actix-web")]
+mod actix_impls {
+    use actix_web::{
+        body::BoxBody,
+        http::{header::ContentType, StatusCode},
+        HttpRequest, HttpResponse, Responder, ResponseError,
+    };
+
+    use super::Html;
+    use crate::Error;
+
+    impl<T: Into<String>> Responder for Html<T> {
+        type Body = BoxBody;
+
+        fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+            HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body(self.0.into())
+        }
+    }
+
+    impl ResponseError for Error {
+        fn status_code(&self) -> StatusCode {
+            StatusCode::from_u16(Error::status_code(self))
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+
+        fn error_response(&self) -> HttpResponse<BoxBody> {
+            // The error's message describes the template, which shouldn't be
+            // revealed to clients.
+            HttpResponse::new(ResponseError::status_code(self))
+        }
+    }
+}
+
+#[test]
+fn status_codes() {
+    assert_eq!(
+        Error::from(ErrorKind::UnknownTemplate(String::from("index"))).status_code(),
+        404
+    );
+    assert_eq!(
+        ErrorKind::UnknownTemplate(String::from("index"))
+            .located(0..1)
+            .status_code(),
+        404
+    );
+    assert_eq!(
+        Error::from(ErrorKind::MissingArgument(String::from("title"))).status_code(),
+        500
+    );
+    assert_eq!(Error::from(ErrorKind::BudgetExceeded).status_code(), 500);
+}